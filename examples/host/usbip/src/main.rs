@@ -0,0 +1,292 @@
+//! Run a zeptos USB device on the simulated controller and export it over USB/IP.
+//!
+//! ```sh
+//! cargo run
+//! sudo modprobe vhci-hcd
+//! sudo usbip attach -r 127.0.0.1 -b 1-1
+//! ```
+//!
+//! The device then enumerates on the local machine, and can be removed with
+//! `sudo usbip detach -p 0`. Each connection starts the device from scratch.
+
+mod usbip;
+
+use std::cell::RefCell;
+use std::future::{poll_fn, Future};
+use std::net::TcpListener;
+use std::pin::{pin, Pin};
+use std::task::Poll;
+
+use futures_util::future;
+
+use zeptos::usb::descriptors::{descriptors, language_list, string_descriptor, webusb_url, Config, ConfigDescriptor, Device, DescriptorBuilder, Endpoint as EndpointDescriptor, Interface, StringTable};
+use zeptos::usb::descriptors::{BinaryObjectStore, PlatformCapabilityWebUsb, UrlTable, WEBUSB_GET_URL};
+use zeptos::usb::descriptors::{MicrosoftOs10CompatibleID, MicrosoftOs10Descriptors, MicrosoftOs10ExtendedCompatId, MicrosoftOs10String, MS_OS_10_STRING_INDEX};
+use zeptos::usb::sim::Host;
+use zeptos::usb::{Endpoint, Endpoints, Handler, In, Out, Responded, Setup, Usb, UsbBuffer};
+use zeptos::Runtime;
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    panic!("defmt panic")
+}
+
+defmt::timestamp!("");
+
+fn main() {
+    let listener = TcpListener::bind(("127.0.0.1", 3240)).expect("failed to listen on the USB/IP port");
+    println!("Exporting bus ID {} on {}", usbip::BUS_ID, listener.local_addr().unwrap());
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {e}");
+                continue;
+            }
+        };
+        stream.set_nodelay(true).ok();
+
+        let mut host = Host::new();
+        let mut usb = unsafe { Usb::new(Runtime::steal()) };
+        let echo = Task::new();
+        let mut handler = ExampleDevice { echo: &echo, echo_payload: RefCell::new([0; 16]) };
+        let dev = pin!(future::join(usb.run_device(&mut handler), echo.run()));
+
+        if let Err(e) = usbip::serve(&mut stream, &mut host, dev) {
+            eprintln!("connection closed: {e}");
+        }
+    }
+}
+
+/// Stands in for a `#[zeptos::task]`, which needs the interrupt-driven
+/// executor, by polling the spawned future alongside the device.
+struct Task {
+    fut: RefCell<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+}
+
+impl Task {
+    fn new() -> Self {
+        Task { fut: RefCell::new(None) }
+    }
+
+    fn spawn(&self, fut: impl Future<Output = ()> + 'static) {
+        self.fut.replace(Some(Box::pin(fut)));
+    }
+
+    fn cancel(&self) {
+        self.fut.replace(None);
+    }
+
+    async fn run(&self) {
+        poll_fn(|cx| {
+            let mut fut = self.fut.borrow_mut();
+            if fut.as_mut().is_some_and(|f| f.as_mut().poll(cx).is_ready()) {
+                *fut = None;
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+const REQ_ECHO: u8 = 0x03;
+
+struct ExampleDevice<'a> {
+    echo: &'a Task,
+    echo_payload: RefCell<[u8; 16]>,
+}
+
+impl Handler for ExampleDevice<'_> {
+    fn handle_reset(&self) {
+        self.echo.cancel();
+    }
+
+    fn get_descriptor<'a>(&self, kind: u8, index: u8, lang: u16, _builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        use usb::descriptor_type::{BOS, CONFIGURATION, DEVICE, STRING};
+        match (kind, index) {
+            (DEVICE, _) => Some(DEVICE_DESCRIPTOR),
+            (CONFIGURATION, 0) => Some(CONFIG_DESCRIPTOR),
+            (BOS, 0) => Some(BOS_DESCRIPTOR),
+            (STRING, MS_OS_10_STRING_INDEX) => Some(MSOS10.string),
+            (STRING, _) => STRINGS.get(index, lang),
+            _ => None,
+        }
+    }
+
+    async fn set_configuration(&self, cfg: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        self.echo.cancel();
+        match cfg {
+            0 => Ok(()),
+            CFG_MAIN => {
+                let endpoints = endpoints.described::<EchoConfig>();
+                let ep_echo_out = endpoints.bulk_out::<EP_ECHO_OUT>();
+                let ep_echo_in = endpoints.bulk_in::<EP_ECHO_IN>();
+                self.echo.spawn(echo_task(ep_echo_out, ep_echo_in));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        use zeptos::usb::ControlData::*;
+        use zeptos::usb::ControlType::*;
+        use zeptos::usb::Recipient::*;
+
+        let req = match MSOS10.handle_control(req).await {
+            Ok(r) => return r,
+            Err(req) => req,
+        };
+
+        match req {
+            Setup { ty: Vendor, recipient: Device, request: REQ_ECHO, data: Out(mut data), .. } => {
+                let mut buf = [0; 16];
+                let Ok(d) = data.receive_into(&mut buf).await else {
+                    return data.reject();
+                };
+                let len = d.len();
+                self.echo_payload.borrow_mut()[..len].copy_from_slice(&buf[..len]);
+                data.accept().await
+            }
+            Setup { ty: Vendor, recipient: Device, request: REQ_ECHO, data: In(data), .. } => {
+                let echo = *self.echo_payload.borrow();
+                data.respond(&echo).await
+            }
+            Setup { ty: Vendor, recipient: Device, request: VENDOR_CODE, index: WEBUSB_GET_URL, value, data: In(data) } => {
+                match URLS.get(value) {
+                    Some(url) => data.respond(url).await,
+                    None => data.reject(),
+                }
+            }
+            req => req.reject(),
+        }
+    }
+}
+
+async fn echo_task(mut ep_out: Endpoint<Out, EP_ECHO_OUT>, mut ep_in: Endpoint<In, EP_ECHO_IN>) {
+    let mut buf = UsbBuffer::<64>::new();
+    loop {
+        let len = ep_out.receive(&mut buf).await;
+        ep_in.send(&buf, len, false).await;
+    }
+}
+
+const EP_ECHO_OUT: u8 = 0x01;
+const EP_ECHO_IN: u8 = 0x81;
+
+const CFG_MAIN: u8 = 1;
+const INTF_ECHO: u8 = 0;
+
+const STRING_MFG: u8 = 1;
+const STRING_PRODUCT: u8 = 2;
+const STRING_INTF_ECHO: u8 = 3;
+
+const LANGUAGE_GERMAN: u16 = 0x0407;
+
+static STRINGS: StringTable = StringTable {
+    languages: language_list!(usb::language_id::ENGLISH_US, LANGUAGE_GERMAN),
+    strings: &[
+        &[string_descriptor!("zeptos project")],
+        &[string_descriptor!("simulated test device"), string_descriptor!("simuliertes Testgerät")],
+        &[string_descriptor!("Echo Interface"), string_descriptor!("Echo-Schnittstelle")],
+    ],
+};
+
+/// `bRequest` of the vendor requests for MS OS 1.0 descriptors and WebUSB URLs.
+const VENDOR_CODE: u8 = 0x20;
+
+static URLS: UrlTable = UrlTable {
+    urls: &[webusb_url!("https://example.com/zeptos")],
+};
+
+static BOS_DESCRIPTOR: &[u8] = descriptors! {
+    BinaryObjectStore {
+        +PlatformCapabilityWebUsb {
+            vendor_code: VENDOR_CODE,
+            landing_page: 1,
+        }
+    }
+};
+
+static MSOS10: MicrosoftOs10Descriptors = MicrosoftOs10Descriptors {
+    string: descriptors! {
+        MicrosoftOs10String {
+            vendor_code: VENDOR_CODE,
+        }
+    },
+    compat_id: descriptors! {
+        MicrosoftOs10ExtendedCompatId {
+            +MicrosoftOs10CompatibleID {
+                first_interface: INTF_ECHO,
+                compatible_id: "WINUSB",
+                sub_compatible_id: "",
+            }
+        }
+    },
+    properties: &[],
+};
+
+static DEVICE_DESCRIPTOR: &[u8] = descriptors! {
+    Device {
+        bcdUSB: 0x0210,
+        bDeviceClass: ::usb::class_code::VENDOR_SPECIFIC,
+        bDeviceSubClass: 0x00,
+        bDeviceProtocol: 0x00,
+        bMaxPacketSize0: 64,
+        idVendor: 0x59e3,
+        idProduct: 0x00AA,
+        bcdDevice: 0x0000,
+        iManufacturer: STRING_MFG,
+        iProduct: STRING_PRODUCT,
+        iSerialNumber: 0,
+        bNumConfigurations: 1,
+    }
+};
+
+struct EchoConfig;
+impl ConfigDescriptor for EchoConfig {
+    const DESCRIPTOR: &'static [u8] = CONFIG_DESCRIPTOR;
+}
+
+static CONFIG_DESCRIPTOR: &[u8] = descriptors! {
+    Config {
+        bConfigurationValue: CFG_MAIN,
+        iConfiguration: 0,
+        bmAttributes: 0x80,
+        bMaxPower: 50,
+
+        +Interface {
+            bInterfaceNumber: INTF_ECHO,
+            bAlternateSetting: 0,
+            bInterfaceClass: usb::class_code::VENDOR_SPECIFIC,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0,
+            iInterface: STRING_INTF_ECHO,
+
+            +EndpointDescriptor {
+                bEndpointAddress: EP_ECHO_OUT,
+                bmAttributes: usb::endpoint_attributes::transfer_type::BULK,
+                wMaxPacketSize: 64,
+                bInterval: 0,
+            }
+
+            +EndpointDescriptor {
+                bEndpointAddress: EP_ECHO_IN,
+                bmAttributes: usb::endpoint_attributes::transfer_type::BULK,
+                wMaxPacketSize: 64,
+                bInterval: 0,
+            }
+        }
+    }
+};
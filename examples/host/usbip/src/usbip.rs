@@ -0,0 +1,430 @@
+//! Server side of the USB/IP protocol, exporting the device on the simulated bus.
+//!
+//! Each connection performs one operation: listing the device, or importing
+//! it and then forwarding URBs until the client disconnects. Multi-byte
+//! fields are big-endian on the wire.
+//!
+//! Control transfers complete immediately. Bulk and interrupt URBs are queued
+//! per endpoint and complete as the device sends or consumes the data, so an
+//! IN URB on an idle endpoint stays pending until the device sends something
+//! or the client unlinks it. Isochronous transfers are not supported.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+
+use usb::descriptor_type::{CONFIGURATION, DEVICE, INTERFACE};
+use usb::endpoint_address::IN as EP_IN;
+use usb::standard_request::{GET_DESCRIPTOR, SET_ADDRESS, SET_FEATURE};
+use zeptos::usb::sim::{ControlError, Host};
+
+/// Bus ID of the exported device, as passed to `usbip attach -b`.
+pub const BUS_ID: &str = "1-1";
+const BUS_NUM: u32 = 1;
+const DEV_NUM: u32 = 1;
+const PATH: &str = "/sys/devices/zeptos/usb1/1-1";
+
+const USBIP_VERSION: u16 = 0x0111;
+
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const CMD_SUBMIT: u32 = 1;
+const CMD_UNLINK: u32 = 2;
+const RET_SUBMIT: u32 = 3;
+const RET_UNLINK: u32 = 4;
+
+const DIR_IN: u32 = 1;
+
+const URB_ZERO_PACKET: u32 = 0x0040;
+
+const USB_SPEED_FULL: u32 = 2;
+
+/// Hub class SetPortFeature(PORT_RESET), which usbip-host handles by resetting the device.
+const PORT_RESET_REQUEST: [u8; 4] = [0x23, SET_FEATURE, 4, 0];
+
+const EINVAL: i32 = 22;
+const EPIPE: i32 = 32;
+const EPROTO: i32 = 71;
+const EOVERFLOW: i32 = 75;
+const ECONNRESET: i32 = 104;
+
+fn setup(ty: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let [v0, v1] = value.to_le_bytes();
+    let [i0, i1] = index.to_le_bytes();
+    let [l0, l1] = length.to_le_bytes();
+    [ty, request, v0, v1, i0, i1, l0, l1]
+}
+
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn control_failed(e: ControlError) -> io::Error {
+    io::Error::other(format!("control transfer failed: {e:?}"))
+}
+
+fn control_status(e: ControlError) -> i32 {
+    match e {
+        ControlError::Stall => -EPIPE,
+        ControlError::Incomplete => -EPROTO,
+    }
+}
+
+/// Reset the device and address it, as it would be on the exporting host.
+fn reset<F: Future>(host: &mut Host, mut dev: Pin<&mut F>) -> Result<(), ControlError> {
+    host.reset(dev.as_mut());
+    host.control_out(dev, setup(0x00, SET_ADDRESS, DEV_NUM as u16, 0, 0), &[])
+}
+
+/// Fields of the device and configuration descriptors reported to the client.
+struct DeviceInfo {
+    device: [u8; 18],
+
+    /// Class, subclass and protocol of each interface
+    interfaces: Vec<[u8; 3]>,
+}
+
+impl DeviceInfo {
+    fn read<F: Future>(host: &mut Host, mut dev: Pin<&mut F>) -> io::Result<DeviceInfo> {
+        let device = host
+            .control_in(dev.as_mut(), setup(0x80, GET_DESCRIPTOR, (DEVICE as u16) << 8, 0, 18))
+            .map_err(control_failed)?;
+        let device: [u8; 18] = device.try_into().map_err(|_| invalid("short device descriptor"))?;
+
+        let header = host
+            .control_in(dev.as_mut(), setup(0x80, GET_DESCRIPTOR, (CONFIGURATION as u16) << 8, 0, 9))
+            .map_err(control_failed)?;
+        let [_, _, l0, l1, ..] = header[..] else {
+            return Err(invalid("short configuration descriptor"));
+        };
+        let config = host
+            .control_in(dev, setup(0x80, GET_DESCRIPTOR, (CONFIGURATION as u16) << 8, 0, u16::from_le_bytes([l0, l1])))
+            .map_err(control_failed)?;
+
+        let mut interfaces = Vec::new();
+        let mut rest = &config[..];
+        while let [len, ..] = *rest {
+            let len = len as usize;
+            if len < 2 || len > rest.len() {
+                return Err(invalid("malformed configuration descriptor"));
+            }
+            if let [9, INTERFACE, _, 0, _, class, subclass, protocol, _] = rest[..len] {
+                interfaces.push([class, subclass, protocol]);
+            }
+            rest = &rest[len..];
+        }
+
+        Ok(DeviceInfo { device, interfaces })
+    }
+
+    /// Append the `usbip_usb_device` structure.
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut path = [0; 256];
+        path[..PATH.len()].copy_from_slice(PATH.as_bytes());
+        out.extend(path);
+
+        let mut bus_id = [0; 32];
+        bus_id[..BUS_ID.len()].copy_from_slice(BUS_ID.as_bytes());
+        out.extend(bus_id);
+
+        out.extend(BUS_NUM.to_be_bytes());
+        out.extend(DEV_NUM.to_be_bytes());
+        out.extend(USB_SPEED_FULL.to_be_bytes());
+
+        let d = &self.device;
+        out.extend([d[9], d[8], d[11], d[10], d[13], d[12]]); // idVendor, idProduct, bcdDevice
+        out.extend([d[4], d[5], d[6]]); // bDeviceClass, bDeviceSubClass, bDeviceProtocol
+        out.push(0); // bConfigurationValue
+        out.push(d[17]); // bNumConfigurations
+        out.push(self.interfaces.len() as u8);
+    }
+}
+
+fn op_header(code: u16, status: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(USBIP_VERSION.to_be_bytes());
+    out.extend(code.to_be_bytes());
+    out.extend(status.to_be_bytes());
+    out
+}
+
+fn ret_header(command: u32, seqnum: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(48);
+    out.extend(command.to_be_bytes());
+    out.extend(seqnum.to_be_bytes());
+    out.extend([0; 12]); // devid, direction, ep
+    out
+}
+
+/// Build a RET_SUBMIT reply, followed by `data` received on an IN endpoint.
+fn ret_submit(seqnum: u32, status: i32, actual_length: usize, data: &[u8]) -> Vec<u8> {
+    let mut out = ret_header(RET_SUBMIT, seqnum);
+    out.extend(status.to_be_bytes());
+    out.extend((actual_length as u32).to_be_bytes());
+    out.extend([0; 20]); // start_frame, number_of_packets, error_count, padding
+    out.extend(data);
+    out
+}
+
+/// Handle one USB/IP connection to the device future `dev`.
+///
+/// `dev` must not have been polled yet. It is reset and queried for its
+/// descriptors before the client's request is read.
+pub fn serve<S: Read + Write, F: Future>(stream: &mut S, host: &mut Host, mut dev: Pin<&mut F>) -> io::Result<()> {
+    reset(host, dev.as_mut()).map_err(control_failed)?;
+    let info = DeviceInfo::read(host, dev.as_mut())?;
+
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let version = u16::from_be_bytes([header[0], header[1]]);
+    let code = u16::from_be_bytes([header[2], header[3]]);
+
+    if version != USBIP_VERSION {
+        return Err(invalid("unsupported USB/IP version"));
+    }
+
+    match code {
+        OP_REQ_DEVLIST => {
+            let mut reply = op_header(OP_REP_DEVLIST, 0);
+            reply.extend(1u32.to_be_bytes());
+            info.write(&mut reply);
+            for interface in &info.interfaces {
+                reply.extend(interface);
+                reply.push(0);
+            }
+            stream.write_all(&reply)
+        }
+
+        OP_REQ_IMPORT => {
+            let mut bus_id = [0; 32];
+            stream.read_exact(&mut bus_id)?;
+            if bus_id.split(|&b| b == 0).next() != Some(BUS_ID.as_bytes()) {
+                return stream.write_all(&op_header(OP_REP_IMPORT, 1));
+            }
+
+            let mut reply = op_header(OP_REP_IMPORT, 0);
+            info.write(&mut reply);
+            stream.write_all(&reply)?;
+
+            Bridge { host, dev, pending: Vec::new(), received: BTreeMap::new() }.run(stream)
+        }
+
+        _ => Err(invalid("unsupported USB/IP operation")),
+    }
+}
+
+/// A bulk or interrupt URB waiting on the device.
+struct Urb {
+    seqnum: u32,
+
+    /// Endpoint address, including the direction bit
+    ep: u8,
+    flags: u32,
+
+    /// Length requested by an IN URB
+    len: usize,
+
+    /// Data to send for an OUT URB, or received so far for an IN URB
+    data: Vec<u8>,
+
+    /// The OUT data has been queued on the bus
+    queued: bool,
+}
+
+struct Bridge<'a, F: Future> {
+    host: &'a mut Host,
+    dev: Pin<&'a mut F>,
+
+    /// Pending URBs in submission order. Only the first on each endpoint is active.
+    pending: Vec<Urb>,
+
+    /// Packets taken from each IN endpoint but not yet returned in a URB
+    received: BTreeMap<u8, VecDeque<Vec<u8>>>,
+}
+
+impl<F: Future> Bridge<'_, F> {
+    fn run<S: Read + Write>(mut self, stream: &mut S) -> io::Result<()> {
+        loop {
+            let mut header = [0; 48];
+            match stream.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+
+            let command = be32(&header, 0);
+            let seqnum = be32(&header, 4);
+            let direction = be32(&header, 12);
+            let ep = be32(&header, 16) as u8 | if direction == DIR_IN { EP_IN } else { 0 };
+
+            match command {
+                CMD_SUBMIT => {
+                    let flags = be32(&header, 20);
+                    let len = be32(&header, 24) as usize;
+                    let number_of_packets = be32(&header, 32);
+                    let setup: [u8; 8] = header[40..48].try_into().unwrap();
+
+                    let mut data = Vec::new();
+                    if direction != DIR_IN {
+                        data.resize(len, 0);
+                        stream.read_exact(&mut data)?;
+                    }
+
+                    if number_of_packets != 0 && number_of_packets != u32::MAX {
+                        let mut iso_descriptors = vec![0; number_of_packets as usize * 16];
+                        stream.read_exact(&mut iso_descriptors)?;
+                        stream.write_all(&ret_submit(seqnum, -EINVAL, 0, &[]))?;
+                    } else if ep & !EP_IN == 0 {
+                        let reply = match self.control(setup, direction == DIR_IN, len, &data) {
+                            Ok(data) if direction == DIR_IN => ret_submit(seqnum, 0, data.len(), &data),
+                            Ok(_) => ret_submit(seqnum, 0, len, &[]),
+                            Err(status) => ret_submit(seqnum, status, 0, &[]),
+                        };
+                        stream.write_all(&reply)?;
+                    } else {
+                        self.pending.push(Urb { seqnum, ep, flags, len, data, queued: false });
+                    }
+                }
+
+                CMD_UNLINK => {
+                    // OUT data already queued on the bus can't be taken back,
+                    // and the device may still receive it.
+                    let unlink = be32(&header, 20);
+                    let status = match self.pending.iter().position(|urb| urb.seqnum == unlink) {
+                        Some(i) => {
+                            self.pending.remove(i);
+                            -ECONNRESET
+                        }
+                        None => 0,
+                    };
+
+                    let mut reply = ret_header(RET_UNLINK, seqnum);
+                    reply.extend(status.to_be_bytes());
+                    reply.extend([0; 24]);
+                    stream.write_all(&reply)?;
+                }
+
+                _ => return Err(invalid("unsupported USB/IP command")),
+            }
+
+            self.complete(stream)?;
+        }
+    }
+
+    /// Perform a control transfer, returning the IN data or a negative errno.
+    fn control(&mut self, setup: [u8; 8], dir_in: bool, len: usize, data: &[u8]) -> Result<Vec<u8>, i32> {
+        if setup[..4] == PORT_RESET_REQUEST {
+            self.received.clear();
+            return reset(self.host, self.dev.as_mut()).map(|()| Vec::new()).map_err(control_status);
+        }
+
+        if dir_in {
+            let mut data = self.host.control_in(self.dev.as_mut(), setup).map_err(control_status)?;
+            data.truncate(len);
+            Ok(data)
+        } else if u16::from_le_bytes([setup[6], setup[7]]) as usize != data.len() {
+            Err(-EINVAL)
+        } else {
+            self.host.control_out(self.dev.as_mut(), setup, data).map_err(control_status)?;
+            Ok(Vec::new())
+        }
+    }
+
+    /// Run the device, and send replies for the URBs it completes until it is idle.
+    fn complete<S: Write>(&mut self, stream: &mut S) -> io::Result<()> {
+        loop {
+            self.host.run(self.dev.as_mut());
+            let mut progress = false;
+
+            // Only take IN data when a URB is waiting for it, so the device
+            // can't send faster than the client reads.
+            let in_eps: BTreeSet<u8> = self.pending.iter().map(|urb| urb.ep).filter(|ep| ep & EP_IN != 0).collect();
+            for ep in in_eps {
+                let packets = self.host.take_in(ep);
+                progress |= !packets.is_empty();
+                self.received.entry(ep).or_default().extend(packets);
+            }
+
+            let mut active = BTreeSet::new();
+            let mut i = 0;
+            while i < self.pending.len() {
+                let ep = self.pending[i].ep;
+                if !active.insert(ep) {
+                    i += 1;
+                    continue;
+                }
+
+                let queued = self.pending[i].queued;
+                match self.step(i) {
+                    Some(reply) => {
+                        self.pending.remove(i);
+                        active.remove(&ep);
+                        stream.write_all(&reply)?;
+                        progress = true;
+                    }
+                    None => {
+                        progress |= self.pending[i].queued != queued;
+                        i += 1;
+                    }
+                }
+            }
+
+            if !progress {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Advance URB `i`, returning its reply if it completed.
+    fn step(&mut self, i: usize) -> Option<Vec<u8>> {
+        let Bridge { host, dev, pending, received } = self;
+        let urb = &mut pending[i];
+
+        let Some(max_packet_size) = host.max_packet_size(urb.ep) else {
+            return Some(ret_submit(urb.seqnum, -EPROTO, 0, &[]));
+        };
+        let max_packet_size = max_packet_size as usize;
+
+        if host.is_halted(urb.ep) {
+            return Some(ret_submit(urb.seqnum, -EPIPE, 0, &[]));
+        }
+
+        if urb.ep & EP_IN != 0 {
+            let packets = received.entry(urb.ep).or_default();
+            while let Some(packet) = packets.pop_front() {
+                let space = urb.len - urb.data.len();
+                if packet.len() > space {
+                    urb.data.extend_from_slice(&packet[..space]);
+                    return Some(ret_submit(urb.seqnum, -EOVERFLOW, urb.data.len(), &urb.data));
+                }
+
+                urb.data.extend_from_slice(&packet);
+                if packet.len() < max_packet_size || urb.data.len() == urb.len {
+                    return Some(ret_submit(urb.seqnum, 0, urb.data.len(), &urb.data));
+                }
+            }
+            None
+        } else {
+            if !urb.queued {
+                for packet in urb.data.chunks(max_packet_size) {
+                    host.out_packet(dev.as_mut(), urb.ep, packet);
+                }
+                let zlp = urb.flags & URB_ZERO_PACKET != 0 && urb.data.len() % max_packet_size == 0;
+                if urb.data.is_empty() || zlp {
+                    host.out_packet(dev.as_mut(), urb.ep, &[]);
+                }
+                urb.queued = true;
+            }
+
+            (host.out_pending(urb.ep) == 0).then(|| ret_submit(urb.seqnum, 0, urb.data.len(), &[]))
+        }
+    }
+}
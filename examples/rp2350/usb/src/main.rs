@@ -24,7 +24,7 @@ use zeptos::usb::descriptors::{MicrosoftOsCcgp, MicrosoftOsConfiguration, Micros
 #[zeptos::main]
 async fn main(rt: Runtime, mut hw: Hardware) {
     info!("init");
-    hw.usb.run_device(&mut ExampleDevice { rt, count: Cell::new(0), echo_payload: RefCell::new([0; 16]) }).await;
+    hw.usb.run_device(&mut ExampleDevice { rt, count: Cell::new(0), echo_payload: RefCell::new([0; 256]), echo_len: Cell::new(0) }).await;
 }
 
 const REQ_COUNT: u8 = 0x01;
@@ -34,7 +34,8 @@ const REQ_ECHO: u8 = 0x03;
 struct ExampleDevice {
     rt: Runtime,
     count: Cell<u32>,
-    echo_payload: RefCell<[u8; 16]>,
+    echo_payload: RefCell<[u8; 256]>,
+    echo_len: Cell<usize>,
 }
 
 impl zeptos::usb::Handler for ExampleDevice {
@@ -109,19 +110,17 @@ impl zeptos::usb::Handler for ExampleDevice {
             }
             Setup { ty: Vendor, recipient: Device, request: REQ_ECHO, value, index, data: Out(mut data) } => {
                 let mut echo = self.echo_payload.borrow_mut();
-                let d = data.receive().await;
-                let len = d.len().min(12);
                 echo[0..2].copy_from_slice(&value.to_le_bytes());
                 echo[2..4].copy_from_slice(&index.to_le_bytes());
-                echo[4..4 + len].copy_from_slice(&d[..len]);
-                if data.remaining() != 0 {
+                let Ok(d) = data.receive_into(&mut echo[4..]).await else {
                     return data.reject();
-                }
+                };
+                self.echo_len.set(4 + d.len());
                 data.accept().await
             }
             Setup { ty: Vendor, recipient: Device, request: REQ_ECHO, value: _, index: _, data: In(data) } => {
                 let echo = self.echo_payload.borrow();
-                data.respond(&echo[..]).await
+                data.respond(&echo[..self.echo_len.get()]).await
             }
             Setup { ty: Vendor, recipient: Device, request: REQ_SLOW, value, index: _, data } => {
                 self.rt.delay_us(value as u32 * 8).await;
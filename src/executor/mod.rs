@@ -17,28 +17,58 @@ pub use task_only::TaskOnly;
 
 use crate::Runtime;
 
-static RUN_QUEUE: RunQueue = RunQueue::new();
+cfg_select! {
+    feature = "multicore" => {
+        /// One run queue per core, each run by that core's PendSV.
+        static RUN_QUEUES: [RunQueue; 2] = [RunQueue::new(), RunQueue::new()];
+
+        fn run_queue() -> &'static RunQueue {
+            &RUN_QUEUES[crate::rp::multicore::core_id() as usize]
+        }
+
+        /// Queue a task to be polled on its own core, which may not be this one.
+        fn wake(node: &'static RunQueueNode) {
+            use crate::rp::multicore;
+            RUN_QUEUES[node.core() as usize].enqueue(node);
+            if node.core() == multicore::core_id() {
+                SCB::set_pendsv();
+            } else {
+                multicore::ring_doorbell(node.core());
+            }
+        }
+    }
+    _ => {
+        static RUN_QUEUE: RunQueue = RunQueue::new();
+
+        fn run_queue() -> &'static RunQueue {
+            &RUN_QUEUE
+        }
+
+        fn wake(node: &'static RunQueueNode) {
+            RUN_QUEUE.enqueue(node);
+            SCB::set_pendsv();
+        }
+    }
+}
 
 /// A handle to wake a task, obtained from `task_ref()` on a task's unique type.
 ///
 /// This is effectively a more efficient `Waker`.
-pub struct TaskRef {
-    rt: Runtime,
+pub struct TaskRef<const CORE: u8 = 0> {
+    _rt: Runtime<CORE>,
     node: &'static RunQueueNode,
 }
 
-impl TaskRef {
+impl<const CORE: u8> TaskRef<CORE> {
     #[doc(hidden)]
     /// Called from #[task] macro
-    pub fn new(rt: Runtime, node: &'static RunQueueNode) -> Self {
-        Self { rt, node }
+    pub fn new(rt: Runtime<CORE>, node: &'static RunQueueNode) -> Self {
+        Self { _rt: rt, node }
     }
 
     /// Wake the task.
     pub fn wake(&self) {
-        let _ = self.rt;
-        RUN_QUEUE.enqueue(self.node);
-        SCB::set_pendsv();
+        wake(self.node);
     }
 }
 
@@ -64,8 +94,7 @@ unsafe fn waker_clone(d: *const ()) -> RawWaker {
 
 unsafe fn waker_wake(p: *const ()) {
     let node = unsafe { &*(p as *const RunQueueNode) };
-    RUN_QUEUE.enqueue(node);
-    SCB::set_pendsv();
+    wake(node);
 }
 
 #[repr(C)]
@@ -153,6 +182,6 @@ impl<T: Task> TaskStorage<T> {
 #[exception]
 fn PendSV() {
     unsafe {
-        RUN_QUEUE.run_all()
+        run_queue().run_all()
     }
 }
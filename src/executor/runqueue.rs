@@ -9,6 +9,25 @@ pub struct RunQueue {
 pub struct RunQueueNode {
     next: AtomicPtr<RunQueueNode>,
     func: unsafe fn(),
+    /// Core whose run queue the task goes on.
+    #[cfg(feature = "multicore")]
+    core: u8,
+}
+
+/// Run `f` with exclusive access to the run queue heads.
+///
+/// With one core, tasks don't preempt each other, so there is nothing to do.
+/// With two, either core can wake a task on the other.
+#[inline(always)]
+fn locked<R>(f: impl FnOnce() -> R) -> R {
+    cfg_select! {
+        feature = "multicore" => {
+            crate::rp::multicore::with_run_queue_lock(f)
+        }
+        _ => {
+            f()
+        }
+    }
 }
 
 impl RunQueue {
@@ -18,16 +37,21 @@ impl RunQueue {
 
     pub fn enqueue(&self, node: &'static RunQueueNode) {
         // TODO: use compare_exchange_weak on architectures with atomics
-        if node.next.load(Ordering::Relaxed) == UNLINKED {
-            let next = self.head.load(Ordering::Relaxed);
-            node.next.store(next, Ordering::Relaxed);
-            self.head.store(node as *const RunQueueNode as *mut _, Ordering::Relaxed);
-        }
+        locked(|| {
+            if node.next.load(Ordering::Relaxed) == UNLINKED {
+                let next = self.head.load(Ordering::Relaxed);
+                node.next.store(next, Ordering::Relaxed);
+                self.head.store(node as *const RunQueueNode as *mut _, Ordering::Relaxed);
+            }
+        })
     }
 
     pub unsafe fn run_all(&self) {
-        let head = self.head.load(Ordering::Relaxed);
-        self.head.store(ptr::null_mut(), Ordering::Relaxed);
+        let head = locked(|| {
+            let head = self.head.load(Ordering::Relaxed);
+            self.head.store(ptr::null_mut(), Ordering::Relaxed);
+            head
+        });
 
         let mut next = NonNull::new(head);
         while let Some(node) = next {
@@ -45,8 +69,20 @@ impl RunQueue {
 }
 
 impl RunQueueNode {
-    pub const fn new(func: unsafe fn()) -> RunQueueNode {
-        Self { next: AtomicPtr::new(UNLINKED), func }
+    #[cfg_attr(not(feature = "multicore"), allow(unused_variables))]
+    pub const fn new(func: unsafe fn(), core: u8) -> RunQueueNode {
+        Self {
+            next: AtomicPtr::new(UNLINKED),
+            func,
+            #[cfg(feature = "multicore")]
+            core,
+        }
+    }
+
+    /// The core the task runs on.
+    #[cfg(feature = "multicore")]
+    pub fn core(&self) -> u8 {
+        self.core
     }
 
     pub fn func(&self) -> unsafe fn() {
@@ -4,10 +4,12 @@ use crate::Runtime;
 
 /// Wrapper for placing a value that is not Send + Sync in a `static` but only
 /// allowing it to be accessed from a task.
+///
+/// The value can only be accessed with the [`Runtime`] of core `CORE`.
 #[repr(transparent)]
-pub struct TaskOnly<T>(T);
+pub struct TaskOnly<T, const CORE: u8 = 0>(T);
 
-impl<T> TaskOnly<T> {
+impl<T, const CORE: u8> TaskOnly<T, CORE> {
     /// Wrap a value.
     pub const fn new(v: T) -> Self where T: Send{
         TaskOnly(v)
@@ -31,15 +33,15 @@ impl<T> TaskOnly<T> {
     }
 
     /// Get the wrapped value.
-    pub const fn get(&self, _runtime: Runtime) -> &T {
+    pub const fn get(&self, _runtime: Runtime<CORE>) -> &T {
         unsafe { self.get_unchecked() }
     }
 
     /// Get a pinned reference to the wrapped value.
-    pub const fn get_pinned(&'static self, _runtime: Runtime) -> Pin<&'static T> {
+    pub const fn get_pinned(&'static self, _runtime: Runtime<CORE>) -> Pin<&'static T> {
         unsafe { Pin::new_unchecked(self.get_unchecked()) }
     }
 }
 
-unsafe impl<T> Send for TaskOnly<T> {}
-unsafe impl<T> Sync for TaskOnly<T> {}
+unsafe impl<T, const CORE: u8> Send for TaskOnly<T, CORE> {}
+unsafe impl<T, const CORE: u8> Sync for TaskOnly<T, CORE> {}
@@ -17,12 +17,22 @@
 //!
 //! * `rp2040` or `rp2350`: Support for the Raspberry Pi RP2040 or RP2350 microcontroller.
 //!    * `rp2040-boot2-w25q080` (RP2040 only): Use the W25Q080 bootloader for XIP on Raspberry Pi Pico.
-//!    * `rom-func-cache` (RP2040 only): Enable ROM function cache.
-//!    * `i2c0`, `i2c1`, `spi0`, or `spi1`: Enable clocks and interrupts for the corresponding peripheral, and add it to the `Hardware` struct passed to the main task.
+//!    * `rom-func-cache`: Enable ROM function cache.
+//!    * `i2c0`, `i2c1`, `spi0`, `spi1`, `uart0`, or `uart1`: Enable clocks and interrupts for the corresponding peripheral, and add it to the `Hardware` struct passed to the main task.
+//!    * `pio0`, `pio1`, or `pio2`: Enable interrupts for the corresponding PIO block, and add it to the `Hardware` struct passed to the main task. `pio2` is RP2350 only.
+//!    * `dma`: Enables the DMA controller and its completion interrupt.
+//!    * `adc`: Enables the ADC interrupt, and adds the ADC to the `Hardware` struct passed to the main task.
+//!    * `pwm`: Enables the PWM block and its wrap interrupt.
 //!    * `gpio-interrupts`: Enables GPIO interrupts.
+//!    * `multicore`: Run a second executor on core 1, and add a handle to launch it to the `Hardware` struct passed to the main task.
+//!    * `flash`: Add a handle for erasing and programming flash, implementing `embedded_storage` traits, to the `Hardware` struct passed to the main task.
 //!
 //! * `usb`: Enables USB support.
+//!    * `smoltcp`: Implement `smoltcp::phy::Device` for the CDC-NCM network class.
+//!    * `embedded-io-async`: Byte stream adapters over bulk endpoints implementing `embedded_io_async` traits.
+//!    * `usb-sim` (host only): Replace the USB controller with a simulation for testing `usb::Handler` implementations.
 //! * `time`: Enables systick timer.
+//! * `watchdog`: Add the watchdog timer to the `Hardware` struct passed to the main task.
 #![no_std]
 #![allow(unused_features)]
 #![feature(impl_trait_in_assoc_type, sync_unsafe_cell, doc_cfg)]
@@ -39,12 +49,20 @@ pub use zeptos_macros::task;
 mod executor;
 pub use executor::{Interrupt, InterruptList, TaskOnly, TaskRef};
 
+pub mod power;
+
 #[cfg(any(feature="samd11", feature="samd21"))]
 pub mod samd;
 
 #[cfg(any(feature="rp2040", feature="rp2350"))]
 pub mod rp;
 
+#[cfg(all(feature="multicore", not(any(feature="rp2040", feature="rp2350"))))]
+compile_error!("the `multicore` feature requires `rp2040` or `rp2350`");
+
+#[cfg(all(feature="pio2", not(feature="rp2350")))]
+compile_error!("the `pio2` feature requires `rp2350`, as RP2040 has only two PIO blocks");
+
 cfg_select! {
     any(feature="samd11", feature="samd21") => {
         pub use samd::{serial_number::{serial_number, SERIAL_NUMBER_LEN}};
@@ -131,6 +149,36 @@ pub mod internal {
             #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "spi1"))]
             spi1: unsafe { <crate::rp::spi::Spi1 as crate::rp::spi::StaticInstance>::steal() },
 
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "uart0"))]
+            uart0: unsafe { <crate::rp::uart::Uart0 as crate::rp::uart::StaticInstance>::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "uart1"))]
+            uart1: unsafe { <crate::rp::uart::Uart1 as crate::rp::uart::StaticInstance>::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "pio0"))]
+            pio0: unsafe { <crate::rp::pio::Pio0 as crate::rp::pio::StaticInstance>::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "pio1"))]
+            pio1: unsafe { <crate::rp::pio::Pio1 as crate::rp::pio::StaticInstance>::steal() },
+
+            #[cfg(all(feature = "rp2350", feature = "pio2"))]
+            pio2: unsafe { <crate::rp::pio::Pio2 as crate::rp::pio::StaticInstance>::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "adc"))]
+            adc: unsafe { crate::rp::adc::AdcInstance::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "multicore"))]
+            core1: unsafe { crate::rp::multicore::Core1::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "flash"))]
+            flash: unsafe { crate::rp::flash::FlashInstance::steal() },
+
+            #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "watchdog"))]
+            watchdog: unsafe { crate::rp::watchdog::Watchdog::steal() },
+
+            #[cfg(all(any(feature = "samd11", feature = "samd21"), feature = "watchdog"))]
+            watchdog: unsafe { crate::samd::watchdog::Watchdog::steal() },
+
             #[cfg(all(any(feature = "samd11", feature = "samd21"), feature = "sercom0"))]
             sercom0: unsafe { <crate::samd::sercom::Sercom0 as crate::samd::sercom::StaticSercom>::steal() },
 
@@ -165,8 +213,11 @@ pub mod internal {
 }
 
 /// A token whose possession proves that you are on the task thread
+///
+/// With the `multicore` feature, each core runs its own tasks, and `CORE`
+/// is the core the token is for. Tokens can't be sent between cores.
 #[derive(Copy, Clone)]
-pub struct Runtime {
+pub struct Runtime<const CORE: u8 = 0> {
     _not_send: PhantomData<*mut ()>,
 }
 
@@ -183,6 +234,20 @@ impl Runtime {
     }
 }
 
+#[cfg(feature = "multicore")]
+impl Runtime<1> {
+    /// Create a new `Runtime` token for core 1 by assuming that we are running on its task thread.
+    ///
+    /// ## Safety
+    /// Can only be called from inside a task on core 1, and not
+    /// at a higher interrupt priority.
+    pub unsafe fn steal_core1() -> Runtime<1> {
+        Runtime {
+            _not_send: PhantomData,
+        }
+    }
+}
+
 /// Exclusive access to peripherals passed to the main task.
 ///
 /// The fields in this struct depend on the cargo features enabled.
@@ -202,6 +267,36 @@ pub struct Hardware {
     #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "spi1"))]
     pub spi1: rp::spi::Spi1,
 
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "uart0"))]
+    pub uart0: rp::uart::Uart0,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "uart1"))]
+    pub uart1: rp::uart::Uart1,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "pio0"))]
+    pub pio0: rp::pio::Pio0,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "pio1"))]
+    pub pio1: rp::pio::Pio1,
+
+    #[cfg(all(feature = "rp2350", feature = "pio2"))]
+    pub pio2: rp::pio::Pio2,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "adc"))]
+    pub adc: rp::adc::AdcInstance,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "multicore"))]
+    pub core1: rp::multicore::Core1,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "flash"))]
+    pub flash: rp::flash::FlashInstance,
+
+    #[cfg(all(any(feature = "rp2040", feature = "rp2350"), feature = "watchdog"))]
+    pub watchdog: rp::watchdog::Watchdog,
+
+    #[cfg(all(any(feature = "samd11", feature = "samd21"), feature = "watchdog"))]
+    pub watchdog: samd::watchdog::Watchdog,
+
     #[cfg(all(any(feature = "samd11", feature = "samd21"), feature = "sercom0"))]
     pub sercom0: samd::sercom::Sercom0,
 
@@ -0,0 +1,26 @@
+//! Control of the sleep mode entered when no tasks are ready to run.
+
+use cortex_m::peripheral::SCB;
+
+use crate::Runtime;
+
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+/// Select whether the processor enters deep sleep rather than regular sleep while idle.
+///
+/// The runtime sleeps whenever no tasks are ready to run. With deep sleep
+/// enabled, the chip-specific deep sleep mode is used instead, stopping
+/// clocks that are not configured to run in standby. A typical use is
+/// to enable it from [`Handler::handle_suspend`](crate::usb::Handler::handle_suspend)
+/// and disable it again on resume.
+pub fn set_deep_sleep(_rt: Runtime, deep: bool) {
+    unsafe {
+        (*SCB::PTR).scr.modify(|scr| {
+            if deep {
+                scr | SCB_SCR_SLEEPDEEP
+            } else {
+                scr & !SCB_SCR_SLEEPDEEP
+            }
+        });
+    }
+}
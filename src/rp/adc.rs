@@ -0,0 +1,323 @@
+//! Analog to digital converter.
+//!
+//! Single conversions are started with [`Adc::read`]. For sampling at a set
+//! rate, [`Adc::capture`] runs conversions continuously into the FIFO,
+//! optionally cycling through several channels, to be read with
+//! [`Capture::read`] or by DMA.
+//!
+//! ```ignore
+//! let mut adc = Adc::new(hw.adc);
+//! let ch = adc.pin(IoPin::bank0(26));
+//! let value = adc.read(ch).await?;
+//! let celsius = temperature_celsius(adc.read_temperature().await?);
+//! ```
+//!
+//! Only the ADC pins of the RP2040 and RP2350A, GPIO 26 to 29, are supported.
+//! The RP2350B's GPIO 40 to 47 and its temperature sensor on channel 8 are not.
+use crate::rp::gpio::IoPin;
+use crate::rp::RpReg as _;
+use crate::{Interrupt, Runtime, TaskOnly};
+use crate::rp::pac::{interrupt, resets, ADC, RESETS};
+
+/// Channel of the on-die temperature sensor.
+pub const TEMPERATURE_CHANNEL: u8 = 4;
+
+/// GPIOs connected to ADC channels, the first as channel 0.
+const ADC_PINS: core::ops::RangeInclusive<u8> = 26..=29;
+
+/// Depth of the result FIFO.
+const FIFO_DEPTH: u8 = 4;
+
+/// Cycles of `clk_adc` per conversion, the minimum sample period.
+const CONVERSION_CYCLES: u32 = 96;
+
+static ADC_INT: TaskOnly<Interrupt> = TaskOnly::new(Interrupt::new());
+
+pub struct AdcInstance(Runtime);
+
+impl AdcInstance {
+    /// ## Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    pub unsafe fn steal() -> Self {
+        unsafe { AdcInstance(Runtime::steal()) }
+    }
+
+    fn interrupt(&self) -> &'static Interrupt {
+        ADC_INT.get(self.0)
+    }
+}
+
+fn reset_bit() -> resets::regs::Peripherals {
+    let mut p = resets::regs::Peripherals::default();
+    p.set_adc(true);
+    p
+}
+
+#[interrupt]
+fn ADC_IRQ_FIFO() {
+    ADC.inte().write(|_| { });
+    unsafe { ADC_INT.get_unchecked().notify() };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// The conversion did not complete correctly.
+    Conversion,
+    /// The FIFO was full and samples were lost.
+    Overflow,
+}
+
+/// Error from [`CaptureConfig::set_sample_rate`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SampleRateError {
+    /// The rate is above the ADC's 500ksps.
+    TooHigh,
+    /// The rate is zero or needs a clock divider above 65536.
+    TooLow,
+}
+
+/// Convert a reading of [`TEMPERATURE_CHANNEL`] to degrees Celsius.
+///
+/// This uses the typical sensor characteristics from the datasheet and a 3.3V reference.
+pub fn temperature_celsius(raw: u16) -> f32 {
+    let volts = raw as f32 * 3.3 / 4096.0;
+    27.0 - (volts - 0.706) / 0.001721
+}
+
+#[derive(Clone, Copy)]
+pub struct CaptureConfig {
+    /// Bit mask of channels to convert in turn, starting from the lowest.
+    pub channels: u16,
+    pub clkdiv_int: u16,
+    pub clkdiv_frac: u8,
+    /// Request DMA transfers of each sample, instead of using the interrupt.
+    pub dma: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            channels: 1 << 0,
+            clkdiv_int: 0,
+            clkdiv_frac: 0,
+            dma: false,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Set the divider for the sample rate in Hz, shared between all channels.
+    ///
+    /// Returns an error if the rate is faster than the ADC's 500ksps, or too slow
+    /// for the divider.
+    pub fn set_sample_rate(&mut self, hz: u32) -> Result<(), SampleRateError> {
+        if hz == 0 {
+            return Err(SampleRateError::TooLow);
+        }
+        // A sample is taken every 1 + INT + FRAC / 256 cycles
+        let div = ((crate::rp::CLK_ADC_HZ as u64 * 256) + hz as u64 / 2) / hz as u64;
+        if div < CONVERSION_CYCLES as u64 * 256 {
+            return Err(SampleRateError::TooHigh);
+        }
+        if div > 0x1_0000 * 256 {
+            return Err(SampleRateError::TooLow);
+        }
+        let div = div - 256;
+        self.clkdiv_int = (div >> 8) as u16;
+        self.clkdiv_frac = div as u8;
+        Ok(())
+    }
+}
+
+pub struct Adc {
+    instance: AdcInstance,
+}
+
+impl Adc {
+    pub fn new(instance: AdcInstance) -> Self {
+        RESETS.reset().write_value_set(reset_bit());
+        RESETS.reset().write_value_clear(reset_bit());
+        while ((!RESETS.reset_done().read().0) & reset_bit().0) != 0 {}
+
+        ADC.cs().write(|w| w.set_en(true));
+        while !ADC.cs().read().ready() {}
+
+        Self { instance }
+    }
+
+    /// Configure a GPIO as an analog input, and return its channel number.
+    pub fn pin(&self, pin: IoPin) -> u8 {
+        assert!(ADC_PINS.contains(&pin.pin), "GPIO {} is not an ADC input", pin.pin);
+        pin.set_analog();
+        pin.pin - ADC_PINS.start()
+    }
+
+    /// Power the temperature sensor, which is off by default.
+    pub fn set_temperature_sensor(&mut self, enabled: bool) {
+        ADC.cs().modify(|w| w.set_ts_en(enabled));
+    }
+
+    /// Convert one sample of `channel`.
+    pub fn read(&mut self, channel: u8) -> impl Future<Output = Result<u16, Error>> {
+        async fn read(interrupt: &Interrupt, channel: u8) -> Result<u16, Error> {
+            assert!(channel <= TEMPERATURE_CHANNEL);
+
+            // Results go through the FIFO to raise the interrupt
+            drain_fifo();
+            ADC.fcs().write(|w| {
+                w.set_en(true);
+                w.set_err(true);
+                w.set_thresh(1);
+            });
+            ADC.cs().modify(|w| {
+                w.set_ainsel(channel);
+                w.set_start_once(true);
+            });
+
+            interrupt.until(|| {
+                if ADC.fcs().read().level() == 0 {
+                    ADC.inte().write(|w| w.set_fifo(true));
+                    false
+                } else {
+                    true
+                }
+            }).await;
+
+            let sample = ADC.fifo().read();
+            ADC.fcs().write(|_| { });
+
+            if sample.err() {
+                Err(Error::Conversion)
+            } else {
+                Ok(sample.val())
+            }
+        }
+        read(self.instance.interrupt(), channel)
+    }
+
+    /// Convert one sample of the temperature sensor, powering it if necessary.
+    ///
+    /// Use [`temperature_celsius`] to convert the result.
+    pub async fn read_temperature(&mut self) -> Result<u16, Error> {
+        if !ADC.cs().read().ts_en() {
+            self.set_temperature_sensor(true);
+            // The sensor settles within a few microseconds of power-on;
+            // discard the first conversion.
+            self.read(TEMPERATURE_CHANNEL).await?;
+        }
+        self.read(TEMPERATURE_CHANNEL).await
+    }
+
+    /// Start converting continuously into the FIFO, until the returned `Capture` is dropped.
+    pub fn capture(&mut self, config: &CaptureConfig) -> Capture<'_> {
+        assert!(config.channels != 0 && config.channels < 1 << (TEMPERATURE_CHANNEL + 1));
+
+        drain_fifo();
+        ADC.div().write(|w| {
+            w.set_int(config.clkdiv_int);
+            w.set_frac(config.clkdiv_frac);
+        });
+        ADC.fcs().write(|w| {
+            w.set_en(true);
+            w.set_err(true);
+            w.set_dreq_en(config.dma);
+            w.set_thresh(if config.dma { 1 } else { FIFO_DEPTH / 2 });
+            w.set_over(true);
+            w.set_under(true);
+        });
+        ADC.cs().modify(|w| {
+            w.set_ainsel(config.channels.trailing_zeros() as u8);
+            cfg_select! {
+                // Five inputs, checked above
+                feature = "rp2040" => { w.set_rrobin(config.channels as u8 & 0x1f); }
+                feature = "rp2350" => { w.set_rrobin(config.channels); }
+            }
+            w.set_start_many(true);
+        });
+
+        Capture { adc: self }
+    }
+}
+
+impl Drop for Adc {
+    fn drop(&mut self) {
+        defmt::debug!("Dropping ADC");
+        RESETS.reset().write_value_set(reset_bit());
+    }
+}
+
+fn drain_fifo() {
+    while !ADC.fcs().read().empty() {
+        ADC.fifo().read();
+    }
+}
+
+/// Free-running conversions into the FIFO, started by [`Adc::capture`].
+///
+/// Samples of round-robin channels are interleaved in channel order.
+pub struct Capture<'a> {
+    adc: &'a mut Adc,
+}
+
+impl Capture<'_> {
+    /// Read at least one sample, waiting until one is available.
+    ///
+    /// Samples with a conversion error are returned with bit 15 set.
+    pub fn read<'a>(&'a mut self, buf: &'a mut [u16]) -> impl Future<Output = Result<usize, Error>> + 'a {
+        async fn read(interrupt: &Interrupt, buf: &mut [u16]) -> Result<usize, Error> {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            interrupt.until(|| {
+                if ADC.fcs().read().level() == 0 {
+                    ADC.inte().write(|w| w.set_fifo(true));
+                    false
+                } else {
+                    true
+                }
+            }).await;
+
+            if ADC.fcs().read().over() {
+                ADC.fcs().modify(|w| w.set_over(true));
+                drain_fifo();
+                return Err(Error::Overflow);
+            }
+
+            let mut n = 0;
+            while n < buf.len() && !ADC.fcs().read().empty() {
+                buf[n] = ADC.fifo().read().0 as u16;
+                n += 1;
+            }
+            Ok(n)
+        }
+        read(self.adc.instance.interrupt(), buf)
+    }
+
+    /// Address of the FIFO, for DMA with [`Dreq::ADC`](crate::rp::dma::Dreq::ADC)
+    /// when capturing with [`CaptureConfig::dma`].
+    pub fn fifo_ptr(&self) -> *const u16 {
+        ADC.fifo().as_ptr() as *const u16
+    }
+
+    /// Fill `buf` with samples by DMA.
+    #[cfg(feature = "dma")]
+    pub async fn read_dma(&mut self, channel: &mut crate::rp::dma::Channel, buf: &mut [u16]) {
+        assert!(ADC.fcs().read().dreq_en());
+        unsafe { channel.from_peripheral(self.fifo_ptr(), buf, crate::rp::dma::Dreq::ADC) }.await;
+    }
+}
+
+impl Drop for Capture<'_> {
+    fn drop(&mut self) {
+        ADC.cs().modify(|w| {
+            w.set_start_many(false);
+            w.set_rrobin(0);
+        });
+        while !ADC.cs().read().ready() {}
+        ADC.fcs().write(|_| { });
+        drain_fifo();
+    }
+}
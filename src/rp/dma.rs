@@ -0,0 +1,342 @@
+//! DMA channels.
+//!
+//! Channels are allocated with [`Channel::claim`] and freed when dropped. A
+//! transfer is awaited through the channel's completion interrupt, and is
+//! aborted if the future is dropped before it completes, like USB transfers.
+//!
+//! ```ignore
+//! let mut ch = Channel::claim(rt).unwrap();
+//! unsafe { ch.to_peripheral(&data, pac::SPI0.dr().as_ptr() as *mut u8, Dreq::SPI0_TX) }.await;
+//! ```
+//!
+//! The SPI and UART drivers take channels for DMA transfers directly, with
+//! `spi::Controller::transfer_dma` and `uart::Uart::write_dma` / `read_dma`.
+use core::cell::Cell;
+use core::mem;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use rp_pac::dma::vals::TreqSel;
+use rp_pac::{interrupt, DMA};
+use scopeguard::ScopeGuard;
+
+use crate::rp::RpReg as _;
+use crate::{Interrupt, Runtime, TaskOnly};
+
+cfg_select! {
+    feature = "rp2040" => {
+        pub const NUM_CHANNELS: usize = 12;
+    }
+    feature = "rp2350" => {
+        pub const NUM_CHANNELS: usize = 16;
+    }
+}
+
+static CLAIMED: TaskOnly<Cell<u16>> = TaskOnly::new(Cell::new(0));
+static NOTIFY_CHANNEL: TaskOnly<[Interrupt; NUM_CHANNELS]> = TaskOnly::new([const { Interrupt::new() }; NUM_CHANNELS]);
+
+/// Data request signal that paces a transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Dreq(pub u8);
+
+impl Dreq {
+    /// Transfer as fast as possible, as for memory to memory copies.
+    pub const PERMANENT: Dreq = Dreq(0x3f);
+
+    /// Paced by one of the four pacing timers.
+    pub const fn timer(n: u8) -> Dreq {
+        assert!(n < 4);
+        Dreq(0x3b + n)
+    }
+
+    /// TX FIFO of a PIO state machine.
+    pub const fn pio_tx(block: u8, sm: u8) -> Dreq {
+        Dreq(block * 8 + sm)
+    }
+
+    /// RX FIFO of a PIO state machine.
+    pub const fn pio_rx(block: u8, sm: u8) -> Dreq {
+        Dreq(block * 8 + 4 + sm)
+    }
+}
+
+cfg_select! {
+    feature = "rp2040" => {
+        impl Dreq {
+            pub const SPI0_TX: Dreq = Dreq(16);
+            pub const SPI0_RX: Dreq = Dreq(17);
+            pub const SPI1_TX: Dreq = Dreq(18);
+            pub const SPI1_RX: Dreq = Dreq(19);
+            pub const UART0_TX: Dreq = Dreq(20);
+            pub const UART0_RX: Dreq = Dreq(21);
+            pub const UART1_TX: Dreq = Dreq(22);
+            pub const UART1_RX: Dreq = Dreq(23);
+            pub const I2C0_TX: Dreq = Dreq(32);
+            pub const I2C0_RX: Dreq = Dreq(33);
+            pub const I2C1_TX: Dreq = Dreq(34);
+            pub const I2C1_RX: Dreq = Dreq(35);
+            pub const ADC: Dreq = Dreq(36);
+
+            /// Wrap of a PWM slice's counter.
+            pub const fn pwm_wrap(slice: u8) -> Dreq {
+                assert!(slice < 8);
+                Dreq(24 + slice)
+            }
+        }
+    }
+    feature = "rp2350" => {
+        impl Dreq {
+            pub const SPI0_TX: Dreq = Dreq(24);
+            pub const SPI0_RX: Dreq = Dreq(25);
+            pub const SPI1_TX: Dreq = Dreq(26);
+            pub const SPI1_RX: Dreq = Dreq(27);
+            pub const UART0_TX: Dreq = Dreq(28);
+            pub const UART0_RX: Dreq = Dreq(29);
+            pub const UART1_TX: Dreq = Dreq(30);
+            pub const UART1_RX: Dreq = Dreq(31);
+            pub const I2C0_TX: Dreq = Dreq(44);
+            pub const I2C0_RX: Dreq = Dreq(45);
+            pub const I2C1_TX: Dreq = Dreq(46);
+            pub const I2C1_RX: Dreq = Dreq(47);
+            pub const ADC: Dreq = Dreq(48);
+
+            /// Wrap of a PWM slice's counter.
+            pub const fn pwm_wrap(slice: u8) -> Dreq {
+                assert!(slice < 12);
+                Dreq(32 + slice)
+            }
+        }
+    }
+}
+
+/// Size of each transfer of a channel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DataSize {
+    Byte,
+    Halfword,
+    Word,
+}
+
+impl DataSize {
+    fn to_pac(self) -> rp_pac::dma::vals::DataSize {
+        match self {
+            DataSize::Byte => rp_pac::dma::vals::DataSize::SIZE_BYTE,
+            DataSize::Halfword => rp_pac::dma::vals::DataSize::SIZE_HALFWORD,
+            DataSize::Word => rp_pac::dma::vals::DataSize::SIZE_WORD,
+        }
+    }
+}
+
+/// Type transferred by a channel in units of its [`DataSize`].
+pub trait Word: Copy {
+    const SIZE: DataSize;
+}
+
+impl Word for u8 {
+    const SIZE: DataSize = DataSize::Byte;
+}
+
+impl Word for u16 {
+    const SIZE: DataSize = DataSize::Halfword;
+}
+
+impl Word for u32 {
+    const SIZE: DataSize = DataSize::Word;
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub data_size: DataSize,
+    pub incr_read: bool,
+    pub incr_write: bool,
+    pub dreq: Dreq,
+    /// Channel to trigger when this one completes.
+    pub chain_to: Option<u8>,
+    /// Reverse the byte order of each transfer.
+    pub bswap: bool,
+    pub high_priority: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_size: DataSize::Word,
+            incr_read: true,
+            incr_write: true,
+            dreq: Dreq::PERMANENT,
+            chain_to: None,
+            bswap: false,
+            high_priority: false,
+        }
+    }
+}
+
+impl Config {
+    /// Write `W`s from memory to a fixed peripheral register, paced by `dreq`.
+    pub fn to_peripheral<W: Word>(dreq: Dreq) -> Self {
+        Config { data_size: W::SIZE, incr_write: false, dreq, ..Config::default() }
+    }
+
+    /// Read `W`s from a fixed peripheral register to memory, paced by `dreq`.
+    pub fn from_peripheral<W: Word>(dreq: Dreq) -> Self {
+        Config { data_size: W::SIZE, incr_read: false, dreq, ..Config::default() }
+    }
+}
+
+/// An allocated DMA channel.
+pub struct Channel {
+    index: u8,
+    rt: Runtime,
+}
+
+impl Channel {
+    /// Allocate a free channel.
+    pub fn claim(rt: Runtime) -> Option<Channel> {
+        let claimed = CLAIMED.get(rt);
+        let index = (0..NUM_CHANNELS as u8).find(|i| claimed.get() & (1 << i) == 0)?;
+        claimed.set(claimed.get() | 1 << index);
+        Some(Channel { index, rt })
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn regs(&self) -> rp_pac::dma::Channel {
+        DMA.ch(self.index as usize)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.regs().ctrl_trig().read().busy()
+    }
+
+    /// Start a transfer of `count` items of `config.data_size`.
+    ///
+    /// ## Safety
+    ///
+    /// `read` and `write` must be valid for the whole transfer, and remain
+    /// so until it completes or is aborted.
+    pub unsafe fn start(&mut self, config: &Config, read: *const u8, write: *mut u8, count: u32) {
+        assert!(!self.is_busy());
+        let ch = self.regs();
+
+        // Memory written before the transfer must be visible to the DMA
+        compiler_fence(Ordering::Release);
+
+        ch.read_addr().write_value(read as u32);
+        ch.write_addr().write_value(write as u32);
+        cfg_select! {
+            feature = "rp2040" => { ch.trans_count().write_value(count); }
+            feature = "rp2350" => { ch.trans_count().write(|w| w.set_count(count)); }
+        }
+        ch.ctrl_trig().write(|w| {
+            w.set_data_size(config.data_size.to_pac());
+            w.set_incr_read(config.incr_read);
+            w.set_incr_write(config.incr_write);
+            w.set_treq_sel(TreqSel::from_bits(config.dreq.0));
+            w.set_chain_to(config.chain_to.unwrap_or(self.index));
+            w.set_bswap(config.bswap);
+            w.set_high_priority(config.high_priority);
+            w.set_en(true);
+        });
+    }
+
+    /// Abort the transfer in progress, if any.
+    pub fn abort(&mut self) {
+        abort(self.index);
+    }
+
+    fn cancel_on_drop(&self) -> ScopeGuard<(), impl FnOnce(()) + '_> {
+        scopeguard::guard((), move |()| abort(self.index))
+    }
+
+    /// Wait for the transfer in progress to complete.
+    ///
+    /// If this future is dropped before completion, the transfer is aborted.
+    pub async fn wait(&mut self) {
+        let index = self.index;
+        let ch = self.regs();
+        let guard = self.cancel_on_drop();
+
+        NOTIFY_CHANNEL.get(self.rt)[index as usize].until(|| {
+            if ch.ctrl_trig().read().busy() {
+                DMA.inte(0).write_set(|w| *w = 1 << index);
+                false
+            } else {
+                true
+            }
+        }).await;
+
+        mem::forget(guard);
+
+        // The DMA is done writing memory
+        compiler_fence(Ordering::Acquire);
+
+        let ctrl = ch.ctrl_trig().read();
+        if ctrl.ahb_error() {
+            defmt::error!("dma channel {} bus error: read {} write {}", index, ctrl.read_error(), ctrl.write_error());
+        }
+    }
+
+    /// Copy `src` to `dst`, which must have the same length.
+    pub async fn copy<W: Word>(&mut self, src: &[W], dst: &mut [W]) {
+        assert!(src.len() == dst.len());
+        let config = Config { data_size: W::SIZE, ..Config::default() };
+        unsafe { self.start(&config, src.as_ptr() as *const u8, dst.as_mut_ptr() as *mut u8, src.len() as u32) };
+        self.wait().await;
+    }
+
+    /// Write `src` to a peripheral register, paced by `dreq`.
+    ///
+    /// ## Safety
+    ///
+    /// `dst` must be a peripheral register that accepts writes of `W`.
+    pub async unsafe fn to_peripheral<W: Word>(&mut self, src: &[W], dst: *mut W, dreq: Dreq) {
+        unsafe { self.start(&Config::to_peripheral::<W>(dreq), src.as_ptr() as *const u8, dst as *mut u8, src.len() as u32) };
+        self.wait().await;
+    }
+
+    /// Fill `dst` from a peripheral register, paced by `dreq`.
+    ///
+    /// ## Safety
+    ///
+    /// `src` must be a peripheral register that can be read as `W`.
+    pub async unsafe fn from_peripheral<W: Word>(&mut self, src: *const W, dst: &mut [W], dreq: Dreq) {
+        unsafe { self.start(&Config::from_peripheral::<W>(dreq), src as *const u8, dst.as_mut_ptr() as *mut u8, dst.len() as u32) };
+        self.wait().await;
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.abort();
+        let claimed = CLAIMED.get(self.rt);
+        claimed.set(claimed.get() & !(1 << self.index));
+    }
+}
+
+fn abort(index: u8) {
+    let mask = 1u32 << index;
+
+    // Disable the interrupt first, as aborting may raise it (RP2040-E13)
+    DMA.inte(0).write_clear(|w| *w = mask);
+    DMA.chan_abort().write(|w| w.0 = mask);
+    while DMA.chan_abort().read().0 & mask != 0 {}
+    DMA.ints(0).write_value(mask);
+}
+
+#[interrupt]
+fn DMA_IRQ_0() {
+    let ints = DMA.ints(0).read();
+    DMA.ints(0).write_value(ints);
+
+    // Disable the interrupts that fired, re-enabled by the tasks still waiting
+    DMA.inte(0).write_clear(|w| *w = ints);
+
+    let notify = unsafe { NOTIFY_CHANNEL.get_unchecked() };
+    for (i, n) in notify.iter().enumerate() {
+        if ints & (1 << i) != 0 {
+            unsafe { n.notify() };
+        }
+    }
+}
@@ -1,10 +1,48 @@
 // Based on rp2040-flash by Jan Niehusmann under MIT OR Apache-2.0
 // https://github.com/jannic/rp2040-flash/blob/568bd55ca6fa2a9a94b9b67bb7c1e36799699515/src/lib.rs
 
-use core::{marker::PhantomData, slice};
+//! Flash erase, program and ID commands using the bootrom.
+//!
+//! These work the same way on RP2040 and RP2350. On RP2040, "boot2" is the
+//! second stage boot loader at the start of flash; on RP2350, it is the XIP
+//! setup function the bootrom leaves at the start of boot RAM. Either way,
+//! calling it after a flash operation restores the fast XIP mode it set up at
+//! boot, where otherwise flash is left in the slow 03h read mode.
+
+use core::marker::PhantomData;
 
 use super::rom_data;
 
+#[cfg(feature = "flash")]
+mod nor;
+#[cfg(feature = "flash")]
+pub use nor::*;
+
+/// `asm!` calling bootrom functions, which may clobber the caller-saved registers.
+///
+/// This is `clobber_abi("C")`, except on RP2350, where that would also name
+/// registers D16-D31, which its FPU doesn't have.
+macro_rules! rom_asm {
+    ($($args:tt)*) => {
+        cfg_select! {
+            feature = "rp2040" => {
+                core::arch::asm!($($args)* clobber_abi("C"))
+            }
+            feature = "rp2350" => {
+                core::arch::asm!(
+                    $($args)*
+                    out("r12") _,
+                    out("lr") _,
+                    out("s0") _, out("s1") _, out("s2") _, out("s3") _,
+                    out("s4") _, out("s5") _, out("s6") _, out("s7") _,
+                    out("s8") _, out("s9") _, out("s10") _, out("s11") _,
+                    out("s12") _, out("s13") _, out("s14") _, out("s15") _,
+                )
+            }
+        }
+    };
+}
+
 #[derive(defmt::Format)]
 #[repr(C)]
 struct FlashFunctionPointers<'a> {
@@ -20,6 +58,19 @@ struct FlashFunctionPointers<'a> {
     phantom: PhantomData<&'a ()>,
 }
 
+/// Copy boot2 somewhere it can be called while flash is unavailable.
+unsafe fn copy_boot2(boot2: &mut [u32; 64]) {
+    cfg_select! {
+        feature = "rp2040" => {
+            unsafe { rom_data::memcpy44(boot2 as *mut _, 0x10000000 as *const _, 256) };
+        }
+        feature = "rp2350" => {
+            // BOOTRAM_BASE
+            unsafe { core::ptr::copy_nonoverlapping(0x400e0000 as *const u32, boot2.as_mut_ptr(), boot2.len()) };
+        }
+    }
+}
+
 #[allow(unused)]
 fn flash_function_pointers(erase: bool, write: bool) -> FlashFunctionPointers<'static> {
     FlashFunctionPointers {
@@ -97,7 +148,7 @@ pub unsafe fn flash_range_erase(addr: u32, len: u32, use_boot2: bool) {
         assert!(addr < 0x1000000);
         let mut boot2 = [0u32; 256 / 4];
         let ptrs = if use_boot2 {
-            rom_data::memcpy44(&mut boot2 as *mut _, 0x10000000 as *const _, 256);
+            copy_boot2(&mut boot2);
             flash_function_pointers_with_boot2(true, false, &boot2)
         } else {
             flash_function_pointers(true, false)
@@ -130,7 +181,7 @@ pub unsafe fn flash_range_erase_and_program(addr: u32, data: &[u8], use_boot2: b
         assert!(addr < 0x1000000);
         let mut boot2 = [0u32; 256 / 4];
         let ptrs = if use_boot2 {
-            rom_data::memcpy44(&mut boot2 as *mut _, 0x10000000 as *const _, 256);
+            copy_boot2(&mut boot2);
             flash_function_pointers_with_boot2(true, true, &boot2)
         } else {
             flash_function_pointers(true, true)
@@ -168,7 +219,7 @@ pub unsafe fn flash_range_program(addr: u32, data: &[u8], use_boot2: bool) {
         assert!(addr < 0x1000000);
         let mut boot2 = [0u32; 256 / 4];
         let ptrs = if use_boot2 {
-            rom_data::memcpy44(&mut boot2 as *mut _, 0x10000000 as *const _, 256);
+            copy_boot2(&mut boot2);
             flash_function_pointers_with_boot2(false, true, &boot2)
         } else {
             flash_function_pointers(false, true)
@@ -211,7 +262,7 @@ unsafe fn write_flash_inner(
             rom_data::flash_flush_cache();
             rom_data::flash_enter_cmd_xip();
         */
-        core::arch::asm!(
+        rom_asm!(
             "mov r8, r0",
             "mov r9, r2",
             "mov r10, r1",
@@ -247,9 +298,9 @@ unsafe fn write_flash_inner(
             "ldr r4, [{ptrs}, #20]",
             "blx r4", // flash_enter_cmd_xip();
             ptrs = in(reg) ptrs,
-            in("r0") addr,
-            in("r2") data.map(|d| d.as_ptr()).unwrap_or(core::ptr::null()),
-            in("r1") len,
+            inout("r0") addr => _,
+            inout("r2") data.map(|d| d.as_ptr()).unwrap_or(core::ptr::null()) => _,
+            inout("r1") len => _,
             out("r3") _,
             out("r4") _,
             // Registers r8-r10 are used to store values
@@ -260,7 +311,6 @@ unsafe fn write_flash_inner(
             out("r8") _,
             out("r9") _,
             out("r10") _,
-            clobber_abi("C"),
         );
     }
 }
@@ -301,9 +351,8 @@ struct FlashCommand {
 pub unsafe fn flash_unique_id(out: &mut [u8], use_boot2: bool) {
     unsafe {
         let mut boot2 = [0u32; 256 / 4];
-        boot2.copy_from_slice(slice::from_raw_parts(0x10000000 as *const _, 256 / 4));
         let ptrs = if use_boot2 {
-            rom_data::memcpy44(&mut boot2 as *mut _, 0x10000000 as *const _, 256);
+            copy_boot2(&mut boot2);
             flash_function_pointers_with_boot2(false, false, &boot2)
         } else {
             flash_function_pointers(false, false)
@@ -331,7 +380,7 @@ pub unsafe fn flash_jedec_id(use_boot2: bool) -> u32 {
     unsafe {
         let mut boot2 = [0u32; 256 / 4];
         let ptrs = if use_boot2 {
-            rom_data::memcpy44(&mut boot2 as *mut _, 0x10000000 as *const _, 256);
+            copy_boot2(&mut boot2);
             flash_function_pointers_with_boot2(false, false, &boot2)
         } else {
             flash_function_pointers(false, false)
@@ -375,6 +424,7 @@ unsafe fn read_flash(
 ///
 /// * `cmd` - `FlashCommand` structure
 /// * `ptrs` - Flash function pointers as per `write_flash_inner`
+#[cfg(feature = "rp2040")]
 #[inline(never)]
 #[unsafe(link_section = ".data.ram_func")]
 unsafe fn read_flash_inner(cmd: FlashCommand, ptrs: *const FlashFunctionPointers) {
@@ -491,3 +541,111 @@ unsafe fn read_flash_inner(cmd: FlashCommand, ptrs: *const FlashFunctionPointers
         );
     }
 }
+
+/// Issue a generic SPI flash read command
+///
+/// RP2350 has no SSI, so this uses the QMI's direct mode, sending one byte and
+/// receiving one byte at a time.
+///
+/// # Arguments
+///
+/// * `cmd` - `FlashCommand` structure
+/// * `ptrs` - Flash function pointers as per `write_flash_inner`
+#[cfg(feature = "rp2350")]
+#[inline(never)]
+#[unsafe(link_section = ".data.ram_func")]
+unsafe fn read_flash_inner(cmd: FlashCommand, ptrs: *const FlashFunctionPointers) {
+    unsafe {
+        rom_asm!(
+            // r6, r7 are LLVM-reserved and can't be marked as a clobber, so save/restore them manually
+            // (r6 is not actually used, but we need to push two words to maintain stack alignment)
+            "push {{r6, r7}}",
+
+            "mov r7, r0", // cmd
+            "mov r5, r1", // ptrs
+
+            "ldr r4, [r5, #0]",
+            "blx r4", // connect_internal_flash()
+
+            "ldr r4, [r5, #4]",
+            "blx r4", // flash_exit_xip()
+
+            "movw r4, #0x0000",
+            "movt r4, #0x400d", // 0x400d0000, QMI, RP2350 datasheet 12.14.6
+
+            // Enable direct mode, then assert chip select 0
+            "ldr r0, [r4, #0]", // DIRECT_CSR
+            "orr r0, r0, #0x1", // EN
+            "str r0, [r4, #0]",
+            "orr r0, r0, #0x4", // ASSERT_CS0N
+            "str r0, [r4, #0]",
+
+            // Write cmd/addr phase, discarding what is read back
+            "ldr r1, [r7, #0]", // cmd_addr
+            "ldr r2, [r7, #4]", // cmd_addr_len
+            "10:",
+            "ldrb r0, [r1], #1",
+            "str r0, [r4, #4]", // DIRECT_TX
+            "11:",
+            "ldr r3, [r4, #0]", // DIRECT_CSR
+            "tst r3, #0x10000", // RXEMPTY
+            "bne 11b",
+            "ldr r3, [r4, #8]", // DIRECT_RX
+            "subs r2, #1",
+            "bne 10b",
+
+            // Skip any dummy cycles
+            "ldr r2, [r7, #8]", // dummy_len
+            "cmp r2, #0",
+            "beq 9f",
+            "movs r0, #0",
+            "4:",
+            "str r0, [r4, #4]", // DIRECT_TX
+            "5:",
+            "ldr r3, [r4, #0]", // DIRECT_CSR
+            "tst r3, #0x10000", // RXEMPTY
+            "bne 5b",
+            "ldr r3, [r4, #8]", // DIRECT_RX
+            "subs r2, #1",
+            "bne 4b",
+
+            // Read data
+            "9:",
+            "ldr r1, [r7, #12]", // data
+            "ldr r2, [r7, #16]", // data_len
+            "movs r0, #0",
+            "2:",
+            "str r0, [r4, #4]", // DIRECT_TX
+            "3:",
+            "ldr r3, [r4, #0]", // DIRECT_CSR
+            "tst r3, #0x10000", // RXEMPTY
+            "bne 3b",
+            "ldr r3, [r4, #8]", // DIRECT_RX
+            "strb r3, [r1], #1",
+            "subs r2, #1",
+            "bne 2b",
+
+            // Deassert chip select, then disable direct mode
+            "ldr r0, [r4, #0]", // DIRECT_CSR
+            "bic r0, r0, #0x4", // ASSERT_CS0N
+            "str r0, [r4, #0]",
+            "bic r0, r0, #0x1", // EN
+            "str r0, [r4, #0]",
+
+            "ldr r4, [r5, #16]",
+            "blx r4", // flash_flush_cache();
+
+            "ldr r4, [r5, #20]",
+            "blx r4", // flash_enter_cmd_xip();
+
+            "pop {{r6, r7}}",
+
+            inout("r0") &cmd as *const FlashCommand => _,
+            inout("r1") ptrs => _,
+            out("r2") _,
+            out("r3") _,
+            out("r4") _,
+            out("r5") _,
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Safe flash erase and program, for storage.
+//!
+//! [`Flash`] implements the `embedded_storage` NOR flash traits, with offsets
+//! from the start of flash. Nothing stops it overwriting the program, so
+//! storage should use a range beyond the end of the image.
+//!
+//! XIP is unavailable during each erase or program operation. Tasks don't
+//! preempt each other, so no other task runs meanwhile, but interrupts of a
+//! higher priority could. Enabled interrupts whose handlers are in flash are
+//! masked for the operation, and run afterwards if pending. Handlers in RAM
+//! stay enabled, and must not call code in flash.
+//!
+//! DMA must not read from flash during an operation. With the `multicore`
+//! feature, core 1 is parked in RAM with interrupts disabled for each
+//! operation.
+//!
+//! [`Flash::erase_async`] and [`Flash::write_async`] let other tasks run
+//! between sectors and pages, so a long erase doesn't hold up the executor.
+//!
+//! ```ignore
+//! let mut flash = Flash::new(hw.flash, 2 * 1024 * 1024);
+//! flash.erase(0x1f_0000, 0x1f_1000)?;
+//! flash.write(0x1f_0000, b"hello")?;
+//! ```
+use core::task::Poll;
+
+use cortex_m::peripheral::{NVIC, SCB};
+use embedded_storage::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+use crate::Runtime;
+
+/// Size of the smallest erasable block.
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Size of the block programmed at once.
+pub const PAGE_SIZE: u32 = 256;
+
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// End of the XIP address space, including its cache and translation aliases.
+const XIP_END: u32 = 0x2000_0000;
+
+/// Largest flash addressable by the bootrom functions.
+const MAX_SIZE: u32 = 0x100_0000;
+
+cfg_select! {
+    feature = "rp2040" => {
+        const NVIC_WORDS: usize = 1;
+    }
+    feature = "rp2350" => {
+        const NVIC_WORDS: usize = 2;
+    }
+}
+
+pub struct FlashInstance(Runtime);
+
+impl FlashInstance {
+    /// ## Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    pub unsafe fn steal() -> Self {
+        unsafe { FlashInstance(Runtime::steal()) }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// The range extends past the end of flash.
+    OutOfBounds,
+    /// An erase range does not start and end on sector boundaries.
+    NotAligned,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+        }
+    }
+}
+
+/// Mask the enabled interrupts whose handlers are in flash, returning them to unmask afterwards.
+fn mask_flash_handlers() -> [u32; NVIC_WORDS] {
+    let nvic = unsafe { &*NVIC::PTR };
+    let vectors = unsafe { (*SCB::PTR).vtor.read() } as *const u32;
+    let mut masked = [0; NVIC_WORDS];
+    for (word, masked) in masked.iter_mut().enumerate() {
+        let enabled = nvic.iser[word].read();
+        for bit in 0..32 {
+            if enabled & (1 << bit) != 0 {
+                let handler = unsafe { *vectors.add(16 + word * 32 + bit) };
+                if (XIP_BASE..XIP_END).contains(&handler) {
+                    *masked |= 1 << bit;
+                }
+            }
+        }
+        unsafe { nvic.icer[word].write(*masked) };
+    }
+    // Make sure no masked interrupt is taken after this returns
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    masked
+}
+
+fn unmask(masked: [u32; NVIC_WORDS]) {
+    let nvic = unsafe { &*NVIC::PTR };
+    for (word, masked) in masked.iter().enumerate() {
+        unsafe { nvic.iser[word].write(*masked) };
+    }
+}
+
+/// Let other tasks run before continuing.
+async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }).await
+}
+
+/// Flash erase, program and read, passed to the main task as a [`FlashInstance`].
+pub struct Flash {
+    _instance: FlashInstance,
+    size: u32,
+}
+
+impl Flash {
+    /// `size` is the size of the flash chip in bytes.
+    pub fn new(instance: FlashInstance, size: u32) -> Self {
+        assert!(size <= MAX_SIZE && size.is_multiple_of(SECTOR_SIZE));
+        Self { _instance: instance, size }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn check_bounds(&self, offset: u32, len: usize) -> Result<(), Error> {
+        if offset as u64 + len as u64 > self.size as u64 {
+            Err(Error::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run `f` with flash unavailable.
+    fn with_xip_disabled<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        let run = || {
+            let masked = mask_flash_handlers();
+            let r = f();
+            unmask(masked);
+            r
+        };
+        cfg_select! {
+            feature = "multicore" => { crate::rp::multicore::with_core1_parked(run) }
+            _ => { run() }
+        }
+    }
+
+    /// Read from flash through XIP.
+    pub fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.check_bounds(offset, buf.len())?;
+        unsafe { core::ptr::copy_nonoverlapping((XIP_BASE + offset) as *const u8, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    fn check_erase(&self, from: u32, to: u32) -> Result<(), Error> {
+        if from > to {
+            return Err(Error::OutOfBounds);
+        }
+        self.check_bounds(from, (to - from) as usize)?;
+        if !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, sector: u32) {
+        // SAFETY: Handlers that could run meanwhile are masked, and the range is checked
+        self.with_xip_disabled(|| unsafe { super::flash_range_erase(sector, SECTOR_SIZE, true) });
+    }
+
+    /// Program the start of `data` at `offset`, up to the end of its page, and
+    /// return the number of bytes programmed.
+    fn write_page(&mut self, offset: u32, data: &[u8]) -> usize {
+        let page = offset & !(PAGE_SIZE - 1);
+        let start = (offset - page) as usize;
+        let n = data.len().min(PAGE_SIZE as usize - start);
+
+        // The data must be in RAM, and a whole page
+        let mut buf = [0xff; PAGE_SIZE as usize];
+        buf[start..start + n].copy_from_slice(&data[..n]);
+
+        // SAFETY: Handlers that could run meanwhile are masked, and the range is checked
+        self.with_xip_disabled(|| unsafe { super::flash_range_program(page, &buf, true) });
+        n
+    }
+
+    /// Erase the sectors from `from` up to `to`, which must be multiples of [`SECTOR_SIZE`].
+    ///
+    /// Interrupts are masked for one sector at a time, about 50ms each.
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.check_erase(from, to)?;
+        for sector in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.erase_sector(sector);
+        }
+        Ok(())
+    }
+
+    /// Erase like [`erase`](Self::erase), letting other tasks run between sectors.
+    pub async fn erase_async(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.check_erase(from, to)?;
+        for sector in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.erase_sector(sector);
+            yield_now().await;
+        }
+        Ok(())
+    }
+
+    /// Program `data` at `offset`, which must have been erased.
+    ///
+    /// There is no alignment requirement: bytes of a page outside `data` are
+    /// programmed with 0xff, which leaves them unchanged.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        self.check_bounds(offset, data.len())?;
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let n = self.write_page(offset, data);
+            offset += n as u32;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Program like [`write`](Self::write), letting other tasks run between pages.
+    pub async fn write_async(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        self.check_bounds(offset, data.len())?;
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let n = self.write_page(offset, data);
+            offset += n as u32;
+            data = &data[n..];
+            yield_now().await;
+        }
+        Ok(())
+    }
+
+    /// Read the flash chip's JEDEC ID, e.g. 0xEF7015 for Winbond W25Q16JV.
+    pub fn jedec_id(&mut self) -> u32 {
+        // SAFETY: Handlers that could run meanwhile are masked
+        self.with_xip_disabled(|| unsafe { super::flash_jedec_id(true) })
+    }
+
+    /// Read the flash chip's unique ID, if it has one; see [`flash_unique_id`](super::flash_unique_id).
+    pub fn unique_id(&mut self, out: &mut [u8]) {
+        // SAFETY: Handlers that could run meanwhile are masked
+        self.with_xip_disabled(|| unsafe { super::flash_unique_id(out, true) })
+    }
+}
+
+impl ErrorType for Flash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        Flash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        Flash::erase(self, from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        Flash::write(self, offset, bytes)
+    }
+}
+
+/// Writes only clear bits, and bytes outside the written range are left unchanged.
+impl MultiwriteNorFlash for Flash {}
@@ -82,6 +82,20 @@ impl IoPin {
         });
     }
 
+    /// Disconnect the pin's digital input and output for use as an ADC input.
+    #[inline]
+    pub fn set_analog(&self) {
+        self.disable();
+        self.pad().write(|w| {
+            #[cfg(feature = "rp2350")]
+            w.set_iso(false);
+            w.set_ie(false);
+            w.set_od(true);
+            w.set_pue(false);
+            w.set_pde(false);
+        });
+    }
+
     #[inline]
     pub fn configure_pad(&self, pull_up: bool, pull_down: bool) {
         self.pad().write(|w| {
@@ -8,10 +8,7 @@ pub use rp_reg::RpReg;
 
 pub mod gpio;
 
-#[cfg(feature = "rp2040")]
 pub mod rom_data;
-
-#[cfg(feature = "rp2040")]
 pub mod flash;
 
 #[cfg(feature="time")]
@@ -22,6 +19,23 @@ pub(crate) mod usb;
 
 pub mod i2c;
 pub mod spi;
+pub mod uart;
+pub mod pio;
+
+#[cfg(feature = "dma")]
+pub mod dma;
+
+#[cfg(feature = "adc")]
+pub mod adc;
+
+#[cfg(feature = "pwm")]
+pub mod pwm;
+
+#[cfg(feature = "multicore")]
+pub mod multicore;
+
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 
 #[cfg(all(feature = "rp2040", feature = "rp2040-boot2-w25q080"))]
 #[unsafe(link_section = ".boot2")]
@@ -48,6 +62,7 @@ const PLL_USB_HZ: u32 = 48_000_000;
 pub const CLK_REF_HZ: u32 = XOSC_HZ;
 pub const CLK_SYS_HZ: u32 = PLL_SYS_HZ;
 pub const CLK_PERI_HZ: u32 = PLL_USB_HZ;
+pub const CLK_ADC_HZ: u32 = PLL_USB_HZ;
 
 pub(crate) fn init() {
     #![allow(unused_variables, unused_mut)]
@@ -149,6 +164,8 @@ pub(crate) fn init() {
     enable.set_sysinfo(true);
     enable.set_busctrl(true);
     #[cfg(feature = "usb")] enable.set_usbctrl(true);
+    #[cfg(feature = "dma")] enable.set_dma(true);
+    #[cfg(feature = "pwm")] enable.set_pwm(true);
     #[cfg(all(feature = "time", feature="rp2040"))] enable.set_timer(true);
     #[cfg(all(feature = "time", feature="rp2350"))] enable.set_timer0(true);
 
@@ -173,6 +190,24 @@ pub(crate) fn init() {
         cortex_m::peripheral::NVIC::unmask(Interrupt::SPI0_IRQ);
         #[cfg(feature = "spi1")]
         cortex_m::peripheral::NVIC::unmask(Interrupt::SPI1_IRQ);
+        #[cfg(feature = "uart0")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::UART0_IRQ);
+        #[cfg(feature = "uart1")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::UART1_IRQ);
+        #[cfg(feature = "dma")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::DMA_IRQ_0);
+        #[cfg(feature = "adc")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::ADC_IRQ_FIFO);
+        #[cfg(all(feature = "pwm", feature = "rp2040"))]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::PWM_IRQ_WRAP);
+        #[cfg(all(feature = "pwm", feature = "rp2350"))]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::PWM_IRQ_WRAP_0);
+        #[cfg(feature = "pio0")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::PIO0_IRQ_0);
+        #[cfg(feature = "pio1")]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::PIO1_IRQ_0);
+        #[cfg(all(feature = "pio2", feature = "rp2350"))]
+        cortex_m::peripheral::NVIC::unmask(Interrupt::PIO2_IRQ_0);
         #[cfg(all(feature = "time", feature = "rp2040"))]
         cortex_m::peripheral::NVIC::unmask(Interrupt::TIMER_IRQ_0);
         #[cfg(all(feature = "time", feature = "rp2350"))]
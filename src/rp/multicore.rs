@@ -0,0 +1,484 @@
+//! Running tasks on core 1.
+//!
+//! Core 1 runs its own executor, with tasks declared with
+//! `#[zeptos::task(core = 1)]`. Its [`Runtime<1>`] is a different type from
+//! core 0's `Runtime`, so a core can only access its own [`TaskOnly`] values
+//! and the peripherals passed to its tasks. Peripheral interrupts are enabled
+//! on core 0, so drivers using them must stay there.
+//!
+//! The cores communicate through the SIO FIFOs, one word at a time, and can
+//! share data protected by a [`Spinlock`].
+//!
+//! A `Waker` may be woken from either core, and the task is polled on its own
+//! core. To wake a task on the other core, the runtime forces that core's
+//! QSPI GPIO interrupt, which is otherwise unused as the QSPI pins are
+//! connected to flash.
+//!
+//! ```ignore
+//! static mut CORE1_STACK: Stack<4096> = Stack::new();
+//!
+//! fn core1_main(rt: Runtime<1>, fifo: Fifo<1>) {
+//!     echo(rt).spawn(fifo);
+//! }
+//!
+//! #[zeptos::task(core = 1)]
+//! async fn echo(mut fifo: Fifo<1>) {
+//!     loop {
+//!         let word = fifo.recv().await;
+//!         fifo.send(word + 1);
+//!     }
+//! }
+//!
+//! let mut fifo = hw.core1.spawn(unsafe { &mut *&raw mut CORE1_STACK }, core1_main);
+//! fifo.send(1);
+//! assert_eq!(fifo.recv().await, 2);
+//! ```
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use cortex_m::peripheral::NVIC;
+use rp_pac::{interrupt, Interrupt as Irq, IO_QSPI, PSM, SIO};
+
+use crate::rp::RpReg as _;
+
+#[allow(unused_imports)]
+use crate::{Interrupt, Runtime, TaskOnly};
+
+static FIFO_INT0: TaskOnly<Interrupt, 0> = TaskOnly::new(Interrupt::new());
+static FIFO_INT1: TaskOnly<Interrupt, 1> = TaskOnly::new(Interrupt::new());
+
+/// Entry function passed to [`Core1::spawn`], stored for core 1 to pick up.
+static CORE1_ENTRY: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set once core 1 has been launched. It never stops.
+static CORE1_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Set by core 0 to park core 1, and cleared to release it.
+#[cfg(feature = "flash")]
+static LOCKOUT: AtomicBool = AtomicBool::new(false);
+
+/// Set by core 1 while it is parked.
+#[cfg(feature = "flash")]
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+/// The number of the core this is running on.
+#[inline]
+pub fn core_id() -> u8 {
+    SIO.cpuid().read() as u8
+}
+
+cfg_select! {
+    feature = "rp2040" => {
+        const fn fifo_irq(core: u8) -> Irq {
+            if core == 0 { Irq::SIO_IRQ_PROC0 } else { Irq::SIO_IRQ_PROC1 }
+        }
+
+        #[interrupt]
+        fn SIO_IRQ_PROC0() {
+            on_fifo_irq();
+        }
+
+        #[interrupt]
+        fn SIO_IRQ_PROC1() {
+            on_fifo_irq();
+        }
+    }
+    feature = "rp2350" => {
+        /// Each core has its own NVIC, with the FIFO interrupt on the same line.
+        const fn fifo_irq(_core: u8) -> Irq {
+            Irq::SIO_IRQ_FIFO
+        }
+
+        #[interrupt]
+        fn SIO_IRQ_FIFO() {
+            on_fifo_irq();
+        }
+    }
+}
+
+fn on_fifo_irq() {
+    let core = core_id();
+
+    // The interrupt stays asserted while the FIFO has data, so it's masked
+    // until the receiving task has emptied it
+    NVIC::mask(fifo_irq(core));
+
+    // SAFETY: This is an ISR at task priority on `core`
+    unsafe {
+        if core == 0 {
+            FIFO_INT0.get_unchecked().notify();
+        } else {
+            FIFO_INT1.get_unchecked().notify();
+        }
+    }
+}
+
+/// Interrupt forced by the other core to run this core's run queue.
+const DOORBELL_IRQ: Irq = Irq::IO_IRQ_QSPI;
+
+/// Make `core` run its run queue, after a task on it was woken from this core.
+pub(crate) fn ring_doorbell(core: u8) {
+    IO_QSPI.int_proc(core as usize).intf(0).write_set(|w| w.0 = 1);
+}
+
+#[interrupt]
+fn IO_IRQ_QSPI() {
+    IO_QSPI.int_proc(core_id() as usize).intf(0).write_clear(|w| w.0 = 1);
+    #[cfg(feature = "flash")]
+    if core_id() == 1 && LOCKOUT.load(Ordering::Acquire) {
+        // SAFETY: This is core 1, at the doorbell's priority with interrupts enabled
+        unsafe { park() };
+    }
+    cortex_m::peripheral::SCB::set_pendsv();
+}
+
+/// Run `f` on core 0 with core 1 parked in RAM with interrupts disabled, so
+/// `f` can disable XIP.
+///
+/// This is the handshake of the SDK's multicore lockout, but core 1 is
+/// signalled with the doorbell interrupt, as the FIFO belongs to the
+/// application's [`Fifo`].
+#[cfg(feature = "flash")]
+pub(crate) fn with_core1_parked<R>(f: impl FnOnce() -> R) -> R {
+    if !core1_running() {
+        return f();
+    }
+
+    LOCKOUT.store(true, Ordering::Release);
+    ring_doorbell(1);
+    while !PARKED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+
+    // Release core 1 even if `f` panics, and wait until it is out of the
+    // loop so it can't miss the next lockout
+    scopeguard::defer! {
+        LOCKOUT.store(false, Ordering::Release);
+        cortex_m::asm::dsb();
+        cortex_m::asm::sev();
+        while PARKED.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+    }
+    f()
+}
+
+/// Spin with interrupts disabled until core 0 clears `LOCKOUT`.
+///
+/// This runs from RAM while XIP is unavailable, and is written in assembly
+/// so it calls nothing in flash at any optimization level.
+///
+/// ## Safety
+///
+/// This must be called on core 1 with interrupts enabled.
+#[cfg(feature = "flash")]
+#[inline(never)]
+#[unsafe(link_section = ".data.ram_func")]
+unsafe fn park() {
+    unsafe {
+        core::arch::asm!(
+            "cpsid i",
+            "movs {tmp}, #1",
+            "strb {tmp}, [{parked}]",
+            "dsb",
+            "2:",
+            "wfe",
+            "ldrb {tmp}, [{lockout}]",
+            "cmp {tmp}, #0",
+            "bne 2b",
+            "movs {tmp}, #0",
+            "strb {tmp}, [{parked}]",
+            "dsb",
+            "cpsie i",
+            parked = in(reg) PARKED.as_ptr(),
+            lockout = in(reg) LOCKOUT.as_ptr(),
+            tmp = out(reg) _,
+            options(nostack),
+        );
+    }
+}
+
+/// Whether core 1 has been launched with [`Core1::spawn`].
+pub fn core1_running() -> bool {
+    CORE1_RUNNING.load(Ordering::Relaxed)
+}
+
+/// Stack for core 1, a multiple of 8 bytes to keep the top 8-byte aligned.
+#[repr(C, align(8))]
+pub struct Stack<const SIZE: usize> {
+    mem: MaybeUninit<[u8; SIZE]>,
+}
+
+impl<const SIZE: usize> Stack<SIZE> {
+    pub const fn new() -> Self {
+        const { assert!(SIZE.is_multiple_of(8), "core 1 stack size must be a multiple of 8") };
+        Stack { mem: MaybeUninit::uninit() }
+    }
+}
+
+impl<const SIZE: usize> Default for Stack<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle to launch core 1, passed to the main task in `Hardware`.
+pub struct Core1(Runtime);
+
+impl Core1 {
+    /// ## Safety
+    ///
+    /// This must be called from within the runtime on core 0, and the handle must not exist
+    /// elsewhere in the program.
+    pub unsafe fn steal() -> Self {
+        unsafe { Core1(Runtime::steal()) }
+    }
+
+    /// Start core 1 running `entry` on `stack`, and return core 0's end of the FIFO.
+    ///
+    /// `entry` is called with interrupts disabled, like core 0's `main`, to spawn
+    /// core 1's tasks. When it returns, core 1 runs its executor.
+    pub fn spawn<const SIZE: usize>(self, stack: &'static mut Stack<SIZE>, entry: fn(Runtime<1>, Fifo<1>)) -> Fifo<0> {
+        CORE1_ENTRY.store(entry as *mut (), Ordering::Release);
+        CORE1_RUNNING.store(true, Ordering::Relaxed);
+
+        // Reset core 1 into the boot ROM, which waits to be launched through the FIFO
+        PSM.frce_off().modify(|w| w.set_proc1(true));
+        while !PSM.frce_off().read().proc1() {}
+        PSM.frce_off().modify(|w| w.set_proc1(false));
+
+        // Core 1 uses the same vector table, with handlers that check which core they're on
+        let vector_table = unsafe { (*cortex_m::peripheral::SCB::PTR).vtor.read() };
+        let stack_top = unsafe { (stack.mem.as_mut_ptr() as *mut u8).add(SIZE) };
+
+        let sequence = [0, 0, 1, vector_table, stack_top as u32, core1_startup as *const () as usize as u32];
+        let mut i = 0;
+        let mut failures = 0;
+        while i < sequence.len() {
+            let cmd = sequence[i];
+            if cmd == 0 {
+                // Discard anything core 1 may have sent before it was reset
+                fifo_drain();
+                cortex_m::asm::sev();
+            }
+            fifo_write_blocking(cmd);
+            if fifo_read_blocking() == cmd {
+                i += 1;
+            } else {
+                i = 0;
+                failures += 1;
+                assert!(failures < 16, "core 1 failed to launch");
+            }
+        }
+
+        // The FIFO is free for the application now the launch is done
+        unsafe {
+            NVIC::unmask(fifo_irq(0));
+            NVIC::unmask(DOORBELL_IRQ);
+        }
+
+        Fifo { _rt: self.0 }
+    }
+}
+
+extern "C" fn core1_startup() -> ! {
+    cortex_m::interrupt::disable();
+
+    #[cfg(feature = "rp2350")]
+    unsafe {
+        // Enable the FPU, as the reset handler does on core 0
+        let scb = &*cortex_m::peripheral::SCB::PTR;
+        scb.cpacr.modify(|r| r | (0b1111 << 20));
+    }
+
+    // SAFETY: This is core 1's task thread, with interrupts disabled
+    let rt = unsafe { Runtime::steal_core1() };
+    let entry: fn(Runtime<1>, Fifo<1>) = unsafe { core::mem::transmute(CORE1_ENTRY.load(Ordering::Acquire)) };
+    entry(rt, Fifo { _rt: rt });
+
+    unsafe {
+        NVIC::unmask(fifo_irq(1));
+        NVIC::unmask(DOORBELL_IRQ);
+        crate::internal::post_init();
+    }
+}
+
+fn fifo_drain() {
+    while SIO.fifo().st().read().vld() {
+        SIO.fifo().rd().read();
+    }
+}
+
+fn fifo_write_blocking(value: u32) {
+    while !SIO.fifo().st().read().rdy() {}
+    SIO.fifo().wr().write_value(value);
+    cortex_m::asm::sev();
+}
+
+fn fifo_read_blocking() -> u32 {
+    while !SIO.fifo().st().read().vld() {
+        cortex_m::asm::wfe();
+    }
+    SIO.fifo().rd().read()
+}
+
+/// A core's end of the inter-core FIFOs.
+///
+/// Each direction holds a few words: 8 on RP2040 and 4 on RP2350.
+pub struct Fifo<const CORE: u8> {
+    _rt: Runtime<CORE>,
+}
+
+impl<const CORE: u8> Fifo<CORE> {
+    fn interrupt(&self) -> &'static Interrupt {
+        // SAFETY: The runtime token proves this is `CORE`
+        unsafe {
+            if CORE == 0 {
+                FIFO_INT0.get_unchecked()
+            } else {
+                FIFO_INT1.get_unchecked()
+            }
+        }
+    }
+
+    /// Send a word to the other core if there is space.
+    pub fn try_send(&mut self, value: u32) -> Result<(), u32> {
+        if SIO.fifo().st().read().rdy() {
+            SIO.fifo().wr().write_value(value);
+            Ok(())
+        } else {
+            Err(value)
+        }
+    }
+
+    /// Send a word to the other core.
+    ///
+    /// There is no interrupt for space becoming available, so like the SDK,
+    /// this busy-waits while the FIFO is full.
+    pub fn send(&mut self, value: u32) {
+        fifo_write_blocking(value);
+    }
+
+    /// Receive a word from the other core if one is waiting.
+    pub fn try_recv(&mut self) -> Option<u32> {
+        if SIO.fifo().st().read().vld() {
+            Some(SIO.fifo().rd().read())
+        } else {
+            None
+        }
+    }
+
+    /// Wait for a word from the other core.
+    pub async fn recv(&mut self) -> u32 {
+        self.interrupt().until(|| {
+            if SIO.fifo().st().read().vld() {
+                true
+            } else {
+                // SAFETY: The interrupt only notifies this core's waiting task
+                unsafe { NVIC::unmask(fifo_irq(CORE)) };
+                false
+            }
+        }).await;
+        SIO.fifo().rd().read()
+    }
+}
+
+/// One of the 32 hardware spinlocks, for data shared between the cores.
+///
+/// Tasks on a core don't preempt each other, so the lock is only needed
+/// against the other core. It should only be held briefly, as the other core
+/// spins while waiting, and never across an `await`.
+///
+/// On RP2350, the hardware spinlocks can be released by writes to other SIO
+/// registers (erratum RP2350-E2), so they are implemented with atomic
+/// instructions instead, as in the SDK.
+///
+/// `N` must be less than [`NUM_SPINLOCKS`].
+pub struct Spinlock<const N: usize>;
+
+/// Number of spinlocks available to the application. The last of the 32 is
+/// used by the runtime.
+pub const NUM_SPINLOCKS: usize = 31;
+
+/// Spinlock protecting the run queues, which either core can add a task to.
+const RUN_QUEUE_SPINLOCK: usize = 31;
+
+/// Run `f` holding the run queue lock.
+///
+/// Interrupts are disabled, so nothing on this core can wait for the lock while it is held.
+pub(crate) fn with_run_queue_lock<R>(f: impl FnOnce() -> R) -> R {
+    cortex_m::interrupt::free(|_| {
+        let _guard = loop {
+            if let Some(guard) = Spinlock::<RUN_QUEUE_SPINLOCK>::try_claim() {
+                break guard;
+            }
+        };
+        f()
+    })
+}
+
+/// Holds a [`Spinlock`] until dropped.
+pub struct SpinlockGuard<const N: usize> {
+    _not_send: core::marker::PhantomData<*mut ()>,
+}
+
+cfg_select! {
+    feature = "rp2040" => {
+        impl<const N: usize> Spinlock<N> {
+            fn try_claim() -> Option<SpinlockGuard<N>> {
+                const { assert!(N < 32) };
+                // Reading claims the lock, returning 0 if it was already claimed
+                if SIO.spinlock(N).read() != 0 {
+                    core::sync::atomic::compiler_fence(Ordering::Acquire);
+                    Some(SpinlockGuard { _not_send: core::marker::PhantomData })
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl<const N: usize> Drop for SpinlockGuard<N> {
+            fn drop(&mut self) {
+                core::sync::atomic::compiler_fence(Ordering::Release);
+                SIO.spinlock(N).write_value(1);
+            }
+        }
+    }
+    feature = "rp2350" => {
+        static LOCKS: [core::sync::atomic::AtomicBool; 32] = [const { core::sync::atomic::AtomicBool::new(false) }; 32];
+
+        impl<const N: usize> Spinlock<N> {
+            fn try_claim() -> Option<SpinlockGuard<N>> {
+                const { assert!(N < 32) };
+                if !LOCKS[N].swap(true, Ordering::Acquire) {
+                    Some(SpinlockGuard { _not_send: core::marker::PhantomData })
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl<const N: usize> Drop for SpinlockGuard<N> {
+            fn drop(&mut self) {
+                LOCKS[N].store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Spinlock<N> {
+    /// Claim the lock if the other core doesn't hold it.
+    pub fn try_lock() -> Option<SpinlockGuard<N>> {
+        const { assert!(N < NUM_SPINLOCKS) };
+        Self::try_claim()
+    }
+
+    /// Claim the lock, spinning until the other core releases it.
+    pub fn lock() -> SpinlockGuard<N> {
+        loop {
+            if let Some(guard) = Self::try_lock() {
+                return guard;
+            }
+        }
+    }
+}
@@ -0,0 +1,666 @@
+use defmt::Format;
+
+/// Number of instructions in a PIO block's instruction memory.
+pub const INSTRUCTION_MEMORY_LEN: usize = 32;
+
+const MAX_TOKENS: usize = 16;
+const MAX_SYMBOLS: usize = 48;
+
+/// Side-set configuration of a program, from its `.side_set` directive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct SideSet {
+    /// Number of side-set pins.
+    pub bits: u8,
+    /// Side-set is optional per instruction, using an extra enable bit.
+    pub optional: bool,
+    /// Side-set drives pin directions instead of pin values.
+    pub pindirs: bool,
+}
+
+impl SideSet {
+    pub const NONE: SideSet = SideSet { bits: 0, optional: false, pindirs: false };
+
+    /// Number of bits of the delay/side-set field used for side-set, including the enable bit.
+    pub const fn count(&self) -> u8 {
+        self.bits + self.optional as u8
+    }
+}
+
+/// An assembled PIO program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Program {
+    code: [u16; INSTRUCTION_MEMORY_LEN],
+    len: u8,
+    /// Address the program must be loaded at, from `.origin`.
+    pub origin: Option<u8>,
+    /// Address of the instruction marked `.wrap_target`, relative to the start of the program.
+    pub wrap_target: u8,
+    /// Address of the instruction before `.wrap`, relative to the start of the program.
+    pub wrap: u8,
+    pub side_set: SideSet,
+}
+
+impl Program {
+    /// The instructions, encoded for loading at address 0.
+    pub fn code(&self) -> &[u16] {
+        &self.code[..self.len as usize]
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ErrorKind {
+    /// The program has more instructions than fit in instruction memory, or no instructions at all.
+    Length,
+    UnknownDirective,
+    UnknownInstruction,
+    /// An operand is missing, not valid for the instruction, or followed by unexpected tokens.
+    InvalidOperand,
+    /// A number is out of range for its field.
+    OutOfRange,
+    UndefinedSymbol,
+    DuplicateSymbol,
+    /// The instruction has no side-set value, but side-set is not optional.
+    MissingSideSet,
+    /// The line has too many tokens, or too many symbols are defined.
+    TooComplex,
+}
+
+impl ErrorKind {
+    pub const fn message(&self) -> &'static str {
+        match self {
+            ErrorKind::Length => "program length must be 1 to 32 instructions",
+            ErrorKind::UnknownDirective => "unknown directive",
+            ErrorKind::UnknownInstruction => "unknown instruction",
+            ErrorKind::InvalidOperand => "invalid operand",
+            ErrorKind::OutOfRange => "value out of range",
+            ErrorKind::UndefinedSymbol => "undefined symbol",
+            ErrorKind::DuplicateSymbol => "symbol defined twice",
+            ErrorKind::MissingSideSet => "side-set value required",
+            ErrorKind::TooComplex => "too many tokens or symbols",
+        }
+    }
+}
+
+/// Assembler error, with the 1-based line number of the source.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Error {
+    pub line: u16,
+    pub kind: ErrorKind,
+}
+
+impl Error {
+    /// Panic with a message describing the error, failing compilation in a const context.
+    pub const fn panic(&self) -> ! {
+        let mut buf = [0u8; 80];
+        let mut n = push(&mut buf, 0, b"pio_asm line ");
+        let mut div = 10000;
+        let mut started = false;
+        while div > 0 {
+            let digit = (self.line / div % 10) as u8;
+            if digit != 0 || started || div == 1 {
+                buf[n] = b'0' + digit;
+                n += 1;
+                started = true;
+            }
+            div /= 10;
+        }
+        n = push(&mut buf, n, b": ");
+        n = push(&mut buf, n, self.kind.message().as_bytes());
+        match core::str::from_utf8(buf.split_at(n).0) {
+            Ok(msg) => panic!("{}", msg),
+            Err(_) => panic!("pio_asm error"),
+        }
+    }
+}
+
+const fn err(line: u16, kind: ErrorKind) -> Result<Program, Error> {
+    Err(Error { line, kind })
+}
+
+const fn push(buf: &mut [u8], mut n: usize, s: &[u8]) -> usize {
+    let mut i = 0;
+    while i < s.len() && n < buf.len() {
+        buf[n] = s[i];
+        n += 1;
+        i += 1;
+    }
+    n
+}
+
+/// Assemble a PIO program, with `code` and `wrap` relative to address 0.
+///
+/// This accepts the syntax of the Pico SDK's `pioasm` for a single program,
+/// with the following limitations:
+///
+///  * Operands are numbers (decimal, `0x` hex or `0b` binary), `.define`d
+///    symbols or labels. Arithmetic expressions are not supported.
+///  * Only the RP2040 instruction set is accepted.
+///  * `.program` and `.lang_opt` are ignored. Other directives for
+///    configuration outside of the instructions are not supported.
+///
+/// See [`pio_asm!`](crate::pio_asm) for a macro that does this at compile time.
+pub const fn assemble(src: &str) -> Result<Program, Error> {
+    let src = src.as_bytes();
+    let mut symbols = Symbols { names: [&[]; MAX_SYMBOLS], values: [0; MAX_SYMBOLS], len: 0 };
+    let mut program = Program {
+        code: [0; INSTRUCTION_MEMORY_LEN],
+        len: 0,
+        origin: None,
+        wrap_target: 0,
+        wrap: 0,
+        side_set: SideSet::NONE,
+    };
+    let mut wrap = None;
+
+    // The first pass collects labels, defines and the directives; the second encodes instructions.
+    let mut pass = 0;
+    while pass < 2 {
+        let mut pc: usize = 0;
+        let mut start = 0;
+        let mut line_number: u16 = 0;
+        while start < src.len() {
+            let mut end = start;
+            while end < src.len() && src[end] != b'\n' {
+                end += 1;
+            }
+            line_number += 1;
+            let line = sub(src, start, end);
+            start = end + 1;
+
+            let mut toks = match tokenize(line) {
+                Ok(toks) => toks,
+                Err(kind) => return err(line_number, kind),
+            };
+
+            // Labels, optionally PUBLIC
+            let label_start = if toks.len > 2 && eq_ignore_case(toks.toks[0], "public") { 1 } else { 0 };
+            if toks.len > label_start + 1 && eq(toks.toks[label_start + 1], b":") {
+                if pass == 0
+                    && let Err(kind) = symbols.define(toks.toks[label_start], pc as i32)
+                {
+                    return err(line_number, kind);
+                }
+                toks.pos = label_start + 2;
+            }
+
+            let Some(first) = toks.next() else {
+                continue;
+            };
+
+            if first[0] == b'.' {
+                if eq_ignore_case(first, ".word") {
+                    if pc >= INSTRUCTION_MEMORY_LEN {
+                        return err(line_number, ErrorKind::Length);
+                    }
+                    if pass == 1 {
+                        match value(&mut toks, &symbols, 0xffff) {
+                            Ok(v) => program.code[pc] = v as u16,
+                            Err(kind) => return err(line_number, kind),
+                        }
+                    } else {
+                        toks.pos = toks.len;
+                    }
+                    pc += 1;
+                } else if pass == 1 || eq_ignore_case(first, ".program") || eq_ignore_case(first, ".lang_opt") {
+                    continue;
+                } else if eq_ignore_case(first, ".define") {
+                    if toks.peek_is("public") {
+                        toks.pos += 1;
+                    }
+                    let Some(name) = toks.next() else {
+                        return err(line_number, ErrorKind::InvalidOperand);
+                    };
+                    let v = match value(&mut toks, &symbols, i32::MAX as u32) {
+                        Ok(v) => v,
+                        Err(kind) => return err(line_number, kind),
+                    };
+                    if let Err(kind) = symbols.define(name, v as i32) {
+                        return err(line_number, kind);
+                    }
+                } else if eq_ignore_case(first, ".origin") {
+                    match value(&mut toks, &symbols, INSTRUCTION_MEMORY_LEN as u32 - 1) {
+                        Ok(v) => program.origin = Some(v as u8),
+                        Err(kind) => return err(line_number, kind),
+                    }
+                } else if eq_ignore_case(first, ".side_set") {
+                    let bits = match value(&mut toks, &symbols, 5) {
+                        Ok(v) => v as u8,
+                        Err(kind) => return err(line_number, kind),
+                    };
+                    program.side_set.bits = bits;
+                    if toks.peek_is("opt") {
+                        toks.pos += 1;
+                        program.side_set.optional = true;
+                    }
+                    if toks.peek_is("pindirs") {
+                        toks.pos += 1;
+                        program.side_set.pindirs = true;
+                    }
+                    if program.side_set.count() > 5 {
+                        return err(line_number, ErrorKind::OutOfRange);
+                    }
+                } else if eq_ignore_case(first, ".wrap_target") {
+                    program.wrap_target = pc as u8;
+                } else if eq_ignore_case(first, ".wrap") {
+                    if pc == 0 {
+                        return err(line_number, ErrorKind::InvalidOperand);
+                    }
+                    wrap = Some(pc as u8 - 1);
+                } else {
+                    return err(line_number, ErrorKind::UnknownDirective);
+                }
+            } else {
+                if pc >= INSTRUCTION_MEMORY_LEN {
+                    return err(line_number, ErrorKind::Length);
+                }
+                // Labels may be used before they are defined, so encode on the second pass
+                if pass == 1 {
+                    toks.pos -= 1;
+                    match encode(&mut toks, &symbols, program.side_set) {
+                        Ok(instr) => program.code[pc] = instr,
+                        Err(kind) => return err(line_number, kind),
+                    }
+                } else {
+                    toks.pos = toks.len;
+                }
+                pc += 1;
+            }
+
+            if toks.pos < toks.len {
+                return err(line_number, ErrorKind::InvalidOperand);
+            }
+        }
+
+        if pc == 0 {
+            return err(if line_number > 0 { line_number } else { 1 }, ErrorKind::Length);
+        }
+        program.len = pc as u8;
+        pass += 1;
+    }
+
+    program.wrap = match wrap {
+        Some(wrap) => wrap,
+        None => program.len - 1,
+    };
+    Ok(program)
+}
+
+/// Assemble a PIO program at compile time, failing compilation on errors.
+///
+/// The arguments are lines of source, or a single string with the whole
+/// program, and the result is a [`Program`](crate::rp::pio::Program).
+///
+/// ```ignore
+/// const SQUARE: Program = pio_asm!(
+///     ".side_set 1 opt",
+///     ".wrap_target",
+///     "    set pins, 1 side 0 [31]",
+///     "    set pins, 0 side 1 [31]",
+///     ".wrap",
+/// );
+/// ```
+#[macro_export]
+macro_rules! pio_asm {
+    ($($line:expr),+ $(,)?) => {
+        const {
+            match $crate::rp::pio::assemble(concat!($($line, "\n"),+)) {
+                Ok(program) => program,
+                Err(e) => e.panic(),
+            }
+        }
+    };
+}
+
+struct Symbols<'a> {
+    names: [&'a [u8]; MAX_SYMBOLS],
+    values: [i32; MAX_SYMBOLS],
+    len: usize,
+}
+
+impl<'a> Symbols<'a> {
+    const fn define(&mut self, name: &'a [u8], value: i32) -> Result<(), ErrorKind> {
+        if !is_identifier(name) {
+            return Err(ErrorKind::InvalidOperand);
+        }
+        if self.get(name).is_some() {
+            return Err(ErrorKind::DuplicateSymbol);
+        }
+        if self.len == MAX_SYMBOLS {
+            return Err(ErrorKind::TooComplex);
+        }
+        self.names[self.len] = name;
+        self.values[self.len] = value;
+        self.len += 1;
+        Ok(())
+    }
+
+    const fn get(&self, name: &[u8]) -> Option<i32> {
+        let mut i = 0;
+        while i < self.len {
+            if eq(self.names[i], name) {
+                return Some(self.values[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+struct Tokens<'a> {
+    toks: [&'a [u8]; MAX_TOKENS],
+    len: usize,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    const fn next(&mut self) -> Option<&'a [u8]> {
+        if self.pos < self.len {
+            self.pos += 1;
+            Some(self.toks[self.pos - 1])
+        } else {
+            None
+        }
+    }
+
+    const fn peek_is(&self, keyword: &str) -> bool {
+        self.pos < self.len && eq_ignore_case(self.toks[self.pos], keyword)
+    }
+
+    /// Consume the next token if it is `keyword`.
+    const fn take(&mut self, keyword: &str) -> bool {
+        let is = self.peek_is(keyword);
+        if is {
+            self.pos += 1;
+        }
+        is
+    }
+}
+
+/// Split a line into words and punctuation, dropping comments and commas.
+const fn tokenize(line: &[u8]) -> Result<Tokens<'_>, ErrorKind> {
+    let mut toks = Tokens { toks: [&[]; MAX_TOKENS], len: 0, pos: 0 };
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        let next = if i + 1 < line.len() { line[i + 1] } else { 0 };
+        let start = i;
+        if c == b';' || (c == b'/' && next == b'/') {
+            break;
+        } else if c == b' ' || c == b'\t' || c == b'\r' || c == b',' {
+            i += 1;
+            continue;
+        } else if is_word_char(c) {
+            while i < line.len() && is_word_char(line[i]) {
+                i += 1;
+            }
+        } else if (c == b':' && next == b':') || (c == b'!' && next == b'=') || (c == b'-' && next == b'-') {
+            i += 2;
+        } else {
+            i += 1;
+        }
+        if toks.len == MAX_TOKENS {
+            return Err(ErrorKind::TooComplex);
+        }
+        toks.toks[toks.len] = sub(line, start, i);
+        toks.len += 1;
+    }
+    Ok(toks)
+}
+
+const fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'.'
+}
+
+const fn is_identifier(s: &[u8]) -> bool {
+    !s.is_empty() && (s[0].is_ascii_alphabetic() || s[0] == b'_')
+}
+
+const fn sub(s: &[u8], start: usize, end: usize) -> &[u8] {
+    s.split_at(end).0.split_at(start).1
+}
+
+const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Compare with a lowercase keyword, ignoring the case of `a`.
+const fn eq_ignore_case(a: &[u8], keyword: &str) -> bool {
+    let b = keyword.as_bytes();
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i].to_ascii_lowercase() != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Parse a number or symbol, which must be at most `max`.
+const fn value(toks: &mut Tokens, symbols: &Symbols, max: u32) -> Result<u32, ErrorKind> {
+    let Some(tok) = toks.next() else {
+        return Err(ErrorKind::InvalidOperand);
+    };
+
+    let v = if tok[0].is_ascii_digit() {
+        let (radix, mut i) = if tok.len() > 2 && tok[0] == b'0' && (tok[1] == b'x' || tok[1] == b'X') {
+            (16, 2)
+        } else if tok.len() > 2 && tok[0] == b'0' && (tok[1] == b'b' || tok[1] == b'B') {
+            (2, 2)
+        } else {
+            (10, 0)
+        };
+        let mut v: u64 = 0;
+        while i < tok.len() {
+            let digit = match (tok[i] as char).to_digit(radix) {
+                Some(d) => d,
+                None => return Err(ErrorKind::InvalidOperand),
+            };
+            v = v * radix as u64 + digit as u64;
+            if v > u32::MAX as u64 {
+                return Err(ErrorKind::OutOfRange);
+            }
+            i += 1;
+        }
+        v as u32
+    } else if is_identifier(tok) {
+        match symbols.get(tok) {
+            Some(v) if v >= 0 => v as u32,
+            Some(_) => return Err(ErrorKind::OutOfRange),
+            None => return Err(ErrorKind::UndefinedSymbol),
+        }
+    } else {
+        return Err(ErrorKind::InvalidOperand);
+    };
+
+    if v > max {
+        return Err(ErrorKind::OutOfRange);
+    }
+    Ok(v)
+}
+
+/// Look up a keyword operand in a table of `(keyword, encoding)`.
+const fn operand(toks: &mut Tokens, table: &[(&str, u16)]) -> Result<u16, ErrorKind> {
+    let Some(tok) = toks.next() else {
+        return Err(ErrorKind::InvalidOperand);
+    };
+    let mut i = 0;
+    while i < table.len() {
+        if eq_ignore_case(tok, table[i].0) {
+            return Ok(table[i].1);
+        }
+        i += 1;
+    }
+    Err(ErrorKind::InvalidOperand)
+}
+
+/// Bit count of IN and OUT, where 32 is encoded as 0.
+const fn bit_count(toks: &mut Tokens, symbols: &Symbols) -> Result<u16, ErrorKind> {
+    match value(toks, symbols, 32) {
+        Ok(0) => Err(ErrorKind::OutOfRange),
+        Ok(v) => Ok(v as u16 & 0x1f),
+        Err(e) => Err(e),
+    }
+}
+
+macro_rules! tri {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        }
+    };
+}
+
+/// Encode the instruction at the tokens' position, consuming its side-set and delay.
+const fn encode(toks: &mut Tokens, symbols: &Symbols, side_set: SideSet) -> Result<u16, ErrorKind> {
+    // The side-set and delay follow the operands, in either order
+    let mut end = toks.pos;
+    while end < toks.len && !eq_ignore_case(toks.toks[end], "side") && !eq(toks.toks[end], b"[") {
+        end += 1;
+    }
+    let line_len = toks.len;
+    toks.len = end;
+
+    let Some(mnemonic) = toks.next() else {
+        return Err(ErrorKind::UnknownInstruction);
+    };
+
+    let instr = if eq_ignore_case(mnemonic, "jmp") {
+        let condition = if toks.take("!") {
+            tri!(operand(toks, &[("x", 1), ("y", 3), ("osre", 7)]))
+        } else if toks.take("pin") {
+            6
+        } else if toks.len - toks.pos > 1 && toks.peek_is("x") {
+            toks.pos += 1;
+            if toks.take("--") {
+                2
+            } else if toks.take("!=") && toks.take("y") {
+                5
+            } else {
+                return Err(ErrorKind::InvalidOperand);
+            }
+        } else if toks.len - toks.pos > 1 && toks.peek_is("y") {
+            toks.pos += 1;
+            if !toks.take("--") {
+                return Err(ErrorKind::InvalidOperand);
+            }
+            4
+        } else {
+            0
+        };
+        let target = tri!(value(toks, symbols, INSTRUCTION_MEMORY_LEN as u32 - 1)) as u16;
+        condition << 5 | target
+    } else if eq_ignore_case(mnemonic, "wait") {
+        let polarity = tri!(value(toks, symbols, 1)) as u16;
+        let source = tri!(operand(toks, &[("gpio", 0), ("pin", 1), ("irq", 2)]));
+        let index = if source == 2 {
+            let irq = tri!(value(toks, symbols, 7)) as u16;
+            if toks.take("rel") { irq | 0x10 } else { irq }
+        } else {
+            tri!(value(toks, symbols, 31)) as u16
+        };
+        0x2000 | polarity << 7 | source << 5 | index
+    } else if eq_ignore_case(mnemonic, "in") {
+        let source = tri!(operand(toks, &[("pins", 0), ("x", 1), ("y", 2), ("null", 3), ("isr", 6), ("osr", 7)]));
+        0x4000 | source << 5 | tri!(bit_count(toks, symbols))
+    } else if eq_ignore_case(mnemonic, "out") {
+        let dest = tri!(operand(
+            toks,
+            &[("pins", 0), ("x", 1), ("y", 2), ("null", 3), ("pindirs", 4), ("pc", 5), ("isr", 6), ("exec", 7)]
+        ));
+        0x6000 | dest << 5 | tri!(bit_count(toks, symbols))
+    } else if eq_ignore_case(mnemonic, "push") || eq_ignore_case(mnemonic, "pull") {
+        let pull = eq_ignore_case(mnemonic, "pull");
+        let conditional = toks.take(if pull { "ifempty" } else { "iffull" });
+        let block = !toks.take("noblock");
+        if block {
+            toks.take("block");
+        }
+        0x8000 | (pull as u16) << 7 | (conditional as u16) << 6 | (block as u16) << 5
+    } else if eq_ignore_case(mnemonic, "mov") {
+        let dest = tri!(operand(toks, &[("pins", 0), ("x", 1), ("y", 2), ("exec", 4), ("pc", 5), ("isr", 6), ("osr", 7)]));
+        let op = if toks.take("!") || toks.take("~") {
+            1
+        } else if toks.take("::") {
+            2
+        } else {
+            0
+        };
+        let source = tri!(operand(toks, &[("pins", 0), ("x", 1), ("y", 2), ("null", 3), ("status", 5), ("isr", 6), ("osr", 7)]));
+        0xa000 | dest << 5 | op << 3 | source
+    } else if eq_ignore_case(mnemonic, "irq") {
+        let (clear, wait) = if toks.take("clear") {
+            (true, false)
+        } else if toks.take("wait") {
+            (false, true)
+        } else {
+            if !toks.take("set") {
+                toks.take("nowait");
+            }
+            (false, false)
+        };
+        let irq = tri!(value(toks, symbols, 7)) as u16;
+        let index = if toks.take("rel") { irq | 0x10 } else { irq };
+        0xc000 | (clear as u16) << 6 | (wait as u16) << 5 | index
+    } else if eq_ignore_case(mnemonic, "set") {
+        let dest = tri!(operand(toks, &[("pins", 0), ("x", 1), ("y", 2), ("pindirs", 4)]));
+        0xe000 | dest << 5 | tri!(value(toks, symbols, 31)) as u16
+    } else if eq_ignore_case(mnemonic, "nop") {
+        // mov y, y
+        0xa042
+    } else {
+        return Err(ErrorKind::UnknownInstruction);
+    };
+
+    if toks.pos < toks.len {
+        return Err(ErrorKind::InvalidOperand);
+    }
+    toks.len = line_len;
+
+    let delay_bits = 5 - side_set.count();
+    let mut side = None;
+    let mut delay = 0;
+    while toks.pos < toks.len {
+        if toks.take("side") && side.is_none() {
+            side = Some(tri!(value(toks, symbols, (1 << side_set.bits) - 1)) as u16);
+        } else if toks.take("[") && delay == 0 {
+            delay = tri!(value(toks, symbols, (1 << delay_bits) - 1)) as u16;
+            if !toks.take("]") {
+                return Err(ErrorKind::InvalidOperand);
+            }
+        } else {
+            return Err(ErrorKind::InvalidOperand);
+        }
+    }
+
+    let field = match side {
+        Some(_) if side_set.bits == 0 => return Err(ErrorKind::InvalidOperand),
+        Some(v) if side_set.optional => 0x10 | v << delay_bits,
+        Some(v) => v << delay_bits,
+        None if side_set.bits > 0 && !side_set.optional => return Err(ErrorKind::MissingSideSet),
+        None => 0,
+    };
+    Ok(instr | (field | delay) << 8)
+}
@@ -0,0 +1,645 @@
+//! Programmable I/O blocks.
+//!
+//! Programs are assembled at compile time with [`pio_asm!`](crate::pio_asm),
+//! loaded into a block's instruction memory with [`Pio::load`], and run on
+//! one of the block's four state machines:
+//!
+//! ```ignore
+//! const SQUARE: Program = pio_asm!(
+//!     ".wrap_target",
+//!     "    set pins, 1 [31]",
+//!     "    set pins, 0 [31]",
+//!     ".wrap",
+//! );
+//!
+//! let pio = Pio::new(unsafe { Pio0::steal() });
+//! let program = pio.load(&SQUARE).unwrap();
+//! pio.pin(IoPin::bank0(25));
+//!
+//! let mut sm = pio.state_machine(0);
+//! let mut config = Config::default();
+//! config.set_pins = (25, 1);
+//! config.set_frequency(2_000).unwrap();
+//! sm.configure(&program, &config);
+//! sm.set_pindirs(25, 1, 1);
+//! sm.set_enabled(true);
+//! ```
+//!
+//! All state machines of a block share one interrupt, so tasks can wait on
+//! the FIFOs of different state machines at the same time.
+use core::{cell::Cell, pin::Pin};
+
+use crate::rp::RpReg as _;
+use crate::rp::gpio::{Function, IoPin};
+#[allow(unused_imports)]
+use crate::{InterruptList, Runtime, TaskOnly};
+#[allow(unused_imports)]
+use crate::rp::pac::{interrupt, pio, resets, RESETS};
+
+mod asm;
+pub use asm::{assemble, Error, ErrorKind, Program, SideSet, INSTRUCTION_MEMORY_LEN};
+
+pub const NUM_STATE_MACHINES: u8 = 4;
+
+/// `INTE` bits for a state machine's RX FIFO not empty.
+const INT_RXNEMPTY: u32 = 1 << 0;
+/// `INTE` bits for a state machine's TX FIFO not full.
+const INT_TXNFULL: u32 = 1 << 4;
+/// `INTE` bits for the IRQ flags 0 to 3 set by state machines.
+const INT_SM: u32 = 1 << 8;
+
+pub trait StaticInstance: Instance {
+    const ID: u8;
+
+    /// # Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    unsafe fn steal() -> Self;
+}
+
+pub trait Instance {
+    fn into_dyn(self) -> Dyn where Self: Sized + 'static {
+        Dyn { regs: self.regs(), interrupt: self.interrupt() }
+    }
+
+    fn interrupt(&self) -> Pin<&'static InterruptList>;
+
+    fn regs(&self) -> pio::Pio;
+
+    fn id(&self) -> u8 {
+        if self.regs() == rp_pac::PIO0 {
+            0
+        } else if self.regs() == rp_pac::PIO1 {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn reset(&mut self) {
+        RESETS.reset().write_value_set(reset_bit(self.id()));
+    }
+
+    fn unreset(&mut self) {
+        let flags = reset_bit(self.id());
+        RESETS.reset().write_value_clear(flags);
+        while ((!RESETS.reset_done().read().0) & flags.0) != 0 {}
+    }
+}
+
+fn reset_bit(id: u8) -> resets::regs::Peripherals {
+    let mut p = resets::regs::Peripherals::default();
+    match id {
+        0 => p.set_pio0(true),
+        1 => p.set_pio1(true),
+        _ => {
+            #[cfg(feature = "rp2350")]
+            p.set_pio2(true);
+        }
+    }
+    p
+}
+
+macro_rules! instance {
+    ($feature:literal, $name:ident, $pac_name:ident, $irq:ident, $int:ident, $id:literal) => {
+        #[cfg(feature = $feature)]
+        pub struct $name(Runtime);
+
+        #[cfg(feature = $feature)]
+        static $int: TaskOnly<InterruptList> = unsafe { TaskOnly::new_unsend(InterruptList::new()) };
+
+        #[cfg(feature = $feature)]
+        impl StaticInstance for $name {
+            const ID: u8 = $id;
+
+            /// ## Safety
+            ///
+            /// This must be called from within the runtime and the peripheral must not exist
+            /// elsewhere in the program.
+            unsafe fn steal() -> Self {
+                unsafe { $name(Runtime::steal()) }
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        impl Instance for $name {
+            fn interrupt(&self) -> Pin<&'static InterruptList> {
+                $int.get_pinned(self.0)
+            }
+
+            fn regs(&self) -> pio::Pio {
+                rp_pac::$pac_name
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        #[interrupt]
+        fn $irq() {
+            // Disable all interrupts before notifying tasks so a task can re-enable any it's still interested in
+            rp_pac::$pac_name.irqs(0).inte().write_clear(|w| w.0 = !0);
+
+            // SAFETY: This is an ISR at task priority
+            unsafe { $int.get_unchecked().notify_all() };
+        }
+    };
+}
+
+instance!("pio0", Pio0, PIO0, PIO0_IRQ_0, PIO0_INT, 0);
+instance!("pio1", Pio1, PIO1, PIO1_IRQ_0, PIO1_INT, 1);
+#[cfg(feature = "rp2350")]
+instance!("pio2", Pio2, PIO2, PIO2_IRQ_0, PIO2_INT, 2);
+
+pub struct Dyn {
+    regs: pio::Pio,
+    interrupt: Pin<&'static InterruptList>,
+}
+
+impl Instance for Dyn {
+    fn interrupt(&self) -> Pin<&'static InterruptList> {
+        self.interrupt
+    }
+
+    fn regs(&self) -> pio::Pio {
+        self.regs
+    }
+}
+
+impl<T> Instance for &mut T where T: Instance {
+    fn interrupt(&self) -> Pin<&'static InterruptList> {
+        (**self).interrupt()
+    }
+
+    fn regs(&self) -> pio::Pio {
+        (**self).regs()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LoadError {
+    /// There is no free space of the program's length in instruction memory, or at its `.origin`.
+    NoSpace,
+}
+
+/// A program loaded into instruction memory.
+///
+/// Addresses are absolute, with jumps relocated to the program's offset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct LoadedProgram {
+    pub offset: u8,
+    pub len: u8,
+    pub wrap_target: u8,
+    pub wrap: u8,
+    pub side_set: SideSet,
+}
+
+impl LoadedProgram {
+    fn mask(&self) -> u32 {
+        program_mask(self.offset, self.len)
+    }
+}
+
+fn program_mask(offset: u8, len: u8) -> u32 {
+    (((1u64 << len) - 1) << offset) as u32
+}
+
+/// Relocate an instruction of a program loaded at `offset`.
+fn relocate(instr: u16, offset: u8) -> u16 {
+    // Only JMP has an absolute address
+    if instr & 0xe000 == 0 {
+        (instr & !0x1f) | ((instr + offset as u16) & 0x1f)
+    } else {
+        instr
+    }
+}
+
+/// A PIO block, with its instruction memory and state machines.
+pub struct Pio<I: Instance> {
+    instance: I,
+    /// Bitmask of used instruction memory.
+    used: Cell<u32>,
+    /// Bitmask of state machines that were handed out.
+    claimed: Cell<u8>,
+}
+
+impl<I: Instance> Pio<I> {
+    pub fn new(mut instance: I) -> Self {
+        instance.reset();
+        instance.unreset();
+        Self { instance, used: Cell::new(0), claimed: Cell::new(0) }
+    }
+
+    fn regs(&self) -> pio::Pio {
+        self.instance.regs()
+    }
+
+    /// Load a program into free instruction memory.
+    ///
+    /// Programs without an `.origin` are placed at the highest free address,
+    /// as the Pico SDK does, which leaves low addresses for programs that need them.
+    pub fn load(&self, program: &Program) -> Result<LoadedProgram, LoadError> {
+        let len = program.len() as u8;
+        let max_offset = INSTRUCTION_MEMORY_LEN as u8 - len;
+        let fits = |offset: u8| offset <= max_offset && self.used.get() & program_mask(offset, len) == 0;
+
+        let offset = match program.origin {
+            Some(origin) => fits(origin).then_some(origin),
+            None => (0..=max_offset).rev().find(|&offset| fits(offset)),
+        };
+        let Some(offset) = offset else {
+            return Err(LoadError::NoSpace);
+        };
+
+        for (i, &instr) in program.code().iter().enumerate() {
+            let instr = relocate(instr, offset);
+            self.regs().instr_mem(offset as usize + i).write(|w| w.set_instr_mem(instr));
+        }
+
+        let loaded = LoadedProgram {
+            offset,
+            len,
+            wrap_target: offset + program.wrap_target,
+            wrap: offset + program.wrap,
+            side_set: program.side_set,
+        };
+        self.used.set(self.used.get() | loaded.mask());
+        Ok(loaded)
+    }
+
+    /// Free a program's instruction memory.
+    ///
+    /// State machines running the program must be reconfigured before another program is loaded.
+    pub fn unload(&self, program: LoadedProgram) {
+        self.used.set(self.used.get() & !program.mask());
+    }
+
+    /// Connect a pin to this block, for use by its state machines.
+    pub fn pin(&self, pin: IoPin) {
+        pin.set_function(match self.instance.id() {
+            0 => Function::F6,
+            1 => Function::F7,
+            _ => Function::F8,
+        });
+    }
+
+    /// Get a state machine of this block.
+    ///
+    /// Panics if the state machine is already in use.
+    pub fn state_machine(&self, index: u8) -> StateMachine<'_, I> {
+        assert!(index < NUM_STATE_MACHINES);
+        let claimed = self.claimed.get();
+        assert!(claimed & (1 << index) == 0, "state machine in use");
+        self.claimed.set(claimed | 1 << index);
+
+        StateMachine { pio: self, index }
+    }
+
+    /// Wait until a state machine sets IRQ flag `flag` (0 to 3), and clear it.
+    pub async fn wait_irq(&self, flag: u8) {
+        assert!(flag < 4);
+        let regs = self.regs();
+        self.instance.interrupt().until(|| {
+            if regs.irq().read().irq() & (1 << flag) != 0 {
+                regs.irq().write(|w| w.set_irq(1 << flag));
+                true
+            } else {
+                regs.irqs(0).inte().write_set(|w| w.0 = INT_SM << flag);
+                false
+            }
+        }).await
+    }
+}
+
+impl<I: Instance> Drop for Pio<I> {
+    fn drop(&mut self) {
+        defmt::debug!("Dropping PIO");
+        self.instance.reset();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ShiftDirection {
+    Left,
+    Right,
+}
+
+/// Configuration of the input or output shift register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ShiftConfig {
+    pub direction: ShiftDirection,
+    /// Push or pull automatically when `threshold` bits have been shifted.
+    pub auto: bool,
+    /// Number of bits, 1 to 32.
+    pub threshold: u8,
+}
+
+impl Default for ShiftConfig {
+    fn default() -> Self {
+        Self { direction: ShiftDirection::Right, auto: false, threshold: 32 }
+    }
+}
+
+/// Joining of the TX and RX FIFOs into a single FIFO of twice the depth.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FifoJoin {
+    Duplex,
+    Tx,
+    Rx,
+}
+
+/// State machine configuration.
+///
+/// Pin groups are `(base, count)` of consecutive GPIOs. The number of
+/// side-set pins and the wrap addresses come from the program.
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Config {
+    /// Integer part of the clock divider, where 0 means 65536.
+    pub clkdiv_int: u16,
+    /// Fractional part of the clock divider, in 1/256.
+    pub clkdiv_frac: u8,
+    pub out_pins: (u8, u8),
+    pub set_pins: (u8, u8),
+    pub sideset_base: u8,
+    pub in_base: u8,
+    /// Pin tested by `jmp pin`.
+    pub jmp_pin: u8,
+    pub out_shift: ShiftConfig,
+    pub in_shift: ShiftConfig,
+    pub fifo_join: FifoJoin,
+    /// Keep asserting the last value written by OUT or SET.
+    pub out_sticky: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clkdiv_int: 1,
+            clkdiv_frac: 0,
+            out_pins: (0, 0),
+            set_pins: (0, 0),
+            sideset_base: 0,
+            in_base: 0,
+            jmp_pin: 0,
+            out_shift: ShiftConfig::default(),
+            in_shift: ShiftConfig::default(),
+            fifo_join: FifoJoin::Duplex,
+            out_sticky: false,
+        }
+    }
+}
+
+/// Error from [`Config::set_frequency`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FrequencyError {
+    /// The frequency is above the system clock.
+    TooHigh,
+    /// The frequency is zero or needs a clock divider above 65536.
+    TooLow,
+}
+
+impl Config {
+    /// Set the clock divider to run the state machine at `hz`, rounding to the nearest 1/256.
+    pub fn set_frequency(&mut self, hz: u32) -> Result<(), FrequencyError> {
+        if hz == 0 {
+            return Err(FrequencyError::TooLow);
+        }
+        let div = ((crate::rp::CLK_SYS_HZ as u64 * 256) + hz as u64 / 2) / hz as u64;
+        if div < 256 {
+            return Err(FrequencyError::TooHigh);
+        }
+        if div > 65536 * 256 {
+            return Err(FrequencyError::TooLow);
+        }
+        self.clkdiv_int = (div >> 8) as u16;
+        self.clkdiv_frac = div as u8;
+        Ok(())
+    }
+}
+
+/// A state machine of a PIO block.
+pub struct StateMachine<'p, I: Instance> {
+    pio: &'p Pio<I>,
+    index: u8,
+}
+
+impl<I: Instance> StateMachine<'_, I> {
+    fn regs(&self) -> pio::Pio {
+        self.pio.regs()
+    }
+
+    fn sm(&self) -> pio::StateMachine {
+        self.regs().sm(self.index as usize)
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Stop the state machine and configure it to run `program` from its start.
+    ///
+    /// The FIFOs are cleared, but pin directions are left unchanged.
+    pub fn configure(&mut self, program: &LoadedProgram, config: &Config) {
+        self.set_enabled(false);
+        let sm = self.sm();
+        let side_set = program.side_set;
+
+        sm.clkdiv().write(|w| {
+            w.set_int(config.clkdiv_int);
+            w.set_frac(config.clkdiv_frac);
+        });
+        sm.execctrl().write(|w| {
+            w.set_side_en(side_set.optional);
+            w.set_side_pindir(side_set.pindirs);
+            w.set_jmp_pin(config.jmp_pin);
+            w.set_out_sticky(config.out_sticky);
+            w.set_wrap_top(program.wrap);
+            w.set_wrap_bottom(program.wrap_target);
+        });
+        sm.shiftctrl().write(|w| {
+            w.set_fjoin_tx(config.fifo_join == FifoJoin::Tx);
+            w.set_fjoin_rx(config.fifo_join == FifoJoin::Rx);
+            w.set_pull_thresh(config.out_shift.threshold & 0x1f);
+            w.set_push_thresh(config.in_shift.threshold & 0x1f);
+            w.set_out_shiftdir(config.out_shift.direction == ShiftDirection::Right);
+            w.set_in_shiftdir(config.in_shift.direction == ShiftDirection::Right);
+            w.set_autopull(config.out_shift.auto);
+            w.set_autopush(config.in_shift.auto);
+        });
+        sm.pinctrl().write(|w| {
+            w.set_sideset_count(side_set.count());
+            w.set_sideset_base(config.sideset_base);
+            w.set_set_base(config.set_pins.0);
+            w.set_set_count(config.set_pins.1);
+            w.set_out_base(config.out_pins.0);
+            w.set_out_count(config.out_pins.1);
+            w.set_in_base(config.in_base);
+        });
+
+        // Toggling a join clears both FIFOs
+        sm.shiftctrl().write_xor(|w| w.set_fjoin_rx(true));
+        sm.shiftctrl().write_xor(|w| w.set_fjoin_rx(true));
+
+        self.restart();
+        // jmp to the start
+        self.exec(program.offset as u16);
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        let mask = 1 << self.index;
+        if enabled {
+            self.regs().ctrl().write_set(|w| w.set_sm_enable(mask));
+        } else {
+            self.regs().ctrl().write_clear(|w| w.set_sm_enable(mask));
+        }
+    }
+
+    /// Clear the internal state of the state machine and restart its clock divider.
+    pub fn restart(&mut self) {
+        let mask = 1 << self.index;
+        self.regs().ctrl().write_set(|w| {
+            w.set_sm_restart(mask);
+            w.set_clkdiv_restart(mask);
+        });
+    }
+
+    /// Execute an instruction immediately.
+    ///
+    /// If the instruction stalls, the state machine stays stalled on it until it completes.
+    pub fn exec(&mut self, instr: u16) {
+        self.sm().instr().write(|w| w.set_instr(instr));
+    }
+
+    /// Current program counter.
+    pub fn addr(&self) -> u8 {
+        self.sm().addr().read().addr()
+    }
+
+    /// Set the values of `count` pins starting at `base` from the low bits of `values`.
+    ///
+    /// The state machine must be disabled. This changes its pin configuration,
+    /// so configure it afterwards.
+    pub fn set_pins(&mut self, base: u8, count: u8, values: u32) {
+        self.set_pins_with(base, count, values, 0);
+    }
+
+    /// Set the directions of `count` pins starting at `base` from the low bits of `dirs`, where 1 is output.
+    ///
+    /// The state machine must be disabled. This changes its pin configuration,
+    /// so configure it afterwards.
+    pub fn set_pindirs(&mut self, base: u8, count: u8, dirs: u32) {
+        self.set_pins_with(base, count, dirs, 4);
+    }
+
+    fn set_pins_with(&mut self, base: u8, count: u8, mut values: u32, dest: u16) {
+        let sm = self.sm();
+        let pinctrl = sm.pinctrl().read();
+        let execctrl = sm.execctrl().read();
+        // Side-set would be applied to the executed instructions too
+        sm.pinctrl().write(|w| w.set_sideset_count(0));
+        sm.execctrl().modify(|w| w.set_side_en(false));
+
+        // SET writes up to 5 pins at a time
+        let mut pin = base;
+        let end = base + count;
+        while pin < end {
+            let n = (end - pin).min(5);
+            sm.pinctrl().write(|w| {
+                w.set_set_base(pin % 32);
+                w.set_set_count(n);
+            });
+            self.exec(0xe000 | dest << 5 | (values & ((1 << n) - 1)) as u16);
+            values >>= n;
+            pin += n;
+        }
+
+        sm.pinctrl().write_value(pinctrl);
+        sm.execctrl().write_value(execctrl);
+    }
+
+    /// Address of the TX FIFO, for writing by DMA paced by [`tx_dreq`](Self::tx_dreq).
+    pub fn txf_ptr(&self) -> *mut u32 {
+        self.regs().txf(self.index as usize).as_ptr()
+    }
+
+    /// Address of the RX FIFO, for reading by DMA paced by [`rx_dreq`](Self::rx_dreq).
+    pub fn rxf_ptr(&self) -> *const u32 {
+        self.regs().rxf(self.index as usize).as_ptr() as *const u32
+    }
+
+    #[cfg(feature = "dma")]
+    pub fn tx_dreq(&self) -> crate::rp::dma::Dreq {
+        crate::rp::dma::Dreq::pio_tx(self.pio.instance.id(), self.index)
+    }
+
+    #[cfg(feature = "dma")]
+    pub fn rx_dreq(&self) -> crate::rp::dma::Dreq {
+        crate::rp::dma::Dreq::pio_rx(self.pio.instance.id(), self.index)
+    }
+
+    /// Number of words in the TX FIFO.
+    pub fn tx_level(&self) -> u8 {
+        ((self.regs().flevel().read().0 >> (self.index * 8)) & 0x0f) as u8
+    }
+
+    /// Number of words in the RX FIFO.
+    pub fn rx_level(&self) -> u8 {
+        ((self.regs().flevel().read().0 >> (self.index * 8 + 4)) & 0x0f) as u8
+    }
+
+    /// Write a word to the TX FIFO if it is not full.
+    pub fn try_push(&mut self, word: u32) -> bool {
+        try_push(self.regs(), self.index, word)
+    }
+
+    /// Read a word from the RX FIFO if it is not empty.
+    pub fn try_pull(&mut self) -> Option<u32> {
+        try_pull(self.regs(), self.index)
+    }
+
+    /// Write a word to the TX FIFO, waiting until it is not full.
+    pub async fn push(&mut self, word: u32) {
+        let (regs, index) = (self.regs(), self.index);
+        self.pio.instance.interrupt().until(|| {
+            try_push(regs, index, word) || {
+                regs.irqs(0).inte().write_set(|w| w.0 = INT_TXNFULL << index);
+                false
+            }
+        }).await
+    }
+
+    /// Read a word from the RX FIFO, waiting until it is not empty.
+    pub async fn pull(&mut self) -> u32 {
+        let (regs, index) = (self.regs(), self.index);
+        self.pio.instance.interrupt().until(|| {
+            try_pull(regs, index).or_else(|| {
+                regs.irqs(0).inte().write_set(|w| w.0 = INT_RXNEMPTY << index);
+                None
+            })
+        }).await
+    }
+}
+
+fn try_push(regs: pio::Pio, index: u8, word: u32) -> bool {
+    if regs.fstat().read().txfull() & (1 << index) == 0 {
+        regs.txf(index as usize).write_value(word);
+        true
+    } else {
+        false
+    }
+}
+
+fn try_pull(regs: pio::Pio, index: u8) -> Option<u32> {
+    if regs.fstat().read().rxempty() & (1 << index) == 0 {
+        Some(regs.rxf(index as usize).read())
+    } else {
+        None
+    }
+}
+
+impl<I: Instance> Drop for StateMachine<'_, I> {
+    fn drop(&mut self) {
+        self.set_enabled(false);
+        self.pio.claimed.set(self.pio.claimed.get() & !(1 << self.index));
+    }
+}
@@ -0,0 +1,280 @@
+//! PWM slices.
+//!
+//! Each slice has a counter and two outputs, A and B, on adjacent GPIOs. The
+//! counter counts from 0 to `top`, or up and down again in phase-correct
+//! mode, and each output is high while the counter is below its level.
+//!
+//! ```ignore
+//! let mut slice = Slice::claim(rt, 4).unwrap();
+//! let led = slice.pin(IoPin::bank0(25));
+//! let mut config = Config::default();
+//! config.set_frequency(1_000)?;
+//! slice.configure(&config);
+//! slice.set_level(led, config.top / 4);
+//! slice.set_enabled(true);
+//! ```
+//!
+//! Channel B can instead be an input that gates or clocks the counter, to
+//! measure a duty cycle or count edges.
+use core::cell::Cell;
+
+use rp_pac::pwm::vals::Divmode;
+use rp_pac::{interrupt, PWM};
+
+use crate::rp::gpio::{Function, IoPin};
+use crate::rp::RpReg as _;
+use crate::{Interrupt, Runtime, TaskOnly};
+
+cfg_select! {
+    feature = "rp2040" => {
+        pub const NUM_SLICES: usize = 8;
+    }
+    feature = "rp2350" => {
+        pub const NUM_SLICES: usize = 12;
+    }
+}
+
+static CLAIMED: TaskOnly<Cell<u16>> = TaskOnly::new(Cell::new(0));
+static NOTIFY_SLICE: TaskOnly<[Interrupt; NUM_SLICES]> = TaskOnly::new([const { Interrupt::new() }; NUM_SLICES]);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Channel {
+    A,
+    B,
+}
+
+/// What advances the counter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Mode {
+    /// Count every divided clock cycle.
+    FreeRunning,
+    /// Count divided clock cycles while the B input is high.
+    Level,
+    /// Count rising edges of the B input.
+    RisingEdge,
+    /// Count falling edges of the B input.
+    FallingEdge,
+}
+
+/// Error from [`Config::set_frequency`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FrequencyError {
+    /// The frequency needs a clock divider below 1.
+    TooHigh,
+    /// The frequency is zero or needs a clock divider of 256 or more.
+    TooLow,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Integer part of the clock divider, 1 to 255.
+    pub clkdiv_int: u8,
+    /// Fractional part of the clock divider, in 1/16.
+    pub clkdiv_frac: u8,
+    /// Counter value at which to wrap.
+    pub top: u16,
+    /// Count up and down, halving the frequency and centering the outputs' pulses.
+    pub phase_correct: bool,
+    pub invert_a: bool,
+    pub invert_b: bool,
+    pub mode: Mode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clkdiv_int: 1,
+            clkdiv_frac: 0,
+            top: 0xffff,
+            phase_correct: false,
+            invert_a: false,
+            invert_b: false,
+            mode: Mode::FreeRunning,
+        }
+    }
+}
+
+impl Config {
+    /// Set the divider for a wrap frequency in Hz, with the current `top` and `phase_correct`.
+    pub fn set_frequency(&mut self, hz: u32) -> Result<(), FrequencyError> {
+        if hz == 0 {
+            return Err(FrequencyError::TooLow);
+        }
+        let period = (self.top as u64 + 1) * if self.phase_correct { 2 } else { 1 };
+        let div = ((crate::rp::CLK_SYS_HZ as u64 * 16) + hz as u64 * period / 2) / (hz as u64 * period);
+        if div < 16 {
+            return Err(FrequencyError::TooHigh);
+        }
+        if div >= 256 * 16 {
+            return Err(FrequencyError::TooLow);
+        }
+        self.clkdiv_int = (div >> 4) as u8;
+        self.clkdiv_frac = (div & 0xf) as u8;
+        Ok(())
+    }
+}
+
+/// The slice and channel driven by a GPIO.
+pub fn slice_for_pin(pin: IoPin) -> (u8, Channel) {
+    let channel = if pin.pin & 1 == 0 { Channel::A } else { Channel::B };
+    ((pin.pin >> 1) % NUM_SLICES as u8, channel)
+}
+
+/// A claimed PWM slice.
+pub struct Slice {
+    index: u8,
+    rt: Runtime,
+}
+
+impl Slice {
+    /// Claim slice `index`, if it is not in use.
+    pub fn claim(rt: Runtime, index: u8) -> Option<Slice> {
+        assert!((index as usize) < NUM_SLICES);
+        let claimed = CLAIMED.get(rt);
+        if claimed.get() & (1 << index) != 0 {
+            return None;
+        }
+        claimed.set(claimed.get() | 1 << index);
+        Some(Slice { index, rt })
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn regs(&self) -> rp_pac::pwm::Channel {
+        PWM.ch(self.index as usize)
+    }
+
+    /// Connect a GPIO to this slice, and return the channel it's on.
+    ///
+    /// The pin is an output, or the input for channel B when counting in a `Mode` other than `FreeRunning`.
+    pub fn pin(&self, pin: IoPin) -> Channel {
+        let (slice, channel) = slice_for_pin(pin);
+        assert!(slice == self.index);
+        pin.set_function(Function::F4);
+        channel
+    }
+
+    /// Configure the slice, leaving it enabled or disabled.
+    pub fn configure(&mut self, config: &Config) {
+        assert!(config.clkdiv_int >= 1 && config.clkdiv_frac < 16);
+        let regs = self.regs();
+        regs.div().write(|w| {
+            w.set_int(config.clkdiv_int);
+            w.set_frac(config.clkdiv_frac);
+        });
+        regs.top().write(|w| w.set_top(config.top));
+        regs.csr().modify(|w| {
+            w.set_ph_correct(config.phase_correct);
+            w.set_a_inv(config.invert_a);
+            w.set_b_inv(config.invert_b);
+            w.set_divmode(match config.mode {
+                Mode::FreeRunning => Divmode::DIV,
+                Mode::Level => Divmode::LEVEL,
+                Mode::RisingEdge => Divmode::RISE,
+                Mode::FallingEdge => Divmode::FALL,
+            });
+        });
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.regs().csr().modify(|w| w.set_en(enabled));
+    }
+
+    pub fn set_top(&mut self, top: u16) {
+        self.regs().top().write(|w| w.set_top(top));
+    }
+
+    /// Set the counter value below which `channel`'s output is high.
+    ///
+    /// The new level takes effect when the counter next wraps.
+    pub fn set_level(&mut self, channel: Channel, level: u16) {
+        self.regs().cc().modify(|w| match channel {
+            Channel::A => w.set_a(level),
+            Channel::B => w.set_b(level),
+        });
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.regs().ctr().read().ctr()
+    }
+
+    pub fn set_counter(&mut self, value: u16) {
+        self.regs().ctr().write(|w| w.set_ctr(value));
+    }
+
+    /// Wait for the counter to wrap.
+    ///
+    /// Levels and `top` written just after this returns apply from the next cycle.
+    pub async fn wait_wrap(&mut self) {
+        let mask = 1u32 << self.index;
+        PWM.intr().write(|w| w.0 = mask);
+        NOTIFY_SLICE.get(self.rt)[self.index as usize].until(|| {
+            if PWM.intr().read().0 & mask != 0 {
+                PWM.intr().write(|w| w.0 = mask);
+                true
+            } else {
+                inte().write_set(|w| w.0 = mask);
+                false
+            }
+        }).await;
+    }
+}
+
+impl Drop for Slice {
+    fn drop(&mut self) {
+        let mask = 1u32 << self.index;
+        inte().write_clear(|w| w.0 = mask);
+        self.regs().csr().write(|_| { });
+        let claimed = CLAIMED.get(self.rt);
+        claimed.set(claimed.get() & !(1 << self.index));
+    }
+}
+
+cfg_select! {
+    feature = "rp2040" => {
+        fn inte() -> rp_pac::common::Reg<rp_pac::pwm::regs::Inte, rp_pac::common::RW> {
+            PWM.inte()
+        }
+
+        fn ints() -> u32 {
+            PWM.ints().read().0
+        }
+
+        #[interrupt]
+        fn PWM_IRQ_WRAP() {
+            on_wrap();
+        }
+    }
+    feature = "rp2350" => {
+        fn inte() -> rp_pac::common::Reg<rp_pac::pwm::regs::Irq0inte, rp_pac::common::RW> {
+            PWM.irq0_inte()
+        }
+
+        fn ints() -> u32 {
+            PWM.irq0_ints().read().0
+        }
+
+        #[interrupt]
+        fn PWM_IRQ_WRAP_0() {
+            on_wrap();
+        }
+    }
+}
+
+fn on_wrap() {
+    let ints = ints();
+
+    // Disable the interrupts that fired, re-enabled by the tasks still waiting.
+    // The raw flags are left for the tasks to check and clear.
+    inte().write_clear(|w| w.0 = ints);
+
+    let notify = unsafe { NOTIFY_SLICE.get_unchecked() };
+    for (i, n) in notify.iter().enumerate() {
+        if ints & (1 << i) != 0 {
+            unsafe { n.notify() };
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! RP2350 bootrom tables.
+//!
+//! Functions and data are found with a single lookup function, by code and by
+//! a mask selecting the Arm secure, Arm non-secure or RISC-V entry point.
+//! Zeptos runs in Arm secure mode.
+
+use super::RomFnTableCode;
+
+/// Looks up the entry matching a code and mask, returning 0 if there is none.
+type RomTableLookupFn = unsafe extern "C" fn(code: u32, mask: u32) -> usize;
+
+/// Pointer to the lookup function supplied by the rom, described at `5.4.2. Bootrom Function Lookup`.
+///
+/// This is for the A2 and later bootroms; A1 engineering samples are not supported.
+const ROM_TABLE_LOOKUP_PTR: *const u16 = 0x0000_0016 as _;
+
+/// Address of the version number of the ROM.
+const VERSION_NUMBER: *const u8 = 0x0000_0013 as _;
+
+/// Lookup mask for functions callable from Arm secure mode.
+const RT_FLAG_FUNC_ARM_SEC: u32 = 0x0004;
+
+/// Lookup mask for data.
+const RT_FLAG_DATA: u32 = 0x0040;
+
+/// Retrieve rom content using a code.
+fn rom_table_lookup(tag: RomFnTableCode, mask: u32) -> usize {
+    unsafe {
+        let rom_table_lookup_ptr = *ROM_TABLE_LOOKUP_PTR as usize;
+        let rom_table_lookup: RomTableLookupFn = core::mem::transmute(rom_table_lookup_ptr);
+        rom_table_lookup(u16::from_le_bytes(tag) as u32, mask)
+    }
+}
+
+macro_rules! rom_functions {
+    () => {};
+
+    (
+        $(#[$outer:meta])*
+        $c:literal fn $name:ident( $($argname:ident: $ty:ty),* ) -> $ret:ty;
+
+        $($rest:tt)*
+    ) => {
+        declare_rom_function! {
+            $(#[$outer])*
+            fn $name( $($argname: $ty),* ) -> $ret {
+                rom_table_lookup(*$c, RT_FLAG_FUNC_ARM_SEC) as *const u32
+            }
+        }
+
+        rom_functions!($($rest)*);
+    };
+
+    (
+        $(#[$outer:meta])*
+        $c:literal unsafe fn $name:ident( $($argname:ident: $ty:ty),* ) -> $ret:ty;
+
+        $($rest:tt)*
+    ) => {
+        declare_rom_function! {
+            $(#[$outer])*
+            unsafe fn $name( $($argname: $ty),* ) -> $ret {
+                rom_table_lookup(*$c, RT_FLAG_FUNC_ARM_SEC) as *const u32
+            }
+        }
+
+        rom_functions!($($rest)*);
+    };
+}
+
+rom_functions! {
+    /// Restore all QSPI pad controls to their default state, and connect the QMI peripheral to the QSPI pads.
+    b"IF" unsafe fn connect_internal_flash() -> ();
+
+    /// Initialise the QMI for serial operations (direct mode), and also initialise a basic XIP mode,
+    /// where the QMI will perform 03h serial read commands at low speed (CLKDIV=12) in response to
+    /// XIP reads.
+    ///
+    /// Then, issue a sequence to the QSPI device on chip select 0, designed to return it from
+    /// continuous read mode ("XIP mode") and/or QPI mode to a state where it will accept serial
+    /// commands.
+    b"EX" unsafe fn flash_exit_xip() -> ();
+
+    /// Erase count bytes, starting at addr (offset from start of flash).
+    ///
+    /// Optionally, pass a block erase command e.g. D8h block erase, and the size of the block erased
+    /// by this command — this function will use the larger block erase where possible, for much higher
+    /// erase speed. addr must be aligned to a 4096-byte sector, and count must be a multiple of
+    /// 4096 bytes.
+    b"RE" unsafe fn flash_range_erase(addr: u32, count: usize, block_size: u32, block_cmd: u8) -> ();
+
+    /// Program data to a range of flash addresses starting at `addr` (and
+    /// offset from the start of flash) and `count` bytes in size.
+    ///
+    /// The value `addr` must be aligned to a 256-byte boundary, and `count` must be a
+    /// multiple of 256.
+    b"RP" unsafe fn flash_range_program(addr: u32, data: *const u8, count: usize) -> ();
+
+    /// Flush the entire XIP cache, by issuing an invalidate by set/way maintenance operation to every
+    /// cache line. Also clears the IO forcing on QSPI CSn, so that the QMI can drive the flash chip
+    /// select as normal.
+    b"FC" unsafe fn flash_flush_cache() -> ();
+
+    /// Configure the QMI to generate a standard 03h serial read command, with 24 address bits,
+    /// upon each XIP access.
+    ///
+    /// This is a slow XIP configuration, but is widely supported. The debugger calls this function
+    /// after performing a flash erase/programming operation, so that the freshly-programmed code
+    /// and data is visible to the debug host.
+    b"CX" unsafe fn flash_enter_cmd_xip() -> ();
+
+    /// Restore the QMI address translation registers to their reset state, which maps each 4 MiB
+    /// window of the XIP address space directly to the same offset in flash.
+    b"RA" unsafe fn flash_reset_address_trans() -> ();
+
+    /// Reboot the chip, into BOOTSEL mode, a flash image or a RAM image depending on `flags`.
+    ///
+    /// `delay_ms` is the delay before the reboot, and `p0` and `p1` are parameters depending on
+    /// the reboot type, e.g. the activity LED pin and interface mask for BOOTSEL mode. Returns a
+    /// negative error code if the reboot is not possible, otherwise does not return if
+    /// `REBOOT2_FLAG_NO_RETURN_ON_SUCCESS` is set in `flags`.
+    b"RB" unsafe fn reboot(flags: u32, delay_ms: u32, p0: u32, p1: u32) -> i32;
+
+    /// Fill `out_buffer` with the system information words selected by `flags`, returning the
+    /// number of words written, or a negative error code.
+    b"GS" unsafe fn get_sys_info(out_buffer: *mut u32, out_buffer_word_size: u32, flags: u32) -> i32;
+}
+
+/// The version number of the rom.
+pub fn rom_version_number() -> u8 {
+    unsafe { *VERSION_NUMBER }
+}
+
+/// The 8 most significant hex digits of the Bootrom git revision.
+pub fn git_revision() -> u32 {
+    let s = rom_table_lookup(*b"GR", RT_FLAG_DATA) as *const u32;
+    unsafe { *s }
+}
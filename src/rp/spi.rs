@@ -1,5 +1,10 @@
 use core::slice;
 
+#[cfg(feature = "dma")]
+use scopeguard::ScopeGuard;
+
+#[cfg(feature = "dma")]
+use crate::rp::dma::{self, Channel, Dreq};
 use crate::rp::RpReg as _;
 #[allow(unused_imports)]
 use crate::{Interrupt, Runtime};
@@ -264,6 +269,37 @@ impl<I: Instance> Controller<I> {
         }
         transfer(self.instance.regs(), self.instance.interrupt(), src.into_iter(), dest.into_dest())
     }
+
+    /// Send `src` while receiving the same number of bytes into `dest`, with
+    /// the `tx` and `rx` DMA channels moving the data.
+    ///
+    /// If the future is dropped before completion, both transfers are aborted.
+    #[cfg(feature = "dma")]
+    pub async fn transfer_dma(&mut self, tx: &mut Channel, rx: &mut Channel, src: &[u8], dest: &mut [u8]) {
+        assert!(src.len() == dest.len());
+        let regs = self.instance.regs();
+        let (tx_dreq, rx_dreq) = if self.instance.id() == 0 {
+            (Dreq::SPI0_TX, Dreq::SPI0_RX)
+        } else {
+            (Dreq::SPI1_TX, Dreq::SPI1_RX)
+        };
+
+        // Discard anything left in the RX FIFO by an aborted transfer
+        while regs.sr().read().rne() {
+            regs.dr().read();
+        }
+        regs.dmacr().write(|w| {
+            w.set_txdmae(true);
+            w.set_rxdmae(true);
+        });
+
+        // Receive in the background while sending, the RX channel finishing last
+        let dr = regs.dr().as_ptr() as *mut u8;
+        let mut rx = scopeguard::guard(rx, |rx| rx.abort());
+        unsafe { rx.start(&dma::Config::from_peripheral::<u8>(rx_dreq), dr, dest.as_mut_ptr(), dest.len() as u32) };
+        unsafe { tx.to_peripheral(src, dr, tx_dreq) }.await;
+        ScopeGuard::into_inner(rx).wait().await;
+    }
 }
 
 impl<I: Instance> Drop for Controller<I> {
@@ -0,0 +1,465 @@
+use crate::rp::RpReg as _;
+#[allow(unused_imports)]
+use crate::{Interrupt, Runtime};
+#[allow(unused_imports)]
+use crate::rp::pac::{interrupt, resets, uart::Uart as UartRegs, RESETS};
+#[cfg(feature = "dma")]
+use crate::rp::dma::{Channel, Dreq};
+
+pub trait StaticInstance: Instance {
+    const ID: u8;
+
+    /// # Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    unsafe fn steal() -> Self;
+}
+
+pub trait Instance {
+    fn into_dyn(self) -> Dyn where Self:Sized + 'static {
+        Dyn { regs: self.regs(), interrupt: self.interrupt() }
+    }
+
+    fn interrupt(&self) -> &'static Interrupt;
+
+    fn regs(&self) -> UartRegs;
+
+    fn id(&self) -> u8 {
+        if self.regs() == rp_pac::UART0 { 0 } else { 1 }
+    }
+
+    fn reset(&mut self) {
+        RESETS.reset().write_value_set(reset_bit(self.id()));
+    }
+
+    fn unreset(&mut self) {
+        let flags = reset_bit(self.id());
+        RESETS.reset().write_value_clear(flags);
+        while ((!RESETS.reset_done().read().0) & flags.0) != 0 {}
+    }
+}
+
+fn reset_bit(id: u8) -> resets::regs::Peripherals {
+    let mut p = resets::regs::Peripherals::default();
+    if id == 0 {
+        p.set_uart0(true)
+    } else {
+        p.set_uart1(true);
+    }
+    p
+}
+
+macro_rules! instance {
+    ($feature:literal, $name:ident, $pac_name:ident, $irq:ident, $int:ident, $id:literal) => {
+        #[cfg(feature = $feature)]
+        pub struct $name(Runtime);
+
+        #[cfg(feature = $feature)]
+        static $int: crate::TaskOnly<Interrupt> = crate::TaskOnly::new(Interrupt::new());
+
+        #[cfg(feature = $feature)]
+        impl StaticInstance for $name {
+            const ID: u8 = $id;
+
+            /// ## Safety
+            ///
+            /// This must be called from within the runtime and the peripheral must not exist
+            /// elsewhere in the program.
+            unsafe fn steal() -> Self {
+                unsafe { $name(Runtime::steal()) }
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        impl Instance for $name {
+            fn interrupt(&self) -> &'static Interrupt {
+                $int.get(self.0)
+            }
+
+            fn regs(&self) -> UartRegs {
+                rp_pac::$pac_name
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        #[interrupt]
+        fn $irq() {
+            rp_pac::$pac_name.uartimsc().write(|_| { });
+            unsafe { $int.get_unchecked().notify() };
+        }
+    };
+}
+
+instance!("uart0", Uart0, UART0, UART0_IRQ, UART0_INT, 0);
+instance!("uart1", Uart1, UART1, UART1_IRQ, UART1_INT, 1);
+
+pub struct Dyn {
+    regs: UartRegs,
+    interrupt: &'static Interrupt,
+}
+
+impl Instance for Dyn {
+    fn interrupt(&self) -> &'static Interrupt {
+        self.interrupt
+    }
+
+    fn regs(&self) -> UartRegs {
+        self.regs
+    }
+}
+
+impl<T> Instance for &mut T where T: Instance {
+    fn interrupt(&self) -> &'static Interrupt {
+        (**self).interrupt()
+    }
+
+    fn regs(&self) -> UartRegs {
+        (**self).regs()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[non_exhaustive]
+pub struct Config {
+    pub baud_rate: u32,
+    /// Number of data bits, 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// Use the CTS and RTS pins for hardware flow control.
+    pub flow_control: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            baud_rate: 115_200,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: false,
+        }
+    }
+}
+
+impl Config {
+    /// Integer and fractional (in 1/64) baud rate divisor.
+    fn divisor(&self) -> (u16, u8) {
+        assert!(self.baud_rate > 0, "UART baud rate must not be 0");
+        let div = 8 * crate::rp::CLK_PERI_HZ / self.baud_rate;
+        let ibrd = div >> 7;
+        if ibrd == 0 {
+            (1, 0)
+        } else if ibrd >= 0xffff {
+            (0xffff, 0)
+        } else {
+            (ibrd as u16, (div & 0x7f).div_ceil(2) as u8)
+        }
+    }
+
+    /// The baud rate actually produced by the divisor.
+    pub fn actual_baud_rate(&self) -> u32 {
+        let (ibrd, fbrd) = self.divisor();
+        4 * crate::rp::CLK_PERI_HZ / (64 * ibrd as u32 + fbrd as u32)
+    }
+}
+
+/// Receive error, reported for the character it occurred on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// The character did not have a valid stop bit.
+    Framing,
+    Parity,
+    /// The line was held low for longer than a character.
+    Break,
+    /// The RX FIFO was full and characters were lost.
+    Overrun,
+}
+
+impl Error {
+    /// The error for the flags of a received character, most severe first.
+    fn from_flags(overrun: bool, brk: bool, parity: bool, framing: bool) -> Option<Error> {
+        if overrun {
+            Some(Error::Overrun)
+        } else if brk {
+            Some(Error::Break)
+        } else if parity {
+            Some(Error::Parity)
+        } else if framing {
+            Some(Error::Framing)
+        } else {
+            None
+        }
+    }
+}
+
+fn clear_errors(regs: UartRegs) {
+    regs.uartrsr().write(|w| {
+        w.set_oe(true);
+        w.set_be(true);
+        w.set_pe(true);
+        w.set_fe(true);
+    });
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        embedded_io_async::ErrorKind::Other
+    }
+}
+
+/// A UART with interrupt-driven async reads and writes.
+///
+/// Connect the pins with `IoPin::set_function(Function::F2)` before use.
+pub struct Uart<I: Instance> {
+    instance: I,
+    /// Error found after the characters returned by the last read.
+    pending_error: Option<Error>,
+    /// The TX FIFO was full since configuration, so it has passed its trigger level.
+    tx_filled: bool,
+}
+
+fn set_config(regs: UartRegs, config: &Config) {
+    assert!((5..=8).contains(&config.data_bits));
+    let (ibrd, fbrd) = config.divisor();
+
+    regs.uartcr().write(|w| w.set_uarten(false));
+    regs.uartibrd().write(|w| w.set_baud_divint(ibrd));
+    regs.uartfbrd().write(|w| w.set_baud_divfrac(fbrd));
+
+    // Writing LCR_H latches the divisor
+    regs.uartlcr_h().write(|w| {
+        w.set_wlen(config.data_bits - 5);
+        w.set_fen(true);
+        w.set_stp2(config.stop_bits == StopBits::Two);
+        w.set_pen(config.parity != Parity::None);
+        w.set_eps(config.parity == Parity::Even);
+    });
+
+    // Interrupt when the TX FIFO is down to 1/8 or the RX FIFO is up to 1/2 full
+    regs.uartifls().write(|w| {
+        w.set_txiflsel(0b000);
+        w.set_rxiflsel(0b010);
+    });
+
+    regs.uartcr().write(|w| {
+        w.set_uarten(true);
+        w.set_txe(true);
+        w.set_rxe(true);
+        w.set_ctsen(config.flow_control);
+        w.set_rtsen(config.flow_control);
+    });
+}
+
+impl<I: Instance> Uart<I> {
+    pub fn new(mut instance: I, config: Config) -> Self {
+        instance.reset();
+        instance.unreset();
+        set_config(instance.regs(), &config);
+        Self { instance, pending_error: None, tx_filled: false }
+    }
+
+    /// Change the configuration.
+    pub fn set_config(&mut self, config: Config) {
+        set_config(self.instance.regs(), &config);
+        self.pending_error = None;
+        self.tx_filled = false;
+    }
+
+    /// Read at least one character, waiting until one is received.
+    ///
+    /// An error is returned in place of the character it occurred on. If
+    /// characters before it were read, they are returned first, and the error
+    /// on the next call.
+    pub fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize, Error>> + 'a {
+        async fn read(regs: UartRegs, interrupt: &Interrupt, pending_error: &mut Option<Error>, buf: &mut [u8]) -> Result<usize, Error> {
+            if let Some(e) = pending_error.take() {
+                return Err(e);
+            }
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            interrupt.until(|| {
+                if regs.uartfr().read().rxfe() {
+                    // The receive timeout interrupt catches data below the FIFO level
+                    regs.uartimsc().write(|w| {
+                        w.set_rxim(true);
+                        w.set_rtim(true);
+                    });
+                    false
+                } else {
+                    true
+                }
+            }).await;
+
+            let mut n = 0;
+            while n < buf.len() && !regs.uartfr().read().rxfe() {
+                let dr = regs.uartdr().read();
+                if let Some(e) = Error::from_flags(dr.oe(), dr.be(), dr.pe(), dr.fe()) {
+                    clear_errors(regs);
+                    if n == 0 {
+                        return Err(e);
+                    }
+                    *pending_error = Some(e);
+                    break;
+                }
+
+                buf[n] = dr.data();
+                n += 1;
+            }
+            Ok(n)
+        }
+        read(self.instance.regs(), self.instance.interrupt(), &mut self.pending_error, buf)
+    }
+
+    /// Write at least one character, waiting until there is space in the TX FIFO.
+    pub fn write<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = usize> + 'a {
+        async fn write(regs: UartRegs, interrupt: &Interrupt, tx_filled: &mut bool, buf: &[u8]) -> usize {
+            if buf.is_empty() {
+                return 0;
+            }
+
+            // The TX interrupt is raised when the FIFO drains through its
+            // trigger level, which it's above when full.
+            interrupt.until(|| {
+                if regs.uartfr().read().txff() {
+                    regs.uartimsc().write(|w| w.set_txim(true));
+                    false
+                } else {
+                    true
+                }
+            }).await;
+
+            let mut n = 0;
+            while n < buf.len() && !regs.uartfr().read().txff() {
+                regs.uartdr().write(|w| w.set_data(buf[n]));
+                n += 1;
+            }
+            *tx_filled |= regs.uartfr().read().txff();
+            n
+        }
+        write(self.instance.regs(), self.instance.interrupt(), &mut self.tx_filled, buf)
+    }
+
+    /// Write all of `buf`.
+    pub async fn write_all(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let n = self.write(buf).await;
+            buf = &buf[n..];
+        }
+    }
+
+    /// Wait until all written characters have been sent.
+    ///
+    /// This awaits the TX interrupt until the FIFO drains to its trigger
+    /// level of 4 characters. There is no interrupt when transmission
+    /// completes, so it then busy-waits for those and the one being shifted
+    /// out. Until the FIFO has been filled once after configuration, the
+    /// interrupt status can't tell where the FIFO is, and this busy-waits for
+    /// all written characters.
+    pub async fn flush(&mut self) {
+        let regs = self.instance.regs();
+        if self.tx_filled {
+            // The raw interrupt status is clear while the FIFO is above its
+            // trigger level, and set once it drains through it
+            self.instance.interrupt().until(|| {
+                if regs.uartris().read().txris() {
+                    true
+                } else {
+                    regs.uartimsc().write(|w| w.set_txim(true));
+                    false
+                }
+            }).await;
+        }
+        while regs.uartfr().read().busy() {}
+    }
+
+    /// Write all of `buf` with the DMA channel `ch` feeding the TX FIFO.
+    ///
+    /// Returns when the last character is in the FIFO, see [`flush`](Self::flush).
+    /// If the future is dropped before completion, the transfer is aborted.
+    #[cfg(feature = "dma")]
+    pub async fn write_dma(&mut self, ch: &mut Channel, buf: &[u8]) {
+        let regs = self.instance.regs();
+        let dreq = if self.instance.id() == 0 { Dreq::UART0_TX } else { Dreq::UART1_TX };
+        regs.uartdmacr().modify(|w| w.set_txdmae(true));
+        unsafe { ch.to_peripheral(buf, regs.uartdr().as_ptr() as *mut u8, dreq) }.await;
+    }
+
+    /// Fill all of `buf` with the DMA channel `ch` draining the RX FIFO.
+    ///
+    /// DMA reads only the data bits, so a receive error is reported after
+    /// `buf` is filled, without the position of the character it occurred on.
+    /// If the future is dropped before completion, the transfer is aborted.
+    #[cfg(feature = "dma")]
+    pub async fn read_dma(&mut self, ch: &mut Channel, buf: &mut [u8]) -> Result<(), Error> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+
+        let regs = self.instance.regs();
+        let dreq = if self.instance.id() == 0 { Dreq::UART0_RX } else { Dreq::UART1_RX };
+        regs.uartdmacr().modify(|w| w.set_rxdmae(true));
+        unsafe { ch.from_peripheral(regs.uartdr().as_ptr() as *const u8, buf, dreq) }.await;
+
+        let rsr = regs.uartrsr().read();
+        match Error::from_flags(rsr.oe(), rsr.be(), rsr.pe(), rsr.fe()) {
+            Some(e) => {
+                clear_errors(regs);
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Hold the TX line low to send a break, until called again with `false`.
+    pub fn set_break(&mut self, enabled: bool) {
+        self.instance.regs().uartlcr_h().modify(|w| w.set_brk(enabled));
+    }
+}
+
+impl<I: Instance> Drop for Uart<I> {
+    fn drop(&mut self) {
+        defmt::debug!("Dropping UART");
+        self.instance.reset();
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<I: Instance> embedded_io_async::ErrorType for Uart<I> {
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<I: Instance> embedded_io_async::Read for Uart<I> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        Uart::read(self, buf).await
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<I: Instance> embedded_io_async::Write for Uart<I> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        Ok(Uart::write(self, buf).await)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Uart::flush(self).await;
+        Ok(())
+    }
+}
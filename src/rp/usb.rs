@@ -1,3 +1,4 @@
+use core::cell::Cell;
 use core::future::Future;
 use core::task::{Context, Poll};
 use core::{mem, slice};
@@ -5,7 +6,7 @@ use core::ops::Deref;
 use core::sync::atomic::{compiler_fence, Ordering};
 
 use crate::{executor::Interrupt, usb::Event, Runtime, TaskOnly};
-use defmt::{assert, debug, debug_assert};
+use defmt::{assert, debug, debug_assert, panic};
 use rp_pac::common::{Reg, RW};
 use rp_pac::usb::regs::{EpAbort, EpAbortDone};
 use rp_pac::usb_dpram::regs::{EpBufferControl, EpControl};
@@ -20,6 +21,15 @@ const EP_COUNT: usize = 16;
 const EP_MEMORY_SIZE: usize = 4096;
 const EP_MEMORY: *mut u8 = pac::USB_DPRAM.as_ptr() as *mut u8;
 
+/// DPRAM after the fixed 64-byte buffers, allocated to endpoints when enabled.
+const POOL_START: u16 = 0x100 + 0x40 * 2 * EP_COUNT as u16;
+
+/// End of the pool for double-buffering bulk and interrupt endpoints.
+///
+/// The rest is kept for isochronous endpoints, enough for one with the largest
+/// packet size, so enabling bulk endpoints first can't leave no space for them.
+const POOL_BULK_END: u16 = EP_MEMORY_SIZE as u16 - crate::usb::MAX_ISO_PACKET_SIZE.next_multiple_of(64);
+
 #[derive(Copy, Clone)]
 pub struct UsbShared {
     rt: Runtime,
@@ -95,6 +105,8 @@ impl Usb {
         self.usb().inte().write_set(|w| {
             w.set_bus_reset(true);
             w.set_setup_req(true);
+            w.set_dev_suspend(true);
+            w.set_dev_resume_from_host(true);
         });
     }
 
@@ -103,6 +115,8 @@ impl Usb {
         self.usb().inte().write_clear(|w| {
             w.set_bus_reset(true);
             w.set_setup_req(true);
+            w.set_dev_suspend(true);
+            w.set_dev_resume_from_host(true);
         });
     }
 
@@ -120,6 +134,13 @@ impl Usb {
             for i in 1..EP_COUNT {
                 USB_DPRAM.ep_in_control(i - 1).write(|w| { w.set_enable(false) });
                 USB_DPRAM.ep_out_control(i - 1).write(|w| { w.set_enable(false) });
+                USB_DPRAM.ep_in_buffer_control(i).write_value(EpBufferControl(0));
+                USB_DPRAM.ep_out_buffer_control(i).write_value(EpBufferControl(0));
+            }
+            // The endpoints are enabled again with new buffers when the host configures the device
+            for (buffer, ring) in POOL_BUFFERS.get(self.rt).iter().zip(RINGS.get(self.rt)) {
+                buffer.set((0, 0));
+                ring.set(Ring::DISABLED);
             }
             self.usb().sie_status().write(|w| w.set_bus_reset(true));
             Poll::Ready(Event::Reset)
@@ -140,6 +161,12 @@ impl Usb {
             self.usb().sie_status().write(|w| w.set_setup_rec(true));
 
             Poll::Ready(Event::Setup(setup))
+        } else if status.suspended() {
+            self.usb().sie_status().write(|w| w.set_suspended(true));
+            Poll::Ready(Event::Suspend)
+        } else if status.resume() {
+            self.usb().sie_status().write(|w| w.set_resume(true));
+            Poll::Ready(Event::Resume)
         } else {
             Poll::Pending
         }
@@ -174,6 +201,10 @@ impl UsbShared {
         });
     }
 
+    pub(crate) fn signal_resume(&self) {
+        self.usb().sie_ctrl().write_set(|w| w.set_resume(true));
+    }
+
     pub fn set_address(&self, addr: u8) {
         self.usb().addr_endp().write(|w| {
             w.set_address(addr);
@@ -200,87 +231,449 @@ impl UsbShared {
 
     /// Buffer address relative to start of DPRAM
     ///
-    /// This is our arbitrary choice of fixed DPRAM allocation.
+    /// This is our arbitrary choice of fixed DPRAM allocation, unless the
+    /// endpoint was allocated a buffer from the pool when enabled.
     fn buffer_address(&self, ep: u8) -> u16 {
         if ep & 0xF == 0 {
             0x100
+        } else if let (start, len) = POOL_BUFFERS.get(self.rt)[ep_slot(ep)].get() && len != 0 {
+            start
         } else {
-            0x100 + 0x40 * ((ep & 0xF) << 1 | (ep >> 7)) as u16
+            0x100 + 0x40 * ep_slot(ep) as u16
         }
     }
 
-    pub fn enable_ep(&self, ep: u8) {
-        self.ep_buffer_control(ep).write(|w| {
-            w.set_pid(0, true);
+    /// Allocate a DPRAM buffer from the pool below `end`, returning `None` if there is no space.
+    fn alloc_buffer(&self, ep: u8, len: u16, end: u16) -> Option<u16> {
+        let buffers = POOL_BUFFERS.get(self.rt);
+        let len = len.next_multiple_of(64);
+
+        let overlaps = |start: u16| {
+            buffers.iter().any(|b| {
+                let (b_start, b_len) = b.get();
+                b_len != 0 && start < b_start + b_len && b_start < start + len
+            })
+        };
+
+        // First fit, trying the start of the region and the end of each existing buffer
+        let start = core::iter::once(POOL_START)
+            .chain(buffers.iter().filter(|b| b.get().1 != 0).map(|b| b.get().0 + b.get().1))
+            .filter(|&start| start + len <= end && !overlaps(start))
+            .min()?;
+
+        buffers[ep_slot(ep)].set((start, len));
+        Some(start)
+    }
+
+    pub fn frame_number(&self) -> u16 {
+        self.usb().sof_rd().read().count()
+    }
+
+    /// Wait for the next start-of-frame packet, returning its frame number.
+    pub async fn wait_sof(&self) -> u16 {
+        let start = self.frame_number();
+        NOTIFY_SOF.get(self.rt)
+            .until(|| {
+                let frame = self.frame_number();
+                if frame != start {
+                    Some(frame)
+                } else {
+                    // Disabled by the ISR after each SOF so it doesn't interrupt when not needed
+                    self.usb().inte().write_set(|w| w.set_dev_sof(true));
+                    None
+                }
+            })
+            .await
+    }
+
+    /// Enable a bulk or interrupt endpoint with packets of up to `max_packet_size` bytes.
+    ///
+    /// The endpoint is double-buffered if there is space for a pair of buffers
+    /// in the DPRAM pool, and otherwise falls back to its fixed single buffer.
+    pub fn enable_ep(&self, ep: u8, max_packet_size: u16) {
+        assert!(max_packet_size <= 64);
+        let double_buffered = self.alloc_buffer(ep, 128, POOL_BULK_END).is_some();
+
+        self.ring(ep).set(Ring {
+            depth: if double_buffered { 2 } else { 1 },
+            max_packet_size: max_packet_size as u8,
+            ..Ring::DISABLED
         });
 
+        self.ep_buffer_control(ep).write_value(EpBufferControl(0));
+
         self.ep_ctrl(ep).write(|w| {
             w.set_buffer_address(self.buffer_address(ep));
-            w.set_double_buffered(false);
+            w.set_double_buffered(double_buffered);
             w.set_endpoint_type(EpControlEndpointType::BULK);
             w.set_interrupt_per_buff(true);
             w.set_enable(true);
         });
     }
 
+    /// Enable an isochronous endpoint with packets of up to `max_packet_size` bytes.
+    ///
+    /// The buffer comes from the DPRAM pool, or is the endpoint's fixed buffer
+    /// if the packets fit in it. The pool always has space for one endpoint of
+    /// any size, but several large ones at once may not fit, which panics.
+    pub fn enable_iso_ep(&self, ep: u8, max_packet_size: u16) {
+        let buffer_address = match self.alloc_buffer(ep, max_packet_size, EP_MEMORY_SIZE as u16) {
+            Some(address) => address,
+            None if max_packet_size <= 64 => self.buffer_address(ep),
+            None => panic!("out of USB DPRAM for isochronous endpoint {:02x}", ep),
+        };
+
+        self.ep_buffer_control(ep).write(|w| {
+            w.set_pid(0, false);
+        });
+
+        self.ep_ctrl(ep).write(|w| {
+            w.set_buffer_address(buffer_address);
+            w.set_double_buffered(false);
+            w.set_endpoint_type(EpControlEndpointType::ISOCHRONOUS);
+            w.set_interrupt_per_buff(true);
+            w.set_enable(true);
+        });
+    }
+
     pub fn disable_ep(&self, ep: u8) {
         self.ep_ctrl(ep).write(|w| { w.set_enable(false) });
+        POOL_BUFFERS.get(self.rt)[ep_slot(ep)].set((0, 0));
+        self.ring(ep).set(Ring::DISABLED);
     }
 
-    fn cancel_on_drop(&self, ep: u8) -> ScopeGuard<(), impl FnOnce(()) + '_> {
-        scopeguard::guard((), move |()| {
-            debug!("canceling transfer on ep {:02x}", ep);
+    pub fn set_ep_halt(&self, ep: u8, halt: bool) {
+        let ring = self.ring(ep);
+        self.ep_buffer_control(ep).modify(|w| {
+            w.set_stall(halt);
+            if !halt && ring.get().depth == 0 {
+                // Reset the data toggle so the next packet is DATA0. The PID is
+                // toggled before each packet, unless one is already queued.
+                w.set_pid(0, !w.available(0));
+            } else if !halt {
+                // Renumber the queued packets starting from DATA0
+                let mut r = ring.get();
+                let mut pid = false;
+                for i in 0..r.count {
+                    let half = r.half(i) as usize;
+                    if w.available(half) {
+                        w.set_pid(half, pid);
+                        pid = !pid;
+                    }
+                }
+                r.pid = pid;
+                ring.set(r);
+            }
+        });
+    }
+
+    /// Abort the transfer on `ep`, calling `f` to update the buffer control
+    /// register while the endpoint is stopped.
+    fn abort(&self, ep: u8, f: impl FnOnce(&mut EpBufferControl)) {
+        debug!("canceling transfer on ep {:02x}", ep);
 
-            let mask = 1u32 << (((ep >> 7) ^ 1) | (ep << 1));
-            self.usb().ep_abort().write_value_set(EpAbort(mask));
-            while self.usb().ep_abort_done().read().0 & mask == 0 {}
+        let mask = 1u32 << (((ep >> 7) ^ 1) | (ep << 1));
+        self.usb().ep_abort().write_value_set(EpAbort(mask));
+        while self.usb().ep_abort_done().read().0 & mask == 0 {}
 
-            compiler_fence(Ordering::SeqCst);
+        compiler_fence(Ordering::SeqCst);
 
-            let buffer_control = self.ep_buffer_control(ep);
-            buffer_control.modify(|w| {
+        self.ep_buffer_control(ep).modify(f);
+
+        self.usb().ep_abort().write_value_clear(EpAbort(mask));
+        self.usb().ep_abort_done().write_value_clear(EpAbortDone(mask));
+    }
+
+    fn cancel_on_drop(&self, ep: u8) -> ScopeGuard<(), impl FnOnce(()) + '_> {
+        scopeguard::guard((), move |()| {
+            self.abort(ep, |w| {
                 if w.available(0) {
                     w.set_pid(0, !w.pid(0)); // undo toggle if not sent
                 }
                 w.set_available(0, false);
                 w.set_full(0, false);
             });
+        })
+    }
+
+    fn ring(&self, ep: u8) -> &Cell<Ring> {
+        &RINGS.get(self.rt)[ep_slot(ep)]
+    }
+
+    fn ring_buffer(&self, ep: u8, half: u8) -> *mut u8 {
+        unsafe { EP_MEMORY.add(self.buffer_address(ep) as usize + 64 * half as usize) }
+    }
+
+    /// Hand the next free buffer of `ep` to the hardware.
+    fn arm(&self, ep: u8, len: usize) {
+        let mut r = self.ring(ep).get();
+        debug_assert!(r.count < r.depth);
+        let half = r.half(r.count) as usize;
+        let buffer_control = self.ep_buffer_control(ep);
+
+        let mut v = EpBufferControl(0);
+        v.set_pid(half, r.pid);
+        v.set_length(half, len as _);
+        v.set_full(half, ep & EP_DIR_MASK == EP_IN);
+        if half == 0 {
+            v.set_stall(buffer_control.read().stall());
+        }
+
+        // Writing to start the transfer gives hardware control of the buffer
+        compiler_fence(Ordering::Release);
+
+        // Write only this buffer's half of the register, because the hardware
+        // may be updating the other half as it completes a packet.
+        let half_ptr = unsafe { (buffer_control.as_ptr() as *mut u16).add(half) };
+        unsafe { half_ptr.write_volatile((v.0 >> (16 * half)) as u16) };
+        cortex_m::asm::delay(12);
+        v.set_available(half, true);
+        unsafe { half_ptr.write_volatile((v.0 >> (16 * half)) as u16) };
+
+        debug!("armed {:02x} buffer {}: {} bytes DATA{}", ep, half, len, r.pid as u8);
 
-            self.usb().ep_abort().write_value_clear(EpAbort(mask));
-            self.usb().ep_abort_done().write_value_clear(EpAbortDone(mask));
+        r.pid = !r.pid;
+        r.count += 1;
+        self.ring(ep).set(r);
+    }
+
+    /// Length of the oldest queued packet of `ep` if the hardware has completed it.
+    fn completed(&self, ep: u8) -> Option<usize> {
+        let r = self.ring(ep).get();
+        if r.count == 0 {
+            return None;
+        }
+        let c = self.ep_buffer_control(ep).read();
+        let half = r.next as usize;
+        (!c.available(half)).then_some(c.length(half) as usize)
+    }
+
+    /// Retire the oldest queued packet of `ep`.
+    fn retire(&self, ep: u8) {
+        let mut r = self.ring(ep).get();
+        r.next = r.half(1);
+        r.count -= 1;
+        self.ring(ep).set(r);
+    }
+
+    /// Wait for a free buffer on IN endpoint `ep`, returning a pointer to it in DPRAM.
+    ///
+    /// The buffer is not queued until [`submit_packet`](Self::submit_packet) is called.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled bulk or interrupt IN endpoint, used only by the caller.
+    pub async unsafe fn packet_buffer(&self, ep: u8) -> *mut u8 {
+        assert!(ep & EP_DIR_MASK == EP_IN);
+
+        let buf = NOTIFY_EP_IN.get(self.rt)[(ep & 0xF) as usize]
+            .until(|| {
+                while self.completed(ep).is_some() {
+                    self.retire(ep);
+                }
+                let r = self.ring(ep).get();
+                (r.count < r.depth).then(|| self.ring_buffer(ep, r.half(r.count)))
+            })
+            .await;
+
+        // The hardware is done reading the buffer
+        compiler_fence(Ordering::Acquire);
+
+        buf
+    }
+
+    /// Queue the buffer returned by [`packet_buffer`](Self::packet_buffer) to send `len` bytes.
+    ///
+    /// # Safety
+    ///
+    /// [`packet_buffer`](Self::packet_buffer) must have returned a buffer for
+    /// `ep` that has not been submitted yet, and the pointer to it must not be
+    /// used afterwards.
+    pub unsafe fn submit_packet(&self, ep: u8, len: usize) {
+        assert!(len <= self.ring(ep).get().max_packet_size as usize);
+        self.arm(ep, len);
+    }
+
+    /// Wait until all packets queued on IN endpoint `ep` have been sent.
+    pub async fn flush(&self, ep: u8) {
+        NOTIFY_EP_IN.get(self.rt)[(ep & 0xF) as usize]
+            .until(|| {
+                while self.completed(ep).is_some() {
+                    self.retire(ep);
+                }
+                self.ring(ep).get().count == 0
+            })
+            .await;
+    }
+
+    /// Queue all free buffers of OUT endpoint `ep` to receive.
+    fn fill_out(&self, ep: u8) {
+        while self.ring(ep).get().count < self.ring(ep).get().depth {
+            self.arm(ep, self.ring(ep).get().max_packet_size as usize);
+        }
+    }
+
+    /// Wait for a packet on OUT endpoint `ep`, returning a pointer to it in DPRAM and its length.
+    ///
+    /// The packet remains valid, and later packets are queued behind it, until
+    /// [`release_packet`](Self::release_packet) is called.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled bulk or interrupt OUT endpoint, used only by
+    /// the caller, with no packet received and not yet released.
+    pub async unsafe fn receive_packet(&self, ep: u8) -> (*const u8, usize) {
+        assert!(ep & EP_DIR_MASK == EP_OUT);
+
+        self.fill_out(ep);
+
+        let len = NOTIFY_EP_OUT.get(self.rt)[(ep & 0xF) as usize]
+            .until(|| self.completed(ep))
+            .await;
+
+        // The hardware is done writing the buffer
+        compiler_fence(Ordering::Acquire);
+
+        debug!("completed OUT to {:02x}: {} bytes", ep, len);
+
+        (self.ring_buffer(ep, self.ring(ep).get().next), len)
+    }
+
+    /// Return the packet from [`receive_packet`](Self::receive_packet) to the hardware.
+    ///
+    /// # Safety
+    ///
+    /// A packet must have been received on `ep`, and the pointer to it must
+    /// not be used afterwards.
+    pub unsafe fn release_packet(&self, ep: u8) {
+        self.retire(ep);
+        self.fill_out(ep);
+    }
+
+    /// Abort packets queued on IN endpoint `ep` that have not been sent.
+    fn cancel_ring_on_drop(&self, ep: u8) -> ScopeGuard<(), impl FnOnce(()) + '_> {
+        scopeguard::guard((), move |()| {
+            let ring = self.ring(ep);
+            self.abort(ep, |w| {
+                let mut r = ring.get();
+                // The hardware continues with the oldest unsent buffer, so the
+                // ring restarts there with that buffer's PID.
+                for i in 0..r.count {
+                    let half = r.half(i);
+                    if w.available(half as usize) {
+                        r.next = half;
+                        r.pid = w.pid(half as usize);
+                        break;
+                    } else if i + 1 == r.count {
+                        r.next = r.half(r.count);
+                    }
+                }
+                r.count = 0;
+                for half in 0..2 {
+                    w.set_available(half, false);
+                    w.set_full(half, false);
+                }
+                ring.set(r);
+            });
         })
     }
 
+    /// Send `len` bytes from `ptr`, keeping both buffers of a double-buffered endpoint queued.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled bulk or interrupt IN endpoint, used only by
+    /// the caller, and `ptr` must be valid for reads of `len` bytes.
     pub async unsafe fn transfer_in(&self, ep: u8, ptr: *const u8, len: usize, zlp: bool) {
         assert!(ep & EP_DIR_MASK == EP_IN);
 
+        let slice = unsafe { slice::from_raw_parts(ptr, len) };
+        let mps = self.ring(ep).get().max_packet_size as usize;
+
+        let guard = self.cancel_ring_on_drop(ep);
+
+        for pkt in slice.chunks(mps).chain((len == 0 || (zlp && len.is_multiple_of(mps))).then_some(&[] as &[u8])) {
+            let buf = unsafe { self.packet_buffer(ep).await };
+            unsafe { buf.copy_from_nonoverlapping(pkt.as_ptr(), pkt.len()) }
+            unsafe { self.submit_packet(ep, pkt.len()) };
+        }
+
+        self.flush(ep).await;
+
+        debug!("completed IN to {:02x}: {} bytes", ep, len);
+
+        mem::forget(guard);
+    }
+
+    /// Receive up to `len` bytes to `ptr`, ending at a short packet.
+    ///
+    /// Buffers stay queued between calls, so a packet that arrives after this
+    /// returns, or after it is canceled, is received by the next call.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled bulk or interrupt OUT endpoint, used only by
+    /// the caller, and `ptr` must be valid for writes of `len` bytes.
+    pub async unsafe fn transfer_out(&self, ep: u8, ptr: *mut u8, len: usize) -> usize {
+        assert!(ep & EP_DIR_MASK == EP_OUT);
+
+        let mut total_len = 0;
+        let mut slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
+        let mps = self.ring(ep).get().max_packet_size as usize;
+
+        loop {
+            let (buf, pkt_len) = unsafe { self.receive_packet(ep).await };
+
+            debug_assert!(pkt_len <= slice.len());
+
+            unsafe { slice.as_mut_ptr().copy_from_nonoverlapping(buf, pkt_len) }
+            unsafe { self.release_packet(ep) };
+            total_len += pkt_len;
+            slice = &mut slice[pkt_len..];
+
+            if pkt_len < mps || slice.is_empty() {
+                break
+            }
+        }
+
+        debug!("end transfer OUT to {:02x}: {} bytes", ep, total_len);
+
+        total_len
+    }
+
+    async unsafe fn ep0_transfer_in(&self, ptr: *const u8, len: usize, zlp: bool) {
+        let ep = 0x80;
+
         let slice = unsafe { slice::from_raw_parts(ptr, len) };
         let buf = unsafe { EP_MEMORY.add(self.buffer_address(ep) as usize) };
         let buffer_control = self.ep_buffer_control(ep);
-        let mut pid = buffer_control.read().pid(0);
 
         debug_assert!(buffer_control.read().available(0) == false);
 
         let guard = self.cancel_on_drop(ep);
 
-        for pkt in slice.chunks(64).chain((len == 0 || (zlp && len % 64 == 0)).then_some(&[] as &[u8]).into_iter()) {
+        for pkt in slice.chunks(64).chain((len == 0 || (zlp && len.is_multiple_of(64))).then_some(&[] as &[u8])) {
             // Copy packet to DPRAM
             unsafe { buf.copy_from_nonoverlapping(pkt.as_ptr(), pkt.len()) }
 
             // Writing to start the transfer gives hardware control of the buffer
             compiler_fence(Ordering::Release);
 
-            pid = !pid;
+            // Read the PID and STALL for each packet because the host may clear or set a halt
+            let prev = buffer_control.read();
+            let (pid, stall) = (!prev.pid(0), prev.stall());
             buffer_control.write(|w| {
                 w.set_pid(0, pid);
                 w.set_length(0, pkt.len() as _);
                 w.set_full(0, true);
+                w.set_stall(stall);
             });
             cortex_m::asm::delay(12);
             buffer_control.write(|w| {
                 w.set_pid(0, pid);
                 w.set_length(0, pkt.len() as _);
                 w.set_full(0, true);
+                w.set_stall(stall);
                 w.set_available(0, true);
             });
 
@@ -299,65 +692,95 @@ impl UsbShared {
         mem::forget(guard);
     }
 
-    pub async unsafe fn transfer_out(&self, ep: u8, ptr: *mut u8, len: usize) -> usize {
-        assert!(ep & EP_DIR_MASK == EP_OUT);
+    /// Send one packet on an isochronous endpoint in the next frame the host polls it.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled isochronous IN endpoint with no other transfer
+    /// in progress, and `ptr` must be valid for reads of `len` bytes.
+    pub async unsafe fn iso_transfer_in(&self, ep: u8, ptr: *const u8, len: usize, max_packet_size: u16) {
+        assert!(ep & EP_DIR_MASK == EP_IN);
+        debug_assert!(len <= max_packet_size as usize);
 
-        let mut total_len = 0;
-        let mut slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
         let buf = unsafe { EP_MEMORY.add(self.buffer_address(ep) as usize) };
         let buffer_control = self.ep_buffer_control(ep);
-        let mut pid = buffer_control.read().pid(0);
 
-        debug_assert!(buffer_control.read().available(0) == false);
+        debug_assert!(!buffer_control.read().available(0));
 
         let guard = self.cancel_on_drop(ep);
 
-        loop {
-            // Writing to start the transfer gives hardware control of the buffer
-            compiler_fence(Ordering::Release);
+        unsafe { buf.copy_from_nonoverlapping(ptr, len) }
 
-            pid = !pid;
-            buffer_control.write(|w| {
-                w.set_pid(0, pid);
-                w.set_length(0, 64);
-            });
-            cortex_m::asm::delay(12);
-            buffer_control.write(|w| {
-                w.set_pid(0, pid);
-                w.set_length(0, 64);
-                w.set_available(0, true);
-            });
+        // Writing to start the transfer gives hardware control of the buffer
+        compiler_fence(Ordering::Release);
 
-            debug!("start OUT to {:02x}: DATA{}, {} bytes remaining", ep, pid as u8, slice.len());
+        // Full-speed isochronous packets always use DATA0
+        buffer_control.write(|w| {
+            w.set_length(0, len as _);
+            w.set_full(0, true);
+        });
+        cortex_m::asm::delay(12);
+        buffer_control.write(|w| {
+            w.set_length(0, len as _);
+            w.set_full(0, true);
+            w.set_available(0, true);
+        });
 
-            let pkt_len = NOTIFY_EP_OUT.get(self.rt)[(ep & 0xF) as usize]
-                .until(|| {
-                    let c = buffer_control.read();
-                    (!c.available(0)).then_some(c.length(0) as usize)
-                })
-                .await;
+        NOTIFY_EP_IN.get(self.rt)[(ep & 0xF) as usize]
+            .until(|| !buffer_control.read().available(0))
+            .await;
 
-            // The hardware is done writing the buffer
-            compiler_fence(Ordering::Acquire);
+        // The hardware is done reading the buffer
+        compiler_fence(Ordering::Acquire);
 
-            debug!("completed OUT to {:02x}, {} bytes", ep, pkt_len);
+        mem::forget(guard);
+    }
 
-            debug_assert!(pkt_len <= slice.len());
+    /// Receive one packet on an isochronous endpoint.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled isochronous OUT endpoint with no other transfer
+    /// in progress, and `ptr` must be valid for writes of `max_packet_size` bytes.
+    pub async unsafe fn iso_transfer_out(&self, ep: u8, ptr: *mut u8, max_packet_size: u16) -> usize {
+        let len = max_packet_size as usize;
+        assert!(ep & EP_DIR_MASK == EP_OUT);
 
-            unsafe { slice.as_mut_ptr().copy_from_nonoverlapping(buf, pkt_len) }
-            total_len += pkt_len;
-            slice = &mut slice[pkt_len..];
+        let buf = unsafe { EP_MEMORY.add(self.buffer_address(ep) as usize) };
+        let buffer_control = self.ep_buffer_control(ep);
 
-            if pkt_len < 64 || slice.is_empty() {
-                break
-            }
-        }
+        debug_assert!(!buffer_control.read().available(0));
 
-        debug!("end transfer OUT to {:02x}: {} bytes", ep, total_len);
+        let guard = self.cancel_on_drop(ep);
+
+        // Writing to start the transfer gives hardware control of the buffer
+        compiler_fence(Ordering::Release);
+
+        buffer_control.write(|w| {
+            w.set_length(0, len as _);
+        });
+        cortex_m::asm::delay(12);
+        buffer_control.write(|w| {
+            w.set_length(0, len as _);
+            w.set_available(0, true);
+        });
+
+        let pkt_len = NOTIFY_EP_OUT.get(self.rt)[(ep & 0xF) as usize]
+            .until(|| {
+                let c = buffer_control.read();
+                (!c.available(0)).then_some(c.length(0) as usize)
+            })
+            .await;
+
+        // The hardware is done writing the buffer
+        compiler_fence(Ordering::Acquire);
+
+        debug_assert!(pkt_len <= len);
+        unsafe { ptr.copy_from_nonoverlapping(buf, pkt_len) }
 
         mem::forget(guard);
 
-        total_len
+        pkt_len
     }
 
     pub async unsafe fn out_packet(&self, ep: u8) -> (*mut u8, usize) {
@@ -406,7 +829,7 @@ pub struct Endpoint0 {
 impl Endpoint0 {
     pub fn ep0_transfer_in<'b>(&'b mut self, data: &'b [u8], is_full: bool) -> impl Future<Output = ()> + 'b {
         unsafe {
-            self.usb.transfer_in(0x80, data.as_ptr(), data.len(), !is_full)
+            self.usb.ep0_transfer_in(data.as_ptr(), data.len(), !is_full)
         }
     }
 
@@ -422,7 +845,48 @@ impl Endpoint0 {
     }
 }
 
+fn ep_slot(ep: u8) -> usize {
+    ((ep & 0xF) << 1 | (ep >> 7)) as usize
+}
+
+/// DPRAM offset and length of the pool buffer for each endpoint, or length 0 if none
+static POOL_BUFFERS: TaskOnly<[Cell<(u16, u16)>; 2 * EP_COUNT]> = TaskOnly::new([const { Cell::new((0, 0)) }; 2 * EP_COUNT]);
+
+/// Packets queued in the buffers of a bulk or interrupt endpoint.
+///
+/// In double-buffered mode, the hardware alternates between buffer 0 and
+/// buffer 1, so packets complete in the order they were queued.
+#[derive(Clone, Copy)]
+struct Ring {
+    /// Number of buffers: 2 if double-buffered, 1 if single-buffered, 0 if not a ring endpoint
+    depth: u8,
+
+    /// Buffer of the oldest queued packet
+    next: u8,
+
+    /// Number of queued packets, including received packets not yet released
+    count: u8,
+
+    /// PID of the next packet to queue
+    pid: bool,
+
+    /// Packet size of the endpoint; a shorter packet ends an OUT transfer
+    max_packet_size: u8,
+}
+
+impl Ring {
+    const DISABLED: Ring = Ring { depth: 0, next: 0, count: 0, pid: false, max_packet_size: 64 };
+
+    /// Buffer `i` places after the oldest queued packet
+    fn half(&self, i: u8) -> u8 {
+        (self.next + i) % self.depth
+    }
+}
+
+static RINGS: TaskOnly<[Cell<Ring>; 2 * EP_COUNT]> = TaskOnly::new([const { Cell::new(Ring::DISABLED) }; 2 * EP_COUNT]);
+
 static NOTIFY_BUS_EVENT: TaskOnly<Interrupt> = TaskOnly::new(Interrupt::new());
+static NOTIFY_SOF: TaskOnly<Interrupt> = TaskOnly::new(Interrupt::new());
 
 static NOTIFY_EP_IN: TaskOnly<[Interrupt; 8]> = TaskOnly::new([const { Interrupt::new() }; 8]);
 static NOTIFY_EP_OUT: TaskOnly<[Interrupt; 8]> = TaskOnly::new([const { Interrupt::new() }; 8]);
@@ -436,10 +900,17 @@ fn USBCTRL_IRQ() {
 
     defmt::trace!("usb irq: flags {:08x} buf_status {:08x}", flags.0, buff_status.0);
 
-    if flags.bus_reset() || flags.setup_req() {
+    if flags.bus_reset() || flags.setup_req() || flags.dev_suspend() || flags.dev_resume_from_host() {
         unsafe { NOTIFY_BUS_EVENT.get_unchecked().notify() };
     }
 
+    if flags.dev_sof() {
+        // Reading SOF_RD clears the interrupt
+        let _ = USB.sof_rd().read();
+        USB.inte().write_clear(|w| w.set_dev_sof(true));
+        unsafe { NOTIFY_SOF.get_unchecked().notify() };
+    }
+
     for ep in 0..EP_COUNT {
         if buff_status.ep_out(ep) {
             if ep > 0 { defmt::trace!("wake ep{} OUT", ep) };
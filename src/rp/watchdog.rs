@@ -0,0 +1,204 @@
+//! Watchdog timer and reset reason.
+//!
+//! Once started, the watchdog resets the chip unless fed within the timeout.
+//!
+//! ```ignore
+//! defmt::info!("reset by {}", watchdog::reset_reason());
+//! hw.watchdog.start(1_000_000);
+//! hw.watchdog.feed_every(rt, 500_000).await;
+//! ```
+use crate::Runtime;
+use crate::rp::pac::WATCHDOG;
+
+/// Number of scratch registers free for the application.
+///
+/// The remaining scratch registers are used by the boot ROM.
+pub const NUM_SCRATCH: usize = 4;
+
+cfg_select! {
+    feature = "rp2040" => {
+        /// The longest timeout that can be set.
+        pub const MAX_TIMEOUT_US: u32 = 0xff_ffff / 2;
+        /// All peripherals in `PSM.WDSEL`.
+        const WDSEL_BITS: u32 = 0x1_ffff;
+    }
+    feature = "rp2350" => {
+        /// The longest timeout that can be set.
+        pub const MAX_TIMEOUT_US: u32 = 0xff_ffff;
+        /// All peripherals in `PSM.WDSEL`.
+        const WDSEL_BITS: u32 = 0x1ff_ffff;
+    }
+}
+
+/// Cause of the last reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ResetReason {
+    /// Power was applied, including after a brownout on RP2040.
+    PowerOn,
+    /// The supply voltage dropped below the brownout threshold (RP2350 only).
+    Brownout,
+    /// The watchdog timed out.
+    Watchdog,
+    /// The watchdog was triggered by [`Watchdog::reset`].
+    Software,
+    /// The RUN pin was pulled low.
+    External,
+    /// The debugger requested a reset.
+    Debug,
+    Unknown,
+}
+
+/// Get the cause of the last reset.
+pub fn reset_reason() -> ResetReason {
+    let reason = WATCHDOG.reason().read();
+    if reason.force() {
+        return ResetReason::Software;
+    } else if reason.timer() {
+        return ResetReason::Watchdog;
+    }
+
+    cfg_select! {
+        feature = "rp2040" => {
+            let chip_reset = rp_pac::VREG_AND_CHIP_RESET.chip_reset().read();
+            if chip_reset.had_por() {
+                ResetReason::PowerOn
+            } else if chip_reset.had_run() {
+                ResetReason::External
+            } else if chip_reset.had_psm_restart() {
+                ResetReason::Debug
+            } else {
+                ResetReason::Unknown
+            }
+        }
+        feature = "rp2350" => {
+            let chip_reset = rp_pac::POWMAN.chip_reset().read();
+            if chip_reset.had_bor() {
+                ResetReason::Brownout
+            } else if chip_reset.had_por() {
+                ResetReason::PowerOn
+            } else if chip_reset.had_run_low() {
+                ResetReason::External
+            } else if chip_reset.had_dp_reset_req() {
+                ResetReason::Debug
+            } else {
+                ResetReason::Unknown
+            }
+        }
+    }
+}
+
+/// The watchdog timer, passed to the main task in `Hardware`.
+pub struct Watchdog {
+    _rt: Runtime,
+    load: u32,
+}
+
+impl Watchdog {
+    /// ## Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    pub unsafe fn steal() -> Self {
+        unsafe { Watchdog { _rt: Runtime::steal(), load: 0 } }
+    }
+
+    /// Start the watchdog, resetting the chip if not fed for `timeout_us` microseconds.
+    ///
+    /// This also pauses the watchdog while the debugger halts either core; see
+    /// [`set_pause_on_debug`](Self::set_pause_on_debug).
+    pub fn start(&mut self, timeout_us: u32) {
+        assert!(timeout_us > 0 && timeout_us <= MAX_TIMEOUT_US);
+
+        // The watchdog counts the 1MHz tick, which the timer may already use
+        cfg_select! {
+            feature = "rp2040" => {
+                WATCHDOG.tick().write(|w| {
+                    w.set_cycles((super::CLK_REF_HZ / 1_000_000) as u16);
+                    w.set_enable(true);
+                });
+                // The counter decrements twice per tick (RP2040-E1)
+                self.load = timeout_us * 2;
+            }
+            feature = "rp2350" => {
+                rp_pac::TICKS.watchdog_cycles().write(|w| w.set_watchdog_cycles((super::CLK_REF_HZ / 1_000_000) as u16));
+                rp_pac::TICKS.watchdog_ctrl().write(|w| w.set_enable(true));
+                self.load = timeout_us;
+            }
+        }
+
+        // Reset everything except the oscillators, as the SDK does
+        rp_pac::PSM.wdsel().write(|w| {
+            w.0 = WDSEL_BITS;
+            w.set_rosc(false);
+            w.set_xosc(false);
+        });
+
+        WATCHDOG.ctrl().modify(|w| w.set_enable(false));
+        self.feed();
+        WATCHDOG.ctrl().modify(|w| {
+            w.set_pause_dbg0(true);
+            w.set_pause_dbg1(true);
+            w.set_pause_jtag(true);
+            w.set_enable(true);
+        });
+    }
+
+    /// Stop the watchdog.
+    pub fn stop(&mut self) {
+        WATCHDOG.ctrl().modify(|w| w.set_enable(false));
+    }
+
+    /// Restart the countdown.
+    pub fn feed(&mut self) {
+        WATCHDOG.load().write(|w| w.set_load(self.load));
+    }
+
+    /// Feed the watchdog every `period_us` microseconds, forever.
+    ///
+    /// Run this in its own task, so the chip resets if the executor stops
+    /// running tasks.
+    #[cfg(feature = "time")]
+    pub async fn feed_every(&mut self, rt: Runtime, period_us: u32) -> ! {
+        loop {
+            self.feed();
+            rt.delay_us(period_us).await;
+        }
+    }
+
+    /// Select whether the countdown pauses while the debugger halts a core.
+    pub fn set_pause_on_debug(&mut self, pause: bool) {
+        WATCHDOG.ctrl().modify(|w| {
+            w.set_pause_dbg0(pause);
+            w.set_pause_dbg1(pause);
+            w.set_pause_jtag(pause);
+        });
+    }
+
+    /// Reset the chip now, with [`ResetReason::Software`].
+    pub fn reset(&mut self) -> ! {
+        WATCHDOG.ctrl().modify(|w| w.set_trigger(true));
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    /// Read a scratch register, which keeps its value through watchdog and software resets.
+    pub fn scratch(&self, index: usize) -> u32 {
+        scratch_reg(index).read()
+    }
+
+    /// Write a scratch register.
+    pub fn set_scratch(&mut self, index: usize, value: u32) {
+        scratch_reg(index).write_value(value);
+    }
+}
+
+fn scratch_reg(index: usize) -> rp_pac::common::Reg<u32, rp_pac::common::RW> {
+    match index {
+        0 => WATCHDOG.scratch0(),
+        1 => WATCHDOG.scratch1(),
+        2 => WATCHDOG.scratch2(),
+        3 => WATCHDOG.scratch3(),
+        _ => panic!("scratch register out of range"),
+    }
+}
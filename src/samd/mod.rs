@@ -15,6 +15,9 @@ pub mod sercom;
 pub mod clock;
 pub mod calibration;
 
+#[cfg(feature="watchdog")]
+pub mod watchdog;
+
 #[cfg(feature="usb")]
 pub(crate) mod usb;
 
@@ -31,6 +34,9 @@ pub(crate) fn init() {
     ))]
     crate::samd::clock::configure_clocks();
 
+    #[cfg(feature="watchdog")]
+    crate::samd::watchdog::init();
+
     let pm = unsafe { crate::samd::pac::PM::steal() };
     let mut gclk = unsafe { crate::samd::pac::GCLK::steal() };
 
@@ -6,6 +6,11 @@ pub use i2c::{ I2cController, I2cError };
 mod spi;
 pub use spi::{ SpiController, SpiConfig };
 
+#[cfg(any(feature = "sercom0", feature = "sercom1", feature = "sercom2", feature = "sercom3", feature = "sercom4", feature = "sercom5"))]
+mod usart;
+#[cfg(any(feature = "sercom0", feature = "sercom1", feature = "sercom2", feature = "sercom3", feature = "sercom4", feature = "sercom5"))]
+pub use usart::{ UsartController, UsartConfig, UsartError, UsartParity };
+
 pub trait StaticSercom: Sercom {
     const ID: u8;
 
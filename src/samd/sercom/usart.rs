@@ -0,0 +1,248 @@
+use crate::{Interrupt, samd::gpio::IoPin, samd::pac::sercom0::USART};
+
+use super::Sercom;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum UsartParity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone)]
+pub struct UsartConfig {
+    /// TX pad selection: 0 for PAD[0], 1 for PAD[2], 2 for PAD[0] with RTS on PAD[2] and CTS on PAD[3].
+    /// 3 is reserved.
+    pub txpo: u8,
+    /// RX pad selection, the number of the pad RX is on.
+    pub rxpo: u8,
+    pub baud_rate: u32,
+    /// Number of data bits, 5 to 8.
+    pub data_bits: u8,
+    pub parity: UsartParity,
+    pub two_stop_bits: bool,
+    /// RS-485 transmit enable pin, driven high while transmitting.
+    pub te_pin: Option<IoPin>,
+}
+
+impl UsartConfig {
+    /// Integer and fractional (in 1/8) baud rate divisor, for 16x oversampling.
+    fn divisor(&self) -> (u16, u8) {
+        assert!(self.baud_rate > 0, "USART baud rate must not be 0");
+        let div = (crate::CLOCK_HZ + self.baud_rate) / (2 * self.baud_rate);
+        let baud = (div >> 3).clamp(1, 0x1fff);
+        (baud as u16, (div & 0x7) as u8)
+    }
+
+    /// The baud rate actually produced by the divisor.
+    pub fn actual_baud_rate(&self) -> u32 {
+        let (baud, fp) = self.divisor();
+        crate::CLOCK_HZ / (2 * (8 * baud as u32 + fp as u32))
+    }
+}
+
+impl Default for UsartConfig {
+    fn default() -> Self {
+        UsartConfig {
+            txpo: 0,
+            rxpo: 1,
+            baud_rate: 115_200,
+            data_bits: 8,
+            parity: UsartParity::None,
+            two_stop_bits: false,
+            te_pin: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, defmt::Format)]
+pub enum UsartError {
+    /// The character did not have a valid stop bit.
+    Framing,
+    Parity,
+    /// The receive buffer was full and characters were lost.
+    Overflow,
+}
+
+/// USART controller, with the pins set to the SERCOM alternate function (C or D).
+pub struct UsartController<S: Sercom> {
+    sercom: S,
+    te_pin: Option<IoPin>,
+    /// A character was written since the last flush, so TXC will be set.
+    sending: bool,
+}
+
+impl<S: Sercom> UsartController<S> {
+    pub fn new(sercom: S, config: UsartConfig) -> Self {
+        if let Some(te) = config.te_pin {
+            te.outclr();
+            te.dirset();
+        }
+        init(sercom.regs().usart(), config);
+        Self { sercom, te_pin: config.te_pin, sending: false }
+    }
+
+    /// Wait for a character and return it.
+    pub fn read_byte(&mut self) -> impl Future<Output = Result<u8, UsartError>> {
+        read_byte(self.sercom.regs().usart(), self.sercom.interrupt())
+    }
+
+    /// Fill `buf` with received characters.
+    ///
+    /// Returns the first error, after which the rest of `buf` is not filled.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<(), UsartError> {
+        for b in buf {
+            *b = self.read_byte().await?;
+        }
+        Ok(())
+    }
+
+    /// Send a character, waiting until it is buffered.
+    pub async fn write_byte(&mut self, data: u8) {
+        self.write(&[data]).await
+    }
+
+    /// Send all of `buf`, waiting until the last character is buffered.
+    ///
+    /// The RS-485 transmit enable pin stays high until [`flush`](Self::flush).
+    pub fn write<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = ()> + 'a {
+        write(self.sercom.regs().usart(), self.sercom.interrupt(), self.te_pin, &mut self.sending, buf)
+    }
+
+    /// Wait until the last written character is sent, then release the
+    /// RS-485 transmit enable pin.
+    pub async fn flush(&mut self) {
+        if self.sending {
+            let regs = self.sercom.regs().usart();
+            // TXC is cleared by writing DATA, so is only set here once the
+            // final character has left the shift register
+            self.sercom.interrupt().until(|| {
+                if regs.intflag.read().txc().bit_is_set() {
+                    true
+                } else {
+                    regs.intenset.write(|w| w.txc().set_bit());
+                    false
+                }
+            }).await;
+            self.sending = false;
+        }
+        if let Some(te) = self.te_pin {
+            te.outclr();
+        }
+    }
+}
+
+impl<S: Sercom> Drop for UsartController<S> {
+    fn drop(&mut self) {
+        deinit(self.sercom.regs().usart());
+        if let Some(te) = self.te_pin {
+            te.outclr();
+        }
+    }
+}
+
+fn init(regs: &USART, config: UsartConfig) {
+    assert!((5..=8).contains(&config.data_bits));
+    assert!(config.txpo <= 2, "USART txpo 3 is reserved");
+    assert!(config.rxpo <= 3);
+    let (baud, fp) = config.divisor();
+
+    regs.ctrla.write(|w| w.swrst().set_bit());
+    while regs.syncbusy.read().swrst().bit_is_set() {}
+
+    regs.ctrlb.write(|w| {
+        w.chsize().variant(config.data_bits & 0x7); // 8 bits is 0
+        w.sbmode().variant(config.two_stop_bits);
+        w.pmode().variant(config.parity == UsartParity::Odd);
+        w.txen().set_bit();
+        w.rxen().set_bit()
+    });
+    regs.baud_frac_mode().write(|w| {
+        w.baud().variant(baud);
+        w.fp().variant(fp)
+    });
+    regs.ctrla.write(|w| {
+        w.mode().usart_int_clk();
+        w.sampr().variant(1); // 16x oversampling with fractional baud
+        w.txpo().variant(config.txpo);
+        w.rxpo().variant(config.rxpo);
+        w.form().variant(if config.parity == UsartParity::None { 0 } else { 1 });
+        w.dord().set_bit(); // LSB first
+        w.enable().set_bit()
+    });
+    while regs.syncbusy.read().enable().bit_is_set() {}
+}
+
+fn deinit(regs: &USART) {
+    regs.ctrla.write(|w| w.swrst().set_bit());
+}
+
+async fn read_byte(regs: &USART, interrupt: &Interrupt) -> Result<u8, UsartError> {
+    interrupt.until(|| {
+        if regs.intflag.read().rxc().bit_is_set() {
+            true
+        } else {
+            regs.intenset.write(|w| w.rxc().set_bit());
+            false
+        }
+    }).await;
+
+    // The status is for the character at the head of the buffer, so it must be
+    // read first, and the data read even on error to remove it.
+    let status = regs.status.read();
+    let data = regs.data.read().data().bits() as u8;
+
+    let error = if status.bufovf().bit_is_set() {
+        Some(UsartError::Overflow)
+    } else if status.ferr().bit_is_set() {
+        Some(UsartError::Framing)
+    } else if status.perr().bit_is_set() {
+        Some(UsartError::Parity)
+    } else {
+        None
+    };
+
+    match error {
+        Some(e) => {
+            regs.status.write(|w| {
+                w.bufovf().set_bit();
+                w.ferr().set_bit();
+                w.perr().set_bit()
+            });
+            Err(e)
+        }
+        None => Ok(data),
+    }
+}
+
+async fn write(regs: &USART, interrupt: &Interrupt, te_pin: Option<IoPin>, sending: &mut bool, buf: &[u8]) {
+    if buf.is_empty() {
+        return;
+    }
+
+    if let Some(te) = te_pin {
+        te.outset();
+    }
+
+    // Release the bus if the write is cancelled
+    let te = scopeguard::guard(te_pin, |te_pin| {
+        if let Some(te) = te_pin {
+            te.outclr();
+        }
+    });
+
+    for &b in buf {
+        interrupt.until(|| {
+            if regs.intflag.read().dre().bit_is_set() {
+                true
+            } else {
+                regs.intenset.write(|w| w.dre().set_bit());
+                false
+            }
+        }).await;
+        regs.data.write(|w| w.data().variant(b as u16));
+        *sending = true;
+    }
+
+    scopeguard::ScopeGuard::into_inner(te);
+}
@@ -1,3 +1,4 @@
+use core::cell::Cell;
 use core::mem;
 use core::ops::Deref;
 use core::sync::atomic::{compiler_fence, AtomicPtr, AtomicU16, AtomicU32, AtomicU8, Ordering};
@@ -87,6 +88,20 @@ impl PacketSize {
             PacketSize::Size1023 => 1023,
         }
     }
+
+    /// Smallest packet size that fits `len` bytes.
+    pub const fn for_len(len: u16) -> Self {
+        match len {
+            0..=8 => PacketSize::Size8,
+            9..=16 => PacketSize::Size16,
+            17..=32 => PacketSize::Size32,
+            33..=64 => PacketSize::Size64,
+            65..=128 => PacketSize::Size128,
+            129..=256 => PacketSize::Size256,
+            257..=512 => PacketSize::Size512,
+            _ => PacketSize::Size1023,
+        }
+    }
 }
 
 /// Per-endpoint data descriptor accessed by the hardware but stored in RAM.
@@ -105,7 +120,7 @@ pub struct EndpointBank {
 
 impl EndpointBank {
     pub fn prepare_out(&self, packet_size: PacketSize, ptr: *mut u8, len: usize) {
-        debug_assert!(len % (1 << (packet_size as u8 + 3)) == 0);
+        debug_assert!(len.is_multiple_of(1 << (packet_size as u8 + 3)));
         debug_assert!(len < (1 << 14));
         self.addr.store(ptr, Ordering::Relaxed);
         self.pcksize.store(
@@ -205,13 +220,23 @@ impl Usb {
             w.detach().clear_bit()
         });
 
-        self.usb().intenset.write(|w| w.eorst().set_bit());
+        self.usb().intenset.write(|w| {
+            w.eorst().set_bit();
+            w.suspend().set_bit();
+            w.wakeup().set_bit();
+            w.eorsm().set_bit()
+        });
     }
 
     pub fn detach(&mut self) {
         self.usb().ctrlb.write(|w| w.detach().set_bit());
 
-        self.usb().intenclr.write(|w| w.eorst().set_bit());
+        self.usb().intenclr.write(|w| {
+            w.eorst().set_bit();
+            w.suspend().set_bit();
+            w.wakeup().set_bit();
+            w.eorsm().set_bit()
+        });
 
         self.ep(0).epintenclr.write(|w| w.rxstp().set_bit());
     }
@@ -237,6 +262,15 @@ impl Usb {
             ep_reg.epintflag.write(|w| w.rxstp().set_bit());
 
             Poll::Ready(Event::Setup(setup))
+        } else if flags.suspend().bit_is_set() {
+            self.usb().intflag.write(|w| w.suspend().set_bit());
+            Poll::Ready(Event::Suspend)
+        } else if flags.wakeup().bit_is_set() || flags.eorsm().bit_is_set() {
+            self.usb().intflag.write(|w| {
+                w.wakeup().set_bit();
+                w.eorsm().set_bit()
+            });
+            Poll::Ready(Event::Resume)
         } else {
             Poll::Pending
         }
@@ -264,6 +298,10 @@ impl UsbShared {
         &EP_RAM[(ep & 0b111) as usize][(ep >> 7) as usize]
     }
 
+    fn packet_size(&self, ep: u8) -> &Cell<PacketSize> {
+        &PACKET_SIZE.get(self.rt)[(ep & 0b111) as usize][(ep >> 7) as usize]
+    }
+
     pub fn configure_ep0(&self) {
         let ptr = &raw mut CONTROL_BUF as *mut u8;
         self.ep_ram(0).prepare_out(PacketSize::Size64, ptr, 64);
@@ -284,6 +322,31 @@ impl UsbShared {
         })
     }
 
+    pub fn frame_number(&self) -> u16 {
+        self.usb().fnum.read().fnum().bits()
+    }
+
+    /// Wait for the next start-of-frame packet, returning its frame number.
+    pub async fn wait_sof(&self) -> u16 {
+        let start = self.frame_number();
+        NOTIFY_SOF.get(self.rt)
+            .until(|| {
+                let frame = self.frame_number();
+                if frame != start {
+                    Some(frame)
+                } else {
+                    // Disabled by the ISR after each SOF so it doesn't interrupt when not needed
+                    self.usb().intenset.write(|w| w.sof().set_bit());
+                    None
+                }
+            })
+            .await
+    }
+
+    pub(crate) fn signal_resume(&self) {
+        self.usb().ctrlb.modify(|_, w| w.uprsm().set_bit());
+    }
+
     pub fn set_address(&self, addr: u8) {
         self.usb().dadd.write(|w| {
             w.adden().set_bit();
@@ -291,7 +354,15 @@ impl UsbShared {
         });
     }
 
-    pub fn enable_ep(&self, ep: u8) {
+    /// Enable a bulk or interrupt endpoint with packets of up to `max_packet_size` bytes.
+    pub fn enable_ep(&self, ep: u8, max_packet_size: u16) {
+        let packet_size = PacketSize::for_len(max_packet_size);
+        assert!(
+            packet_size.value() == max_packet_size as usize,
+            "SAMD endpoints need a max packet size of 8, 16, 32, or 64"
+        );
+        self.packet_size(ep).set(packet_size);
+        self.set_ep_halt(ep, false);
         if ep & EP_DIR_MASK == EP_IN {
             // IN
             self.ep(ep).epcfg.modify(|_, w| w.eptype1().variant(3));
@@ -301,6 +372,19 @@ impl UsbShared {
         }
     }
 
+    pub fn enable_iso_ep(&self, ep: u8, max_packet_size: u16) {
+        // PCKSIZE.SIZE is set from `max_packet_size` for each transfer
+        let _ = max_packet_size;
+        self.set_ep_halt(ep, false);
+        if ep & EP_DIR_MASK == EP_IN {
+            // IN
+            self.ep(ep).epcfg.modify(|_, w| w.eptype1().variant(2));
+        } else {
+            // OUT
+            self.ep(ep).epcfg.modify(|_, w| w.eptype0().variant(2));
+        }
+    }
+
     pub fn disable_ep(&self, ep: u8) {
         if ep & EP_DIR_MASK == EP_IN {
             // IN
@@ -311,11 +395,33 @@ impl UsbShared {
         }
     }
 
+    pub fn set_ep_halt(&self, ep: u8, halt: bool) {
+        let ep_reg = self.ep(ep);
+        let is_in = ep & EP_DIR_MASK == EP_IN;
+        if halt {
+            ep_reg.epstatusset.write(|w| {
+                if is_in { w.stallrq1().set_bit() } else { w.stallrq0().set_bit() }
+            });
+        } else {
+            // Clearing the halt also resets the data toggle to DATA0
+            ep_reg.epstatusclr.write(|w| {
+                if is_in {
+                    w.stallrq1().set_bit();
+                    w.dtglin().set_bit()
+                } else {
+                    w.stallrq0().set_bit();
+                    w.dtglout().set_bit()
+                }
+            });
+        }
+    }
+
     pub async unsafe fn transfer_in(&self, ep: u8, ptr: *const u8, mut len: usize, zlp: bool) {
         assert!(ep & EP_DIR_MASK == EP_IN);
 
         let ep_reg = self.ep(ep);
         let ep_ram = self.ep_ram(ep);
+        let packet_size = self.packet_size(ep).get();
 
         ep_reg.epintflag.write(|w| {
             w.trcpt1().set_bit();
@@ -332,7 +438,7 @@ impl UsbShared {
         }
 
         loop {
-            ep_ram.prepare_in(PacketSize::Size64, ptr.cast_mut(), len);
+            ep_ram.prepare_in(packet_size, ptr.cast_mut(), len);
 
             // Writing to start the transfer gives hardware control of the buffer
             compiler_fence(Ordering::SeqCst);
@@ -347,7 +453,7 @@ impl UsbShared {
             // Reading trcpt1 means the hardware is done reading the buffer
             compiler_fence(Ordering::SeqCst);
 
-            if zlp && len > 0 && len % 64 == 0 {
+            if zlp && len > 0 && len.is_multiple_of(packet_size.value()) {
                 // Send a zero-length packet. The PCKSIZE.AUTO_ZLP bit could do this in hardware,
                 // but might be buggy -- observed AUTO_ZLP on one endpoint causing zero-length
                 // packets to be sent on a different endpoint that did not set AUTO_ZLP.
@@ -365,7 +471,7 @@ impl UsbShared {
         let ep_reg = self.ep(ep);
         let ep_ram = self.ep_ram(ep);
 
-        ep_ram.prepare_out(PacketSize::Size64, ptr, len);
+        ep_ram.prepare_out(self.packet_size(ep).get(), ptr, len);
 
         // Writing to start the transfer gives hardware control of the buffer
         compiler_fence(Ordering::Release);
@@ -395,6 +501,96 @@ impl UsbShared {
 
         ep_ram.out_len()
     }
+
+    /// Send one packet on an isochronous endpoint in the next frame the host polls it.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled isochronous IN endpoint with no other transfer
+    /// in progress. The hardware reads the packet from `ptr`, which must be in
+    /// RAM, 4-byte aligned, and valid for reads of `len` bytes until the
+    /// future completes or is dropped.
+    pub async unsafe fn iso_transfer_in(&self, ep: u8, ptr: *const u8, len: usize, max_packet_size: u16) {
+        assert!(ep & EP_DIR_MASK == EP_IN);
+
+        let ep_reg = self.ep(ep);
+        let ep_ram = self.ep_ram(ep);
+
+        ep_reg.epintflag.write(|w| {
+            w.trcpt1().set_bit();
+            w.trfail1().set_bit()
+        });
+
+        scopeguard::defer! {
+            ep_reg.epstatusclr.write(|w| {
+                w.bk1rdy().set_bit()
+            });
+            ep_reg.epintenclr.write(|w| {
+                w.trcpt1().set_bit()
+            });
+        }
+
+        ep_ram.prepare_in(PacketSize::for_len(max_packet_size), ptr.cast_mut(), len);
+
+        // Writing to start the transfer gives hardware control of the buffer
+        compiler_fence(Ordering::SeqCst);
+
+        ep_reg.epstatusset.write(|w| w.bk1rdy().set_bit());
+        ep_reg.epintenset.write(|w| w.trcpt1().set_bit());
+
+        NOTIFY_EP_IN.get(self.rt)[(ep & 0b111) as usize]
+            .until(|| ep_reg.epintflag.read().trcpt1().bit_is_set())
+            .await;
+
+        // Reading trcpt1 means the hardware is done reading the buffer
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    /// Receive one packet on an isochronous endpoint.
+    ///
+    /// # Safety
+    ///
+    /// `ep` must be an enabled isochronous OUT endpoint with no other transfer
+    /// in progress. The hardware writes the packet to `ptr`, which must be
+    /// 4-byte aligned and valid for writes of `max_packet_size` bytes until
+    /// the future completes or is dropped.
+    pub async unsafe fn iso_transfer_out(&self, ep: u8, ptr: *mut u8, max_packet_size: u16) -> usize {
+        assert!(ep & EP_DIR_MASK == EP_OUT);
+
+        let ep_reg = self.ep(ep);
+        let ep_ram = self.ep_ram(ep);
+
+        // MULTI_PACKET_SIZE of 0 receives a single packet
+        ep_ram.prepare_out(PacketSize::for_len(max_packet_size), ptr, 0);
+
+        // Writing to start the transfer gives hardware control of the buffer
+        compiler_fence(Ordering::Release);
+
+        ep_reg.epintflag.write(|w| {
+            w.trcpt0().set_bit();
+            w.trfail0().set_bit()
+        });
+        ep_reg.epstatusclr.write(|w| w.bk0rdy().set_bit());
+        ep_reg.epintenset.write(|w| w.trcpt0().set_bit());
+
+        scopeguard::defer! {
+            ep_reg.epstatusset.write(|w| {
+                w.bk0rdy().set_bit()
+            });
+            ep_reg.epintenclr.write(|w| {
+                w.trcpt0().set_bit()
+            });
+        }
+
+        NOTIFY_EP_OUT.get(self.rt)[(ep & 0b111) as usize]
+            .until(|| ep_reg.epintflag.read().trcpt0().bit_is_set())
+            .await;
+
+        // Reading trcpt0 means the hardware is done writing the buffer
+        compiler_fence(Ordering::Acquire);
+
+        ep_ram.out_len()
+    }
 }
 
 pub struct Endpoint0 {
@@ -442,6 +638,10 @@ impl Endpoint0 {
 }
 
 static NOTIFY_BUS_EVENT: TaskOnly<Interrupt> = TaskOnly::new(Interrupt::new());
+static NOTIFY_SOF: TaskOnly<Interrupt> = TaskOnly::new(Interrupt::new());
+
+/// Packet size of each bulk or interrupt endpoint bank, set when enabled
+static PACKET_SIZE: TaskOnly<[[Cell<PacketSize>; 2]; 8]> = TaskOnly::new([const { [const { Cell::new(PacketSize::Size64) }; 2] }; 8]);
 static NOTIFY_EP_IN: TaskOnly<[Interrupt; 8]> = TaskOnly::new([const { Interrupt::new() }; 8]);
 static NOTIFY_EP_OUT: TaskOnly<[Interrupt; 8]> = TaskOnly::new([const { Interrupt::new() }; 8]);
 
@@ -450,10 +650,21 @@ fn USB() {
     let usb = unsafe { usb_regs() };
 
     let flags = usb.intflag.read();
-    if flags.eorst().bit_is_set() || ep_regs(usb, 0).epintflag.read().rxstp().bit_is_set() {
+    if flags.eorst().bit_is_set()
+        || flags.suspend().bit_is_set()
+        || flags.wakeup().bit_is_set()
+        || flags.eorsm().bit_is_set()
+        || ep_regs(usb, 0).epintflag.read().rxstp().bit_is_set()
+    {
         unsafe { NOTIFY_BUS_EVENT.get_unchecked().notify() };
     }
 
+    if flags.sof().bit_is_set() {
+        usb.intflag.write(|w| w.sof().set_bit());
+        usb.intenclr.write(|w| w.sof().set_bit());
+        unsafe { NOTIFY_SOF.get_unchecked().notify() };
+    }
+
     let summary = usb.epintsmry.read().bits();
 
     for ep in 0..8 {
@@ -0,0 +1,166 @@
+//! Watchdog timer and reset reason.
+//!
+//! The watchdog runs from the 32kHz ultra low power oscillator, divided to
+//! 1024Hz by generic clock generator 2. Once started, it resets the chip
+//! unless fed within the timeout.
+//!
+//! Generator 2 is reserved for the watchdog: [`Watchdog::start`] reconfigures
+//! it, so it must not be used for other peripherals.
+
+use core::mem::MaybeUninit;
+
+use crate::Runtime;
+use crate::samd::pac::{GCLK, PM, WDT};
+use crate::samd::pac::gclk::clkctrl::{GENSELECT_A, IDSELECT_A};
+use crate::samd::pac::gclk::genctrl::SRCSELECT_A;
+
+/// Number of scratch words kept through resets.
+pub const NUM_SCRATCH: usize = 4;
+
+/// The longest timeout that can be set, 16384 cycles of the 1024Hz clock.
+pub const MAX_TIMEOUT_US: u32 = 16_000_000;
+
+/// Words in RAM that is not initialized on boot, so keep their value through
+/// resets other than power-on.
+#[unsafe(link_section = ".uninit.zeptos_scratch")]
+static mut SCRATCH: MaybeUninit<[u32; NUM_SCRATCH]> = MaybeUninit::uninit();
+
+/// Cause of the last reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ResetReason {
+    PowerOn,
+    /// The core or I/O supply dropped below the brownout threshold.
+    Brownout,
+    /// The watchdog timed out.
+    Watchdog,
+    /// The processor requested a reset, as by [`Watchdog::reset`].
+    Software,
+    /// The RESET pin was pulled low.
+    External,
+    Unknown,
+}
+
+/// Get the cause of the last reset.
+pub fn reset_reason() -> ResetReason {
+    let rcause = unsafe { PM::steal() }.rcause.read();
+    if rcause.por().bit_is_set() {
+        ResetReason::PowerOn
+    } else if rcause.bod12().bit_is_set() || rcause.bod33().bit_is_set() {
+        ResetReason::Brownout
+    } else if rcause.wdt().bit_is_set() {
+        ResetReason::Watchdog
+    } else if rcause.syst().bit_is_set() {
+        ResetReason::Software
+    } else if rcause.ext().bit_is_set() {
+        ResetReason::External
+    } else {
+        ResetReason::Unknown
+    }
+}
+
+/// Clear the scratch words if RAM may not have been retained.
+pub(crate) fn init() {
+    if matches!(reset_reason(), ResetReason::PowerOn | ResetReason::Brownout) {
+        unsafe { (&raw mut SCRATCH).cast::<[u32; NUM_SCRATCH]>().write([0; NUM_SCRATCH]) };
+    }
+}
+
+/// The watchdog timer, passed to the main task in `Hardware`.
+pub struct Watchdog {
+    _rt: Runtime,
+}
+
+impl Watchdog {
+    /// ## Safety
+    ///
+    /// This must be called from within the runtime and the peripheral must not exist
+    /// elsewhere in the program.
+    pub unsafe fn steal() -> Self {
+        unsafe { Watchdog { _rt: Runtime::steal() } }
+    }
+
+    fn regs(&self) -> WDT {
+        unsafe { WDT::steal() }
+    }
+
+    /// Start the watchdog, resetting the chip if not fed for `timeout_us` microseconds.
+    ///
+    /// The timeout is rounded up to a power of two cycles of the 1024Hz clock.
+    /// The watchdog always pauses while the debugger halts the core.
+    ///
+    /// This configures generic clock generator 2, which is reserved for the watchdog.
+    pub fn start(&mut self, timeout_us: u32) {
+        assert!(timeout_us > 0 && timeout_us <= MAX_TIMEOUT_US);
+
+        // Generator 2 only has a 5-bit divider, so use its power of two mode: 32768 / 2^(4+1)
+        let gclk = &mut unsafe { GCLK::steal() };
+        gclk.gendiv.write(|w| unsafe {
+            w.id().bits(u8::from(GENSELECT_A::GCLK2));
+            w.div().bits(4)
+        });
+        while gclk.status.read().syncbusy().bit_is_set() {}
+        gclk.genctrl.write(|w| unsafe {
+            w.id().bits(u8::from(GENSELECT_A::GCLK2));
+            w.src().bits(u8::from(SRCSELECT_A::OSCULP32K));
+            w.divsel().set_bit();
+            w.genen().set_bit()
+        });
+        while gclk.status.read().syncbusy().bit_is_set() {}
+        crate::samd::clock::enable_clock(gclk, IDSELECT_A::WDT, GENSELECT_A::GCLK2);
+
+        // Period is 8 << PER cycles
+        let cycles = (timeout_us as u64 * 1024).div_ceil(1_000_000) as u32;
+        let per = (cycles.max(8).next_power_of_two().trailing_zeros() - 3) as u8;
+
+        let wdt = self.regs();
+        wdt.ctrl.write(|w| w.enable().clear_bit());
+        self.sync();
+        wdt.config.write(|w| unsafe { w.per().bits(per) });
+        wdt.ctrl.write(|w| w.enable().set_bit());
+        self.sync();
+    }
+
+    /// Stop the watchdog.
+    pub fn stop(&mut self) {
+        self.regs().ctrl.write(|w| w.enable().clear_bit());
+        self.sync();
+    }
+
+    /// Restart the countdown.
+    pub fn feed(&mut self) {
+        self.regs().clear.write(|w| unsafe { w.clear().bits(0xa5) });
+    }
+
+    /// Feed the watchdog every `period_us` microseconds, forever.
+    ///
+    /// Run this in its own task, so the chip resets if the executor stops
+    /// running tasks.
+    #[cfg(feature = "time")]
+    pub async fn feed_every(&mut self, rt: Runtime, period_us: u32) -> ! {
+        loop {
+            self.feed();
+            rt.delay_us(period_us).await;
+        }
+    }
+
+    /// Reset the chip now, with [`ResetReason::Software`].
+    pub fn reset(&mut self) -> ! {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+
+    /// Read a scratch word, which keeps its value through resets other than power-on and brownout.
+    pub fn scratch(&self, index: usize) -> u32 {
+        assert!(index < NUM_SCRATCH);
+        unsafe { (*(&raw const SCRATCH).cast::<[u32; NUM_SCRATCH]>())[index] }
+    }
+
+    /// Write a scratch word.
+    pub fn set_scratch(&mut self, index: usize, value: u32) {
+        assert!(index < NUM_SCRATCH);
+        unsafe { (*(&raw mut SCRATCH).cast::<[u32; NUM_SCRATCH]>())[index] = value };
+    }
+
+    fn sync(&self) {
+        while self.regs().status.read().syncbusy().bit_is_set() {}
+    }
+}
@@ -0,0 +1,592 @@
+//! CDC Network Control Model (Ethernet over USB).
+//!
+//! NCM is supported by the built-in drivers on Linux, macOS, and Windows 11,
+//! so it's the most portable way to put a device on a network over USB.
+//!
+//! A function consists of a communication interface with an interrupt IN
+//! endpoint for notifications, and a data interface whose alternate setting 1
+//! has a bulk IN and bulk OUT endpoint. Ethernet frames are carried on the bulk
+//! endpoints in NCM Transfer Blocks (NTBs).
+//!
+//! * [`NcmControl`] answers the class-specific control requests on the communication interface.
+//! * [`notify_connection`] reports link status on the notification endpoint.
+//! * [`NcmData`] receives and sends Ethernet frames on the data endpoints.
+//! * With the `smoltcp` feature, [`NcmDevice`] implements `smoltcp::phy::Device`.
+//!
+//! The descriptors are built with [`descriptors!`](crate::usb::descriptors::descriptors) using the
+//! functional descriptors in this module:
+//!
+//! ```rust,ignore
+//! +Interface {
+//!     bInterfaceNumber: INTF_NCM_COMM,
+//!     bAlternateSetting: 0,
+//!     bInterfaceClass: cdc_ncm::CLASS_CDC,
+//!     bInterfaceSubClass: cdc_ncm::SUBCLASS_NCM,
+//!     bInterfaceProtocol: 0,
+//!     iInterface: 0,
+//!
+//!     +CdcHeader { bcdCDC: 0x0110 }
+//!     +CdcUnion { bControlInterface: INTF_NCM_COMM, bSubordinateInterface0: INTF_NCM_DATA }
+//!     +CdcEthernet { iMACAddress: STRING_MAC, bmEthernetStatistics: 0, wMaxSegmentSize: 1514, wNumberMCFilters: 0, bNumberPowerFilters: 0 }
+//!     +CdcNcm { bcdNcmVersion: 0x0100, bmNetworkCapabilities: 0 }
+//!
+//!     +EndpointDescriptor { bEndpointAddress: EP_NCM_NOTIFY, bmAttributes: INTERRUPT, wMaxPacketSize: 16, bInterval: 32 }
+//! }
+//! +Interface { bInterfaceNumber: INTF_NCM_DATA, bAlternateSetting: 0, bInterfaceClass: cdc_ncm::CLASS_CDC_DATA, bInterfaceSubClass: 0, bInterfaceProtocol: cdc_ncm::PROTOCOL_NTB, iInterface: 0 }
+//! +Interface {
+//!     bInterfaceNumber: INTF_NCM_DATA,
+//!     bAlternateSetting: 1,
+//!     ...
+//!     +EndpointDescriptor { bEndpointAddress: EP_NCM_IN, bmAttributes: BULK, wMaxPacketSize: 64, bInterval: 0 }
+//!     +EndpointDescriptor { bEndpointAddress: EP_NCM_OUT, bmAttributes: BULK, wMaxPacketSize: 64, bInterval: 0 }
+//! }
+//! ```
+//!
+//! The string referenced by `iMACAddress` must be 12 hex digits, and is the
+//! MAC address of the host side of the link.
+
+use core::cell::Cell;
+
+use defmt::debug;
+
+use crate::usb::{ControlData, ControlType, Endpoint, In, Out, Recipient, Responded, Setup, UsbBuffer};
+
+/// NCM Transfer Block (NTB) parsing and generation.
+///
+/// Only the 16-bit NTB format is supported, which is sufficient for blocks
+/// up to 64KB and is the only format hosts are required to implement.
+pub mod ntb;
+use ntb::{NtbCursor, NtbError};
+
+pub const CLASS_CDC: u8 = 0x02;
+pub const CLASS_CDC_DATA: u8 = 0x0a;
+pub const SUBCLASS_NCM: u8 = 0x0d;
+pub const PROTOCOL_NTB: u8 = 0x01;
+
+const CS_INTERFACE: u8 = 0x24;
+
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_UNION: u8 = 0x06;
+const CDC_TYPE_ETHERNET: u8 = 0x0f;
+const CDC_TYPE_NCM: u8 = 0x1a;
+
+const SET_ETHERNET_MULTICAST_FILTERS: u8 = 0x40;
+const SET_ETHERNET_PACKET_FILTER: u8 = 0x43;
+const GET_NTB_PARAMETERS: u8 = 0x80;
+const GET_NTB_FORMAT: u8 = 0x83;
+const SET_NTB_FORMAT: u8 = 0x84;
+const GET_NTB_INPUT_SIZE: u8 = 0x85;
+const SET_NTB_INPUT_SIZE: u8 = 0x86;
+const GET_MAX_DATAGRAM_SIZE: u8 = 0x87;
+const SET_MAX_DATAGRAM_SIZE: u8 = 0x88;
+const GET_CRC_MODE: u8 = 0x89;
+const SET_CRC_MODE: u8 = 0x8a;
+
+const NOTIFY_NETWORK_CONNECTION: u8 = 0x00;
+const NOTIFY_CONNECTION_SPEED_CHANGE: u8 = 0x2a;
+
+/// Maximum size of an NTB in either direction.
+///
+/// This fits one full-size Ethernet frame plus the NTB headers.
+pub const NTB_MAX_SIZE: usize = 2048;
+
+/// Maximum Ethernet frame size, excluding FCS.
+pub const MAX_SEGMENT_SIZE: usize = 1514;
+
+/// CDC Header functional descriptor.
+#[allow(non_snake_case)]
+pub struct CdcHeader {
+    pub bcdCDC: u16,
+}
+
+impl CdcHeader {
+    pub const LEN: usize = 5;
+    pub const DESCRIPTOR_TYPE: u8 = CS_INTERFACE;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            CDC_TYPE_HEADER,
+            self.bcdCDC.to_le_bytes()[0],
+            self.bcdCDC.to_le_bytes()[1],
+        ]
+    }
+}
+
+/// CDC Union functional descriptor with a single subordinate interface.
+#[allow(non_snake_case)]
+pub struct CdcUnion {
+    pub bControlInterface: u8,
+    pub bSubordinateInterface0: u8,
+}
+
+impl CdcUnion {
+    pub const LEN: usize = 5;
+    pub const DESCRIPTOR_TYPE: u8 = CS_INTERFACE;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            CDC_TYPE_UNION,
+            self.bControlInterface,
+            self.bSubordinateInterface0,
+        ]
+    }
+}
+
+/// CDC Ethernet Networking functional descriptor.
+#[allow(non_snake_case)]
+pub struct CdcEthernet {
+    pub iMACAddress: u8,
+    pub bmEthernetStatistics: u32,
+    pub wMaxSegmentSize: u16,
+    pub wNumberMCFilters: u16,
+    pub bNumberPowerFilters: u8,
+}
+
+impl CdcEthernet {
+    pub const LEN: usize = 13;
+    pub const DESCRIPTOR_TYPE: u8 = CS_INTERFACE;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            CDC_TYPE_ETHERNET,
+            self.iMACAddress,
+            self.bmEthernetStatistics.to_le_bytes()[0],
+            self.bmEthernetStatistics.to_le_bytes()[1],
+            self.bmEthernetStatistics.to_le_bytes()[2],
+            self.bmEthernetStatistics.to_le_bytes()[3],
+            self.wMaxSegmentSize.to_le_bytes()[0],
+            self.wMaxSegmentSize.to_le_bytes()[1],
+            self.wNumberMCFilters.to_le_bytes()[0],
+            self.wNumberMCFilters.to_le_bytes()[1],
+            self.bNumberPowerFilters,
+        ]
+    }
+}
+
+/// NCM functional descriptor.
+#[allow(non_snake_case)]
+pub struct CdcNcm {
+    pub bcdNcmVersion: u16,
+    pub bmNetworkCapabilities: u8,
+}
+
+impl CdcNcm {
+    pub const LEN: usize = 6;
+    pub const DESCRIPTOR_TYPE: u8 = CS_INTERFACE;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            CDC_TYPE_NCM,
+            self.bcdNcmVersion.to_le_bytes()[0],
+            self.bcdNcmVersion.to_le_bytes()[1],
+            self.bmNetworkCapabilities,
+        ]
+    }
+}
+
+/// Response to GET_NTB_PARAMETERS
+const NTB_PARAMETERS: [u8; 28] = {
+    const ALIGN: u16 = 4;
+    let mut p = [0u8; 28];
+    let (len, formats, in_max, out_max) = (28u16, 0x0001u16, NTB_MAX_SIZE as u32, NTB_MAX_SIZE as u32);
+    p[0] = len.to_le_bytes()[0];
+    p[1] = len.to_le_bytes()[1];
+    p[2] = formats.to_le_bytes()[0]; // bmNtbFormatsSupported: NTB16 only
+    p[3] = formats.to_le_bytes()[1];
+    p[4] = in_max.to_le_bytes()[0]; // dwNtbInMaxSize
+    p[5] = in_max.to_le_bytes()[1];
+    p[6] = in_max.to_le_bytes()[2];
+    p[7] = in_max.to_le_bytes()[3];
+    p[8] = ALIGN as u8; // wNdpInDivisor
+    p[12] = ALIGN as u8; // wNdpInAlignment
+    p[16] = out_max.to_le_bytes()[0]; // dwNtbOutMaxSize
+    p[17] = out_max.to_le_bytes()[1];
+    p[18] = out_max.to_le_bytes()[2];
+    p[19] = out_max.to_le_bytes()[3];
+    p[20] = ALIGN as u8; // wNdpOutDivisor
+    p[24] = ALIGN as u8; // wNdpOutAlignment
+    p
+};
+
+/// State for the class-specific requests on the communication interface.
+pub struct NcmControl {
+    comm_interface: u8,
+    ntb_input_size: Cell<u32>,
+    max_datagram_size: Cell<u16>,
+    packet_filter: Cell<u16>,
+}
+
+impl NcmControl {
+    pub const fn new(comm_interface: u8) -> Self {
+        NcmControl {
+            comm_interface,
+            ntb_input_size: Cell::new(NTB_MAX_SIZE as u32),
+            max_datagram_size: Cell::new(MAX_SEGMENT_SIZE as u16),
+            packet_filter: Cell::new(0),
+        }
+    }
+
+    /// Restore default settings, as on bus reset or when the data interface is reset to alternate setting 0.
+    pub fn reset(&self) {
+        self.ntb_input_size.set(NTB_MAX_SIZE as u32);
+        self.max_datagram_size.set(MAX_SEGMENT_SIZE as u16);
+        self.packet_filter.set(0);
+    }
+
+    /// Maximum NTB size the host is willing to receive.
+    pub fn ntb_input_size(&self) -> usize {
+        self.ntb_input_size.get() as usize
+    }
+
+    /// `wPacketFilterBitmap` last set by the host.
+    pub fn packet_filter(&self) -> u16 {
+        self.packet_filter.get()
+    }
+
+    /// Returns `true` if `req` is a class request addressed to this function's communication interface.
+    pub fn accepts(&self, req: &Setup) -> bool {
+        req.ty == ControlType::Class && req.recipient == Recipient::Interface && req.index == self.comm_interface as u16
+    }
+
+    /// Handle a class-specific control request.
+    ///
+    /// Requests not addressed to this function are rejected.
+    pub async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        use ControlData::*;
+
+        if !self.accepts(&req) {
+            return req.reject();
+        }
+
+        debug!("ncm request {:02x}", req.request);
+
+        match req {
+            Setup { request: GET_NTB_PARAMETERS, data: In(data), .. } => {
+                data.respond(&NTB_PARAMETERS).await
+            }
+            Setup { request: GET_NTB_FORMAT, data: In(data), .. } => {
+                data.respond(&0u16.to_le_bytes()).await
+            }
+            Setup { request: SET_NTB_FORMAT, value, data: Out(data), .. } => {
+                if value == 0 { data.accept().await } else { data.reject() }
+            }
+            Setup { request: GET_NTB_INPUT_SIZE, data: In(data), .. } => {
+                data.respond(&self.ntb_input_size.get().to_le_bytes()).await
+            }
+            Setup { request: SET_NTB_INPUT_SIZE, data: Out(mut data), .. } => {
+                let size = match *data.receive().await {
+                    [a, b, c, d, ..] => u32::from_le_bytes([a, b, c, d]),
+                    _ => return data.reject(),
+                };
+                if (size as usize) < ntb::DATAGRAM_OFFSET || size as usize > NTB_MAX_SIZE {
+                    return data.reject();
+                }
+                self.ntb_input_size.set(size);
+                data.accept().await
+            }
+            Setup { request: GET_MAX_DATAGRAM_SIZE, data: In(data), .. } => {
+                data.respond(&self.max_datagram_size.get().to_le_bytes()).await
+            }
+            Setup { request: SET_MAX_DATAGRAM_SIZE, data: Out(mut data), .. } => {
+                let size = match *data.receive().await {
+                    [a, b, ..] => u16::from_le_bytes([a, b]),
+                    _ => return data.reject(),
+                };
+                self.max_datagram_size.set(size.min(MAX_SEGMENT_SIZE as u16));
+                data.accept().await
+            }
+            Setup { request: GET_CRC_MODE, data: In(data), .. } => {
+                data.respond(&0u16.to_le_bytes()).await
+            }
+            Setup { request: SET_CRC_MODE, value, data: Out(data), .. } => {
+                if value == 0 { data.accept().await } else { data.reject() }
+            }
+            Setup { request: SET_ETHERNET_PACKET_FILTER, value, data: Out(data), .. } => {
+                self.packet_filter.set(value);
+                data.accept().await
+            }
+            Setup { request: SET_ETHERNET_MULTICAST_FILTERS, data: Out(data), .. } => {
+                // Perfect multicast filtering is not supported (`wNumberMCFilters` = 0)
+                data.reject()
+            }
+            req => req.reject(),
+        }
+    }
+}
+
+/// Send the CONNECTION_SPEED_CHANGE and NETWORK_CONNECTION notifications on the interrupt endpoint.
+///
+/// Hosts won't bring up the link until they receive a NETWORK_CONNECTION
+/// notification with `connected` set after the data interface is enabled.
+pub async fn notify_connection<const EP: u8>(ep: &mut Endpoint<In, EP>, comm_interface: u8, connected: bool, bits_per_second: u32) {
+    let mut buf = UsbBuffer::<16>::new();
+
+    if connected {
+        buf[0] = 0xa1; // bmRequestType: class, interface, IN
+        buf[1] = NOTIFY_CONNECTION_SPEED_CHANGE;
+        buf[2..4].copy_from_slice(&0u16.to_le_bytes());
+        buf[4..6].copy_from_slice(&(comm_interface as u16).to_le_bytes());
+        buf[6..8].copy_from_slice(&8u16.to_le_bytes());
+        buf[8..12].copy_from_slice(&bits_per_second.to_le_bytes()); // DLBitRate
+        buf[12..16].copy_from_slice(&bits_per_second.to_le_bytes()); // ULBitRate
+        ep.send(&buf, 16, false).await;
+    }
+
+    buf[0] = 0xa1;
+    buf[1] = NOTIFY_NETWORK_CONNECTION;
+    buf[2..4].copy_from_slice(&(connected as u16).to_le_bytes());
+    buf[4..6].copy_from_slice(&(comm_interface as u16).to_le_bytes());
+    buf[6..8].copy_from_slice(&0u16.to_le_bytes());
+    ep.send(&buf, 8, false).await;
+}
+
+struct RxState {
+    buf: UsbBuffer<NTB_MAX_SIZE>,
+    len: usize,
+    cursor: NtbCursor,
+}
+
+impl RxState {
+    fn next_datagram(&mut self) -> Option<&[u8]> {
+        let ntb = &self.buf[..self.len];
+        self.cursor.next(ntb).map(|range| &ntb[range])
+    }
+}
+
+struct TxState {
+    buf: UsbBuffer<NTB_MAX_SIZE>,
+    len: usize,
+    sequence: u16,
+
+    /// Largest NTB the host accepts.
+    max_len: usize,
+}
+
+impl TxState {
+    fn prepare(&mut self, datagram_len: usize) -> Result<&mut [u8], NtbError> {
+        debug_assert!(self.len == 0);
+        let len = ntb::write_ntb16_headers(&mut self.buf[..self.max_len], self.sequence, datagram_len)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.len = len;
+        Ok(&mut self.buf[ntb::DATAGRAM_OFFSET..len])
+    }
+}
+
+/// Data interface of an NCM function.
+///
+/// Create this with the endpoints obtained when the host selects alternate
+/// setting 1 of the data interface. The host may only change the NTB input
+/// size before then, so `control` is read once, by [`NcmData::new`].
+pub struct NcmData<const EP_IN: u8, const EP_OUT: u8> {
+    ep_in: Endpoint<In, EP_IN>,
+    ep_out: Endpoint<Out, EP_OUT>,
+    rx: RxState,
+    tx: TxState,
+}
+
+impl<const EP_IN: u8, const EP_OUT: u8> NcmData<EP_IN, EP_OUT> {
+    pub fn new(ep_in: Endpoint<In, EP_IN>, ep_out: Endpoint<Out, EP_OUT>, control: &NcmControl) -> Self {
+        NcmData {
+            ep_in,
+            ep_out,
+            rx: RxState {
+                buf: UsbBuffer::new(),
+                len: 0,
+                cursor: NtbCursor::EMPTY,
+            },
+            tx: TxState {
+                buf: UsbBuffer::new(),
+                len: 0,
+                sequence: 0,
+                max_len: control.ntb_input_size(),
+            },
+        }
+    }
+
+    /// Wait for the host to send an NTB.
+    ///
+    /// Any datagrams remaining from the previous NTB are discarded. Malformed
+    /// NTBs are skipped.
+    pub async fn receive_ntb(&mut self) {
+        loop {
+            let len = self.ep_out.receive(&mut self.rx.buf).await;
+            match NtbCursor::new(&self.rx.buf[..len]) {
+                Ok(cursor) => {
+                    self.rx.len = len;
+                    self.rx.cursor = cursor;
+                    return;
+                }
+                Err(e) => {
+                    debug!("ncm: discarding invalid NTB: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Get the next Ethernet frame from the last NTB received.
+    pub fn next_datagram(&mut self) -> Option<&[u8]> {
+        self.rx.next_datagram()
+    }
+
+    /// Wait for the next Ethernet frame from the host.
+    pub async fn receive(&mut self) -> &[u8] {
+        loop {
+            if let Some(range) = self.rx.cursor.next(&self.rx.buf[..self.rx.len]) {
+                return &self.rx.buf[range];
+            }
+            self.receive_ntb().await;
+        }
+    }
+
+    /// Largest frame [`NcmData::send`] accepts, limited by the NTB size the host accepts.
+    pub fn max_frame_len(&self) -> usize {
+        (self.tx.max_len - ntb::DATAGRAM_OFFSET).min(MAX_SEGMENT_SIZE)
+    }
+
+    /// Send an Ethernet frame to the host.
+    ///
+    /// A frame too long for an NTB of the size the host accepts is dropped,
+    /// returning [`NtbError::Overflow`].
+    pub async fn send(&mut self, frame: &[u8]) -> Result<(), NtbError> {
+        self.tx.prepare(frame.len())?.copy_from_slice(frame);
+        self.flush().await;
+        Ok(())
+    }
+
+    /// Send the pending NTB, if any.
+    async fn flush(&mut self) {
+        if self.tx.len > 0 {
+            self.ep_in.send(&self.tx.buf, self.tx.len, true).await;
+            self.tx.len = 0;
+        }
+    }
+}
+
+#[cfg(feature = "smoltcp")]
+mod smoltcp_device {
+    use defmt::debug;
+    use smoltcp::phy::{self, DeviceCapabilities, Medium};
+    use smoltcp::time::Instant;
+
+    use super::{NcmData, RxState, TxState, NTB_MAX_SIZE};
+
+    /// Adapter implementing `smoltcp::phy::Device` for an NCM data interface.
+    ///
+    /// smoltcp's interface is synchronous, so frames are exchanged through the
+    /// buffers in [`NcmData`]. Call [`NcmDevice::wait`] between calls to
+    /// `Interface::poll` to move data over USB:
+    ///
+    /// ```rust,ignore
+    /// loop {
+    ///     iface.poll(now(rt), &mut device, &mut sockets);
+    ///     select(device.wait(), rt.delay_us(poll_delay_us)).await;
+    /// }
+    /// ```
+    pub struct NcmDevice<const EP_IN: u8, const EP_OUT: u8> {
+        data: NcmData<EP_IN, EP_OUT>,
+    }
+
+    impl<const EP_IN: u8, const EP_OUT: u8> NcmDevice<EP_IN, EP_OUT> {
+        pub fn new(data: NcmData<EP_IN, EP_OUT>) -> Self {
+            NcmDevice { data }
+        }
+
+        pub fn into_inner(self) -> NcmData<EP_IN, EP_OUT> {
+            self.data
+        }
+
+        /// Send a frame queued by smoltcp, or if there is none, wait for the host to send an NTB.
+        ///
+        /// Dropping this future while waiting for the host to send may discard a
+        /// partially-received NTB, which the network stack will see as packet
+        /// loss.
+        pub async fn wait(&mut self) {
+            if self.data.tx.len > 0 {
+                self.data.flush().await;
+            } else {
+                let mut cursor = self.data.rx.cursor;
+                if cursor.next(&self.data.rx.buf[..self.data.rx.len]).is_none() {
+                    self.data.receive_ntb().await;
+                }
+            }
+        }
+    }
+
+    impl<const EP_IN: u8, const EP_OUT: u8> phy::Device for NcmDevice<EP_IN, EP_OUT> {
+        type RxToken<'a> = RxToken<'a>;
+        type TxToken<'a> = TxToken<'a>;
+
+        fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+            if self.data.tx.len > 0 {
+                // Can't provide a TxToken for a reply until the pending frame is sent
+                return None;
+            }
+
+            let mut cursor = self.data.rx.cursor;
+            cursor.next(&self.data.rx.buf[..self.data.rx.len])?;
+
+            Some((
+                RxToken { rx: &mut self.data.rx },
+                TxToken { tx: &mut self.data.tx },
+            ))
+        }
+
+        fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+            if self.data.tx.len > 0 {
+                None
+            } else {
+                Some(TxToken { tx: &mut self.data.tx })
+            }
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.medium = Medium::Ethernet;
+            caps.max_transmission_unit = self.data.max_frame_len();
+            caps.max_burst_size = Some(1);
+            caps
+        }
+    }
+
+    pub struct RxToken<'a> {
+        rx: &'a mut RxState,
+    }
+
+    impl<'a> phy::RxToken for RxToken<'a> {
+        fn consume<R, F>(self, f: F) -> R where F: FnOnce(&[u8]) -> R {
+            // `receive` checked that a datagram is available
+            f(self.rx.next_datagram().unwrap_or(&[]))
+        }
+    }
+
+    pub struct TxToken<'a> {
+        tx: &'a mut TxState,
+    }
+
+    impl<'a> phy::TxToken for TxToken<'a> {
+        fn consume<R, F>(self, len: usize, f: F) -> R where F: FnOnce(&mut [u8]) -> R {
+            match self.tx.prepare(len) {
+                Ok(buf) => f(buf),
+                Err(_) => {
+                    // Longer than `max_transmission_unit`, so drop it. smoltcp
+                    // still needs somewhere to build the frame, so lend it the
+                    // buffer without queueing anything to send.
+                    debug!("ncm: dropping {} byte frame", len);
+                    assert!(len <= NTB_MAX_SIZE, "ncm: {} byte frame exceeds the NTB buffer", len);
+                    f(&mut self.tx.buf[..len])
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "smoltcp")]
+pub use smoltcp_device::{NcmDevice, RxToken, TxToken};
@@ -0,0 +1,197 @@
+use core::ops::Range;
+
+use defmt::Format;
+
+/// `dwSignature` of the NTH16 header: "NCMH"
+pub const NTH16_SIGNATURE: u32 = u32::from_le_bytes(*b"NCMH");
+
+/// `dwSignature` of an NDP16 without CRC: "NCM0"
+pub const NDP16_SIGNATURE: u32 = u32::from_le_bytes(*b"NCM0");
+
+pub const NTH16_LEN: usize = 12;
+
+/// Length of an NDP16 pointing to a single datagram, including the terminating entry.
+pub const NDP16_SINGLE_LEN: usize = 16;
+
+/// Offset of the datagram in a block produced by [`write_ntb16`].
+pub const DATAGRAM_OFFSET: usize = NTH16_LEN + NDP16_SINGLE_LEN;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum NtbError {
+    /// The block is shorter than its headers claim.
+    Truncated,
+
+    /// The NTH16 or NDP16 signature did not match.
+    BadSignature,
+
+    /// A header or datagram pointer is outside of the block, or an NDP16
+    /// points back to an earlier one.
+    BadPointer,
+
+    /// The datagram doesn't fit in the block.
+    Overflow,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> Result<u16, NtbError> {
+    match buf.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(NtbError::Truncated),
+    }
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> Result<u32, NtbError> {
+    match buf.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(NtbError::Truncated),
+    }
+}
+
+/// Position within a received NTB16.
+///
+/// This stores only offsets rather than borrowing the block, so it can be
+/// kept alongside the buffer the block was received into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NtbCursor {
+    /// Offset of the current NDP16, or 0 at the end of the block.
+    ndp: u16,
+
+    /// Offset of the next datagram pointer entry within the current NDP16.
+    entry: u16,
+
+    /// `wBlockLength` from the NTH16.
+    block_len: u16,
+}
+
+impl NtbCursor {
+    /// A cursor that yields no datagrams.
+    pub const EMPTY: NtbCursor = NtbCursor { ndp: 0, entry: 0, block_len: 0 };
+
+    /// Validate the NTH16 header of a received block and position the cursor at its first datagram.
+    pub fn new(ntb: &[u8]) -> Result<NtbCursor, NtbError> {
+        if u32_at(ntb, 0)? != NTH16_SIGNATURE {
+            return Err(NtbError::BadSignature);
+        }
+
+        if u16_at(ntb, 4)? as usize != NTH16_LEN {
+            return Err(NtbError::BadSignature);
+        }
+
+        let block_len = u16_at(ntb, 8)?;
+        if block_len as usize > ntb.len() {
+            return Err(NtbError::Truncated);
+        }
+
+        let mut cursor = NtbCursor { ndp: 0, entry: 0, block_len };
+        cursor.enter_ndp(ntb, u16_at(ntb, 10)?)?;
+        Ok(cursor)
+    }
+
+    fn enter_ndp(&mut self, ntb: &[u8], ndp: u16) -> Result<(), NtbError> {
+        if ndp == 0 {
+            self.ndp = 0;
+            return Ok(());
+        }
+
+        // Each NDP16 must follow the last, so a malicious chain can't loop forever
+        if (ndp as usize) < NTH16_LEN || ndp <= self.ndp || ndp as usize + 8 > self.block_len as usize || ndp % 4 != 0 {
+            return Err(NtbError::BadPointer);
+        }
+
+        if u32_at(ntb, ndp as usize)? != NDP16_SIGNATURE {
+            return Err(NtbError::BadSignature);
+        }
+
+        self.ndp = ndp;
+        self.entry = ndp + 8;
+        Ok(())
+    }
+
+    /// Get the sequence number of the block.
+    pub fn sequence(ntb: &[u8]) -> u16 {
+        u16_at(ntb, 6).unwrap_or(0)
+    }
+
+    /// Return the byte range of the next datagram in `ntb`, which must be the
+    /// same block passed to [`NtbCursor::new`].
+    ///
+    /// Returns `None` at the end of the block, or if a malformed pointer is
+    /// encountered.
+    pub fn next(&mut self, ntb: &[u8]) -> Option<Range<usize>> {
+        loop {
+            if self.ndp == 0 {
+                return None;
+            }
+
+            let ndp = self.ndp as usize;
+            let ndp_len = u16_at(ntb, ndp + 4).ok()? as usize;
+            let entry = self.entry as usize;
+
+            if entry + 4 > ndp + ndp_len || entry + 4 > self.block_len as usize {
+                self.ndp = 0;
+                return None;
+            }
+
+            let index = u16_at(ntb, entry).ok()? as usize;
+            let len = u16_at(ntb, entry + 2).ok()? as usize;
+
+            if index == 0 || len == 0 {
+                // End of this NDP, follow `wNextNdpIndex`
+                let next = u16_at(ntb, ndp + 6).ok()?;
+                if self.enter_ndp(ntb, next).is_err() {
+                    self.ndp = 0;
+                    return None;
+                }
+                continue;
+            }
+
+            self.entry += 4;
+
+            if index < NTH16_LEN || index + len > self.block_len as usize {
+                self.ndp = 0;
+                return None;
+            }
+
+            return Some(index..index + len);
+        }
+    }
+}
+
+/// Write the headers of an NTB16 containing a single datagram of `datagram_len`
+/// bytes, which the caller places at [`DATAGRAM_OFFSET`].
+///
+/// Returns the total length of the block, or [`NtbError::Overflow`] if it
+/// wouldn't fit in `buf`.
+pub fn write_ntb16_headers(buf: &mut [u8], sequence: u16, datagram_len: usize) -> Result<usize, NtbError> {
+    let block_len = DATAGRAM_OFFSET + datagram_len;
+    if block_len > buf.len() || block_len > u16::MAX as usize {
+        return Err(NtbError::Overflow);
+    }
+
+    // NTH16
+    buf[0..4].copy_from_slice(&NTH16_SIGNATURE.to_le_bytes());
+    buf[4..6].copy_from_slice(&(NTH16_LEN as u16).to_le_bytes());
+    buf[6..8].copy_from_slice(&sequence.to_le_bytes());
+    buf[8..10].copy_from_slice(&(block_len as u16).to_le_bytes());
+    buf[10..12].copy_from_slice(&(NTH16_LEN as u16).to_le_bytes());
+
+    // NDP16
+    let ndp = &mut buf[NTH16_LEN..DATAGRAM_OFFSET];
+    ndp[0..4].copy_from_slice(&NDP16_SIGNATURE.to_le_bytes());
+    ndp[4..6].copy_from_slice(&(NDP16_SINGLE_LEN as u16).to_le_bytes());
+    ndp[6..8].copy_from_slice(&0u16.to_le_bytes()); // wNextNdpIndex
+    ndp[8..10].copy_from_slice(&(DATAGRAM_OFFSET as u16).to_le_bytes());
+    ndp[10..12].copy_from_slice(&(datagram_len as u16).to_le_bytes());
+    ndp[12..16].fill(0); // terminating entry
+
+    Ok(block_len)
+}
+
+/// Build an NTB16 containing `datagram` in `buf`.
+///
+/// Returns the total length of the block, or [`NtbError::Overflow`] if it
+/// wouldn't fit in `buf`.
+pub fn write_ntb16(buf: &mut [u8], sequence: u16, datagram: &[u8]) -> Result<usize, NtbError> {
+    let len = write_ntb16_headers(buf, sequence, datagram.len())?;
+    buf[DATAGRAM_OFFSET..len].copy_from_slice(datagram);
+    Ok(len)
+}
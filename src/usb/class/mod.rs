@@ -0,0 +1,4 @@
+//! Implementations of standard USB device classes.
+
+pub mod cdc_ncm;
+pub mod usbtmc;
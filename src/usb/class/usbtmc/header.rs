@@ -0,0 +1,94 @@
+use defmt::Format;
+
+/// Length of the header at the start of every Bulk-OUT and Bulk-IN transfer.
+pub const HEADER_LEN: usize = 12;
+
+pub const DEV_DEP_MSG_OUT: u8 = 1;
+pub const REQUEST_DEV_DEP_MSG_IN: u8 = 2;
+pub const DEV_DEP_MSG_IN: u8 = 2;
+pub const VENDOR_SPECIFIC_OUT: u8 = 126;
+pub const REQUEST_VENDOR_SPECIFIC_IN: u8 = 127;
+pub const TRIGGER: u8 = 128;
+
+const ATTR_EOM: u8 = 0x01;
+const ATTR_TERM_CHAR: u8 = 0x02;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum HeaderError {
+    /// The transfer is shorter than a header.
+    Short,
+    /// `bTagInverse` doesn't match `bTag`, or `bTag` is 0.
+    InvalidTag,
+    /// The `MsgID` is not supported.
+    UnsupportedMessage(u8),
+}
+
+/// Header of a Bulk-OUT transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum BulkOutHeader {
+    /// DEV_DEP_MSG_OUT, followed by `transfer_size` bytes of the message.
+    DevDepMsgOut { tag: u8, transfer_size: u32, eom: bool },
+    /// REQUEST_DEV_DEP_MSG_IN, asking for a DEV_DEP_MSG_IN transfer of up to `transfer_size` bytes.
+    RequestDevDepMsgIn { tag: u8, transfer_size: u32, term_char: Option<u8> },
+    /// USB488 TRIGGER.
+    Trigger { tag: u8 },
+}
+
+impl BulkOutHeader {
+    /// Parse the header at the start of a Bulk-OUT transfer.
+    ///
+    /// Vendor specific messages are reported as unsupported.
+    pub fn parse(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < HEADER_LEN {
+            return Err(HeaderError::Short);
+        }
+
+        let tag = buf[1];
+        if tag == 0 || buf[2] != !tag {
+            return Err(HeaderError::InvalidTag);
+        }
+
+        let transfer_size = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let attributes = buf[8];
+
+        match buf[0] {
+            DEV_DEP_MSG_OUT => Ok(BulkOutHeader::DevDepMsgOut {
+                tag,
+                transfer_size,
+                eom: attributes & ATTR_EOM != 0,
+            }),
+            REQUEST_DEV_DEP_MSG_IN => Ok(BulkOutHeader::RequestDevDepMsgIn {
+                tag,
+                transfer_size,
+                term_char: (attributes & ATTR_TERM_CHAR != 0).then_some(buf[9]),
+            }),
+            TRIGGER => Ok(BulkOutHeader::Trigger { tag }),
+            id => Err(HeaderError::UnsupportedMessage(id)),
+        }
+    }
+
+    pub fn tag(&self) -> u8 {
+        match *self {
+            BulkOutHeader::DevDepMsgOut { tag, .. } => tag,
+            BulkOutHeader::RequestDevDepMsgIn { tag, .. } => tag,
+            BulkOutHeader::Trigger { tag } => tag,
+        }
+    }
+}
+
+/// Write the header of a DEV_DEP_MSG_IN transfer carrying `transfer_size` bytes.
+pub fn write_dev_dep_msg_in(buf: &mut [u8], tag: u8, transfer_size: u32, eom: bool, term_char: bool) {
+    buf[0] = DEV_DEP_MSG_IN;
+    buf[1] = tag;
+    buf[2] = !tag;
+    buf[3] = 0;
+    buf[4..8].copy_from_slice(&transfer_size.to_le_bytes());
+    buf[8] = if eom { ATTR_EOM } else { 0 } | if term_char { ATTR_TERM_CHAR } else { 0 };
+    buf[9..12].fill(0);
+}
+
+/// Length of a transfer with `len` bytes of message data, including the
+/// header and the alignment padding to a multiple of 4 bytes.
+pub const fn transfer_len(len: usize) -> usize {
+    (HEADER_LEN + len + 3) & !3
+}
@@ -0,0 +1,612 @@
+//! USB Test and Measurement Class (USBTMC) with the USB488 subclass.
+//!
+//! USBTMC carries instrument messages, typically SCPI, and is supported by
+//! VISA implementations and the Linux `usbtmc` driver without a custom driver.
+//!
+//! A function is a single interface with a bulk OUT and a bulk IN endpoint,
+//! and for USB488 optionally an interrupt IN endpoint for status bytes. Each
+//! bulk transfer starts with a 12-byte header parsed and generated by [`header`].
+//!
+//! * [`UsbtmcControl`] answers the class-specific control requests, including
+//!   aborting bulk transfers and clearing the interface.
+//! * [`UsbtmcData`] receives messages and sends responses on the bulk endpoints.
+//! * [`UsbtmcControl::notify_status`] and [`UsbtmcControl::service_request`]
+//!   send USB488 status bytes on the interrupt endpoint.
+//!
+//! ```rust,ignore
+//! +Interface {
+//!     bInterfaceNumber: INTF_TMC,
+//!     bAlternateSetting: 0,
+//!     bInterfaceClass: usbtmc::CLASS_APPLICATION_SPECIFIC,
+//!     bInterfaceSubClass: usbtmc::SUBCLASS_USBTMC,
+//!     bInterfaceProtocol: usbtmc::PROTOCOL_USB488,
+//!     iInterface: 0,
+//!
+//!     +EndpointDescriptor { bEndpointAddress: EP_TMC_OUT, bmAttributes: BULK, wMaxPacketSize: 64, bInterval: 0 }
+//!     +EndpointDescriptor { bEndpointAddress: EP_TMC_IN, bmAttributes: BULK, wMaxPacketSize: 64, bInterval: 0 }
+//!     +EndpointDescriptor { bEndpointAddress: EP_TMC_INT, bmAttributes: INTERRUPT, wMaxPacketSize: 8, bInterval: 10 }
+//! }
+//! ```
+//!
+//! The application handles one message at a time:
+//!
+//! ```rust,ignore
+//! let mut tmc = UsbtmcData::new(&self.tmc, endpoints.bulk_in::<EP_TMC_IN>(), endpoints.bulk_out::<EP_TMC_OUT>());
+//! let mut ep_int = endpoints.endpoint::<In, EP_TMC_INT, 8>();
+//! let mut command = [0; 256];
+//! let mut response = Vec::new();
+//! loop {
+//!     match tmc.receive(&mut command).await {
+//!         Ok(Request::Message(msg)) => response = scpi.execute(msg),
+//!         Ok(Request::Read { .. }) => {
+//!             if let Ok(n) = tmc.respond(&response).await {
+//!                 response.drain(..n);
+//!             }
+//!         }
+//!         Ok(Request::Trigger) => scpi.trigger(),
+//!         Err(_) => {}
+//!     }
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use defmt::{debug, Format};
+
+use crate::usb::{ControlData, ControlType, Endpoint, In, Out, Recipient, Responded, Setup, UsbBuffer};
+
+/// USBTMC Bulk-OUT and Bulk-IN message headers.
+pub mod header;
+use header::{BulkOutHeader, HEADER_LEN};
+
+pub const CLASS_APPLICATION_SPECIFIC: u8 = 0xfe;
+pub const SUBCLASS_USBTMC: u8 = 0x03;
+pub const PROTOCOL_USBTMC: u8 = 0x00;
+pub const PROTOCOL_USB488: u8 = 0x01;
+
+const INITIATE_ABORT_BULK_OUT: u8 = 1;
+const CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+const INITIATE_ABORT_BULK_IN: u8 = 3;
+const CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+const INITIATE_CLEAR: u8 = 5;
+const CHECK_CLEAR_STATUS: u8 = 6;
+const GET_CAPABILITIES: u8 = 7;
+const INDICATOR_PULSE: u8 = 64;
+const READ_STATUS_BYTE: u8 = 128;
+const REN_CONTROL: u8 = 160;
+const GO_TO_LOCAL: u8 = 161;
+const LOCAL_LOCKOUT: u8 = 162;
+
+const STATUS_SUCCESS: u8 = 0x01;
+const STATUS_PENDING: u8 = 0x02;
+const STATUS_INTERRUPT_IN_BUSY: u8 = 0x20;
+const STATUS_FAILED: u8 = 0x80;
+const STATUS_TRANSFER_NOT_IN_PROGRESS: u8 = 0x81;
+
+const NOTIFY_STATUS_BYTE: u8 = 0x80;
+const NOTIFY_SERVICE_REQUEST: u8 = 0x81;
+
+/// Bulk transfers are received and sent in chunks of this size, a multiple
+/// of any bulk max packet size.
+const CHUNK_LEN: usize = 64;
+
+/// Capabilities reported by GET_CAPABILITIES.
+pub struct Capabilities {
+    /// The interface only accepts messages, and never sends a response.
+    pub listen_only: bool,
+    /// The interface only sends responses, and ignores DEV_DEP_MSG_OUT.
+    pub talk_only: bool,
+    /// INDICATOR_PULSE is accepted, and reported by [`UsbtmcControl::take_indicator_pulse`].
+    pub indicator_pulse: bool,
+    /// REQUEST_DEV_DEP_MSG_IN may end the response at a termination character.
+    pub term_char: bool,
+    /// USB488 capabilities, for an interface with [`PROTOCOL_USB488`].
+    pub usb488: Option<Usb488Capabilities>,
+}
+
+/// USB488 subclass capabilities.
+pub struct Usb488Capabilities {
+    /// The TRIGGER message is accepted (DT1).
+    pub trigger: bool,
+    /// REN_CONTROL, GO_TO_LOCAL and LOCAL_LOCKOUT are accepted (RL1).
+    pub remote_local: bool,
+    /// The device requests service with [`UsbtmcControl::service_request`] (SR1).
+    pub service_request: bool,
+    /// The device understands SCPI.
+    pub scpi: bool,
+    /// READ_STATUS_BYTE responses are sent on the interrupt endpoint.
+    pub interrupt_in: bool,
+}
+
+/// Response to GET_CAPABILITIES
+const fn capabilities_response(caps: &Capabilities) -> [u8; 24] {
+    let mut r = [0u8; 24];
+    r[0] = STATUS_SUCCESS;
+    r[2] = 0x00; // bcdUSBTMC 1.00
+    r[3] = 0x01;
+    r[4] = (caps.indicator_pulse as u8) << 2 | (caps.talk_only as u8) << 1 | caps.listen_only as u8;
+    r[5] = caps.term_char as u8;
+    if let Some(usb488) = &caps.usb488 {
+        r[12] = 0x00; // bcdUSB488 1.00
+        r[13] = 0x01;
+        let is_488_2 = usb488.trigger && usb488.remote_local;
+        r[14] = (is_488_2 as u8) << 2 | (usb488.remote_local as u8) << 1 | usb488.trigger as u8;
+        r[15] = (usb488.scpi as u8) << 3
+            | (usb488.service_request as u8) << 2
+            | (usb488.remote_local as u8) << 1
+            | usb488.trigger as u8;
+    }
+    r
+}
+
+/// State for the class-specific requests, shared with [`UsbtmcData`].
+pub struct UsbtmcControl {
+    interface: u8,
+    ep_in: u8,
+    ep_out: u8,
+    capabilities: Capabilities,
+
+    /// Tag of the DEV_DEP_MSG_OUT transfer being received, and its data bytes received so far.
+    out_transfer: Cell<Option<u8>>,
+    out_received: Cell<u32>,
+    /// Tag of the DEV_DEP_MSG_IN transfer being sent, and its data bytes sent so far.
+    in_transfer: Cell<Option<u8>>,
+    in_sent: Cell<u32>,
+    /// Incremented to abort the transfer in progress in each direction.
+    out_epoch: Cell<u8>,
+    in_epoch: Cell<u8>,
+
+    status_byte: Cell<u8>,
+    /// Tag of a READ_STATUS_BYTE request waiting to be answered on the interrupt endpoint.
+    status_tag: Cell<Option<u8>>,
+    indicator_pulse: Cell<bool>,
+    remote_enabled: Cell<bool>,
+    remote: Cell<bool>,
+    local_lockout: Cell<bool>,
+}
+
+impl UsbtmcControl {
+    pub const fn new(interface: u8, ep_in: u8, ep_out: u8, capabilities: Capabilities) -> Self {
+        UsbtmcControl {
+            interface,
+            ep_in,
+            ep_out,
+            capabilities,
+            out_transfer: Cell::new(None),
+            out_received: Cell::new(0),
+            in_transfer: Cell::new(None),
+            in_sent: Cell::new(0),
+            out_epoch: Cell::new(0),
+            in_epoch: Cell::new(0),
+            status_byte: Cell::new(0),
+            status_tag: Cell::new(None),
+            indicator_pulse: Cell::new(false),
+            remote_enabled: Cell::new(false),
+            remote: Cell::new(false),
+            local_lockout: Cell::new(false),
+        }
+    }
+
+    /// Abandon transfers in progress and restore the local state, as on bus reset or configuration change.
+    pub fn reset(&self) {
+        self.abort_out();
+        self.abort_in();
+        self.in_transfer.set(None);
+        self.status_tag.set(None);
+        self.indicator_pulse.set(false);
+        self.remote_enabled.set(false);
+        self.remote.set(false);
+        self.local_lockout.set(false);
+    }
+
+    fn abort_out(&self) {
+        self.out_transfer.set(None);
+        self.out_epoch.set(self.out_epoch.get().wrapping_add(1));
+    }
+
+    fn abort_in(&self) {
+        self.in_epoch.set(self.in_epoch.get().wrapping_add(1));
+    }
+
+    /// Set the status byte returned by READ_STATUS_BYTE and [`service_request`](Self::service_request).
+    pub fn set_status_byte(&self, status_byte: u8) {
+        self.status_byte.set(status_byte);
+    }
+
+    /// Returns `true` once after the host sent INDICATOR_PULSE, to blink an LED.
+    pub fn take_indicator_pulse(&self) -> bool {
+        self.indicator_pulse.replace(false)
+    }
+
+    /// Returns `true` while the device is in the USB488 remote state, where front panel controls should be ignored.
+    pub fn is_remote(&self) -> bool {
+        self.remote.get()
+    }
+
+    /// Returns `true` if the host disabled the front panel's return to local control.
+    pub fn local_lockout(&self) -> bool {
+        self.local_lockout.get()
+    }
+
+    /// Returns `true` if `req` is a class request addressed to this function's interface or endpoints.
+    pub fn accepts(&self, req: &Setup) -> bool {
+        req.ty == ControlType::Class
+            && match req.recipient {
+                Recipient::Interface => req.index == self.interface as u16,
+                Recipient::Endpoint => req.index == self.ep_in as u16 || req.index == self.ep_out as u16,
+                _ => false,
+            }
+    }
+
+    /// Handle a class-specific control request.
+    ///
+    /// Requests not addressed to this function are rejected. With
+    /// [`Usb488Capabilities::interrupt_in`], follow this with
+    /// [`notify_status`](Self::notify_status) to deliver a requested status byte.
+    pub async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        use ControlData::*;
+
+        if !self.accepts(&req) {
+            return req.reject();
+        }
+
+        debug!("usbtmc request {:02x}", req.request);
+
+        let usb488 = self.capabilities.usb488.as_ref();
+
+        match req {
+            Setup { request: INITIATE_ABORT_BULK_OUT, recipient: Recipient::Endpoint, value, index, data: In(data), .. }
+                if index == self.ep_out as u16 =>
+            {
+                let tag = value as u8;
+                let response = match self.out_transfer.get() {
+                    None => [STATUS_FAILED, tag],
+                    Some(current) if current != tag => [STATUS_TRANSFER_NOT_IN_PROGRESS, current],
+                    Some(_) => {
+                        self.abort_out();
+                        [STATUS_SUCCESS, tag]
+                    }
+                };
+                data.respond(&response).await
+            }
+            Setup { request: CHECK_ABORT_BULK_OUT_STATUS, recipient: Recipient::Endpoint, index, data: In(data), .. }
+                if index == self.ep_out as u16 =>
+            {
+                let mut response = [STATUS_SUCCESS, 0, 0, 0, 0, 0, 0, 0];
+                response[4..8].copy_from_slice(&self.out_received.get().to_le_bytes());
+                data.respond(&response).await
+            }
+            Setup { request: INITIATE_ABORT_BULK_IN, recipient: Recipient::Endpoint, value, index, data: In(data), .. }
+                if index == self.ep_in as u16 =>
+            {
+                let tag = value as u8;
+                let response = match self.in_transfer.get() {
+                    None => [STATUS_FAILED, tag],
+                    Some(current) if current != tag => [STATUS_TRANSFER_NOT_IN_PROGRESS, current],
+                    Some(_) => {
+                        self.abort_in();
+                        [STATUS_SUCCESS, tag]
+                    }
+                };
+                data.respond(&response).await
+            }
+            Setup { request: CHECK_ABORT_BULK_IN_STATUS, recipient: Recipient::Endpoint, index, data: In(data), .. }
+                if index == self.ep_in as u16 =>
+            {
+                // While the transfer is finishing, the host reads the Bulk-IN
+                // endpoint until a short packet.
+                let pending = self.in_transfer.get().is_some();
+                let mut response = [if pending { STATUS_PENDING } else { STATUS_SUCCESS }, pending as u8, 0, 0, 0, 0, 0, 0];
+                response[4..8].copy_from_slice(&self.in_sent.get().to_le_bytes());
+                data.respond(&response).await
+            }
+            Setup { request: INITIATE_CLEAR, recipient: Recipient::Interface, data: In(data), .. } => {
+                self.abort_out();
+                self.abort_in();
+                data.respond(&[STATUS_SUCCESS]).await
+            }
+            Setup { request: CHECK_CLEAR_STATUS, recipient: Recipient::Interface, data: In(data), .. } => {
+                let pending = self.in_transfer.get().is_some();
+                data.respond(&[if pending { STATUS_PENDING } else { STATUS_SUCCESS }, pending as u8]).await
+            }
+            Setup { request: GET_CAPABILITIES, recipient: Recipient::Interface, data: In(data), .. } => {
+                data.respond(&capabilities_response(&self.capabilities)).await
+            }
+            Setup { request: INDICATOR_PULSE, recipient: Recipient::Interface, data: In(data), .. } => {
+                if !self.capabilities.indicator_pulse {
+                    return data.respond(&[STATUS_FAILED]).await;
+                }
+                self.indicator_pulse.set(true);
+                data.respond(&[STATUS_SUCCESS]).await
+            }
+            Setup { request: READ_STATUS_BYTE, recipient: Recipient::Interface, value, data: In(data), .. } if usb488.is_some() => {
+                let tag = value as u8 & 0x7f;
+                let response = if !usb488.is_some_and(|c| c.interrupt_in) {
+                    [STATUS_SUCCESS, tag, self.status_byte.get()]
+                } else if self.status_tag.get().is_some() {
+                    [STATUS_INTERRUPT_IN_BUSY, tag, 0]
+                } else {
+                    self.status_tag.set(Some(tag));
+                    [STATUS_SUCCESS, tag, 0]
+                };
+                data.respond(&response).await
+            }
+            Setup { request: REN_CONTROL, recipient: Recipient::Interface, value, data: In(data), .. }
+                if usb488.is_some_and(|c| c.remote_local) =>
+            {
+                let enable = value & 0x01 != 0;
+                self.remote_enabled.set(enable);
+                if !enable {
+                    self.remote.set(false);
+                    self.local_lockout.set(false);
+                }
+                data.respond(&[STATUS_SUCCESS]).await
+            }
+            Setup { request: GO_TO_LOCAL, recipient: Recipient::Interface, data: In(data), .. }
+                if usb488.is_some_and(|c| c.remote_local) =>
+            {
+                self.remote.set(false);
+                data.respond(&[STATUS_SUCCESS]).await
+            }
+            Setup { request: LOCAL_LOCKOUT, recipient: Recipient::Interface, data: In(data), .. }
+                if usb488.is_some_and(|c| c.remote_local) =>
+            {
+                self.local_lockout.set(true);
+                data.respond(&[STATUS_SUCCESS]).await
+            }
+            req => req.reject(),
+        }
+    }
+
+    /// Answer a READ_STATUS_BYTE request on the interrupt endpoint, if one is pending.
+    pub async fn notify_status<const EP: u8, const MPS: u16>(&self, ep: &mut Endpoint<In, EP, MPS>) {
+        if let Some(tag) = self.status_tag.get() {
+            self.send_interrupt(ep, NOTIFY_STATUS_BYTE | tag).await;
+            self.status_tag.set(None);
+        }
+    }
+
+    /// Send a service request (SRQ) with the current status byte on the interrupt endpoint.
+    pub async fn service_request<const EP: u8, const MPS: u16>(&self, ep: &mut Endpoint<In, EP, MPS>) {
+        self.send_interrupt(ep, NOTIFY_SERVICE_REQUEST).await;
+    }
+
+    async fn send_interrupt<const EP: u8, const MPS: u16>(&self, ep: &mut Endpoint<In, EP, MPS>, notify: u8) {
+        let mut buf = UsbBuffer::<2>::new();
+        buf[0] = notify;
+        buf[1] = self.status_byte.get();
+        ep.send(&buf, 2, false).await;
+    }
+}
+
+/// A request from the host on the Bulk-OUT endpoint.
+#[derive(Debug, Format)]
+pub enum Request<'m> {
+    /// A complete device dependent message, such as a SCPI command.
+    Message(&'m [u8]),
+    /// The host is ready to read a response of up to `max_len` bytes, which
+    /// is sent with [`UsbtmcData::respond`].
+    ///
+    /// If `term_char` is set, the response ends after the first occurrence of that byte.
+    Read { max_len: usize, term_char: Option<u8> },
+    /// USB488 TRIGGER.
+    Trigger,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Error {
+    /// The message didn't fit in the buffer, and was discarded.
+    Overflow,
+    /// The transfer was aborted by the host.
+    Aborted,
+}
+
+struct ReadRequest {
+    tag: u8,
+    max_len: usize,
+    term_char: Option<u8>,
+    epoch: u8,
+}
+
+/// Bulk endpoints of a USBTMC function, with a max packet size of `MPS` bytes.
+///
+/// Create this with the endpoints obtained when the host selects the configuration.
+pub struct UsbtmcData<'c, const EP_IN: u8, const EP_OUT: u8, const MPS: u16 = 64> {
+    control: &'c UsbtmcControl,
+    ep_in: Endpoint<In, EP_IN, MPS>,
+    ep_out: Endpoint<Out, EP_OUT, MPS>,
+    buf: UsbBuffer<CHUNK_LEN>,
+    read: Option<ReadRequest>,
+}
+
+impl<'c, const EP_IN: u8, const EP_OUT: u8, const MPS: u16> UsbtmcData<'c, EP_IN, EP_OUT, MPS> {
+    pub fn new(control: &'c UsbtmcControl, ep_in: Endpoint<In, EP_IN, MPS>, ep_out: Endpoint<Out, EP_OUT, MPS>) -> Self {
+        UsbtmcData {
+            control,
+            ep_in,
+            ep_out,
+            buf: UsbBuffer::new(),
+            read: None,
+        }
+    }
+
+    /// Wait for the next request from the host.
+    ///
+    /// A message may be split over several transfers, which are collected in
+    /// `msg` until the one marked end-of-message. A request for a response
+    /// replaces one that was not answered.
+    ///
+    /// Transfers with an invalid header or an unsupported message halt the
+    /// Bulk-OUT endpoint, as required by the specification, until the host
+    /// clears it.
+    pub async fn receive<'m>(&mut self, msg: &'m mut [u8]) -> Result<Request<'m>, Error> {
+        let control = self.control;
+        let mut len = 0;
+        let mut overflow = false;
+        let mut epoch = control.out_epoch.get();
+        // Packets left over from an aborted transfer are skipped quietly.
+        let mut resync = false;
+        // A packet that arrived after an abort, to be parsed as a header.
+        let mut carry = None;
+
+        'transfer: loop {
+            let mut n = match carry.take() {
+                Some(n) => n,
+                None => self.ep_out.receive(&mut self.buf).await,
+            };
+            if control.out_epoch.get() != epoch {
+                epoch = control.out_epoch.get();
+                (len, overflow, resync) = (0, false, true);
+            }
+            if n == 0 {
+                continue;
+            }
+
+            let header = match BulkOutHeader::parse(&self.buf[..n]) {
+                Ok(header) => header,
+                Err(e) => {
+                    if !resync {
+                        debug!("usbtmc: halting on invalid header: {}", e);
+                        self.ep_out.set_halt(true);
+                    }
+                    continue;
+                }
+            };
+            resync = false;
+
+            match header {
+                BulkOutHeader::DevDepMsgOut { tag, transfer_size, eom } => {
+                    let transfer_size = transfer_size as usize;
+                    let total = header::transfer_len(transfer_size);
+                    control.out_transfer.set(Some(tag));
+                    control.out_received.set(0);
+
+                    let mut received = n;
+                    let mut data = &self.buf[HEADER_LEN..n];
+                    loop {
+                        let take = data.len().min(transfer_size - control.out_received.get() as usize);
+                        if len + take <= msg.len() {
+                            msg[len..len + take].copy_from_slice(&data[..take]);
+                            len += take;
+                        } else {
+                            overflow = true;
+                        }
+                        control.out_received.set(control.out_received.get() + take as u32);
+
+                        // A chunk shorter than the buffer ended with a short packet
+                        if received >= total || n < CHUNK_LEN {
+                            break;
+                        }
+                        n = self.ep_out.receive(&mut self.buf).await;
+                        if control.out_epoch.get() != epoch {
+                            carry = Some(n);
+                            continue 'transfer;
+                        }
+                        received += n;
+                        data = &self.buf[..n];
+                    }
+                    control.out_transfer.set(None);
+
+                    if control.remote_enabled.get() {
+                        control.remote.set(true);
+                    }
+
+                    if eom {
+                        if overflow {
+                            return Err(Error::Overflow);
+                        }
+                        return Ok(Request::Message(&msg[..len]));
+                    }
+                }
+                BulkOutHeader::RequestDevDepMsgIn { tag, transfer_size, term_char } => {
+                    if term_char.is_some() && !control.capabilities.term_char {
+                        debug!("usbtmc: halting on unsupported TermChar");
+                        self.ep_out.set_halt(true);
+                        continue;
+                    }
+                    self.read = Some(ReadRequest {
+                        tag,
+                        max_len: transfer_size as usize,
+                        term_char,
+                        epoch: control.in_epoch.get(),
+                    });
+                    return Ok(Request::Read { max_len: transfer_size as usize, term_char });
+                }
+                BulkOutHeader::Trigger { .. } => {
+                    if !control.capabilities.usb488.as_ref().is_some_and(|c| c.trigger) {
+                        debug!("usbtmc: halting on unsupported TRIGGER");
+                        self.ep_out.set_halt(true);
+                        continue;
+                    }
+                    return Ok(Request::Trigger);
+                }
+            }
+        }
+    }
+
+    /// Send a response to the last [`Request::Read`].
+    ///
+    /// Up to `max_len` bytes of `data` are sent, marked end-of-message if
+    /// that is all of it or it ends at the requested termination character.
+    /// Returns the number of bytes sent, and the rest can be sent after the
+    /// host's next read request. Without a pending read request nothing is
+    /// sent and 0 is returned.
+    pub async fn respond(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let control = self.control;
+        let Some(read) = self.read.take() else {
+            return Ok(0);
+        };
+        if read.epoch != control.in_epoch.get() {
+            return Err(Error::Aborted);
+        }
+
+        let mut len = data.len().min(read.max_len);
+        let mut term = false;
+        if let Some(c) = read.term_char
+            && let Some(pos) = data[..len].iter().position(|&b| b == c)
+        {
+            len = pos + 1;
+            term = true;
+        }
+        let eom = term || len == data.len();
+
+        let mut header = [0; HEADER_LEN];
+        header::write_dev_dep_msg_in(&mut header, read.tag, len as u32, eom, term);
+
+        control.in_transfer.set(Some(read.tag));
+        control.in_sent.set(0);
+
+        let total = header::transfer_len(len);
+        let mut sent = 0;
+        let mut result = Ok(len);
+        while sent < total {
+            let n = (total - sent).min(CHUNK_LEN);
+            for (i, b) in self.buf[..n].iter_mut().enumerate() {
+                let pos = sent + i;
+                *b = if pos < HEADER_LEN {
+                    header[pos]
+                } else {
+                    data[..len].get(pos - HEADER_LEN).copied().unwrap_or(0)
+                };
+            }
+            sent += n;
+            let last = sent == total;
+
+            // A transfer that fills the last packet ends with a zero-length packet
+            self.ep_in.send(&self.buf, n, last).await;
+            control.in_sent.set((sent.saturating_sub(HEADER_LEN)).min(len) as u32);
+
+            if control.in_epoch.get() != read.epoch {
+                debug!("usbtmc: response aborted after {} bytes", control.in_sent.get());
+                if !last && n == CHUNK_LEN {
+                    self.ep_in.send(&self.buf, 0, false).await;
+                }
+                result = Err(Error::Aborted);
+                break;
+            }
+        }
+
+        control.in_transfer.set(None);
+        result
+    }
+}
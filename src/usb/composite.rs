@@ -0,0 +1,461 @@
+//! Composite devices built from independent functions.
+//!
+//! Each function (e.g. a network interface, a vendor interface, ...) implements
+//! [`Function`], declaring the interfaces and endpoints it uses and handling
+//! requests for them. A device implementing [`CompositeDevice`] lists its
+//! functions as a tuple, and [`Composite`] implements [`Handler`] for it by
+//! routing each request to the function that owns the interface or endpoint.
+//!
+//! Interface numbers and endpoint addresses are assigned in const context with
+//! an [`Allocator`], and passed to the functions as const generic parameters so
+//! that they can use them for their [`Endpoint`](crate::usb::Endpoint) types:
+//!
+//! ```rust,ignore
+//! struct Numbers {
+//!     net_comm: u8,
+//!     net_notify: u8,
+//!     net_in: u8,
+//!     net_out: u8,
+//!     vendor: u8,
+//!     vendor_in: u8,
+//! }
+//!
+//! const N: Numbers = {
+//!     let mut a = Allocator::new();
+//!     Numbers {
+//!         net_comm: a.interfaces(2),
+//!         net_notify: a.endpoint_in(),
+//!         net_in: a.endpoint_in(),
+//!         net_out: a.endpoint_out(),
+//!         vendor: a.interfaces(1),
+//!         vendor_in: a.endpoint_in(),
+//!     }
+//! };
+//!
+//! type Net = NetFunction<{ N.net_comm }, { N.net_notify }, { N.net_in }, { N.net_out }>;
+//! type Vendor = VendorFunction<{ N.vendor }, { N.vendor_in }>;
+//!
+//! impl CompositeDevice for MyDevice {
+//!     type Functions = (Net, Vendor);
+//!     const DEVICE_DESCRIPTOR: &'static [u8] = DEVICE_DESCRIPTOR;
+//!     const CONFIG_DESCRIPTOR: &'static [u8] = composite_config! {
+//!         Config {
+//!             bConfigurationValue: 1,
+//!             iConfiguration: 0,
+//!             bmAttributes: 0x80,
+//!             bMaxPower: 100,
+//!         }
+//!         functions: [Net, Vendor]
+//!     };
+//!
+//!     fn functions(&self) -> &Self::Functions {
+//!         &self.functions
+//!     }
+//! }
+//!
+//! hw.usb.run_device(&mut Composite(MyDevice { ... })).await;
+//! ```
+//!
+//! An Interface Association Descriptor is emitted for each function with more
+//! than one interface, so the device descriptor should use
+//! `bDeviceClass: 0xEF, bDeviceSubClass: 0x02, bDeviceProtocol: 0x01`.
+//!
+//! Functions that need MS OS 2.0 features, such as a WinUSB compatible ID,
+//! declare them in [`Function::MS_OS_FEATURES`], and [`composite_msos!`](crate::composite_msos)
+//! builds the descriptor set with a function subset for each of them.
+
+use defmt::debug;
+
+use super::{
+    descriptors::{DescriptorBuilder, InterfaceAssociation, MicrosoftOsFunction, MAX_ENDPOINT},
+    Endpoints, Handler, Recipient, Responded, Setup,
+};
+
+/// Assigns interface numbers and endpoint addresses in const context.
+pub struct Allocator {
+    interface: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+}
+
+impl Allocator {
+    pub const fn new() -> Self {
+        Allocator {
+            interface: 0,
+            endpoint_in: 1,
+            endpoint_out: 1,
+        }
+    }
+
+    /// Allocate `count` consecutive interface numbers, returning the first.
+    pub const fn interfaces(&mut self, count: u8) -> u8 {
+        let first = self.interface;
+        assert!(count > 0, "a function needs at least one interface");
+        assert!(count <= u8::MAX - first, "out of interface numbers");
+        self.interface += count;
+        first
+    }
+
+    /// Allocate an IN endpoint, returning its address.
+    pub const fn endpoint_in(&mut self) -> u8 {
+        let ep = self.endpoint_in;
+        assert!(ep <= MAX_ENDPOINT, "out of IN endpoints");
+        self.endpoint_in += 1;
+        ep | usb::endpoint_address::IN
+    }
+
+    /// Allocate an OUT endpoint, returning its address.
+    pub const fn endpoint_out(&mut self) -> u8 {
+        let ep = self.endpoint_out;
+        assert!(ep <= MAX_ENDPOINT, "out of OUT endpoints");
+        self.endpoint_out += 1;
+        ep | usb::endpoint_address::OUT
+    }
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One function of a composite device.
+///
+/// Requests addressed to one of the function's interfaces or endpoints are
+/// passed to it by [`Composite`].
+#[allow(async_fn_in_trait)]
+pub trait Function {
+    /// Interface number of the first interface of this function.
+    const FIRST_INTERFACE: u8;
+
+    /// Number of consecutive interfaces used by this function.
+    const INTERFACES: u8;
+
+    /// `bFunctionClass`, `bFunctionSubClass`, and `bFunctionProtocol` for the Interface Association Descriptor.
+    const CLASS: (u8, u8, u8);
+
+    /// `iFunction` string index for the Interface Association Descriptor.
+    const STRING: u8 = 0;
+
+    /// Interface, endpoint, and class-specific descriptors of the function.
+    ///
+    /// These must use the interface numbers and endpoint addresses assigned to
+    /// the function. [`descriptors::concat`](super::descriptors::concat) can
+    /// be used to build them from const generic parameters.
+    const DESCRIPTORS: &'static [u8];
+
+    /// MS OS 2.0 feature descriptors of the function, such as
+    /// [`MicrosoftOsCompatibleID`](super::descriptors::MicrosoftOsCompatibleID).
+    ///
+    /// [`composite_msos!`](crate::composite_msos) places them in a function
+    /// subset for [`Function::FIRST_INTERFACE`]. Functions without any are
+    /// left out of the descriptor set.
+    const MS_OS_FEATURES: &'static [u8] = &[];
+
+    /// Returns `true` if interface `intf` belongs to this function.
+    fn owns_interface(intf: u8) -> bool {
+        intf >= Self::FIRST_INTERFACE && intf - Self::FIRST_INTERFACE < Self::INTERFACES
+    }
+
+    /// Returns `true` if endpoint address `ep` appears in this function's descriptors.
+    fn owns_endpoint(ep: u8) -> bool {
+        let mut d = Self::DESCRIPTORS;
+        while d.len() >= 3 && d[0] >= 2 {
+            if d[1] == usb::descriptor_type::ENDPOINT && d[2] == ep {
+                return true;
+            }
+            d = &d[(d[0] as usize).min(d.len())..];
+        }
+        false
+    }
+
+    /// Called on bus reset.
+    ///
+    /// By default, this calls [`Function::deconfigure`].
+    fn handle_reset(&self) {
+        self.deconfigure();
+    }
+
+    /// Called when the host selects the configuration, with all interfaces at alternate setting 0.
+    async fn configure(&self, endpoints: &mut Endpoints) {
+        let _ = endpoints;
+    }
+
+    /// Called when the configuration is deselected. Drop any endpoints here.
+    fn deconfigure(&self) {}
+
+    /// Handle SET_INTERFACE for one of this function's interfaces.
+    async fn set_interface(&self, intf: u8, alt: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        let _ = (intf, endpoints);
+        if alt == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Handle a control request addressed to one of this function's interfaces or endpoints.
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        req.reject()
+    }
+}
+
+fn owns_request<F: Function>(req: &Setup) -> bool {
+    match req.recipient {
+        Recipient::Interface => u8::try_from(req.index).is_ok_and(F::owns_interface),
+        Recipient::Endpoint => u8::try_from(req.index).is_ok_and(F::owns_endpoint),
+        _ => false,
+    }
+}
+
+/// Interface Association Descriptor for `F`.
+pub const fn association<F: Function>() -> [u8; InterfaceAssociation::LEN] {
+    InterfaceAssociation {
+        bFirstInterface: F::FIRST_INTERFACE,
+        bInterfaceCount: F::INTERFACES,
+        bFunctionClass: F::CLASS.0,
+        bFunctionSubClass: F::CLASS.1,
+        bFunctionProtocol: F::CLASS.2,
+        iFunction: F::STRING,
+    }.bytes(&[])
+}
+
+/// MS OS 2.0 function subset header for `F`, covering its [`Function::MS_OS_FEATURES`].
+pub const fn ms_os_function<F: Function>() -> [u8; MicrosoftOsFunction::LEN] {
+    MicrosoftOsFunction {
+        first_interface: F::FIRST_INTERFACE,
+    }.bytes(&[F::MS_OS_FEATURES])
+}
+
+/// A tuple of [`Function`]s.
+#[allow(async_fn_in_trait)]
+pub trait Functions {
+    fn handle_reset(&self);
+
+    async fn configure(&self, endpoints: &mut Endpoints);
+
+    fn deconfigure(&self);
+
+    async fn set_interface(&self, intf: u8, alt: u8, endpoints: &mut Endpoints) -> Result<(), ()>;
+
+    /// Pass the request to the function that owns it, or return it if there is none.
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Result<Responded, Setup<'a>>;
+}
+
+macro_rules! impl_functions {
+    ($($f:ident $i:tt),*) => {
+        impl<$($f: Function),*> Functions for ($($f,)*) {
+            fn handle_reset(&self) {
+                $(self.$i.handle_reset();)*
+            }
+
+            async fn configure(&self, endpoints: &mut Endpoints) {
+                $(self.$i.configure(endpoints).await;)*
+            }
+
+            fn deconfigure(&self) {
+                $(self.$i.deconfigure();)*
+            }
+
+            async fn set_interface(&self, intf: u8, alt: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+                $(
+                    if $f::owns_interface(intf) {
+                        return self.$i.set_interface(intf, alt, endpoints).await;
+                    }
+                )*
+                Err(())
+            }
+
+            async fn handle_control<'a>(&self, req: Setup<'a>) -> Result<Responded, Setup<'a>> {
+                $(
+                    if owns_request::<$f>(&req) {
+                        return Ok(self.$i.handle_control(req).await);
+                    }
+                )*
+                Err(req)
+            }
+        }
+    };
+}
+
+impl_functions!(A 0);
+impl_functions!(A 0, B 1);
+impl_functions!(A 0, B 1, C 2);
+impl_functions!(A 0, B 1, C 2, D 3);
+impl_functions!(A 0, B 1, C 2, D 3, E 4);
+impl_functions!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// A device made up of multiple [`Function`]s.
+#[allow(async_fn_in_trait)]
+pub trait CompositeDevice {
+    type Functions: Functions;
+
+    /// Device descriptor.
+    const DEVICE_DESCRIPTOR: &'static [u8];
+
+    /// Configuration descriptor, usually built with [`composite_config!`](crate::composite_config).
+    const CONFIG_DESCRIPTOR: &'static [u8];
+
+    fn functions(&self) -> &Self::Functions;
+
+    /// Returns `true` if the device is currently self-powered, as reported by GET_STATUS.
+    fn self_powered(&self) -> bool {
+        false
+    }
+
+    /// Called when the host suspends the bus.
+    fn handle_suspend(&self) {}
+
+    /// Called when the bus resumes from suspend.
+    fn handle_resume(&self) {}
+
+    /// Return descriptors other than the device and configuration descriptors, such as strings.
+    fn get_descriptor<'a>(&self, _kind: u8, _index: u8, _lang: u16, _builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        None
+    }
+
+    /// Handle a request that is not addressed to any function, such as a vendor request to the device.
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        req.reject()
+    }
+}
+
+/// Implements [`Handler`] for a [`CompositeDevice`].
+pub struct Composite<D>(pub D);
+
+impl<D: CompositeDevice> Handler for Composite<D> {
+    fn handle_reset(&self) {
+        debug!("usb reset");
+        self.0.functions().handle_reset();
+    }
+
+    fn self_powered(&self) -> bool {
+        self.0.self_powered()
+    }
+
+    fn handle_suspend(&self) {
+        debug!("usb suspend");
+        self.0.handle_suspend();
+    }
+
+    fn handle_resume(&self) {
+        debug!("usb resume");
+        self.0.handle_resume();
+    }
+
+    fn get_descriptor<'a>(&self, kind: u8, index: u8, lang: u16, builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        use usb::descriptor_type::{CONFIGURATION, DEVICE};
+        match (kind, index) {
+            (DEVICE, _) => Some(D::DEVICE_DESCRIPTOR),
+            (CONFIGURATION, 0) => Some(D::CONFIG_DESCRIPTOR),
+            _ => self.0.get_descriptor(kind, index, lang, builder),
+        }
+    }
+
+    async fn set_configuration(&self, cfg: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        // bConfigurationValue
+        let value = D::CONFIG_DESCRIPTOR[5];
+
+        if cfg == 0 {
+            self.0.functions().deconfigure();
+            Ok(())
+        } else if cfg == value {
+            self.0.functions().deconfigure();
+            self.0.functions().configure(endpoints).await;
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    async fn set_interface(&self, intf: u8, alt: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        self.0.functions().set_interface(intf, alt, endpoints).await
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        match self.0.functions().handle_control(req).await {
+            Ok(r) => r,
+            Err(req) => self.0.handle_control(req).await,
+        }
+    }
+}
+
+/// Build the configuration descriptor of a composite device.
+///
+/// This emits the configuration descriptor followed by each function's
+/// descriptors, preceded by an Interface Association Descriptor for
+/// functions with more than one interface.
+///
+/// ```rust,ignore
+/// const CONFIG_DESCRIPTOR: &[u8] = composite_config! {
+///     Config {
+///         bConfigurationValue: 1,
+///         iConfiguration: 0,
+///         bmAttributes: 0x80,
+///         bMaxPower: 100,
+///     }
+///     functions: [Net, Vendor]
+/// };
+/// ```
+#[macro_export]
+macro_rules! composite_config {
+    (
+        Config {
+            $($field:ident: $value:expr,)*
+        }
+        functions: [$($function:ty),* $(,)?]
+    ) => {
+        $crate::usb::descriptors::descriptors!(@concat $crate::usb::descriptors::Config { $($field: $value,)* } [
+            $(
+                {
+                    const IAD: [u8; $crate::usb::descriptors::InterfaceAssociation::LEN] = $crate::usb::composite::association::<$function>();
+                    if <$function as $crate::usb::composite::Function>::INTERFACES > 1 { &IAD } else { &[] }
+                },
+                <$function as $crate::usb::composite::Function>::DESCRIPTORS
+            ),*
+        ])
+    };
+}
+
+pub use composite_config;
+
+/// Build the MS OS 2.0 descriptor set of a composite device.
+///
+/// This emits the descriptor set header and a configuration subset holding a
+/// function subset for each function with [`Function::MS_OS_FEATURES`].
+/// Return it for the MS OS 2.0 vendor request, and use its length for
+/// `msos_descriptor_len` in the
+/// [`PlatformCapabilityMicrosoftOs`](super::descriptors::PlatformCapabilityMicrosoftOs)
+/// capability.
+///
+/// ```rust,ignore
+/// const MSOS_DESCRIPTOR: &[u8] = composite_msos! {
+///     MicrosoftOs {
+///         windows_version: 0x06030000,
+///     }
+///     functions: [Net, Vendor]
+/// };
+/// ```
+#[macro_export]
+macro_rules! composite_msos {
+    (
+        MicrosoftOs {
+            $($field:ident: $value:expr,)*
+        }
+        functions: [$($function:ty),* $(,)?]
+    ) => {
+        $crate::usb::descriptors::descriptors!(@concat $crate::usb::descriptors::MicrosoftOs { $($field: $value,)* } [
+            $crate::usb::descriptors::descriptors!(@concat $crate::usb::descriptors::MicrosoftOsConfiguration { configuration_value: 0, } [
+                $(
+                    {
+                        const HEADER: [u8; $crate::usb::descriptors::MicrosoftOsFunction::LEN] = $crate::usb::composite::ms_os_function::<$function>();
+                        if <$function as $crate::usb::composite::Function>::MS_OS_FEATURES.is_empty() { &[] } else { &HEADER }
+                    },
+                    <$function as $crate::usb::composite::Function>::MS_OS_FEATURES
+                ),*
+            ])
+        ])
+    };
+}
+
+pub use composite_msos;
@@ -1,20 +1,16 @@
+use super::{ControlData, ControlType, Recipient, Responded, Setup};
+
 #[macro_export]
 macro_rules! descriptors {
+    // Internal: concatenate a descriptor with already-serialized children
     (
-        $struct:path {
+        @concat $struct:path {
             $($field:ident: $value:expr,)*
-
-            $(+$child:path { $($inner:tt)* })*
         }
+        [$($child:expr),*]
     ) => {{
         use $struct as Desc; // https://github.com/rust-lang/rust/issues/48067
-        const CHILDREN: &[&[u8]] = {
-            &[
-                $(
-                    descriptors!($child { $($inner)* })
-                ),*
-            ]
-        };
+        const CHILDREN: &[&[u8]] = &[$($child),*];
         const LEN: usize = {
             let mut i = 0;
             let mut len = Desc::LEN;
@@ -47,7 +43,21 @@ macro_rules! descriptors {
         };
 
         &ARR
-    }}
+    }};
+
+    (
+        $struct:path {
+            $($field:ident: $value:expr,)*
+
+            $(+$child:path { $($inner:tt)* })*
+        }
+    ) => {
+        descriptors!(@concat $struct { $($field: $value,)* } [
+            $(
+                descriptors!($child { $($inner)* })
+            ),*
+        ])
+    };
 }
 
 pub use descriptors;
@@ -110,26 +120,113 @@ impl Config {
     pub const LEN: usize = 9;
     pub const DESCRIPTOR_TYPE: u8 = usb::descriptor_type::CONFIGURATION;
 
+    /// Serialize the configuration descriptor header for `children`.
+    ///
+    /// Fails const evaluation if the interfaces and endpoints that follow are
+    /// inconsistent:
+    ///
+    /// * interface numbers must be contiguous, and the alternate settings of
+    ///   an interface numbered 0, 1, ... in order;
+    /// * an endpoint address may only be reused by another alternate setting
+    ///   of the same interface;
+    /// * endpoint numbers and `wMaxPacketSize` must be supported by the
+    ///   controller;
+    /// * an Interface Association Descriptor must precede its first interface,
+    ///   and a multi-interface function (CDC, audio or video) must have one
+    ///   unless it is the only function in the configuration.
     pub const fn bytes(self, children: &[&[u8]]) -> [u8; Self::LEN] {
         let mut total_len = Self::LEN as u16;
         let mut interface_number = 0;
+        let mut alternate_setting = 0;
         let mut num_interfaces = 0;
 
+        // Endpoints of earlier interfaces, of any alternate setting of the
+        // current interface, and of the current alternate setting.
+        let mut ep_other = 0u32;
+        let mut ep_interface = 0u32;
+        let mut ep_alternate = 0u32;
+
+        let mut iad_first = 0;
+        let mut iad_end = 0;
+        let mut iad_pending = false;
+        let mut uncovered_functions = 0;
+        let mut other_interfaces = 0;
+
         let mut i = 0;
         while i < children.len() {
             total_len += children[i].len() as u16;
-            if children[i][1] == Interface::DESCRIPTOR_TYPE {
-                let intf = children[i][2];
+
+            // A child may contain several descriptors, e.g. a whole composite function
+            let child = children[i];
+            let mut offset = 0;
+            while offset < child.len() {
+                assert!(child[offset] >= 2, "invalid descriptor length");
+                let desc_type = child[offset + 1];
                 assert!(
-                    intf == interface_number || intf == interface_number + 1,
-                    "interface numbers must be contiguous"
+                    !iad_pending || desc_type == Interface::DESCRIPTOR_TYPE,
+                    "interface association must be followed by its first interface"
                 );
-                interface_number = intf;
-                num_interfaces = intf + 1;
+
+                if desc_type == Interface::DESCRIPTOR_TYPE {
+                    let intf = child[offset + 2];
+                    let alt = child[offset + 3];
+                    let class = (child[offset + 5], child[offset + 6]);
+                    if num_interfaces == 0 || intf != interface_number {
+                        assert!(intf == num_interfaces, "interface numbers must be contiguous");
+                        assert!(alt == 0, "first alternate setting must be 0");
+                        ep_other |= ep_interface;
+                        ep_interface = 0;
+
+                        if intf < iad_end {
+                            other_interfaces += 1;
+                        } else if is_multi_interface_function(class) {
+                            uncovered_functions += 1;
+                        } else if !is_function_companion(class) {
+                            other_interfaces += 1;
+                        }
+                    } else {
+                        assert!(alt == alternate_setting + 1, "alternate settings must be numbered in order");
+                    }
+                    assert!(!iad_pending || intf == iad_first, "interface association must be followed by its first interface");
+                    iad_pending = false;
+
+                    interface_number = intf;
+                    alternate_setting = alt;
+                    num_interfaces = intf + 1;
+                    ep_alternate = 0;
+                } else if desc_type == Endpoint::DESCRIPTOR_TYPE {
+                    assert!(num_interfaces > 0, "endpoint outside of an interface");
+                    let address = child[offset + 2];
+                    let attributes = child[offset + 3];
+                    let max_packet_size = u16::from_le_bytes([child[offset + 4], child[offset + 5]]);
+                    check_endpoint_descriptor(address, attributes, max_packet_size);
+
+                    let mask = super::ep_enabled_mask(address);
+                    assert!(ep_alternate & mask == 0, "duplicate endpoint address in interface");
+                    assert!(ep_other & mask == 0, "endpoint address used by another interface");
+                    ep_alternate |= mask;
+                    ep_interface |= mask;
+                } else if desc_type == InterfaceAssociation::DESCRIPTOR_TYPE {
+                    let first = child[offset + 2];
+                    let count = child[offset + 3];
+                    assert!(count > 0, "interface association must contain an interface");
+                    assert!(first == num_interfaces, "interface association must precede its first interface");
+                    iad_first = first;
+                    iad_end = first + count;
+                    iad_pending = true;
+                }
+                offset += child[offset] as usize;
             }
             i += 1;
         }
 
+        assert!(!iad_pending, "interface association must be followed by its first interface");
+        assert!(iad_end <= num_interfaces, "interface association refers to missing interfaces");
+        assert!(
+            uncovered_functions == 0 || (uncovered_functions == 1 && other_interfaces == 0),
+            "multi-interface function requires an interface association descriptor"
+        );
+
         [
             Self::LEN as u8,
             Self::DESCRIPTOR_TYPE,
@@ -142,8 +239,120 @@ impl Config {
             self.bMaxPower,
         ]
     }
+
+    /// Look up the first endpoint descriptor for `address` in a serialized
+    /// configuration descriptor.
+    pub const fn find_endpoint(config: &[u8], address: u8) -> Option<Endpoint> {
+        let mut offset = 0;
+        while offset + 1 < config.len() {
+            let len = config[offset] as usize;
+            assert!(len >= 2 && offset + len <= config.len(), "invalid descriptor length");
+            if config[offset + 1] == Endpoint::DESCRIPTOR_TYPE && len >= Endpoint::LEN && config[offset + 2] == address {
+                return Some(Endpoint {
+                    bEndpointAddress: address,
+                    bmAttributes: config[offset + 3],
+                    wMaxPacketSize: u16::from_le_bytes([config[offset + 4], config[offset + 5]]),
+                    bInterval: config[offset + 6],
+                });
+            }
+            offset += len;
+        }
+        None
+    }
+
+    /// Check that `config` describes endpoint `address` with one of the
+    /// `transfer_types` and a `wMaxPacketSize` of `max_packet_size`, in any
+    /// alternate setting.
+    ///
+    /// Called in const context by [`DescribedEndpoints`](super::DescribedEndpoints)
+    /// to fail the build when an enabled endpoint does not match its descriptor.
+    pub const fn check_endpoint(config: &[u8], address: u8, transfer_types: &[u8], max_packet_size: u16) {
+        let mut found = false;
+        let mut offset = 0;
+        while offset + 1 < config.len() {
+            let len = config[offset] as usize;
+            assert!(len >= 2 && offset + len <= config.len(), "invalid descriptor length");
+            if config[offset + 1] == Endpoint::DESCRIPTOR_TYPE && len >= Endpoint::LEN && config[offset + 2] == address {
+                found = true;
+                let transfer_type = config[offset + 3] & TRANSFER_TYPE_MASK;
+                let mps = u16::from_le_bytes([config[offset + 4], config[offset + 5]]);
+
+                let mut i = 0;
+                while i < transfer_types.len() {
+                    if transfer_types[i] == transfer_type && mps == max_packet_size {
+                        return;
+                    }
+                    i += 1;
+                }
+            }
+            offset += len;
+        }
+        assert!(found, "endpoint is not in the configuration descriptor");
+        panic!("endpoint transfer type or wMaxPacketSize does not match the configuration descriptor");
+    }
+}
+
+/// A serialized configuration descriptor, for checking the endpoints enabled
+/// in [`Handler::set_configuration`](super::Handler::set_configuration)
+/// against it at compile time.
+///
+/// ```ignore
+/// struct Cfg;
+/// impl ConfigDescriptor for Cfg {
+///     const DESCRIPTOR: &'static [u8] = CONFIG_DESCRIPTOR;
+/// }
+///
+/// let ep_in = endpoints.described::<Cfg>().bulk_in::<EP_IN>();
+/// ```
+pub trait ConfigDescriptor {
+    const DESCRIPTOR: &'static [u8];
+}
+
+/// Highest endpoint number supported by all backends.
+pub const MAX_ENDPOINT: u8 = 7;
+
+const TRANSFER_TYPE_MASK: u8 = 0x03;
+
+const fn check_endpoint_descriptor(address: u8, attributes: u8, max_packet_size: u16) {
+    use usb::endpoint_attributes::transfer_type::{BULK, CONTROL, INTERRUPT, ISOCHRONOUS};
+
+    let number = address & usb::endpoint_address::ADDR_MASK;
+    assert!(number != 0, "endpoint 0 must not be described in the configuration");
+    assert!(number <= MAX_ENDPOINT, "endpoint number not supported by the controller");
+    assert!(address & !(usb::endpoint_address::DIR_MASK | usb::endpoint_address::ADDR_MASK) == 0, "invalid endpoint address");
+
+    match attributes & TRANSFER_TYPE_MASK {
+        CONTROL => panic!("control endpoints other than endpoint 0 are not supported"),
+        BULK => assert!(
+            matches!(max_packet_size, 8 | 16 | 32 | 64),
+            "bulk wMaxPacketSize must be 8, 16, 32, or 64"
+        ),
+        INTERRUPT => assert!(max_packet_size <= 64, "interrupt wMaxPacketSize must be at most 64"),
+        ISOCHRONOUS => assert!(
+            max_packet_size <= super::MAX_ISO_PACKET_SIZE,
+            "isochronous wMaxPacketSize too large for a full-speed endpoint"
+        ),
+        _ => unreachable!(),
+    }
 }
 
+/// Whether an interface with this class and subclass is the first of a
+/// function that spans several interfaces.
+const fn is_multi_interface_function((class, subclass): (u8, u8)) -> bool {
+    matches!((class, subclass), (CLASS_CDC, _) | (CLASS_AUDIO, SUBCLASS_CONTROL) | (CLASS_VIDEO, SUBCLASS_CONTROL))
+}
+
+/// Whether an interface with this class belongs to a preceding multi-interface function.
+const fn is_function_companion((class, _): (u8, u8)) -> bool {
+    matches!(class, CLASS_CDC_DATA | CLASS_AUDIO | CLASS_VIDEO)
+}
+
+const CLASS_AUDIO: u8 = 0x01;
+const CLASS_CDC: u8 = 0x02;
+const CLASS_CDC_DATA: u8 = 0x0a;
+const CLASS_VIDEO: u8 = 0x0e;
+const SUBCLASS_CONTROL: u8 = 0x01;
+
 /// Fields of a USB interface descriptor.
 ///
 /// The `bLength` and `bDescriptorType` are fixed. `bNumEndpoints` is populated from the
@@ -186,6 +395,40 @@ impl Interface {
     }
 }
 
+/// Interface Association Descriptor, grouping the interfaces of one function
+/// of a composite device.
+#[allow(non_snake_case)]
+pub struct InterfaceAssociation {
+    pub bFirstInterface: u8,
+    pub bInterfaceCount: u8,
+    pub bFunctionClass: u8,
+    pub bFunctionSubClass: u8,
+    pub bFunctionProtocol: u8,
+    pub iFunction: u8,
+}
+
+impl InterfaceAssociation {
+    pub const LEN: usize = 8;
+    pub const DESCRIPTOR_TYPE: u8 = DESCRIPTOR_TYPE_INTERFACE_ASSOCIATION;
+
+    pub const fn bytes(self, children: &[&[u8]]) -> [u8; Self::LEN] {
+        assert!(children.is_empty());
+
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            self.bFirstInterface,
+            self.bInterfaceCount,
+            self.bFunctionClass,
+            self.bFunctionSubClass,
+            self.bFunctionProtocol,
+            self.iFunction,
+        ]
+    }
+}
+
+const DESCRIPTOR_TYPE_INTERFACE_ASSOCIATION: u8 = 0x0b;
+
 #[allow(non_snake_case)]
 pub struct Endpoint {
     pub bEndpointAddress: u8,
@@ -211,6 +454,30 @@ impl Endpoint {
     }
 }
 
+/// Concatenate descriptors into an array of exactly `N` bytes.
+///
+/// This is for building descriptors in a `const fn` from runtime parameters,
+/// where [`descriptors!`] can't be used.
+pub const fn concat<const N: usize>(parts: &[&[u8]]) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    let mut len = 0;
+
+    let mut i = 0;
+    while i < parts.len() {
+        let mut j = 0;
+        while j < parts[i].len() {
+            assert!(len < N, "descriptors longer than declared length");
+            bytes[len] = parts[i][j];
+            len += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+
+    assert!(len == N, "descriptors shorter than declared length");
+    bytes
+}
+
 pub struct BinaryObjectStore {}
 
 impl BinaryObjectStore {
@@ -470,7 +737,543 @@ impl MicrosoftOsCcgp {
     }
 }
 
+/// String descriptor index requested by Windows to detect MS OS 1.0 descriptor support.
+pub const MS_OS_10_STRING_INDEX: u8 = 0xee;
+
+/// `wIndex` of the vendor request for [`MicrosoftOs10ExtendedCompatId`].
+pub const MS_OS_10_EXTENDED_COMPAT_ID: u16 = 0x0004;
+
+/// `wIndex` of the vendor request for [`MicrosoftOs10ExtendedProperties`].
+pub const MS_OS_10_EXTENDED_PROPERTIES: u16 = 0x0005;
+
+/// MS OS 1.0 string descriptor, returned for string index [`MS_OS_10_STRING_INDEX`].
+///
+/// The host then reads the feature descriptors with a device (compat ID) or
+/// interface (properties) vendor request using `vendor_code` as `bRequest`
+/// and the descriptor index in `wIndex`. Windows 8.1 and later prefer the
+/// MS OS 2.0 descriptors from [`PlatformCapabilityMicrosoftOs`] if both are present.
+pub struct MicrosoftOs10String {
+    pub vendor_code: u8,
+}
+
+impl MicrosoftOs10String {
+    pub const LEN: usize = 18;
+    pub const DESCRIPTOR_TYPE: u8 = usb::descriptor_type::STRING;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = Self::LEN as u8;
+        bytes[1] = Self::DESCRIPTOR_TYPE;
+
+        let signature = b"MSFT100";
+        let mut i = 0;
+        while i < signature.len() {
+            bytes[2 + i * 2] = signature[i];
+            i += 1;
+        }
+
+        bytes[16] = self.vendor_code;
+        bytes
+    }
+}
+
+/// MS OS 1.0 extended compat ID descriptor, listing a [`MicrosoftOs10CompatibleID`]
+/// for each function.
+///
+/// Windows reads it with a device vendor request with the vendor code from
+/// [`MicrosoftOs10String`] as `bRequest` and `wIndex` set to
+/// [`MS_OS_10_EXTENDED_COMPAT_ID`].
+pub struct MicrosoftOs10ExtendedCompatId {}
+
+impl MicrosoftOs10ExtendedCompatId {
+    pub const LEN: usize = 16;
+
+    pub const fn bytes(self, children: &[&[u8]]) -> [u8; Self::LEN] {
+        let mut total_len = Self::LEN as u32;
+        let mut i = 0;
+        while i < children.len() {
+            assert!(children[i].len() == MicrosoftOs10CompatibleID::LEN);
+            total_len += children[i].len() as u32;
+            i += 1;
+        }
+
+        let mut bytes = [0; Self::LEN];
+        let (len, rest) = bytes.split_at_mut(4);
+        len.copy_from_slice(&total_len.to_le_bytes());
+        rest[0] = 0x00; // bcdVersion 1.00
+        rest[1] = 0x01;
+        rest[2] = MS_OS_10_EXTENDED_COMPAT_ID.to_le_bytes()[0];
+        rest[3] = MS_OS_10_EXTENDED_COMPAT_ID.to_le_bytes()[1];
+        rest[4] = children.len() as u8;
+        bytes
+    }
+}
+
+/// Function section of [`MicrosoftOs10ExtendedCompatId`], e.g. `"WINUSB"` to
+/// bind WinUSB to the function starting at `first_interface`.
+pub struct MicrosoftOs10CompatibleID {
+    pub first_interface: u8,
+    pub compatible_id: &'static str,
+    pub sub_compatible_id: &'static str,
+}
+
+impl MicrosoftOs10CompatibleID {
+    pub const LEN: usize = 24;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = self.first_interface;
+        bytes[1] = 0x01; // reserved
+
+        let src = self.compatible_id.as_bytes();
+        assert!(src.len() <= 8);
+        let dst = bytes.split_at_mut(2).1.split_at_mut(src.len()).0;
+        dst.copy_from_slice(src);
+
+        let src = self.sub_compatible_id.as_bytes();
+        assert!(src.len() <= 8);
+        let dst = bytes.split_at_mut(10).1.split_at_mut(src.len()).0;
+        dst.copy_from_slice(src);
+
+        bytes
+    }
+}
+
+/// MS OS 1.0 extended properties descriptor of one interface, holding
+/// registry properties such as a [`MicrosoftOs10DeviceInterfaceGUID`].
+///
+/// Windows reads it with an interface vendor request with the vendor code
+/// from [`MicrosoftOs10String`] as `bRequest`, `wIndex` set to
+/// [`MS_OS_10_EXTENDED_PROPERTIES`] and the interface number in the high byte
+/// of `wValue`.
+pub struct MicrosoftOs10ExtendedProperties {}
+
+impl MicrosoftOs10ExtendedProperties {
+    pub const LEN: usize = 10;
+
+    pub const fn bytes(self, children: &[&[u8]]) -> [u8; Self::LEN] {
+        let mut total_len = Self::LEN as u32;
+        let mut i = 0;
+        while i < children.len() {
+            total_len += children[i].len() as u32;
+            i += 1;
+        }
+
+        let mut bytes = [0; Self::LEN];
+        let (len, rest) = bytes.split_at_mut(4);
+        len.copy_from_slice(&total_len.to_le_bytes());
+        rest[0] = 0x00; // bcdVersion 1.00
+        rest[1] = 0x01;
+        rest[2] = MS_OS_10_EXTENDED_PROPERTIES.to_le_bytes()[0];
+        rest[3] = MS_OS_10_EXTENDED_PROPERTIES.to_le_bytes()[1];
+        rest[4] = children.len() as u8;
+        bytes
+    }
+}
+
+/// `DeviceInterfaceGUIDs` property of [`MicrosoftOs10ExtendedProperties`],
+/// which applications use to find a WinUSB device.
+///
+/// `guid` is in registry format, including the braces.
+pub struct MicrosoftOs10DeviceInterfaceGUID {
+    pub guid: &'static str,
+}
+
+impl MicrosoftOs10DeviceInterfaceGUID {
+    const PROPERTY_NAME: &'static str = "DeviceInterfaceGUIDs";
+    const NAME_LEN: usize = (Self::PROPERTY_NAME.len() + 1) * 2;
+    const VALUE_LEN: usize = (38 + 1) * 2 + 2;
+    pub const LEN: usize = 14 + Self::NAME_LEN + Self::VALUE_LEN;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = Self::LEN as u8;
+        bytes[4] = 7; // dwPropertyDataType = REG_MULTI_SZ
+        bytes[8] = Self::NAME_LEN as u8;
+
+        let src = Self::PROPERTY_NAME.as_bytes();
+        let mut i = 0;
+        while i < src.len() {
+            bytes[10 + i * 2] = src[i];
+            i += 1;
+        }
+
+        let value_offset = 10 + Self::NAME_LEN;
+        bytes[value_offset] = Self::VALUE_LEN as u8;
+
+        assert!(self.guid.len() == 38);
+        let src = self.guid.as_bytes();
+        let mut i = 0;
+        while i < src.len() {
+            bytes[value_offset + 4 + i * 2] = src[i];
+            i += 1;
+        }
+
+        bytes
+    }
+}
+
+/// MS OS 1.0 descriptors of a device, answering string index
+/// [`MS_OS_10_STRING_INDEX`] and the vendor requests that follow it.
+///
+/// ```ignore
+/// static MSOS10: MicrosoftOs10Descriptors = MicrosoftOs10Descriptors {
+///     string: descriptors! { MicrosoftOs10String { vendor_code: VENDOR_CODE, } },
+///     compat_id: MSOS10_COMPAT_ID,
+///     properties: &[MSOS10_PROPERTIES],
+/// };
+///
+/// // In Handler::get_descriptor
+/// (STRING, MS_OS_10_STRING_INDEX) => Some(MSOS10.string),
+///
+/// // In Handler::handle_control
+/// let req = match MSOS10.handle_control(req).await {
+///     Ok(r) => return r,
+///     Err(req) => req,
+/// };
+/// ```
+pub struct MicrosoftOs10Descriptors {
+    /// Built with [`MicrosoftOs10String`]
+    pub string: &'static [u8],
+
+    /// Built with [`MicrosoftOs10ExtendedCompatId`]
+    pub compat_id: &'static [u8],
+
+    /// Built with [`MicrosoftOs10ExtendedProperties`], by interface number.
+    /// Interfaces without properties may be left out at the end or be empty.
+    pub properties: &'static [&'static [u8]],
+}
+
+impl MicrosoftOs10Descriptors {
+    /// `bRequest` of the vendor requests, taken from [`string`](Self::string).
+    pub fn vendor_code(&self) -> u8 {
+        self.string[16]
+    }
+
+    /// Respond to an MS OS 1.0 vendor request, or return any other request.
+    pub async fn handle_control<'a>(&self, req: Setup<'a>) -> Result<Responded, Setup<'a>> {
+        if req.ty != ControlType::Vendor || req.request != self.vendor_code() {
+            return Err(req);
+        }
+
+        let desc = match (req.recipient, req.index) {
+            (Recipient::Device, MS_OS_10_EXTENDED_COMPAT_ID) => Some(self.compat_id),
+            // Windows may send this one to the device as well
+            (Recipient::Device | Recipient::Interface, MS_OS_10_EXTENDED_PROPERTIES) if req.value & 0xff == 0 => {
+                self.properties.get((req.value >> 8) as usize).copied().filter(|d| !d.is_empty())
+            }
+            _ => return Err(req),
+        };
+
+        Ok(match (desc, req.data) {
+            (Some(desc), ControlData::In(data)) => data.respond(desc).await,
+            (_, ControlData::In(data)) => data.reject(),
+            (_, ControlData::Out(data)) => data.reject(),
+        })
+    }
+}
+
+/// `wIndex` of the WebUSB GET_URL vendor request.
+pub const WEBUSB_GET_URL: u16 = 0x0002;
+
+const WEBUSB_URL_DESCRIPTOR_TYPE: u8 = 0x03;
+
+/// WebUSB platform capability, announcing the vendor request code for GET_URL
+/// and the index of the landing page URL (0 for none).
+///
+/// Browsers request the landing page with a device vendor request with
+/// `bRequest = vendor_code`, `wIndex = WEBUSB_GET_URL` and the URL index in
+/// `wValue`, which can be answered from a [`UrlTable`].
+pub struct PlatformCapabilityWebUsb {
+    pub vendor_code: u8,
+    pub landing_page: u8,
+}
+
+impl PlatformCapabilityWebUsb {
+    pub const LEN: usize = 24;
+    pub const DESCRIPTOR_TYPE: u8 = usb::descriptor_type::DEVICE_CAPABILITY;
+
+    pub const fn bytes(self, _children: &[&[u8]]) -> [u8; Self::LEN] {
+        [
+            Self::LEN as u8,
+            Self::DESCRIPTOR_TYPE,
+            DEVICE_CAPABILITY_TYPE_PLATFORM,
+            0, // reserved
+            0x38, // platform capability UUID: WebUSB
+            0xb6,
+            0x08,
+            0x34,
+            0xa9,
+            0x09,
+            0xa0,
+            0x47,
+            0x8b,
+            0xfd,
+            0xa0,
+            0x76,
+            0x88,
+            0x15,
+            0xb6,
+            0x65,
+            0x00, // bcdVersion 1.00
+            0x01,
+            self.vendor_code,
+            self.landing_page,
+        ]
+    }
+}
+
+/// Split a URL into its WebUSB scheme code and the rest of the URL.
+const fn webusb_url_scheme(url: &str) -> (u8, &[u8]) {
+    let bytes = url.as_bytes();
+    if let Some(rest) = strip_prefix(bytes, b"https://") {
+        (1, rest)
+    } else if let Some(rest) = strip_prefix(bytes, b"http://") {
+        (0, rest)
+    } else {
+        (255, bytes)
+    }
+}
+
+const fn strip_prefix<'a>(bytes: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    if bytes.len() < prefix.len() {
+        return None;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if bytes[i] != prefix[i] {
+            return None;
+        }
+        i += 1;
+    }
+    Some(bytes.split_at(prefix.len()).1)
+}
+
+/// Length in bytes of the WebUSB URL descriptor for `url`.
+pub const fn webusb_url_len(url: &str) -> usize {
+    let len = 3 + webusb_url_scheme(url).1.len();
+    assert!(len <= 255, "URL too long");
+    len
+}
+
+/// Encode `url` as a WebUSB URL descriptor of exactly `N` bytes, which must be [`webusb_url_len`].
+///
+/// An `https://` or `http://` prefix is replaced by the scheme code.
+pub const fn webusb_url_bytes<const N: usize>(url: &str) -> [u8; N] {
+    assert!(N == webusb_url_len(url));
+    let (scheme, rest) = webusb_url_scheme(url);
+    let mut out = [0u8; N];
+    out[0] = N as u8;
+    out[1] = WEBUSB_URL_DESCRIPTOR_TYPE;
+    out[2] = scheme;
+    out.split_at_mut(3).1.copy_from_slice(rest);
+    out
+}
+
+/// Build a WebUSB URL descriptor for a URL constant at compile time.
+///
+/// ```ignore
+/// static URLS: UrlTable = UrlTable { urls: &[webusb_url!("https://example.com/app")] };
+/// ```
+#[macro_export]
+macro_rules! webusb_url {
+    ($s:expr) => {{
+        const S: &str = $s;
+        const ARR: [u8; $crate::usb::descriptors::webusb_url_len(S)] = $crate::usb::descriptors::webusb_url_bytes(S);
+        &ARR
+    }};
+}
+
+pub use webusb_url;
+
+/// WebUSB URL descriptors by index.
+///
+/// `urls[i]` is returned for URL index `i + 1`, matching `landing_page` in
+/// [`PlatformCapabilityWebUsb`]:
+///
+/// ```ignore
+/// Setup { ty: Vendor, recipient: Device, request: WEBUSB_VENDOR_CODE, index: WEBUSB_GET_URL, value, data: In(data) } => {
+///     match URLS.get(value) {
+///         Some(url) => data.respond(url).await,
+///         None => data.reject(),
+///     }
+/// }
+/// ```
+pub struct UrlTable {
+    pub urls: &'static [&'static [u8]],
+}
+
+impl UrlTable {
+    pub fn get(&self, index: u16) -> Option<&'static [u8]> {
+        let i = (index as usize).checked_sub(1)?;
+        self.urls.get(i).copied()
+    }
+}
+
+
+/// Build a UTF-16LE string descriptor for a string constant at compile time.
+///
+/// ```ignore
+/// (STRING, STRING_PRODUCT) => Some(string_descriptor!("Testgerät")),
+/// ```
+#[macro_export]
+macro_rules! string_descriptor {
+    ($s:expr) => {{
+        const S: &str = $s;
+        const ARR: [u8; $crate::usb::descriptors::string_descriptor_len(S)] =
+            $crate::usb::descriptors::string_descriptor_bytes(S);
+        &ARR
+    }};
+}
 
+pub use string_descriptor;
+
+/// Decode the UTF-8 character starting at `bytes[i]`, returning the code point and its length.
+const fn decode_utf8(bytes: &[u8], i: usize) -> (u32, usize) {
+    let b = bytes[i] as u32;
+    let (mut c, len) = if b < 0x80 {
+        (b, 1)
+    } else if b < 0xe0 {
+        (b & 0x1f, 2)
+    } else if b < 0xf0 {
+        (b & 0x0f, 3)
+    } else {
+        (b & 0x07, 4)
+    };
+
+    let mut j = 1;
+    while j < len {
+        c = c << 6 | (bytes[i + j] & 0x3f) as u32;
+        j += 1;
+    }
+    (c, len)
+}
+
+/// Length in bytes of the string descriptor for `s`.
+pub const fn string_descriptor_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut len = 2;
+    let mut i = 0;
+    while i < bytes.len() {
+        let (c, n) = decode_utf8(bytes, i);
+        len += if c >= 0x10000 { 4 } else { 2 };
+        i += n;
+    }
+    assert!(len <= 255, "string descriptor too long");
+    len
+}
+
+/// Encode `s` as a string descriptor of exactly `N` bytes, which must be [`string_descriptor_len`].
+pub const fn string_descriptor_bytes<const N: usize>(s: &str) -> [u8; N] {
+    assert!(N == string_descriptor_len(s));
+    let mut out = [0u8; N];
+    out[0] = N as u8;
+    out[1] = usb::descriptor_type::STRING;
+
+    let bytes = s.as_bytes();
+    let mut len = 2;
+    let mut i = 0;
+    while i < bytes.len() {
+        let (c, n) = decode_utf8(bytes, i);
+        if c >= 0x10000 {
+            let c = c - 0x10000;
+            let high = (0xd800 | (c >> 10)) as u16;
+            let low = (0xdc00 | (c & 0x3ff)) as u16;
+            out[len] = high.to_le_bytes()[0];
+            out[len + 1] = high.to_le_bytes()[1];
+            out[len + 2] = low.to_le_bytes()[0];
+            out[len + 3] = low.to_le_bytes()[1];
+            len += 4;
+        } else {
+            out[len] = (c as u16).to_le_bytes()[0];
+            out[len + 1] = (c as u16).to_le_bytes()[1];
+            len += 2;
+        }
+        i += n;
+    }
+    out
+}
+
+/// Build string descriptor 0, listing the supported language IDs, at compile time.
+///
+/// ```ignore
+/// (STRING, 0) => Some(language_list!(language_id::ENGLISH_US, LANGUAGE_GERMAN)),
+/// ```
+#[macro_export]
+macro_rules! language_list {
+    ($($lang:expr),+ $(,)?) => {{
+        const LANGUAGES: &[u16] = &[$($lang),+];
+        const ARR: [u8; 2 + 2 * LANGUAGES.len()] = $crate::usb::descriptors::language_list_bytes(LANGUAGES);
+        &ARR
+    }};
+}
+
+pub use language_list;
+
+/// Encode `languages` as string descriptor 0 of exactly `N` bytes.
+pub const fn language_list_bytes<const N: usize>(languages: &[u16]) -> [u8; N] {
+    assert!(N == 2 + 2 * languages.len() && N <= 255);
+    let mut out = [0u8; N];
+    out[0] = N as u8;
+    out[1] = usb::descriptor_type::STRING;
+
+    let mut i = 0;
+    while i < languages.len() {
+        out[2 + 2 * i] = languages[i].to_le_bytes()[0];
+        out[3 + 2 * i] = languages[i].to_le_bytes()[1];
+        i += 1;
+    }
+    out
+}
+
+/// String descriptors by index and language.
+///
+/// `strings[i]` holds the descriptors for string index `i + 1`, one for each
+/// of the languages listed in `languages` in the same order. A string with
+/// fewer entries than languages uses its first entry for the rest, as does a
+/// request for a language that is not listed.
+///
+/// ```ignore
+/// static STRINGS: StringTable = StringTable {
+///     languages: language_list!(language_id::ENGLISH_US, LANGUAGE_GERMAN),
+///     strings: &[
+///         &[string_descriptor!("zeptos project")],
+///         &[string_descriptor!("Test device"), string_descriptor!("Testgerät")],
+///     ],
+/// };
+///
+/// fn get_descriptor<'a>(&self, kind: u8, index: u8, lang: u16, builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+///     match kind {
+///         STRING => STRINGS.get(index, lang),
+///         ...
+///     }
+/// }
+/// ```
+pub struct StringTable {
+    /// String descriptor 0, built with [`language_list!`]
+    pub languages: &'static [u8],
+    pub strings: &'static [&'static [&'static [u8]]],
+}
+
+impl StringTable {
+    /// Look up string descriptor `index` in language `lang`.
+    ///
+    /// Index 0 returns `languages`.
+    pub fn get(&self, index: u8, lang: u16) -> Option<&'static [u8]> {
+        if index == 0 {
+            return Some(self.languages);
+        }
+
+        let translations = self.strings.get(index as usize - 1)?;
+        let lang_index = self.languages[2..]
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .position(|&l| u16::from_le_bytes(l) == lang)
+            .unwrap_or(0);
+        translations.get(lang_index).or(translations.first()).copied()
+    }
+}
 
 /// Builder for runtime-generated string descriptors
 pub struct DescriptorBuilder {
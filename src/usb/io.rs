@@ -0,0 +1,200 @@
+//! Byte stream adapters over bulk endpoints.
+//!
+//! [`UsbReader`] and [`UsbWriter`] buffer a bulk OUT or IN endpoint and
+//! implement the `embedded_io_async` traits, hiding packet boundaries from
+//! protocol code. [`UsbStream`] combines a pair of them.
+//!
+//! A USB transfer ends with a short packet. The writer ends a transfer when
+//! flushed, sending a zero-length packet if the data written since the last
+//! flush was a multiple of the max packet size. The reader skips zero-length
+//! packets, since a `read` returning 0 means end of stream.
+
+use core::convert::Infallible;
+
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+
+use super::{Endpoint, In, Out, UsbBuffer};
+
+/// Buffered reader over a bulk OUT endpoint.
+///
+/// `SIZE` is the size of the buffer, which must be a multiple of the
+/// endpoint's max packet size. Larger buffers let the host send more
+/// packets before the reader needs to wait on the endpoint again.
+pub struct UsbReader<const EP: u8, const SIZE: usize, const MPS: u16 = 64> {
+    ep: Endpoint<Out, EP, MPS>,
+    buf: UsbBuffer<SIZE>,
+    pos: usize,
+    len: usize,
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> UsbReader<EP, SIZE, MPS> {
+    pub fn new(ep: Endpoint<Out, EP, MPS>) -> Self {
+        const { assert!(SIZE >= MPS as usize && SIZE.is_multiple_of(MPS as usize)) };
+        UsbReader { ep, buf: UsbBuffer::new(), pos: 0, len: 0 }
+    }
+
+    /// Number of bytes received but not yet read.
+    pub fn available(&self) -> usize {
+        self.len - self.pos
+    }
+
+    pub fn into_inner(self) -> Endpoint<Out, EP, MPS> {
+        self.ep
+    }
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> ErrorType for UsbReader<EP, SIZE, MPS> {
+    type Error = Infallible;
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> BufRead for UsbReader<EP, SIZE, MPS> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Infallible> {
+        while self.pos == self.len {
+            self.len = self.ep.receive(&mut self.buf).await;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.len);
+    }
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> Read for UsbReader<EP, SIZE, MPS> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let data = self.fill_buf().await?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Buffered writer over a bulk IN endpoint.
+///
+/// Data is sent when the buffer fills or on [`flush`](Write::flush). `SIZE`
+/// must be a multiple of the endpoint's max packet size, so that sending a
+/// full buffer does not end the transfer.
+pub struct UsbWriter<const EP: u8, const SIZE: usize, const MPS: u16 = 64> {
+    ep: Endpoint<In, EP, MPS>,
+    buf: UsbBuffer<SIZE>,
+    len: usize,
+
+    /// A full buffer was sent, so the transfer must be ended with a short packet on flush.
+    unterminated: bool,
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> UsbWriter<EP, SIZE, MPS> {
+    pub fn new(ep: Endpoint<In, EP, MPS>) -> Self {
+        const { assert!(SIZE >= MPS as usize && SIZE.is_multiple_of(MPS as usize)) };
+        UsbWriter { ep, buf: UsbBuffer::new(), len: 0, unterminated: false }
+    }
+
+    /// Discard buffered data that has not been sent.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn into_inner(self) -> Endpoint<In, EP, MPS> {
+        self.ep
+    }
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> ErrorType for UsbWriter<EP, SIZE, MPS> {
+    type Error = Infallible;
+}
+
+impl<const EP: u8, const SIZE: usize, const MPS: u16> Write for UsbWriter<EP, SIZE, MPS> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        if self.len == SIZE {
+            self.ep.send(&self.buf, SIZE, false).await;
+            self.len = 0;
+            self.unterminated = true;
+        }
+
+        let n = buf.len().min(SIZE - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        if self.len > 0 || self.unterminated {
+            self.ep.send(&self.buf, self.len, true).await;
+            self.len = 0;
+            self.unterminated = false;
+        }
+        Ok(())
+    }
+}
+
+/// Buffered stream over a bulk OUT and bulk IN endpoint pair.
+pub struct UsbStream<
+    const EP_OUT: u8,
+    const EP_IN: u8,
+    const RX_SIZE: usize,
+    const TX_SIZE: usize,
+    const MPS: u16 = 64,
+> {
+    pub reader: UsbReader<EP_OUT, RX_SIZE, MPS>,
+    pub writer: UsbWriter<EP_IN, TX_SIZE, MPS>,
+}
+
+impl<const EP_OUT: u8, const EP_IN: u8, const RX_SIZE: usize, const TX_SIZE: usize, const MPS: u16>
+    UsbStream<EP_OUT, EP_IN, RX_SIZE, TX_SIZE, MPS>
+{
+    pub fn new(ep_out: Endpoint<Out, EP_OUT, MPS>, ep_in: Endpoint<In, EP_IN, MPS>) -> Self {
+        UsbStream {
+            reader: UsbReader::new(ep_out),
+            writer: UsbWriter::new(ep_in),
+        }
+    }
+
+    /// Split into the reader and writer halves, to use from separate tasks.
+    pub fn split(self) -> (UsbReader<EP_OUT, RX_SIZE, MPS>, UsbWriter<EP_IN, TX_SIZE, MPS>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<const EP_OUT: u8, const EP_IN: u8, const RX_SIZE: usize, const TX_SIZE: usize, const MPS: u16> ErrorType
+    for UsbStream<EP_OUT, EP_IN, RX_SIZE, TX_SIZE, MPS>
+{
+    type Error = Infallible;
+}
+
+impl<const EP_OUT: u8, const EP_IN: u8, const RX_SIZE: usize, const TX_SIZE: usize, const MPS: u16> Read
+    for UsbStream<EP_OUT, EP_IN, RX_SIZE, TX_SIZE, MPS>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        self.reader.read(buf).await
+    }
+}
+
+impl<const EP_OUT: u8, const EP_IN: u8, const RX_SIZE: usize, const TX_SIZE: usize, const MPS: u16> BufRead
+    for UsbStream<EP_OUT, EP_IN, RX_SIZE, TX_SIZE, MPS>
+{
+    async fn fill_buf(&mut self) -> Result<&[u8], Infallible> {
+        self.reader.fill_buf().await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+impl<const EP_OUT: u8, const EP_IN: u8, const RX_SIZE: usize, const TX_SIZE: usize, const MPS: u16> Write
+    for UsbStream<EP_OUT, EP_IN, RX_SIZE, TX_SIZE, MPS>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        self.writer.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        self.writer.flush().await
+    }
+}
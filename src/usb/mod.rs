@@ -4,14 +4,29 @@ use core::{
 
 use defmt::{debug, error, panic, write, Format};
 use pin_project::{pin_project, pinned_drop};
-use usb::endpoint_address::{DIR_MASK as EP_DIR_MASK, IN as EP_IN, OUT as EP_OUT};
+use usb::endpoint_address::{ADDR_MASK as EP_ADDR_MASK, DIR_MASK as EP_DIR_MASK, IN as EP_IN, OUT as EP_OUT};
+use usb::endpoint_attributes::transfer_type::{
+    BULK as TRANSFER_TYPE_BULK, INTERRUPT as TRANSFER_TYPE_INTERRUPT, ISOCHRONOUS as TRANSFER_TYPE_ISOCHRONOUS,
+};
 
 pub mod descriptors;
-use descriptors::DescriptorBuilder;
+use descriptors::{Config, ConfigDescriptor, DescriptorBuilder};
+
+pub mod class;
+pub mod composite;
+
+#[cfg(feature = "embedded-io-async")]
+pub mod io;
 
 use crate::TaskOnly;
 
+#[cfg(feature = "usb-sim")]
+pub mod sim;
+
 cfg_select!{
+    feature = "usb-sim" => {
+        pub use sim::{ Usb, UsbShared, Endpoint0 };
+    }
     any(feature = "samd11", feature = "samd21") => {
         pub use crate::samd::usb::{ Usb, UsbShared, Endpoint0 };
     }
@@ -46,6 +61,8 @@ impl<const SIZE: usize> DerefMut for UsbBuffer<SIZE> {
 pub enum Event {
     Reset,
     Setup([u8; 8]),
+    Suspend,
+    Resume,
 }
 
 /// Specification defining the request.
@@ -79,6 +96,9 @@ pub enum Recipient {
     Other = 3,
 }
 
+/// Max packet size of the default control endpoint.
+const EP0_MAX_PACKET_SIZE: usize = 64;
+
 /// Token used to ensure that [`Handler::handle_control`] either rejects or
 /// accepts every request.
 pub struct Responded {}
@@ -101,6 +121,16 @@ pub enum ControlData<'a> {
     Out(ControlOut<'a>),
 }
 
+/// Error from [`ControlOut::receive_into`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ReceiveError {
+    /// The buffer is shorter than the data the host requested to send.
+    BufferTooSmall,
+
+    /// The host sent more data than it requested to.
+    Overflow,
+}
+
 impl<'a> Format for ControlData<'a> {
     fn format(&self, f: defmt::Formatter) {
         match self {
@@ -133,6 +163,43 @@ impl<'a> ControlIn<'a> {
         debug!("status phase complete");
         Responded {}
     }
+
+    /// Respond with data produced a packet at a time, without holding the
+    /// whole response in memory.
+    ///
+    /// `fill(offset, buf)` writes the response data starting at `offset`
+    /// into `buf` and returns the number of bytes written. Writing less than
+    /// `buf.len()` ends the response. `buf` is at most one packet, and never
+    /// extends past the length requested by the host.
+    pub async fn respond_with(mut self, mut fill: impl FnMut(usize, &mut [u8]) -> usize) -> Responded {
+        debug!("accepting IN request with streamed data");
+
+        let length = self.length as usize;
+        let mut buf = [0; EP0_MAX_PACKET_SIZE];
+        let mut offset = 0;
+
+        loop {
+            let limit = (length - offset).min(EP0_MAX_PACKET_SIZE);
+            let len = if limit > 0 { fill(offset, &mut buf[..limit]) } else { 0 };
+            assert!(len <= limit);
+            offset += len;
+
+            // A short packet ends the data phase, or a full packet if it reaches the host's requested length
+            let done = len < EP0_MAX_PACKET_SIZE || offset == length;
+            self.usb.ep0_transfer_in(&buf[..len], !done || offset == length).await;
+
+            if done {
+                break;
+            }
+        }
+
+        debug!("data phase complete, {} bytes", offset);
+
+        self.usb.ep0_transfer_out().await;
+
+        debug!("status phase complete");
+        Responded {}
+    }
 }
 
 impl<'a> ControlOut<'a> {
@@ -160,6 +227,38 @@ impl<'a> ControlOut<'a> {
         }
     }
 
+    /// Receive the rest of the data phase into `buf`, returning the received data.
+    ///
+    /// The data phase ends after the length requested by the host, or early
+    /// with a short packet. Returns [`ReceiveError::BufferTooSmall`] if `buf`
+    /// is too short to hold the remaining data, in which case nothing is
+    /// received, or [`ReceiveError::Overflow`] if the host sends more than it
+    /// requested. The request should then be rejected.
+    pub async fn receive_into<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b mut [u8], ReceiveError> {
+        if buf.len() < self.remaining as usize {
+            return Err(ReceiveError::BufferTooSmall);
+        }
+
+        let mut len = 0;
+        while self.remaining > 0 {
+            let remaining = self.remaining as usize;
+            let data = self.usb.ep0_transfer_out().await;
+            if data.len() > remaining {
+                return Err(ReceiveError::Overflow);
+            }
+            buf[len..len + data.len()].copy_from_slice(data);
+            len += data.len();
+
+            if data.len() < EP0_MAX_PACKET_SIZE {
+                self.remaining = 0;
+            } else {
+                self.remaining -= data.len() as u16;
+            }
+        }
+
+        Ok(&mut buf[..len])
+    }
+
     pub async fn accept(mut self) -> Responded {
         debug_assert!(self.remaining == 0);
         debug!("accept OUT request");
@@ -252,15 +351,71 @@ impl<'a> Setup<'a> {
     }
 }
 
+/// `wValue` of SET_FEATURE / CLEAR_FEATURE for the endpoint halt feature.
+const ENDPOINT_HALT: u16 = 0;
+
+/// `wValue` of SET_FEATURE / CLEAR_FEATURE for the device remote wakeup feature.
+const DEVICE_REMOTE_WAKEUP: u16 = 1;
+
+/// Maximum number of interfaces whose alternate setting is tracked for GET_INTERFACE.
+const MAX_INTERFACES: usize = 16;
+
+/// `bNumInterfaces` of configuration `cfg`, from the handler's configuration descriptors.
+///
+/// This is only called on SET_CONFIGURATION, and the result kept in
+/// `INTERFACE_COUNT`. Only the `bNumConfigurations` descriptors listed in the
+/// device descriptor are searched. If none has that `bConfigurationValue`,
+/// all of the tracked interfaces are accepted.
+fn interface_count<H: Handler + ?Sized>(handler: &H, cfg: u8) -> u8 {
+    if cfg == 0 {
+        return 0;
+    }
+
+    let mut builder = DescriptorBuilder::new();
+    let num_configurations = match handler.get_descriptor(usb::descriptor_type::DEVICE, 0, 0, &mut builder) {
+        Some(&[.., num_configurations]) => num_configurations,
+        _ => 1,
+    };
+
+    for index in 0..num_configurations {
+        let mut builder = DescriptorBuilder::new();
+        match handler.get_descriptor(usb::descriptor_type::CONFIGURATION, index, 0, &mut builder) {
+            Some(&[_, _, _, _, num_interfaces, value, ..]) if value == cfg => return num_interfaces,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    MAX_INTERFACES as u8
+}
+
 #[allow(async_fn_in_trait)]
 pub trait Handler {
     fn handle_reset(&self) {
         debug!("usb reset");
     }
 
+    /// Returns `true` if the device is currently self-powered, as reported by GET_STATUS.
+    fn self_powered(&self) -> bool {
+        false
+    }
+
+    /// Called when the host suspends the bus.
+    ///
+    /// A bus-powered device must reduce its current draw to the suspend limit
+    /// within 10ms. See [`power::set_deep_sleep`](crate::power::set_deep_sleep).
+    fn handle_suspend(&self) {
+        debug!("usb suspend");
+    }
+
+    /// Called when the bus resumes from suspend, whether initiated by the host or by [`UsbShared::remote_wakeup`].
+    fn handle_resume(&self) {
+        debug!("usb resume");
+    }
+
     async fn handle_control_raw<'a>(&self, req: Setup<'a>) {
         use usb::standard_request::{
-            GET_DESCRIPTOR, GET_STATUS, SET_ADDRESS, SET_CONFIGURATION, SET_INTERFACE,
+            CLEAR_FEATURE, GET_CONFIGURATION, GET_DESCRIPTOR, GET_INTERFACE, GET_STATUS, SET_ADDRESS,
+            SET_CONFIGURATION, SET_FEATURE, SET_INTERFACE,
         };
         use ControlData::*;
         use ControlType::*;
@@ -275,10 +430,79 @@ pub trait Handler {
         let Responded {} = match req {
             Setup {
                 ty: Standard,
+                recipient: Device,
                 request: GET_STATUS,
                 data: In(data),
                 ..
-            } => data.respond(&[0, 0]).await,
+            } => {
+                let self_powered = self.self_powered();
+                let remote_wakeup = REMOTE_WAKEUP_ENABLED.get(usb.rt()).get();
+                data.respond(&[(self_powered as u8) | (remote_wakeup as u8) << 1, 0]).await
+            }
+            Setup {
+                ty: Standard,
+                recipient: Interface,
+                request: GET_STATUS,
+                index,
+                data: In(data),
+                ..
+            } => {
+                // No interfaces exist until the device is configured
+                if index < INTERFACE_COUNT.get(usb.rt()).get() as u16 {
+                    data.respond(&[0, 0]).await
+                } else {
+                    data.reject()
+                }
+            }
+            Setup {
+                ty: Standard,
+                recipient: Endpoint,
+                request: GET_STATUS,
+                index,
+                data: In(data),
+                ..
+            } => {
+                let ep = index as u8;
+                if ep & EP_ADDR_MASK == 0 {
+                    data.respond(&[0, 0]).await
+                } else if ep_is_enabled(usb, ep) {
+                    data.respond(&[ep_is_halted(usb, ep) as u8, 0]).await
+                } else {
+                    data.reject()
+                }
+            }
+            Setup {
+                ty: Standard,
+                recipient: Endpoint,
+                request: request @ (SET_FEATURE | CLEAR_FEATURE),
+                value: ENDPOINT_HALT,
+                index,
+                data: Out(data),
+            } => {
+                let ep = index as u8;
+                let halt = request == SET_FEATURE;
+                if ep & EP_ADDR_MASK == 0 {
+                    data.accept().await
+                } else if ep_is_enabled(usb, ep) {
+                    debug!("endpoint {:02x} halt: {}", ep, halt);
+                    set_ep_halted(usb, ep, halt);
+                    data.accept().await
+                } else {
+                    data.reject()
+                }
+            }
+            Setup {
+                ty: Standard,
+                recipient: Device,
+                request: request @ (SET_FEATURE | CLEAR_FEATURE),
+                value: DEVICE_REMOTE_WAKEUP,
+                data: Out(data),
+                ..
+            } => {
+                debug!("remote wakeup enabled: {}", request == SET_FEATURE);
+                REMOTE_WAKEUP_ENABLED.get(usb.rt()).set(request == SET_FEATURE);
+                data.accept().await
+            }
             Setup {
                 ty: Standard,
                 recipient: Device,
@@ -325,10 +549,36 @@ pub trait Handler {
                     .set_configuration(value as u8, &mut Endpoints { usb })
                     .await
                 {
-                    Ok(_) => data.accept().await,
+                    Ok(_) => {
+                        CONFIGURATION.get(usb.rt()).set(value as u8);
+                        INTERFACE_COUNT.get(usb.rt()).set(interface_count(self, value as u8));
+                        ALT_SETTINGS.get(usb.rt()).set([0; MAX_INTERFACES]);
+                        data.accept().await
+                    }
                     Err(_) => data.reject(),
                 }
             }
+            Setup {
+                ty: Standard,
+                recipient: Device,
+                request: GET_CONFIGURATION,
+                data: In(data),
+                ..
+            } => data.respond(&[CONFIGURATION.get(usb.rt()).get()]).await,
+            Setup {
+                ty: Standard,
+                recipient: Interface,
+                request: GET_INTERFACE,
+                index,
+                data: In(data),
+                ..
+            } => {
+                let alt_settings = ALT_SETTINGS.get(usb.rt()).get();
+                match alt_settings.get(index as usize) {
+                    Some(&alt) if index < INTERFACE_COUNT.get(usb.rt()).get() as u16 => data.respond(&[alt]).await,
+                    _ => data.reject(),
+                }
+            }
             Setup {
                 ty: Standard,
                 recipient: Interface,
@@ -339,11 +589,21 @@ pub trait Handler {
                 ..
             } => {
                 debug!("set interface {} {}", index, value);
-                match self
-                    .set_interface(index as u8, value as u8, &mut Endpoints { usb })
-                    .await
-                {
-                    Ok(_) => data.accept().await,
+                let result = if index < INTERFACE_COUNT.get(usb.rt()).get() as u16 {
+                    self.set_interface(index as u8, value as u8, &mut Endpoints { usb }).await
+                } else {
+                    Err(())
+                };
+                match result {
+                    Ok(_) => {
+                        let alt_settings = ALT_SETTINGS.get(usb.rt());
+                        let mut alts = alt_settings.get();
+                        if let Some(alt) = alts.get_mut(index as usize) {
+                            *alt = value as u8;
+                        }
+                        alt_settings.set(alts);
+                        data.accept().await
+                    }
                     Err(_) => data.reject(),
                 }
             }
@@ -415,9 +675,29 @@ impl Usb {
                     Poll::Ready(Event::Reset) => {
                         this.state.set(None);
                         this.usb.configure_ep0();
+                        let rt = this.usb.rt();
+                        REMOTE_WAKEUP_ENABLED.get(rt).set(false);
+                        CONFIGURATION.get(rt).set(0);
+                        INTERFACE_COUNT.get(rt).set(0);
+                        ALT_SETTINGS.get(rt).set([0; MAX_INTERFACES]);
+                        if SUSPENDED.get(rt).replace(false) {
+                            this.handler.handle_resume();
+                        }
                         this.handler.handle_reset();
                     }
 
+                    Poll::Ready(Event::Suspend) => {
+                        if !SUSPENDED.get(this.usb.rt()).replace(true) {
+                            this.handler.handle_suspend();
+                        }
+                    }
+
+                    Poll::Ready(Event::Resume) => {
+                        if SUSPENDED.get(this.usb.rt()).replace(false) {
+                            this.handler.handle_resume();
+                        }
+                    }
+
                     Poll::Ready(Event::Setup(setup)) => {
                         this.state.set(None);
                         if let Ok(setup) = Setup::parse(unsafe { this.usb.ep0() }, setup) {
@@ -460,6 +740,58 @@ impl Usb {
     }
 }
 
+static SUSPENDED: TaskOnly<Cell<bool>> = TaskOnly::new(Cell::new(false));
+static REMOTE_WAKEUP_ENABLED: TaskOnly<Cell<bool>> = TaskOnly::new(Cell::new(false));
+static CONFIGURATION: TaskOnly<Cell<u8>> = TaskOnly::new(Cell::new(0));
+static ALT_SETTINGS: TaskOnly<Cell<[u8; MAX_INTERFACES]>> = TaskOnly::new(Cell::new([0; MAX_INTERFACES]));
+
+/// `bNumInterfaces` of the current configuration, or 0 if not configured.
+static INTERFACE_COUNT: TaskOnly<Cell<u8>> = TaskOnly::new(Cell::new(0));
+
+/// Return the device state kept in statics to its power-on values, so each simulation starts afresh.
+#[cfg(feature = "usb-sim")]
+pub(crate) fn reset_statics(rt: crate::Runtime) {
+    SUSPENDED.get(rt).set(false);
+    REMOTE_WAKEUP_ENABLED.get(rt).set(false);
+    CONFIGURATION.get(rt).set(0);
+    ALT_SETTINGS.get(rt).set([0; MAX_INTERFACES]);
+    INTERFACE_COUNT.get(rt).set(0);
+    EP_ENABLED.get(rt).set(ep_enabled_mask(EP_OUT) | ep_enabled_mask(EP_IN));
+    EP_HALTED.get(rt).set(0);
+}
+
+impl UsbShared {
+    /// Current configuration value set by the host, or 0 if not configured.
+    pub fn configuration(&self) -> u8 {
+        CONFIGURATION.get(self.rt()).get()
+    }
+
+    /// Returns `true` if the bus is suspended.
+    pub fn is_suspended(&self) -> bool {
+        SUSPENDED.get(self.rt()).get()
+    }
+
+    /// Returns `true` if the host has enabled remote wakeup with `SET_FEATURE(DEVICE_REMOTE_WAKEUP)`.
+    pub fn remote_wakeup_enabled(&self) -> bool {
+        REMOTE_WAKEUP_ENABLED.get(self.rt()).get()
+    }
+
+    /// Signal the host to resume the bus.
+    ///
+    /// The configuration descriptor must set the remote wakeup bit (0x20) in
+    /// `bmAttributes`. Returns `false` without signaling if the bus is not
+    /// suspended or the host has not enabled remote wakeup.
+    pub fn remote_wakeup(&self) -> bool {
+        if !self.is_suspended() || !self.remote_wakeup_enabled() {
+            return false;
+        }
+
+        debug!("remote wakeup");
+        self.signal_resume();
+        true
+    }
+}
+
 pub struct Endpoints {
     usb: UsbShared,
 }
@@ -471,6 +803,23 @@ const fn ep_enabled_mask(ep: u8) -> u32 {
     1 << bit
 }
 
+static EP_HALTED: TaskOnly<Cell<u32>> = TaskOnly::new(Cell::new(0));
+
+fn ep_is_enabled(usb: UsbShared, ep: u8) -> bool {
+    EP_ENABLED.get(usb.rt()).get() & ep_enabled_mask(ep) != 0
+}
+
+fn ep_is_halted(usb: UsbShared, ep: u8) -> bool {
+    EP_HALTED.get(usb.rt()).get() & ep_enabled_mask(ep) != 0
+}
+
+fn set_ep_halted(usb: UsbShared, ep: u8, halt: bool) {
+    let halted = EP_HALTED.get(usb.rt());
+    let mask = ep_enabled_mask(ep);
+    halted.set(if halt { halted.get() | mask } else { halted.get() & !mask });
+    usb.set_ep_halt(ep, halt);
+}
+
 impl Endpoints {
     fn mark_enabled(&self, ep: u8) {
         let mask = ep_enabled_mask(ep);
@@ -481,35 +830,150 @@ impl Endpoints {
         enabled.set(enabled.get() | mask);
     }
 
-    fn bulk_interrupt<D: EpDir, const EP: u8>(&self) -> Endpoint<D, EP> {
+    fn claim<D: EpDir, const EP: u8>(&self) {
         const {
             assert!(EP & EP_DIR_MASK == D::DIR);
         }
         self.mark_enabled(EP);
-        self.usb.enable_ep(EP);
+        let halted = EP_HALTED.get(self.usb.rt());
+        halted.set(halted.get() & !ep_enabled_mask(EP));
+    }
+
+    /// Check the endpoints enabled through the returned wrapper against the
+    /// configuration descriptor `C` at compile time.
+    pub fn described<C: ConfigDescriptor>(&self) -> DescribedEndpoints<'_, C> {
+        DescribedEndpoints {
+            endpoints: self,
+            _c: PhantomData,
+        }
+    }
+
+    /// Enable a bulk or interrupt endpoint with a max packet size of `MPS` bytes.
+    ///
+    /// `MPS` must match `wMaxPacketSize` in the endpoint descriptor, and both
+    /// parameters can be taken from the same descriptor constant:
+    ///
+    /// ```ignore
+    /// const EP_IN: Endpoint = Endpoint { bEndpointAddress: 0x81, bmAttributes: 0x03, wMaxPacketSize: 16, bInterval: 10 };
+    /// let ep = endpoints.endpoint::<In, { EP_IN.bEndpointAddress }, { EP_IN.wMaxPacketSize }>();
+    /// ```
+    ///
+    /// Bulk endpoints need a power of two from 8 to 64. Interrupt endpoints
+    /// can use any size up to 64 on RP2040 and RP2350, but SAMD only supports
+    /// the same sizes as bulk.
+    pub fn endpoint<D: EpDir, const EP: u8, const MPS: u16>(&self) -> Endpoint<D, EP, MPS> {
+        const {
+            assert!(MPS > 0 && MPS <= 64, "max packet size must be from 1 to 64");
+        }
+        self.claim::<D, EP>();
+        self.usb.enable_ep(EP, MPS);
         Endpoint {
             usb: self.usb,
             _d: PhantomData,
         }
     }
 
+    fn iso<D: EpDir, const EP: u8>(&self, max_packet_size: u16) -> IsoEndpoint<D, EP> {
+        assert!(max_packet_size <= MAX_ISO_PACKET_SIZE);
+        self.claim::<D, EP>();
+        self.usb.enable_iso_ep(EP, max_packet_size);
+        IsoEndpoint {
+            usb: self.usb,
+            max_packet_size,
+            _d: PhantomData,
+        }
+    }
+
+    pub fn bulk_in<const EP: u8>(&self) -> Endpoint<In, EP> {
+        self.endpoint()
+    }
+
+    pub fn bulk_out<const EP: u8>(&self) -> Endpoint<Out, EP> {
+        self.endpoint()
+    }
+
+    pub fn interrupt_in<const EP: u8>(&self) -> Endpoint<In, EP> {
+        self.endpoint()
+    }
+
+    pub fn interrupt_out<const EP: u8>(&self) -> Endpoint<Out, EP> {
+        self.endpoint()
+    }
+
+    /// Enable an isochronous IN endpoint sending up to `max_packet_size` bytes per frame.
+    ///
+    /// `max_packet_size` must match `wMaxPacketSize` in the endpoint descriptor.
+    pub fn iso_in<const EP: u8>(&self, max_packet_size: u16) -> IsoEndpoint<In, EP> {
+        self.iso(max_packet_size)
+    }
+
+    /// Enable an isochronous OUT endpoint receiving up to `max_packet_size` bytes per frame.
+    ///
+    /// `max_packet_size` must match `wMaxPacketSize` in the endpoint descriptor.
+    pub fn iso_out<const EP: u8>(&self, max_packet_size: u16) -> IsoEndpoint<Out, EP> {
+        self.iso(max_packet_size)
+    }
+}
+
+/// [`Endpoints`] checked at compile time against the configuration descriptor `C`.
+///
+/// Each method fails to build if `C` does not describe the endpoint with the
+/// same address, a matching transfer type, and the same max packet size.
+pub struct DescribedEndpoints<'a, C> {
+    endpoints: &'a Endpoints,
+    _c: PhantomData<C>,
+}
+
+impl<C: ConfigDescriptor> DescribedEndpoints<'_, C> {
+    /// Enable a bulk or interrupt endpoint, see [`Endpoints::endpoint`].
+    pub fn endpoint<D: EpDir, const EP: u8, const MPS: u16>(&self) -> Endpoint<D, EP, MPS> {
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_BULK, TRANSFER_TYPE_INTERRUPT], MPS) };
+        self.endpoints.endpoint()
+    }
+
     pub fn bulk_in<const EP: u8>(&self) -> Endpoint<In, EP> {
-        self.bulk_interrupt()
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_BULK], 64) };
+        self.endpoints.bulk_in()
     }
 
     pub fn bulk_out<const EP: u8>(&self) -> Endpoint<Out, EP> {
-        self.bulk_interrupt()
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_BULK], 64) };
+        self.endpoints.bulk_out()
     }
 
     pub fn interrupt_in<const EP: u8>(&self) -> Endpoint<In, EP> {
-        self.bulk_interrupt()
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_INTERRUPT], 64) };
+        self.endpoints.interrupt_in()
     }
 
     pub fn interrupt_out<const EP: u8>(&self) -> Endpoint<Out, EP> {
-        self.bulk_interrupt()
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_INTERRUPT], 64) };
+        self.endpoints.interrupt_out()
+    }
+
+    /// Enable an isochronous IN endpoint, see [`Endpoints::iso_in`].
+    pub fn iso_in<const EP: u8, const MPS: u16>(&self) -> IsoEndpoint<In, EP> {
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_ISOCHRONOUS], MPS) };
+        self.endpoints.iso_in(MPS)
+    }
+
+    /// Enable an isochronous OUT endpoint, see [`Endpoints::iso_out`].
+    pub fn iso_out<const EP: u8, const MPS: u16>(&self) -> IsoEndpoint<Out, EP> {
+        const { Config::check_endpoint(C::DESCRIPTOR, EP, &[TRANSFER_TYPE_ISOCHRONOUS], MPS) };
+        self.endpoints.iso_out(MPS)
     }
 }
 
+/// Largest `wMaxPacketSize` of a full-speed isochronous endpoint.
+pub const MAX_ISO_PACKET_SIZE: u16 = 1023;
+
+fn release_ep(usb: UsbShared, ep: u8) {
+    usb.disable_ep(ep);
+    let enabled = &EP_ENABLED.get(usb.rt());
+    let mask = ep_enabled_mask(ep);
+    enabled.set(enabled.get() & !mask);
+}
+
 
 pub trait EpDir {
     const DIR: u8;
@@ -524,30 +988,177 @@ impl EpDir for Out {
     const DIR: u8 = EP_OUT;
 }
 
-pub struct Endpoint<D, const EP: u8> {
+pub struct Endpoint<D, const EP: u8, const MPS: u16 = 64> {
     usb: UsbShared,
     _d: PhantomData<D>,
 }
 
-impl<const EP: u8> Endpoint<Out, EP> {
+impl<D, const EP: u8, const MPS: u16> Endpoint<D, EP, MPS> {
+    pub const MAX_PACKET_SIZE: u16 = MPS;
+
+    /// Stall or un-stall the endpoint.
+    ///
+    /// While halted, the endpoint responds to the host with STALL. Clearing
+    /// the halt resets the data toggle. The host can also set or clear the halt
+    /// with SET_FEATURE / CLEAR_FEATURE(ENDPOINT_HALT).
+    pub fn set_halt(&mut self, halt: bool) {
+        set_ep_halted(self.usb, EP, halt);
+    }
+
+    /// Returns `true` if the endpoint is halted.
+    pub fn is_halted(&self) -> bool {
+        ep_is_halted(self.usb, EP)
+    }
+}
+
+impl<const EP: u8, const MPS: u16> Endpoint<Out, EP, MPS> {
+    /// Receive a transfer of up to `SIZE` bytes, ending at a short packet.
+    ///
+    /// On RP2040/RP2350, the endpoint's buffers stay armed between calls, so
+    /// the host may send up to two packets before the next call, which
+    /// receives them. Drop the endpoint to NAK the host instead.
     pub fn receive<const SIZE: usize>(&mut self, buf: &mut UsbBuffer<SIZE>) -> impl Future<Output = usize> + '_ {
-        const { assert!(SIZE >= 64 && SIZE % 64 == 0) };
+        const { assert!(SIZE >= MPS as usize && SIZE.is_multiple_of(MPS as usize)) };
         unsafe { self.usb.transfer_out(EP, buf.as_mut_ptr(), SIZE) }
     }
 }
 
-impl<const EP: u8> Endpoint<In, EP> {
+impl<const EP: u8, const MPS: u16> Endpoint<In, EP, MPS> {
     pub fn send<const SIZE: usize>(&mut self, buf: &UsbBuffer<SIZE>, len: usize, zlp: bool) -> impl Future<Output = ()> + '_ {
         assert!(len <= SIZE);
         unsafe { self.usb.transfer_in(EP, buf.as_ptr(), len, zlp) }
     }
 }
 
-impl<D, const EP: u8> Drop for Endpoint<D, EP> {
+impl<D, const EP: u8, const MPS: u16> Drop for Endpoint<D, EP, MPS> {
     fn drop(&mut self) {
-        self.usb.disable_ep(EP);
-        let enabled = &EP_ENABLED.get(self.usb.rt());
-        let mask = ep_enabled_mask(EP);
-        enabled.set(enabled.get() & !mask);
+        release_ep(self.usb, EP);
+    }
+}
+
+/// Zero-copy packet access
+///
+/// These lend out the endpoint's packet buffers in USB RAM, so data can be
+/// produced or consumed in place rather than copied through a [`UsbBuffer`].
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> Endpoint<Out, EP, MPS> {
+    /// Wait for the next packet and borrow it where the hardware received it.
+    ///
+    /// The buffer is returned to the hardware when the [`OutPacket`] is dropped.
+    pub async fn receive_packet(&mut self) -> OutPacket<'_, EP, MPS> {
+        let (ptr, len) = unsafe { self.usb.receive_packet(EP).await };
+        OutPacket { endpoint: self, ptr, len }
+    }
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> Endpoint<In, EP, MPS> {
+    /// Wait for a free packet buffer to fill in place.
+    pub async fn packet_buffer(&mut self) -> InPacket<'_, EP, MPS> {
+        let ptr = unsafe { self.usb.packet_buffer(EP).await };
+        InPacket { endpoint: self, ptr }
+    }
+
+    /// Wait until all packets queued with [`InPacket::send`] have been sent.
+    pub async fn flush(&mut self) {
+        self.usb.flush(EP).await
+    }
+}
+
+/// A received packet borrowed from USB RAM.
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+pub struct OutPacket<'a, const EP: u8, const MPS: u16> {
+    endpoint: &'a mut Endpoint<Out, EP, MPS>,
+    ptr: *const u8,
+    len: usize,
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> Deref for OutPacket<'_, EP, MPS> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> Drop for OutPacket<'_, EP, MPS> {
+    fn drop(&mut self) {
+        unsafe { self.endpoint.usb.release_packet(EP) }
+    }
+}
+
+/// A packet buffer of `MPS` bytes in USB RAM, queued to send with [`InPacket::send`].
+///
+/// Dropping it without sending leaves the buffer free.
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+pub struct InPacket<'a, const EP: u8, const MPS: u16> {
+    endpoint: &'a mut Endpoint<In, EP, MPS>,
+    ptr: *mut u8,
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> InPacket<'_, EP, MPS> {
+    /// Queue the first `len` bytes of the buffer to send, without waiting for it to be sent.
+    pub fn send(self, len: usize) {
+        assert!(len <= MPS as usize);
+        unsafe { self.endpoint.usb.submit_packet(EP, len) }
+    }
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> Deref for InPacket<'_, EP, MPS> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, MPS as usize) }
+    }
+}
+
+#[cfg(any(feature = "rp2040", feature = "rp2350", feature = "usb-sim"))]
+impl<const EP: u8, const MPS: u16> DerefMut for InPacket<'_, EP, MPS> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, MPS as usize) }
+    }
+}
+
+/// Isochronous endpoint.
+///
+/// Each transfer is a single packet in one frame. There is no retry or
+/// handshake: a packet the host doesn't poll for in a frame is lost. Use
+/// [`UsbShared::wait_sof`] and [`UsbShared::frame_number`] to pace data to the
+/// 1ms frame clock.
+pub struct IsoEndpoint<D, const EP: u8> {
+    usb: UsbShared,
+    max_packet_size: u16,
+    _d: PhantomData<D>,
+}
+
+impl<D, const EP: u8> IsoEndpoint<D, EP> {
+    pub fn max_packet_size(&self) -> u16 {
+        self.max_packet_size
+    }
+}
+
+impl<const EP: u8> IsoEndpoint<In, EP> {
+    /// Send `len` bytes of `buf` as the packet for the next frame the host polls the endpoint.
+    pub fn send<const SIZE: usize>(&mut self, buf: &UsbBuffer<SIZE>, len: usize) -> impl Future<Output = ()> + '_ {
+        assert!(len <= SIZE && len <= self.max_packet_size as usize);
+        unsafe { self.usb.iso_transfer_in(EP, buf.as_ptr(), len, self.max_packet_size) }
+    }
+}
+
+impl<const EP: u8> IsoEndpoint<Out, EP> {
+    /// Receive the packet from the next frame the host sends to the endpoint, returning its length.
+    pub fn receive<const SIZE: usize>(&mut self, buf: &mut UsbBuffer<SIZE>) -> impl Future<Output = usize> + '_ {
+        assert!(SIZE >= self.max_packet_size as usize);
+        unsafe { self.usb.iso_transfer_out(EP, buf.as_mut_ptr(), self.max_packet_size) }
+    }
+}
+
+impl<D, const EP: u8> Drop for IsoEndpoint<D, EP> {
+    fn drop(&mut self) {
+        release_ep(self.usb, EP);
     }
 }
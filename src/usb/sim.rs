@@ -0,0 +1,598 @@
+//! Simulated USB controller for testing on the host.
+//!
+//! With the `usb-sim` feature, this replaces the hardware backend behind
+//! [`Usb`], [`UsbShared`] and [`Endpoint0`], so that a [`Handler`](super::Handler)
+//! and the control transfer handling in [`Usb::run_device`] can be exercised
+//! without a board.
+//!
+//! The device side is driven by polling its future, and the test plays the
+//! host through [`Host`]: it injects bus events, SETUP packets and OUT data,
+//! then inspects the IN data, STALLs and endpoint state produced by the device.
+//!
+//! ```ignore
+//! let mut host = Host::new();
+//! let mut usb = unsafe { Usb::new(Runtime::steal()) };
+//! let mut handler = MyHandler::new();
+//! let mut dev = pin!(usb.run_device(&mut handler));
+//!
+//! host.reset(dev.as_mut());
+//! let desc = host.control_in(dev.as_mut(), [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 18, 0]);
+//! assert_eq!(desc.unwrap()[0], 18);
+//! ```
+//!
+//! IN transfers on EP0 complete as soon as the device queues them, as if the
+//! host was always polling the endpoint. On other endpoints, they complete
+//! once the host takes the data with [`Host::take_in`], so a device that
+//! streams data can't run ahead of the host. OUT transfers wait for data
+//! queued by the test.
+//!
+//! `examples/host/usbip` exports a simulated device over USB/IP, so it can
+//! enumerate on a Linux host through `usbip attach`.
+//!
+//! The device-side state is thread-local, but the generic USB layer keeps
+//! state in statics, so a [`Host`] holds a global lock for the duration of a
+//! test to run simulations one at a time, and returns that state to its
+//! power-on values when created.
+
+extern crate std;
+
+use core::future::{poll_fn, Future};
+use core::ops::Deref;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use usb::endpoint_address::{ADDR_MASK as EP_ADDR_MASK, DIR_MASK as EP_DIR_MASK, IN as EP_IN};
+
+use crate::usb::Event;
+use crate::Runtime;
+
+const EP0_MAX_PACKET_SIZE: usize = 64;
+
+#[derive(Default)]
+struct State {
+    attached: bool,
+    address: u8,
+    events: VecDeque<Event>,
+
+    ep0_stalled: bool,
+
+    /// Max packet size of each enabled endpoint other than EP0
+    enabled: BTreeMap<u8, u16>,
+    halted: BTreeSet<u8>,
+
+    /// Packets queued by the host for each OUT endpoint number
+    out: BTreeMap<u8, VecDeque<Vec<u8>>>,
+
+    /// Packets sent by the device on each IN endpoint number
+    sent: BTreeMap<u8, Vec<Vec<u8>>>,
+
+    /// OUT packet lent out by `receive_packet` on each endpoint number
+    lent_out: BTreeMap<u8, Vec<u8>>,
+
+    /// IN packet buffer lent out by `packet_buffer` on each endpoint number
+    lent_in: BTreeMap<u8, Vec<u8>>,
+
+    frame: u16,
+    resume_signaled: bool,
+
+    /// Incremented by every device-side action, to detect when the device is idle
+    progress: u64,
+
+    waker: Option<Waker>,
+}
+
+impl State {
+    /// Number of packets sent on IN endpoint `ep` that the host has not taken.
+    fn in_queued(&self, ep: u8) -> usize {
+        self.sent.get(&(ep & EP_ADDR_MASK)).map_or(0, |q| q.len())
+    }
+}
+
+std::thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.with(|s| f(&mut s.borrow_mut()))
+}
+
+/// Record device-side progress and return `f`'s result.
+fn device<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    with_state(|s| {
+        s.progress += 1;
+        f(s)
+    })
+}
+
+/// Poll `f`, registering the waker to be woken by the host if it is pending.
+fn wait<R>(mut f: impl FnMut(&mut State) -> Option<R>) -> impl Future<Output = R> {
+    poll_fn(move |cx: &mut Context<'_>| {
+        with_state(|s| match f(s) {
+            Some(r) => {
+                s.progress += 1;
+                Poll::Ready(r)
+            }
+            None => {
+                s.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    })
+}
+
+fn packets(data: &[u8], max_packet_size: usize, zlp: bool) -> impl Iterator<Item = &[u8]> {
+    let zlp = data.is_empty() || (zlp && data.len().is_multiple_of(max_packet_size));
+    data.chunks(max_packet_size).chain(zlp.then_some(&[] as &[u8]))
+}
+
+#[derive(Copy, Clone)]
+pub struct UsbShared {
+    rt: Runtime,
+}
+
+pub struct Usb {
+    usb: UsbShared,
+}
+
+impl Deref for Usb {
+    type Target = UsbShared;
+
+    fn deref(&self) -> &Self::Target {
+        &self.usb
+    }
+}
+
+impl Usb {
+    /// # Safety
+    ///
+    /// Only one `Usb` may exist at a time.
+    pub unsafe fn new(rt: Runtime) -> Self {
+        Usb {
+            usb: UsbShared { rt },
+        }
+    }
+
+    pub fn shared(&self) -> UsbShared {
+        self.usb
+    }
+
+    pub fn rt(&self) -> Runtime {
+        self.usb.rt
+    }
+
+    pub fn enable(&mut self) {}
+
+    pub fn attach(&mut self) {
+        device(|s| s.attached = true);
+    }
+
+    pub fn detach(&mut self) {
+        device(|s| s.attached = false);
+    }
+
+    pub fn poll_event(&self, cx: &mut Context<'_>) -> Poll<Event> {
+        with_state(|s| match s.events.pop_front() {
+            Some(event) => {
+                s.progress += 1;
+                if let Event::Reset = event {
+                    s.address = 0;
+                    s.enabled.clear();
+                    s.halted.clear();
+                }
+                Poll::Ready(event)
+            }
+            None => {
+                s.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// # Safety
+    ///
+    /// Only one `Endpoint0` may be in use at a time.
+    pub unsafe fn ep0(&self) -> Endpoint0 {
+        Endpoint0 {
+            usb: self.shared(),
+            buf: [0; EP0_MAX_PACKET_SIZE],
+        }
+    }
+}
+
+impl UsbShared {
+    pub fn rt(&self) -> Runtime {
+        self.rt
+    }
+
+    pub fn configure_ep0(&self) {}
+
+    pub fn stall_ep0(&self) {
+        device(|s| s.ep0_stalled = true);
+    }
+
+    pub(crate) fn signal_resume(&self) {
+        device(|s| s.resume_signaled = true);
+    }
+
+    pub fn set_address(&self, addr: u8) {
+        device(|s| s.address = addr);
+    }
+
+    pub fn frame_number(&self) -> u16 {
+        with_state(|s| s.frame)
+    }
+
+    pub async fn wait_sof(&self) -> u16 {
+        let start = self.frame_number();
+        wait(|s| (s.frame != start).then_some(s.frame)).await
+    }
+
+    pub fn enable_ep(&self, ep: u8, max_packet_size: u16) {
+        device(|s| {
+            s.enabled.insert(ep, max_packet_size);
+            s.halted.remove(&ep);
+        });
+    }
+
+    pub fn enable_iso_ep(&self, ep: u8, max_packet_size: u16) {
+        self.enable_ep(ep, max_packet_size);
+    }
+
+    pub fn disable_ep(&self, ep: u8) {
+        device(|s| {
+            s.enabled.remove(&ep);
+            s.halted.remove(&ep);
+        });
+    }
+
+    pub fn set_ep_halt(&self, ep: u8, halt: bool) {
+        device(|s| {
+            if halt {
+                s.halted.insert(ep);
+            } else {
+                s.halted.remove(&ep);
+            }
+        });
+    }
+
+    fn max_packet_size(&self, ep: u8) -> usize {
+        with_state(|s| s.enabled.get(&ep).copied().unwrap_or(EP0_MAX_PACKET_SIZE as u16)) as usize
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads of `len` bytes.
+    pub async unsafe fn transfer_in(&self, ep: u8, ptr: *const u8, len: usize, zlp: bool) {
+        assert!(ep & EP_DIR_MASK == EP_IN);
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        let max_packet_size = self.max_packet_size(ep);
+        device(|s| {
+            let sent = s.sent.entry(ep & EP_ADDR_MASK).or_default();
+            sent.extend(packets(data, max_packet_size, zlp).map(|p| p.to_vec()));
+        });
+
+        if ep & EP_ADDR_MASK != 0 {
+            wait(|s| (s.in_queued(ep) == 0).then_some(())).await;
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of `len` bytes.
+    pub async unsafe fn transfer_out(&self, ep: u8, ptr: *mut u8, len: usize) -> usize {
+        assert!(ep & EP_DIR_MASK != EP_IN);
+        let max_packet_size = self.max_packet_size(ep);
+        let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
+        let mut total_len = 0;
+
+        loop {
+            let pkt = wait(|s| s.out.get_mut(&ep).and_then(|q| q.pop_front())).await;
+            assert!(pkt.len() <= buf.len() - total_len, "OUT packet overflows the transfer buffer");
+            buf[total_len..total_len + pkt.len()].copy_from_slice(&pkt);
+            total_len += pkt.len();
+
+            if pkt.len() < max_packet_size || total_len == buf.len() {
+                return total_len;
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads of `len` bytes.
+    pub async unsafe fn iso_transfer_in(&self, ep: u8, ptr: *const u8, len: usize, max_packet_size: u16) {
+        debug_assert!(len <= max_packet_size as usize);
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        device(|s| s.sent.entry(ep & EP_ADDR_MASK).or_default().push(data.to_vec()));
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of `max_packet_size` bytes.
+    pub async unsafe fn iso_transfer_out(&self, ep: u8, ptr: *mut u8, max_packet_size: u16) -> usize {
+        let pkt = wait(|s| s.out.get_mut(&ep).and_then(|q| q.pop_front())).await;
+        let len = pkt.len().min(max_packet_size as usize);
+        unsafe { ptr.copy_from_nonoverlapping(pkt.as_ptr(), len) };
+        len
+    }
+
+    /// # Safety
+    ///
+    /// The buffer must be submitted with [`submit_packet`](Self::submit_packet)
+    /// before another is requested on `ep`.
+    pub async unsafe fn packet_buffer(&self, ep: u8) -> *mut u8 {
+        let max_packet_size = self.max_packet_size(ep);
+
+        // Like the double-buffered hardware, allow two packets to be queued
+        wait(|s| (s.in_queued(ep) < 2).then_some(())).await;
+        device(|s| s.lent_in.entry(ep & EP_ADDR_MASK).or_insert_with(|| std::vec![0; max_packet_size]).as_mut_ptr())
+    }
+
+    /// # Safety
+    ///
+    /// [`packet_buffer`](Self::packet_buffer) must have returned a buffer for `ep`,
+    /// which is not used afterwards.
+    pub unsafe fn submit_packet(&self, ep: u8, len: usize) {
+        device(|s| {
+            let pkt = s.lent_in[&(ep & EP_ADDR_MASK)][..len].to_vec();
+            s.sent.entry(ep & EP_ADDR_MASK).or_default().push(pkt);
+        });
+    }
+
+    pub async fn flush(&self, _ep: u8) {}
+
+    /// # Safety
+    ///
+    /// The packet must not be used after [`release_packet`](Self::release_packet).
+    pub async unsafe fn receive_packet(&self, ep: u8) -> (*const u8, usize) {
+        if let Some(pkt) = with_state(|s| s.lent_out.get(&ep).map(|p| (p.as_ptr(), p.len()))) {
+            return pkt;
+        }
+        wait(|s| {
+            let pkt = s.out.get_mut(&ep).and_then(|q| q.pop_front())?;
+            let pkt = s.lent_out.entry(ep).or_insert(pkt);
+            Some((pkt.as_ptr(), pkt.len()))
+        })
+        .await
+    }
+
+    /// # Safety
+    ///
+    /// A packet must have been received on `ep`, and is not used afterwards.
+    pub unsafe fn release_packet(&self, ep: u8) {
+        device(|s| s.lent_out.remove(&ep));
+    }
+}
+
+pub struct Endpoint0 {
+    pub(crate) usb: UsbShared,
+    buf: [u8; EP0_MAX_PACKET_SIZE],
+}
+
+impl Endpoint0 {
+    pub fn ep0_transfer_in<'b>(&'b mut self, data: &'b [u8], is_full: bool) -> impl Future<Output = ()> + 'b {
+        unsafe { self.usb.transfer_in(0x80, data.as_ptr(), data.len(), !is_full) }
+    }
+
+    pub async fn ep0_transfer_out(&mut self) -> &[u8] {
+        let pkt = wait(|s| s.out.get_mut(&0).and_then(|q| q.pop_front())).await;
+        let len = pkt.len().min(EP0_MAX_PACKET_SIZE);
+        self.buf[..len].copy_from_slice(&pkt[..len]);
+        &self.buf[..len]
+    }
+
+    pub(crate) fn stall_ep0(&mut self) {
+        self.usb.stall_ep0();
+    }
+}
+
+/// Error from a simulated control transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ControlError {
+    /// The device stalled the request.
+    Stall,
+
+    /// The device did not complete the request without waiting on something else.
+    Incomplete,
+}
+
+/// The host side of the simulated bus.
+pub struct Host {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Host {
+    /// Start a simulation, waiting for any other simulation on another thread to finish.
+    pub fn new() -> Host {
+        let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        with_state(|s| *s = State::default());
+        crate::usb::reset_statics(unsafe { Runtime::steal() });
+        Host { _lock: lock }
+    }
+
+    fn host<R>(&mut self, f: impl FnOnce(&mut State) -> R) -> R {
+        let (r, waker) = with_state(|s| (f(s), s.waker.take()));
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        r
+    }
+
+    /// Poll the device future until it stops making progress.
+    ///
+    /// Returns its output if it completed.
+    pub fn run<F: Future>(&mut self, mut fut: Pin<&mut F>) -> Option<F::Output> {
+        let mut cx = Context::from_waker(Waker::noop());
+        for _ in 0..10_000 {
+            let before = with_state(|s| s.progress);
+            if let Poll::Ready(r) = fut.as_mut().poll(&mut cx) {
+                return Some(r);
+            }
+            if with_state(|s| s.progress) == before {
+                return None;
+            }
+        }
+        panic!("device did not become idle");
+    }
+
+    /// Signal a bus reset.
+    pub fn reset<F: Future>(&mut self, dev: Pin<&mut F>) {
+        self.host(|s| s.events.push_back(Event::Reset));
+        self.run(dev);
+    }
+
+    /// Suspend the bus.
+    pub fn suspend<F: Future>(&mut self, dev: Pin<&mut F>) {
+        self.host(|s| s.events.push_back(Event::Suspend));
+        self.run(dev);
+    }
+
+    /// Resume the bus.
+    pub fn resume<F: Future>(&mut self, dev: Pin<&mut F>) {
+        self.host(|s| s.events.push_back(Event::Resume));
+        self.run(dev);
+    }
+
+    /// Send a start-of-frame packet.
+    pub fn sof<F: Future>(&mut self, dev: Pin<&mut F>) {
+        self.host(|s| s.frame = (s.frame + 1) & 0x7ff);
+        self.run(dev);
+    }
+
+    /// Send a SETUP packet, which aborts any control transfer in progress.
+    pub fn setup<F: Future>(&mut self, dev: Pin<&mut F>, packet: [u8; 8]) {
+        self.host(|s| {
+            s.ep0_stalled = false;
+            s.out.remove(&0);
+            s.sent.remove(&0);
+            s.events.push_back(Event::Setup(packet));
+        });
+        self.run(dev);
+    }
+
+    /// Queue one OUT packet on endpoint `ep`.
+    pub fn out_packet<F: Future>(&mut self, dev: Pin<&mut F>, ep: u8, data: &[u8]) {
+        self.host(|s| s.out.entry(ep & EP_ADDR_MASK).or_default().push_back(data.to_vec()));
+        self.run(dev);
+    }
+
+    /// Queue an OUT transfer on endpoint `ep`, split into packets of its max
+    /// packet size, and ended by a zero-length packet if needed.
+    pub fn out_transfer<F: Future>(&mut self, mut dev: Pin<&mut F>, ep: u8, data: &[u8]) {
+        let max_packet_size = with_state(|s| s.enabled.get(&(ep & EP_ADDR_MASK)).copied().unwrap_or(64)) as usize;
+        for pkt in packets(data, max_packet_size, true) {
+            self.out_packet(dev.as_mut(), ep, pkt);
+        }
+    }
+
+    /// Take the packets the device has sent on IN endpoint `ep`.
+    ///
+    /// While the endpoint is halted, the device answers with STALL, so
+    /// nothing is taken and its transfer does not complete.
+    pub fn take_in(&mut self, ep: u8) -> Vec<Vec<u8>> {
+        self.host(|s| {
+            if ep & EP_ADDR_MASK != 0 && s.halted.contains(&(ep | EP_IN)) {
+                return Vec::new();
+            }
+            s.sent.remove(&(ep & EP_ADDR_MASK)).unwrap_or_default()
+        })
+    }
+
+    /// Perform a control transfer with an IN data stage, returning the data sent by the device.
+    pub fn control_in<F: Future>(&mut self, mut dev: Pin<&mut F>, setup: [u8; 8]) -> Result<Vec<u8>, ControlError> {
+        self.setup(dev.as_mut(), setup);
+        if self.ep0_stalled() {
+            return Err(ControlError::Stall);
+        }
+
+        let packets = self.take_in(0);
+        let len = u16::from_le_bytes([setup[6], setup[7]]) as usize;
+        let data: Vec<u8> = packets.concat();
+        let complete = data.len() == len || packets.last().is_some_and(|p| p.len() < EP0_MAX_PACKET_SIZE);
+        if !complete {
+            return Err(ControlError::Incomplete);
+        }
+
+        // Status stage
+        self.out_packet(dev.as_mut(), 0, &[]);
+        if self.ep0_stalled() {
+            return Err(ControlError::Stall);
+        }
+
+        Ok(data)
+    }
+
+    /// Perform a control transfer with an OUT or no data stage.
+    pub fn control_out<F: Future>(&mut self, mut dev: Pin<&mut F>, setup: [u8; 8], data: &[u8]) -> Result<(), ControlError> {
+        assert_eq!(u16::from_le_bytes([setup[6], setup[7]]) as usize, data.len());
+
+        self.setup(dev.as_mut(), setup);
+
+        for pkt in data.chunks(EP0_MAX_PACKET_SIZE) {
+            if self.ep0_stalled() {
+                return Err(ControlError::Stall);
+            }
+            self.out_packet(dev.as_mut(), 0, pkt);
+        }
+
+        if self.ep0_stalled() {
+            return Err(ControlError::Stall);
+        }
+
+        // Status stage
+        match self.take_in(0).as_slice() {
+            [status] if status.is_empty() => Ok(()),
+            _ => Err(ControlError::Incomplete),
+        }
+    }
+
+    /// Number of packets queued on OUT endpoint `ep` that the device has not received yet.
+    pub fn out_pending(&self, ep: u8) -> usize {
+        with_state(|s| s.out.get(&(ep & EP_ADDR_MASK)).map_or(0, |q| q.len()))
+    }
+
+    pub fn is_attached(&self) -> bool {
+        with_state(|s| s.attached)
+    }
+
+    /// Address set by SET_ADDRESS.
+    pub fn address(&self) -> u8 {
+        with_state(|s| s.address)
+    }
+
+    /// Returns `true` if EP0 is stalled for the current control transfer.
+    pub fn ep0_stalled(&self) -> bool {
+        with_state(|s| s.ep0_stalled)
+    }
+
+    /// Addresses of the enabled endpoints other than EP0, in ascending order.
+    pub fn enabled_endpoints(&self) -> Vec<u8> {
+        with_state(|s| s.enabled.keys().copied().collect())
+    }
+
+    /// Max packet size of enabled endpoint `ep`.
+    pub fn max_packet_size(&self, ep: u8) -> Option<u16> {
+        with_state(|s| s.enabled.get(&ep).copied())
+    }
+
+    pub fn is_halted(&self, ep: u8) -> bool {
+        with_state(|s| s.halted.contains(&ep))
+    }
+
+    /// Returns `true` if the device has signaled remote wakeup since the last call.
+    pub fn take_resume_signaled(&mut self) -> bool {
+        with_state(|s| core::mem::take(&mut s.resume_signaled))
+    }
+}
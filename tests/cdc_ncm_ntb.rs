@@ -0,0 +1,87 @@
+mod ntb {
+    include!("../src/usb/class/cdc_ncm/ntb.rs");
+
+    #[test]
+    pub fn test_roundtrip() {
+        let mut buf = [0u8; 128];
+        let frame = [0x55u8; 60];
+        let len = write_ntb16(&mut buf, 7, &frame).unwrap();
+        assert_eq!(len, DATAGRAM_OFFSET + frame.len());
+
+        let ntb = &buf[..len];
+        assert_eq!(NtbCursor::sequence(ntb), 7);
+
+        let mut cursor = NtbCursor::new(ntb).unwrap();
+        assert_eq!(cursor.next(ntb), Some(DATAGRAM_OFFSET..len));
+        assert_eq!(cursor.next(ntb), None);
+        assert_eq!(cursor.next(ntb), None);
+    }
+
+    #[test]
+    pub fn test_empty() {
+        let mut cursor = NtbCursor::EMPTY;
+        assert_eq!(cursor.next(&[]), None);
+    }
+
+    #[test]
+    pub fn test_multiple_datagrams() {
+        let mut ntb = [0u8; 64];
+        ntb[0..4].copy_from_slice(b"NCMH");
+        ntb[4..6].copy_from_slice(&12u16.to_le_bytes());
+        ntb[8..10].copy_from_slice(&64u16.to_le_bytes());
+        ntb[10..12].copy_from_slice(&12u16.to_le_bytes());
+
+        ntb[12..16].copy_from_slice(b"NCM0");
+        ntb[16..18].copy_from_slice(&20u16.to_le_bytes());
+        ntb[18..20].copy_from_slice(&0u16.to_le_bytes());
+        ntb[20..22].copy_from_slice(&32u16.to_le_bytes());
+        ntb[22..24].copy_from_slice(&10u16.to_le_bytes());
+        ntb[24..26].copy_from_slice(&44u16.to_le_bytes());
+        ntb[26..28].copy_from_slice(&20u16.to_le_bytes());
+
+        let mut cursor = NtbCursor::new(&ntb).unwrap();
+        assert_eq!(cursor.next(&ntb), Some(32..42));
+        assert_eq!(cursor.next(&ntb), Some(44..64));
+        assert_eq!(cursor.next(&ntb), None);
+    }
+
+    #[test]
+    pub fn test_invalid() {
+        let mut buf = [0u8; 128];
+        let len = write_ntb16(&mut buf, 0, &[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(NtbCursor::new(&buf[..len - 1]), Err(NtbError::Truncated));
+
+        let mut bad = buf;
+        bad[0] = b'X';
+        assert_eq!(NtbCursor::new(&bad[..len]), Err(NtbError::BadSignature));
+
+        let mut bad = buf;
+        bad[10..12].copy_from_slice(&200u16.to_le_bytes());
+        assert_eq!(NtbCursor::new(&bad[..len]), Err(NtbError::BadPointer));
+
+        let mut bad = buf;
+        bad[20..22].copy_from_slice(&120u16.to_le_bytes());
+        let mut cursor = NtbCursor::new(&bad[..len]).unwrap();
+        assert_eq!(cursor.next(&bad[..len]), None);
+    }
+
+    #[test]
+    pub fn test_ndp_loop() {
+        let mut buf = [0u8; 128];
+        let len = write_ntb16(&mut buf, 0, &[1, 2, 3, 4]).unwrap();
+
+        // wNextNdpIndex pointing back to the same NDP16
+        buf[18..20].copy_from_slice(&12u16.to_le_bytes());
+        let mut cursor = NtbCursor::new(&buf[..len]).unwrap();
+        assert_eq!(cursor.next(&buf[..len]), Some(DATAGRAM_OFFSET..len));
+        assert_eq!(cursor.next(&buf[..len]), None);
+    }
+
+    #[test]
+    pub fn test_overflow() {
+        let mut buf = [0u8; 64];
+        assert_eq!(write_ntb16(&mut buf, 0, &[0; 36]), Ok(64));
+        assert_eq!(write_ntb16(&mut buf, 0, &[0; 37]), Err(NtbError::Overflow));
+    }
+}
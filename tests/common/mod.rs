@@ -0,0 +1,72 @@
+//! Shared setup for the tests that run a device on the simulated USB bus.
+#![allow(dead_code)]
+
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+
+use zeptos::usb::sim::Host;
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    panic!("defmt panic")
+}
+
+defmt::timestamp!("");
+
+pub const DEVICE_DESCRIPTOR: &[u8] = &[
+    18, 0x01, 0x00, 0x02, 0xff, 0x00, 0x00, 64, 0x34, 0x12, 0x78, 0x56, 0x00, 0x01, 0, 0, 0, 1,
+];
+
+/// Build a SETUP packet.
+pub fn setup(ty: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let [v0, v1] = value.to_le_bytes();
+    let [i0, i1] = index.to_le_bytes();
+    let [l0, l1] = length.to_le_bytes();
+    [ty, request, v0, v1, i0, i1, l0, l1]
+}
+
+/// Poll `op` alongside the device until it completes, returning its output and the packets sent on IN endpoint `ep`.
+pub fn run_op<F: Future, O: Future>(host: &mut Host, mut dev: Pin<&mut F>, ep: u8, op: O) -> (O::Output, Vec<Vec<u8>>) {
+    let mut op = pin!(op);
+    let mut packets = Vec::new();
+    loop {
+        let both = pin!(poll_fn(|cx| {
+            let _ = dev.as_mut().poll(cx);
+            op.as_mut().poll(cx)
+        }));
+        let output = host.run(both);
+        let sent = host.take_in(ep);
+        if let Some(output) = output {
+            packets.extend(sent);
+            return (output, packets);
+        }
+        assert!(!sent.is_empty(), "operation did not complete");
+        packets.extend(sent);
+    }
+}
+
+/// Run the device until it ends a transfer on IN endpoint `ep` with a short packet, returning the transfer.
+pub fn bulk_in<F: Future>(host: &mut Host, mut dev: Pin<&mut F>, ep: u8, max_packet_size: usize) -> Vec<u8> {
+    let mut transfer = Vec::new();
+    loop {
+        let packets = host.take_in(ep);
+        host.run(dev.as_mut());
+        assert!(!packets.is_empty(), "no response");
+        for pkt in packets {
+            transfer.extend(&pkt);
+            if pkt.len() < max_packet_size {
+                return transfer;
+            }
+        }
+    }
+}
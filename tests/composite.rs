@@ -0,0 +1,247 @@
+#![cfg(feature = "usb-sim")]
+
+use std::cell::RefCell;
+use std::pin::pin;
+
+use zeptos::usb::composite::{composite_config, composite_msos, Allocator, Composite, CompositeDevice, Function};
+use zeptos::usb::descriptors::{concat, descriptors, Endpoint as EndpointDescriptor, Interface, MicrosoftOsCompatibleID};
+use zeptos::usb::sim::{ControlError, Host};
+use zeptos::usb::{ControlData, Endpoint, Endpoints, In, Out, Responded, Setup, Usb};
+use zeptos::Runtime;
+
+mod common;
+use common::{setup, DEVICE_DESCRIPTOR};
+
+/// Function with a communication interface and a data interface whose alternate setting 1 has bulk endpoints.
+struct NetFunction<const COMM: u8, const NOTIFY: u8, const EP_IN: u8, const EP_OUT: u8> {
+    notify: RefCell<Option<Endpoint<In, NOTIFY, 16>>>,
+    data: RefCell<Option<(Endpoint<In, EP_IN>, Endpoint<Out, EP_OUT>)>>,
+}
+
+impl<const COMM: u8, const NOTIFY: u8, const EP_IN: u8, const EP_OUT: u8> Function for NetFunction<COMM, NOTIFY, EP_IN, EP_OUT> {
+    const FIRST_INTERFACE: u8 = COMM;
+    const INTERFACES: u8 = 2;
+    const CLASS: (u8, u8, u8) = (0x02, 0x0d, 0);
+
+    const DESCRIPTORS: &'static [u8] = &{
+        let notify = EndpointDescriptor { bEndpointAddress: NOTIFY, bmAttributes: 0x03, wMaxPacketSize: 16, bInterval: 32 }.bytes(&[]);
+        let ep_in = EndpointDescriptor { bEndpointAddress: EP_IN, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0 }.bytes(&[]);
+        let ep_out = EndpointDescriptor { bEndpointAddress: EP_OUT, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0 }.bytes(&[]);
+        let comm = Interface { bInterfaceNumber: COMM, bAlternateSetting: 0, bInterfaceClass: 0x02, bInterfaceSubClass: 0x0d, bInterfaceProtocol: 0, iInterface: 0 };
+        let data0 = Interface { bInterfaceNumber: COMM + 1, bAlternateSetting: 0, bInterfaceClass: 0x0a, bInterfaceSubClass: 0, bInterfaceProtocol: 0x01, iInterface: 0 };
+        let data1 = Interface { bInterfaceNumber: COMM + 1, bAlternateSetting: 1, bInterfaceClass: 0x0a, bInterfaceSubClass: 0, bInterfaceProtocol: 0x01, iInterface: 0 };
+        concat::<{ 3 * Interface::LEN + 3 * EndpointDescriptor::LEN }>(&[
+            &comm.bytes(&[&notify]),
+            &notify,
+            &data0.bytes(&[]),
+            &data1.bytes(&[&ep_in, &ep_out]),
+            &ep_in,
+            &ep_out,
+        ])
+    };
+
+    async fn configure(&self, endpoints: &mut Endpoints) {
+        self.notify.replace(Some(endpoints.endpoint()));
+    }
+
+    fn deconfigure(&self) {
+        self.notify.replace(None);
+        self.data.replace(None);
+    }
+
+    async fn set_interface(&self, intf: u8, alt: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        match (intf - COMM, alt) {
+            (0, 0) => Ok(()),
+            (1, 0) => {
+                self.data.replace(None);
+                Ok(())
+            }
+            (1, 1) => {
+                self.data.replace(Some((endpoints.bulk_in(), endpoints.bulk_out())));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Function with a single interface and a bulk IN endpoint.
+struct VendorFunction<const INTF: u8, const EP_IN: u8> {
+    ep_in: RefCell<Option<Endpoint<In, EP_IN>>>,
+}
+
+impl<const INTF: u8, const EP_IN: u8> Function for VendorFunction<INTF, EP_IN> {
+    const FIRST_INTERFACE: u8 = INTF;
+    const INTERFACES: u8 = 1;
+    const CLASS: (u8, u8, u8) = (0xff, 0, 0);
+
+    const DESCRIPTORS: &'static [u8] = &{
+        let ep_in = EndpointDescriptor { bEndpointAddress: EP_IN, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0 }.bytes(&[]);
+        let intf = Interface { bInterfaceNumber: INTF, bAlternateSetting: 0, bInterfaceClass: 0xff, bInterfaceSubClass: 0, bInterfaceProtocol: 0, iInterface: 0 };
+        concat::<{ Interface::LEN + EndpointDescriptor::LEN }>(&[&intf.bytes(&[&ep_in]), &ep_in])
+    };
+
+    const MS_OS_FEATURES: &'static [u8] = descriptors! {
+        MicrosoftOsCompatibleID {
+            compatible_id: "WINUSB",
+            sub_compatible_id: "",
+        }
+    };
+
+    async fn configure(&self, endpoints: &mut Endpoints) {
+        self.ep_in.replace(Some(endpoints.bulk_in()));
+    }
+
+    fn deconfigure(&self) {
+        self.ep_in.replace(None);
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        match req {
+            Setup { request: REQ_WHO, data: ControlData::In(data), .. } => data.respond(&[INTF]).await,
+            req => req.reject(),
+        }
+    }
+}
+
+/// Vendor request answered with the interface number of the function, or 0xff by the device.
+const REQ_WHO: u8 = 0x01;
+
+struct Numbers {
+    net_comm: u8,
+    net_notify: u8,
+    net_in: u8,
+    net_out: u8,
+    vendor: u8,
+    vendor_in: u8,
+}
+
+const N: Numbers = {
+    let mut a = Allocator::new();
+    Numbers {
+        net_comm: a.interfaces(2),
+        net_notify: a.endpoint_in(),
+        net_in: a.endpoint_in(),
+        net_out: a.endpoint_out(),
+        vendor: a.interfaces(1),
+        vendor_in: a.endpoint_in(),
+    }
+};
+
+type Net = NetFunction<{ N.net_comm }, { N.net_notify }, { N.net_in }, { N.net_out }>;
+type Vendor = VendorFunction<{ N.vendor }, { N.vendor_in }>;
+
+struct TestComposite {
+    functions: (Net, Vendor),
+}
+
+impl CompositeDevice for TestComposite {
+    type Functions = (Net, Vendor);
+    const DEVICE_DESCRIPTOR: &'static [u8] = DEVICE_DESCRIPTOR;
+    const CONFIG_DESCRIPTOR: &'static [u8] = composite_config! {
+        Config {
+            bConfigurationValue: 1,
+            iConfiguration: 0,
+            bmAttributes: 0x80,
+            bMaxPower: 50,
+        }
+        functions: [Net, Vendor]
+    };
+
+    fn functions(&self) -> &Self::Functions {
+        &self.functions
+    }
+
+    fn self_powered(&self) -> bool {
+        true
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        match req {
+            Setup { request: REQ_WHO, data: ControlData::In(data), .. } => data.respond(&[0xff]).await,
+            req => req.reject(),
+        }
+    }
+}
+
+const MSOS_DESCRIPTOR: &[u8] = composite_msos! {
+    MicrosoftOs {
+        windows_version: 0x06030000,
+    }
+    functions: [Net, Vendor]
+};
+
+#[test]
+fn test_composite() {
+    assert_eq!((N.net_comm, N.net_notify, N.net_in, N.net_out), (0, 0x81, 0x82, 0x01));
+    assert_eq!((N.vendor, N.vendor_in), (2, 0x83));
+
+    // Configuration, IAD for the network function, and the functions' descriptors
+    let cfg = TestComposite::CONFIG_DESCRIPTOR;
+    assert_eq!(cfg.len(), 9 + 8 + Net::DESCRIPTORS.len() + Vendor::DESCRIPTORS.len());
+    assert_eq!(u16::from_le_bytes([cfg[2], cfg[3]]) as usize, cfg.len());
+    assert_eq!(cfg[4], 3);
+    assert_eq!(&cfg[9..17], &[8, 0x0b, 0, 2, 0x02, 0x0d, 0, 0]);
+
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = Composite(TestComposite {
+        functions: (
+            NetFunction { notify: RefCell::new(None), data: RefCell::new(None) },
+            VendorFunction { ep_in: RefCell::new(None) },
+        ),
+    });
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0200, 0, 255)), Ok(cfg.to_vec()));
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+    assert_eq!(host.enabled_endpoints(), [0x81, 0x83]);
+    assert_eq!(host.max_packet_size(0x81), Some(16));
+
+    host.control_out(dev.as_mut(), setup(0x01, 0x0b, 1, 1, 0), &[]).unwrap();
+    assert_eq!(host.enabled_endpoints(), [0x01, 0x81, 0x82, 0x83]);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x0a, 0, 1, 1)), Ok(vec![1]));
+
+    // The vendor function has no alternate settings, and there is no fourth interface
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x01, 0x0b, 1, 2, 0), &[]), Err(ControlError::Stall));
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x01, 0x0b, 0, 3, 0), &[]), Err(ControlError::Stall));
+
+    // Requests are routed by the whole of wIndex
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc1, REQ_WHO, 0, 2, 1)), Ok(vec![2]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc1, REQ_WHO, 0, 0x0102, 1)), Ok(vec![0xff]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_WHO, 0, 0, 1)), Ok(vec![0xff]));
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x00, 0, 0, 2)), Ok(vec![0b01, 0]));
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 0, 0, 0), &[]).unwrap();
+    assert_eq!(host.enabled_endpoints(), []);
+}
+
+#[test]
+fn test_composite_msos() {
+    // Only the vendor function has features, so it's the only function subset
+    let features = Vendor::MS_OS_FEATURES;
+    assert_eq!(MSOS_DESCRIPTOR.len(), 10 + 8 + 8 + features.len());
+    assert_eq!(u16::from_le_bytes([MSOS_DESCRIPTOR[8], MSOS_DESCRIPTOR[9]]) as usize, MSOS_DESCRIPTOR.len());
+    assert_eq!(&MSOS_DESCRIPTOR[10..18], &[8, 0, 0x01, 0, 0, 0, 8 + 8 + features.len() as u8, 0]);
+    assert_eq!(&MSOS_DESCRIPTOR[18..26], &[8, 0, 0x02, 0, N.vendor, 0, 8 + features.len() as u8, 0]);
+    assert_eq!(&MSOS_DESCRIPTOR[26..], features);
+}
+
+#[test]
+fn test_allocator() {
+    let mut a = Allocator::default();
+    assert_eq!(a.interfaces(3), 0);
+    assert_eq!(a.interfaces(1), 3);
+    assert_eq!(a.endpoint_out(), 0x01);
+}
+
+#[test]
+#[should_panic(expected = "out of interface numbers")]
+fn test_allocator_interfaces_overflow() {
+    let mut a = Allocator::new();
+    a.interfaces(200);
+    a.interfaces(100);
+}
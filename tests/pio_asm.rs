@@ -0,0 +1,155 @@
+#![allow(dead_code)]
+
+mod asm {
+    include!("../src/rp/pio/asm.rs");
+
+    #[test]
+    pub fn test_ws2812() {
+        let program = assemble(
+            "
+            .program ws2812
+            .side_set 1
+
+            .define public T1 2
+            .define public T2 5
+            .define public T3 3
+
+            .wrap_target
+            bitloop:
+                out x, 1       side 0 [2] ; T3 - 1
+                jmp !x do_zero side 1 [1] ; T1 - 1
+            do_one:
+                jmp  bitloop   side 1 [4] ; T2 - 1
+            do_zero:
+                nop            side 0 [4] ; T2 - 1
+            .wrap
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.code(), [0x6221, 0x1123, 0x1400, 0xa442]);
+        assert_eq!(program.side_set, SideSet { bits: 1, optional: false, pindirs: false });
+        assert_eq!((program.wrap_target, program.wrap), (0, 3));
+        assert_eq!(program.origin, None);
+    }
+
+    #[test]
+    pub fn test_uart_tx() {
+        let program = assemble(
+            "
+            .program uart_tx
+            .side_set 1 opt
+                pull       side 1 [7]
+                set x, 7   side 0 [7]
+            bitloop:
+                out pins, 1
+                jmp x-- bitloop   [6]
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.code(), [0x9fa0, 0xf727, 0x6001, 0x0642]);
+        assert_eq!(program.side_set.count(), 2);
+        assert_eq!((program.wrap_target, program.wrap), (0, 3));
+    }
+
+    #[test]
+    pub fn test_const() {
+        const PROGRAM: Program = match assemble(".side_set 1 opt pindirs\nnop side 1\nnop") {
+            Ok(program) => program,
+            Err(e) => e.panic(),
+        };
+        assert_eq!(PROGRAM.code(), [0xb842, 0xa042]);
+        assert_eq!(PROGRAM.len(), 2);
+        assert_eq!(PROGRAM.side_set, SideSet { bits: 1, optional: true, pindirs: true });
+    }
+
+    #[test]
+    pub fn test_wrap_and_origin() {
+        let program = assemble(
+            "
+            .origin 4
+                set pindirs, 1
+            .wrap_target
+            again:
+                set pins, 1 [1]
+                set pins, 0
+            .wrap
+                jmp again // not reached
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.code(), [0xe081, 0xe101, 0xe000, 0x0001]);
+        assert_eq!((program.wrap_target, program.wrap), (1, 2));
+        assert_eq!(program.origin, Some(4));
+    }
+
+    #[test]
+    pub fn test_instructions() {
+        let cases: &[(&str, u16)] = &[
+            ("jmp 3", 0x0003),
+            ("jmp !x, 1", 0x0021),
+            ("jmp x--, 1", 0x0041),
+            ("jmp !y 1", 0x0061),
+            ("jmp y-- 0", 0x0080),
+            ("jmp x!=y 5", 0x00a5),
+            ("jmp pin 1", 0x00c1),
+            ("jmp !osre 0", 0x00e0),
+            ("wait 1 gpio 3", 0x2083),
+            ("wait 0 pin 0", 0x2020),
+            ("wait 1 irq 0 rel", 0x20d0),
+            ("in pins, 32", 0x4000),
+            ("in osr, 8", 0x40e8),
+            ("out pindirs, 2", 0x6082),
+            ("out exec, 16", 0x60f0),
+            ("push", 0x8020),
+            ("push iffull noblock", 0x8040),
+            ("pull ifempty block", 0x80e0),
+            ("pull noblock", 0x8080),
+            ("mov x, ~osr", 0xa02f),
+            ("mov y, !x", 0xa049),
+            ("mov isr, ::x", 0xa0d1),
+            ("mov x, status", 0xa025),
+            ("mov pc, y", 0xa0a2),
+            ("irq 3", 0xc003),
+            ("irq set 3", 0xc003),
+            ("irq nowait 2 rel", 0xc012),
+            ("irq wait 0 rel", 0xc030),
+            ("irq clear 3", 0xc043),
+            ("set y, 0x1f", 0xe05f),
+            ("set x, 0b101", 0xe025),
+            ("nop [31]", 0xbf42),
+            ("JMP 3", 0x0003),
+            (".word 0xa042", 0xa042),
+        ];
+        for &(src, instr) in cases {
+            assert_eq!(assemble(src).map(|p| p.code()[0]), Ok(instr), "{src}");
+        }
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let error = |src: &str, line, kind| assert_eq!(assemble(src), Err(Error { line, kind }), "{src}");
+        error("", 1, ErrorKind::Length);
+        error(&"nop\n".repeat(33), 33, ErrorKind::Length);
+        error("foo x, 1", 1, ErrorKind::UnknownInstruction);
+        error(".fifo txrx\nnop", 1, ErrorKind::UnknownDirective);
+        error("nop\nset x, 32", 2, ErrorKind::OutOfRange);
+        error("in x, 0", 1, ErrorKind::OutOfRange);
+        error("set x", 1, ErrorKind::InvalidOperand);
+        error("set pc, 1", 1, ErrorKind::InvalidOperand);
+        error("set x, 1 1", 1, ErrorKind::InvalidOperand);
+        error("nop [1", 1, ErrorKind::InvalidOperand);
+        error("jmp nowhere", 1, ErrorKind::UndefinedSymbol);
+        error("a:\na:\nnop", 2, ErrorKind::DuplicateSymbol);
+        error(".side_set 1\nnop", 2, ErrorKind::MissingSideSet);
+        error(".side_set 1\nnop side 2", 2, ErrorKind::OutOfRange);
+        error(".side_set 2\nnop [8]", 2, ErrorKind::OutOfRange);
+        error(".side_set 5 opt", 1, ErrorKind::OutOfRange);
+        error("nop side 1", 1, ErrorKind::OutOfRange);
+    }
+
+    #[test]
+    #[should_panic(expected = "pio_asm line 12: undefined symbol")]
+    pub fn test_error_message() {
+        Error { line: 12, kind: ErrorKind::UndefinedSymbol }.panic();
+    }
+}
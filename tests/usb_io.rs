@@ -0,0 +1,169 @@
+#![cfg(all(feature = "usb-sim", feature = "embedded-io-async"))]
+
+use std::cell::RefCell;
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+
+use embedded_io_async::{Read, Write};
+use zeptos::usb::descriptors::DescriptorBuilder;
+use zeptos::usb::io::UsbStream;
+use zeptos::usb::sim::Host;
+use zeptos::usb::{Endpoint, Endpoints, Handler, In, Out, Usb};
+use zeptos::Runtime;
+
+mod common;
+use common::{run_op, setup, DEVICE_DESCRIPTOR};
+
+const CONFIG_DESCRIPTOR: &[u8] = &[
+    9, 0x02, 32, 0, 1, 1, 0, 0x80, 50,
+    9, 0x04, 0, 0, 2, 0xff, 0, 0, 0,
+    7, 0x05, 0x81, 0x02, 64, 0, 0,
+    7, 0x05, 0x01, 0x02, 64, 0, 0,
+];
+
+const EP_OUT: u8 = 0x01;
+const EP_IN: u8 = 0x81;
+
+type Stream = UsbStream<EP_OUT, EP_IN, 128, 128>;
+
+struct TestDevice {
+    endpoints: &'static RefCell<Option<(Endpoint<Out, EP_OUT>, Endpoint<In, EP_IN>)>>,
+}
+
+impl Handler for TestDevice {
+    fn get_descriptor<'a>(&self, kind: u8, index: u8, _lang: u16, _builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        use usb::descriptor_type::{CONFIGURATION, DEVICE};
+        match (kind, index) {
+            (DEVICE, 0) => Some(DEVICE_DESCRIPTOR),
+            (CONFIGURATION, 0) => Some(CONFIG_DESCRIPTOR),
+            _ => None,
+        }
+    }
+
+    async fn set_configuration(&self, cfg: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        self.endpoints.replace(None);
+        match cfg {
+            0 => Ok(()),
+            1 => {
+                self.endpoints.replace(Some((endpoints.bulk_out(), endpoints.bulk_in())));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+fn open_stream<F: Future>(host: &mut Host, mut dev: Pin<&mut F>, endpoints: &RefCell<Option<(Endpoint<Out, EP_OUT>, Endpoint<In, EP_IN>)>>) -> Stream {
+    host.reset(dev.as_mut());
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+    let (ep_out, ep_in) = endpoints.take().unwrap();
+    UsbStream::new(ep_out, ep_in)
+}
+
+#[test]
+fn test_writer_zlp() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let endpoints = Box::leak(Box::new(RefCell::new(None)));
+    let mut handler = TestDevice { endpoints };
+    let mut dev = pin!(usb.run_device(&mut handler));
+    let mut stream = open_stream(&mut host, dev.as_mut(), endpoints);
+    let data: Vec<u8> = (0..200).collect();
+
+    // Nothing is sent until flushed, and then an exact packet is followed by a zero-length packet
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.write_all(&data[..64]));
+    assert!(sent.is_empty());
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.flush());
+    assert_eq!(sent, [data[..64].to_vec(), vec![]]);
+
+    // Flushing again sends nothing
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.flush());
+    assert!(sent.is_empty());
+
+    // A short final packet ends the transfer by itself
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.write_all(&data[..100]));
+    assert!(sent.is_empty());
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.flush());
+    assert_eq!(sent, [data[..64].to_vec(), data[64..100].to_vec()]);
+
+    // A full buffer is sent without ending the transfer, which the flush then ends
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.write_all(&data));
+    assert_eq!(sent, [data[..64].to_vec(), data[64..128].to_vec()]);
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.flush());
+    assert_eq!(sent, [data[128..192].to_vec(), data[192..].to_vec()]);
+
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.write_all(&data[..128]));
+    assert!(sent.is_empty());
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, stream.flush());
+    assert_eq!(sent, [data[..64].to_vec(), data[64..128].to_vec(), vec![]]);
+}
+
+#[test]
+fn test_reader_partial() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let endpoints = Box::leak(Box::new(RefCell::new(None)));
+    let mut handler = TestDevice { endpoints };
+    let mut dev = pin!(usb.run_device(&mut handler));
+    let mut stream = open_stream(&mut host, dev.as_mut(), endpoints);
+    let data: Vec<u8> = (0..200).collect();
+
+    // Reads smaller than a packet, and spanning the two packets of a transfer
+    host.out_packet(dev.as_mut(), EP_OUT, &data[..64]);
+    host.out_packet(dev.as_mut(), EP_OUT, &data[64..94]);
+    let mut buf = [0; 50];
+    let (n, _) = run_op(&mut host, dev.as_mut(), EP_IN, stream.read(&mut buf[..10]));
+    assert_eq!(&buf[..n.unwrap()], &data[..10]);
+    let (n, _) = run_op(&mut host, dev.as_mut(), EP_IN, stream.read(&mut buf));
+    assert_eq!(&buf[..n.unwrap()], &data[10..60]);
+    assert_eq!(stream.reader.available(), 34);
+    let (n, _) = run_op(&mut host, dev.as_mut(), EP_IN, stream.read(&mut buf));
+    assert_eq!(&buf[..n.unwrap()], &data[60..94]);
+
+    // A read spanning transfers, where a zero-length packet ends a transfer that fills the buffer
+    host.out_packet(dev.as_mut(), EP_OUT, &data[..64]);
+    host.out_packet(dev.as_mut(), EP_OUT, &data[64..128]);
+    host.out_packet(dev.as_mut(), EP_OUT, &[]);
+    host.out_packet(dev.as_mut(), EP_OUT, &data[128..140]);
+    let mut buf = [0; 140];
+    let (r, _) = run_op(&mut host, dev.as_mut(), EP_IN, stream.read_exact(&mut buf));
+    r.unwrap();
+    assert_eq!(&buf[..], &data[..140]);
+
+    // A zero-length packet on its own doesn't end the stream
+    host.out_packet(dev.as_mut(), EP_OUT, &[]);
+    let mut read = pin!(stream.read(&mut buf));
+    let both = pin!(poll_fn(|cx| {
+        let _ = dev.as_mut().poll(cx);
+        read.as_mut().poll(cx)
+    }));
+    assert!(host.run(both).is_none());
+}
+
+#[test]
+fn test_writer_halted() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let endpoints = Box::leak(Box::new(RefCell::new(None)));
+    let mut handler = TestDevice { endpoints };
+    let mut dev = pin!(usb.run_device(&mut handler));
+    let mut stream = open_stream(&mut host, dev.as_mut(), endpoints);
+    let data: Vec<u8> = (0..10).collect();
+
+    // Nothing is sent while the host has halted the endpoint
+    host.control_out(dev.as_mut(), setup(0x02, 0x03, 0, EP_IN as u16, 0), &[]).unwrap();
+    let mut write = pin!(async {
+        stream.write_all(&data).await.unwrap();
+        stream.flush().await.unwrap();
+    });
+    let both = pin!(poll_fn(|cx| {
+        let _ = dev.as_mut().poll(cx);
+        write.as_mut().poll(cx)
+    }));
+    assert!(host.run(both).is_none());
+    assert!(host.take_in(EP_IN).is_empty());
+
+    host.control_out(dev.as_mut(), setup(0x02, 0x01, 0, EP_IN as u16, 0), &[]).unwrap();
+    let (_, sent) = run_op(&mut host, dev.as_mut(), EP_IN, write);
+    assert_eq!(sent, std::slice::from_ref(&data));
+}
@@ -0,0 +1,585 @@
+#![cfg(feature = "usb-sim")]
+
+use std::cell::{Cell, RefCell};
+use std::pin::pin;
+
+use zeptos::usb::descriptors::{descriptors, language_list, string_descriptor, webusb_url, Config, ConfigDescriptor, DescriptorBuilder, Endpoint as EndpointDescriptor, Interface, InterfaceAssociation, StringTable};
+use zeptos::usb::descriptors::{BinaryObjectStore, PlatformCapabilityMicrosoftOs, PlatformCapabilityWebUsb, UrlTable, WEBUSB_GET_URL};
+use zeptos::usb::descriptors::{
+    MicrosoftOs10CompatibleID, MicrosoftOs10DeviceInterfaceGUID, MicrosoftOs10ExtendedCompatId, MicrosoftOs10ExtendedProperties, MicrosoftOs10String,
+    MicrosoftOs10Descriptors, MS_OS_10_STRING_INDEX,
+};
+use zeptos::usb::sim::{ControlError, Host};
+use zeptos::usb::{ControlData, ControlType, Endpoint, Endpoints, Handler, In, Out, Recipient, Responded, Setup, Usb};
+use zeptos::Runtime;
+
+mod common;
+use common::{setup, DEVICE_DESCRIPTOR};
+
+const CONFIG_DESCRIPTOR: &[u8] = &[
+    9, 0x02, 32, 0, 1, 1, 0, 0x80, 50,
+    9, 0x04, 0, 0, 2, 0xff, 0, 0, 0,
+    7, 0x05, 0x81, 0x02, 64, 0, 0,
+    7, 0x05, 0x01, 0x02, 64, 0, 0,
+];
+
+struct TestConfig;
+impl ConfigDescriptor for TestConfig {
+    const DESCRIPTOR: &'static [u8] = CONFIG_DESCRIPTOR;
+}
+
+const LANGUAGE_GERMAN: u16 = 0x0407;
+
+static STRINGS: StringTable = StringTable {
+    languages: language_list!(usb::language_id::ENGLISH_US, LANGUAGE_GERMAN),
+    strings: &[
+        &[string_descriptor!("zeptos")],
+        &[string_descriptor!("Test device"), string_descriptor!("Testgerät")],
+    ],
+};
+
+const VENDOR_CODE: u8 = 0x20;
+
+const MSOS10_COMPAT_ID: &[u8] = descriptors! {
+    MicrosoftOs10ExtendedCompatId {
+        +MicrosoftOs10CompatibleID {
+            first_interface: 0,
+            compatible_id: "WINUSB",
+            sub_compatible_id: "",
+        }
+    }
+};
+
+const MSOS10_PROPERTIES: &[u8] = descriptors! {
+    MicrosoftOs10ExtendedProperties {
+        +MicrosoftOs10DeviceInterfaceGUID {
+            guid: "{420F4791-4A3B-40A6-B8E9-4F63EF6017B9}",
+        }
+    }
+};
+
+static MSOS10: MicrosoftOs10Descriptors = MicrosoftOs10Descriptors {
+    string: descriptors! { MicrosoftOs10String { vendor_code: VENDOR_CODE, } },
+    compat_id: MSOS10_COMPAT_ID,
+    properties: &[MSOS10_PROPERTIES],
+};
+
+static URLS: UrlTable = UrlTable {
+    urls: &[webusb_url!("https://example.com/app"), webusb_url!("localhost:8000")],
+};
+
+const REQ_READ: u8 = 0x01;
+const REQ_WRITE: u8 = 0x02;
+const REQ_WRITE_INTO: u8 = 0x03;
+const REQ_READ_STREAM: u8 = 0x04;
+
+struct TestDevice {
+    resets: Cell<u32>,
+    data: RefCell<Vec<u8>>,
+    ep_in: RefCell<Option<Endpoint<In, 0x81>>>,
+    ep_out: RefCell<Option<Endpoint<Out, 0x01>>>,
+}
+
+impl TestDevice {
+    fn new() -> Self {
+        TestDevice {
+            resets: Cell::new(0),
+            data: RefCell::new(Vec::new()),
+            ep_in: RefCell::new(None),
+            ep_out: RefCell::new(None),
+        }
+    }
+}
+
+impl Handler for TestDevice {
+    fn handle_reset(&self) {
+        self.resets.set(self.resets.get() + 1);
+        self.ep_in.replace(None);
+        self.ep_out.replace(None);
+    }
+
+    fn get_descriptor<'a>(&self, kind: u8, index: u8, lang: u16, _builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        use usb::descriptor_type::{CONFIGURATION, DEVICE, STRING};
+        match (kind, index) {
+            (DEVICE, 0) => Some(DEVICE_DESCRIPTOR),
+            (CONFIGURATION, 0) => Some(CONFIG_DESCRIPTOR),
+            (STRING, MS_OS_10_STRING_INDEX) => Some(MSOS10.string),
+            (STRING, _) => STRINGS.get(index, lang),
+            _ => None,
+        }
+    }
+
+    async fn set_configuration(&self, cfg: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        self.ep_in.replace(None);
+        self.ep_out.replace(None);
+        match cfg {
+            0 => Ok(()),
+            1 => {
+                let endpoints = endpoints.described::<TestConfig>();
+                self.ep_in.replace(Some(endpoints.bulk_in()));
+                self.ep_out.replace(Some(endpoints.bulk_out()));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        let req = match MSOS10.handle_control(req).await {
+            Ok(r) => return r,
+            Err(req) => req,
+        };
+
+        match req {
+            Setup { ty: ControlType::Vendor, recipient: Recipient::Device, request: REQ_READ, data: ControlData::In(data), .. } => {
+                let d = self.data.borrow().clone();
+                data.respond(&d).await
+            }
+            Setup { ty: ControlType::Vendor, recipient: Recipient::Device, request: REQ_WRITE, data: ControlData::Out(mut data), .. } => {
+                let mut d = Vec::new();
+                while data.remaining() > 0 {
+                    d.extend_from_slice(data.receive().await);
+                }
+                self.data.replace(d);
+                data.accept().await
+            }
+            Setup { ty: ControlType::Vendor, recipient: Recipient::Device, request: REQ_WRITE_INTO, data: ControlData::Out(mut data), .. } => {
+                let mut buf = [0; 128];
+                match data.receive_into(&mut buf).await {
+                    Ok(d) => {
+                        self.data.replace(d.to_vec());
+                        data.accept().await
+                    }
+                    Err(_) => data.reject(),
+                }
+            }
+            Setup { ty: ControlType::Vendor, recipient: Recipient::Device, request: REQ_READ_STREAM, value, data: ControlData::In(data), .. } => {
+                data.respond_with(|offset, buf| {
+                    let len = buf.len().min((value as usize).saturating_sub(offset));
+                    for (i, b) in buf[..len].iter_mut().enumerate() {
+                        *b = (offset + i) as u8;
+                    }
+                    len
+                }).await
+            }
+            Setup { ty: ControlType::Vendor, recipient: Recipient::Device, request: VENDOR_CODE, index: WEBUSB_GET_URL, value, data: ControlData::In(data) } => {
+                match URLS.get(value) {
+                    Some(url) => data.respond(url).await,
+                    None => data.reject(),
+                }
+            }
+            req => req.reject(),
+        }
+    }
+}
+
+#[test]
+fn test_enumeration() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+
+    assert!(host.is_attached());
+    host.reset(dev.as_mut());
+
+    let desc = host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0100, 0, 64)).unwrap();
+    assert_eq!(desc, DEVICE_DESCRIPTOR);
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x05, 7, 0, 0), &[]).unwrap();
+    assert_eq!(host.address(), 7);
+
+    // Short read of the configuration descriptor header, then the full descriptor
+    let desc = host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0200, 0, 9)).unwrap();
+    assert_eq!(desc, &CONFIG_DESCRIPTOR[..9]);
+    let desc = host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0200, 0, 255)).unwrap();
+    assert_eq!(desc, CONFIG_DESCRIPTOR);
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0f00, 0, 255)), Err(ControlError::Stall));
+
+    assert_eq!(host.enabled_endpoints(), []);
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+    assert_eq!(host.enabled_endpoints(), [0x01, 0x81]);
+    assert_eq!(host.max_packet_size(0x81), Some(64));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x08, 0, 0, 1)), Ok(vec![1]));
+
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x00, 0x09, 2, 0, 0), &[]), Err(ControlError::Stall));
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 0, 0, 0), &[]).unwrap();
+    assert_eq!(host.enabled_endpoints(), []);
+
+    host.reset(dev.as_mut());
+    assert_eq!(host.address(), 0);
+}
+
+#[test]
+fn test_string_descriptor() {
+    assert_eq!(string_descriptor!(""), &[2, 3]);
+    assert_eq!(string_descriptor!("Aé€"), &[8, 3, 0x41, 0, 0xe9, 0, 0xac, 0x20]);
+    assert_eq!(string_descriptor!("😀"), &[6, 3, 0x3d, 0xd8, 0x00, 0xde]);
+}
+
+#[test]
+fn test_string_table() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    let mut get_string = |index: u8, lang: u16| host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x0300 | index as u16, lang, 255));
+
+    assert_eq!(get_string(0, 0), Ok(vec![6, 3, 0x09, 0x04, 0x07, 0x04]));
+    assert_eq!(get_string(2, 0x0409).unwrap(), string_descriptor!("Test device"));
+    assert_eq!(get_string(2, LANGUAGE_GERMAN).unwrap(), string_descriptor!("Testgerät"));
+
+    // Fall back to the first language
+    assert_eq!(get_string(1, LANGUAGE_GERMAN).unwrap(), string_descriptor!("zeptos"));
+    assert_eq!(get_string(2, 0x040c).unwrap(), string_descriptor!("Test device"));
+
+    assert_eq!(get_string(3, 0x0409), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_vendor_requests() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    // Multi-packet OUT data stage
+    let payload: Vec<u8> = (0..150).collect();
+    host.control_out(dev.as_mut(), setup(0x40, REQ_WRITE, 0, 0, 150), &payload).unwrap();
+
+    // Multi-packet IN data stage, limited to wLength
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ, 0, 0, 150)), Ok(payload.clone()));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ, 0, 0, 100)), Ok(payload[..100].to_vec()));
+
+    // A response that is a multiple of the packet size and shorter than
+    // wLength ends with a zero-length packet
+    host.control_out(dev.as_mut(), setup(0x40, REQ_WRITE, 0, 0, 64), &payload[..64]).unwrap();
+    host.setup(dev.as_mut(), setup(0xc0, REQ_READ, 0, 0, 255));
+    assert_eq!(host.take_in(0), [payload[..64].to_vec(), vec![]]);
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, 0x55, 0, 0, 4)), Err(ControlError::Stall));
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x40, 0x55, 0, 0, 0), &[]), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_receive_into() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    let payload: Vec<u8> = (0..128).collect();
+    host.control_out(dev.as_mut(), setup(0x40, REQ_WRITE_INTO, 0, 0, 128), &payload).unwrap();
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ, 0, 0, 255)), Ok(payload.clone()));
+
+    // A short packet ends the data stage before wLength
+    host.setup(dev.as_mut(), setup(0x40, REQ_WRITE_INTO, 0, 0, 100));
+    host.out_packet(dev.as_mut(), 0, &payload[..64]);
+    host.out_packet(dev.as_mut(), 0, &payload[64..70]);
+    assert_eq!(host.take_in(0), [vec![]]);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ, 0, 0, 255)), Ok(payload[..70].to_vec()));
+
+    // Longer than the buffer
+    let payload: Vec<u8> = (0..129).collect();
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x40, REQ_WRITE_INTO, 0, 0, 129), &payload), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_respond_with() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    let expected: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ_STREAM, 150, 0, 255)), Ok(expected[..150].to_vec()));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ_STREAM, 300, 0, 200)), Ok(expected[..200].to_vec()));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, REQ_READ_STREAM, 0, 0, 16)), Ok(vec![]));
+
+    // Ended with a zero-length packet when shorter than wLength
+    host.setup(dev.as_mut(), setup(0xc0, REQ_READ_STREAM, 128, 0, 255));
+    assert_eq!(host.take_in(0), [expected[..64].to_vec(), expected[64..128].to_vec(), vec![]]);
+
+    // But not when it reaches wLength
+    host.setup(dev.as_mut(), setup(0xc0, REQ_READ_STREAM, 300, 0, 128));
+    assert_eq!(host.take_in(0), [expected[..64].to_vec(), expected[64..128].to_vec()]);
+}
+
+#[test]
+fn test_microsoft_os_10() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    let mut expected = vec![18, 0x03];
+    for c in "MSFT100".encode_utf16() {
+        expected.extend(c.to_le_bytes());
+    }
+    expected.extend([VENDOR_CODE, 0]);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x06, 0x03ee, 0, 255)), Ok(expected));
+
+    // Header first, then the full descriptor
+    let header = host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 0, 4, 16)).unwrap();
+    assert_eq!(header, [40, 0, 0, 0, 0x00, 0x01, 0x04, 0x00, 1, 0, 0, 0, 0, 0, 0, 0]);
+    let desc = host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 0, 4, 40)).unwrap();
+    assert_eq!(&desc[16..], b"\x00\x01WINUSB\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+    let props = host.control_in(dev.as_mut(), setup(0xc1, VENDOR_CODE, 0, 5, 255)).unwrap();
+    assert_eq!(props.len(), 146);
+    assert_eq!(props[..10], [146, 0, 0, 0, 0x00, 0x01, 0x05, 0x00, 1, 0]);
+    let property = &props[10..];
+    assert_eq!(property[..10], [136, 0, 0, 0, 7, 0, 0, 0, 42, 0]);
+    let name: Vec<u16> = property[10..52].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    assert_eq!(String::from_utf16(&name).unwrap(), "DeviceInterfaceGUIDs\0");
+    assert_eq!(property[52..56], [80, 0, 0, 0]);
+    let value: Vec<u16> = property[56..].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    assert_eq!(String::from_utf16(&value).unwrap(), "{420F4791-4A3B-40A6-B8E9-4F63EF6017B9}\0\0");
+
+    // Windows may ask the device for the properties of interface 0
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 0, 5, 255)), Ok(props));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc1, VENDOR_CODE, 0x0100, 5, 255)), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc1, VENDOR_CODE, 0x0001, 5, 255)), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE + 1, 0, 4, 16)), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_webusb() {
+    const BOS: &[u8] = descriptors! {
+        BinaryObjectStore {
+            +PlatformCapabilityWebUsb {
+                vendor_code: VENDOR_CODE,
+                landing_page: 1,
+            }
+            +PlatformCapabilityMicrosoftOs {
+                windows_version: 0x06030000,
+                vendor_code: VENDOR_CODE,
+                alt_enum_code: 0,
+                msos_descriptor_len: 10,
+            }
+        }
+    };
+    assert_eq!(BOS[..5], [5, 0x0f, 57, 0, 2]);
+    assert_eq!(
+        BOS[5..29],
+        [
+            24, 0x10, 0x05, 0, 0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65, 0x00,
+            0x01, VENDOR_CODE, 1
+        ]
+    );
+
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    let mut expected = vec![18, 0x03, 1];
+    expected.extend(b"example.com/app");
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 1, 2, 255)), Ok(expected));
+
+    let mut expected = vec![17, 0x03, 255];
+    expected.extend(b"localhost:8000");
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 2, 2, 255)), Ok(expected));
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 0, 2, 255)), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xc0, VENDOR_CODE, 3, 2, 255)), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_endpoint_halt() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x82, 0x00, 0, 0x81, 2)), Ok(vec![0, 0]));
+
+    host.control_out(dev.as_mut(), setup(0x02, 0x03, 0, 0x81, 0), &[]).unwrap();
+    assert!(host.is_halted(0x81));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x82, 0x00, 0, 0x81, 2)), Ok(vec![1, 0]));
+
+    host.control_out(dev.as_mut(), setup(0x02, 0x01, 0, 0x81, 0), &[]).unwrap();
+    assert!(!host.is_halted(0x81));
+
+    // Endpoint that is not enabled
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x82, 0x00, 0, 0x82, 2)), Err(ControlError::Stall));
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x02, 0x03, 0, 0x82, 0), &[]), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_interface_requests() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x0a, 0, 0, 1)), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x00, 0, 0, 2)), Err(ControlError::Stall));
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x0a, 0, 0, 1)), Ok(vec![0]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x00, 0, 0, 2)), Ok(vec![0, 0]));
+
+    // The configuration has a single interface
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x0a, 0, 1, 1)), Err(ControlError::Stall));
+    assert_eq!(host.control_out(dev.as_mut(), setup(0x01, 0x0b, 0, 1, 0), &[]), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x00, 0, 1, 2)), Err(ControlError::Stall));
+
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 0, 0, 0), &[]).unwrap();
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x0a, 0, 0, 1)), Err(ControlError::Stall));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x81, 0x00, 0, 0, 2)), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_simulations_start_unconfigured() {
+    {
+        let mut host = Host::new();
+        let mut usb = unsafe { Usb::new(Runtime::steal()) };
+        let mut handler = TestDevice::new();
+        let mut dev = pin!(usb.run_device(&mut handler));
+        host.reset(dev.as_mut());
+        host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+        host.control_out(dev.as_mut(), setup(0x02, 0x03, 0, 0x81, 0), &[]).unwrap();
+    }
+
+    // The next simulation sees a device fresh from power-on, even before a bus reset
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x08, 0, 0, 1)), Ok(vec![0]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x82, 0x00, 0, 0x81, 2)), Err(ControlError::Stall));
+}
+
+#[test]
+fn test_remote_wakeup() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let shared = usb.shared();
+    let mut handler = TestDevice::new();
+    let mut dev = pin!(usb.run_device(&mut handler));
+    host.reset(dev.as_mut());
+
+    host.suspend(dev.as_mut());
+    assert!(shared.is_suspended());
+    assert!(!shared.remote_wakeup());
+
+    host.resume(dev.as_mut());
+    host.control_out(dev.as_mut(), setup(0x00, 0x03, 1, 0, 0), &[]).unwrap();
+    assert_eq!(host.control_in(dev.as_mut(), setup(0x80, 0x00, 0, 0, 2)), Ok(vec![0b10, 0]));
+
+    host.suspend(dev.as_mut());
+    assert!(shared.remote_wakeup());
+    assert!(host.take_resume_signaled());
+
+    host.resume(dev.as_mut());
+    assert!(!shared.is_suspended());
+}
+
+const COMPOSITE_CONFIG_DESCRIPTOR: &[u8] = descriptors! {
+    Config {
+        bConfigurationValue: 1,
+        iConfiguration: 0,
+        bmAttributes: 0x80,
+        bMaxPower: 50,
+
+        +InterfaceAssociation {
+            bFirstInterface: 0,
+            bInterfaceCount: 2,
+            bFunctionClass: 0x02,
+            bFunctionSubClass: 0x0d,
+            bFunctionProtocol: 0,
+            iFunction: 0,
+        }
+
+        +Interface {
+            bInterfaceNumber: 0,
+            bAlternateSetting: 0,
+            bInterfaceClass: 0x02,
+            bInterfaceSubClass: 0x0d,
+            bInterfaceProtocol: 0,
+            iInterface: 0,
+
+            +EndpointDescriptor { bEndpointAddress: 0x83, bmAttributes: 0x03, wMaxPacketSize: 10, bInterval: 32, }
+        }
+
+        +Interface {
+            bInterfaceNumber: 1,
+            bAlternateSetting: 0,
+            bInterfaceClass: 0x0a,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0x01,
+            iInterface: 0,
+        }
+
+        +Interface {
+            bInterfaceNumber: 1,
+            bAlternateSetting: 1,
+            bInterfaceClass: 0x0a,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0x01,
+            iInterface: 0,
+
+            +EndpointDescriptor { bEndpointAddress: 0x81, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0, }
+            +EndpointDescriptor { bEndpointAddress: 0x01, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0, }
+        }
+
+        +Interface {
+            bInterfaceNumber: 2,
+            bAlternateSetting: 0,
+            bInterfaceClass: 0xff,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0,
+            iInterface: 0,
+
+            +EndpointDescriptor { bEndpointAddress: 0x82, bmAttributes: 0x02, wMaxPacketSize: 64, bInterval: 0, }
+        }
+
+        +Interface {
+            bInterfaceNumber: 2,
+            bAlternateSetting: 1,
+            bInterfaceClass: 0xff,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0,
+            iInterface: 0,
+
+            +EndpointDescriptor { bEndpointAddress: 0x82, bmAttributes: 0x01, wMaxPacketSize: 512, bInterval: 1, }
+        }
+    }
+};
+
+#[test]
+fn test_config_descriptor() {
+    let cfg = COMPOSITE_CONFIG_DESCRIPTOR;
+    assert_eq!(u16::from_le_bytes([cfg[2], cfg[3]]) as usize, cfg.len());
+    assert_eq!(cfg[4], 3);
+
+    let ep = Config::find_endpoint(cfg, 0x83).unwrap();
+    assert_eq!((ep.bmAttributes, ep.wMaxPacketSize, ep.bInterval), (0x03, 10, 32));
+
+    // The first alternate setting that uses an address is found
+    let ep = Config::find_endpoint(cfg, 0x82).unwrap();
+    assert_eq!((ep.bmAttributes, ep.wMaxPacketSize), (0x02, 64));
+
+    assert!(Config::find_endpoint(cfg, 0x02).is_none());
+    assert!(Config::find_endpoint(CONFIG_DESCRIPTOR, 0x81).is_some());
+
+    // Any alternate setting can match
+    const {
+        Config::check_endpoint(COMPOSITE_CONFIG_DESCRIPTOR, 0x83, &[0x02, 0x03], 10);
+        Config::check_endpoint(COMPOSITE_CONFIG_DESCRIPTOR, 0x82, &[0x02], 64);
+        Config::check_endpoint(COMPOSITE_CONFIG_DESCRIPTOR, 0x82, &[0x01], 512);
+    }
+}
@@ -0,0 +1,389 @@
+#![cfg(feature = "usb-sim")]
+
+use std::cell::{Cell, RefCell};
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+use std::task::Poll;
+
+use zeptos::usb::class::usbtmc::{self, Capabilities, Request, Usb488Capabilities, UsbtmcControl, UsbtmcData};
+use zeptos::usb::descriptors::{descriptors, Config, ConfigDescriptor, DescriptorBuilder, Endpoint as EndpointDescriptor, Interface};
+use zeptos::usb::sim::Host;
+use zeptos::usb::{Endpoint, Endpoints, Handler, In, Responded, Setup, Usb};
+use zeptos::Runtime;
+
+mod common;
+use common::{setup, DEVICE_DESCRIPTOR};
+
+const INTF_TMC: u8 = 0;
+const EP_OUT: u8 = 0x01;
+const EP_IN: u8 = 0x81;
+const EP_INT: u8 = 0x82;
+
+/// Smaller than the 64 byte chunks the class transfers in.
+const MPS: u16 = 32;
+const MPS_INT: u16 = 8;
+
+const CONFIG_DESCRIPTOR: &[u8] = descriptors! {
+    Config {
+        bConfigurationValue: 1,
+        iConfiguration: 0,
+        bmAttributes: 0x80,
+        bMaxPower: 50,
+
+        +Interface {
+            bInterfaceNumber: INTF_TMC,
+            bAlternateSetting: 0,
+            bInterfaceClass: usbtmc::CLASS_APPLICATION_SPECIFIC,
+            bInterfaceSubClass: usbtmc::SUBCLASS_USBTMC,
+            bInterfaceProtocol: usbtmc::PROTOCOL_USB488,
+            iInterface: 0,
+
+            +EndpointDescriptor { bEndpointAddress: EP_OUT, bmAttributes: 0x02, wMaxPacketSize: MPS, bInterval: 0, }
+            +EndpointDescriptor { bEndpointAddress: EP_IN, bmAttributes: 0x02, wMaxPacketSize: MPS, bInterval: 0, }
+            +EndpointDescriptor { bEndpointAddress: EP_INT, bmAttributes: 0x03, wMaxPacketSize: MPS_INT, bInterval: 10, }
+        }
+    }
+};
+
+struct TmcConfig;
+impl ConfigDescriptor for TmcConfig {
+    const DESCRIPTOR: &'static [u8] = CONFIG_DESCRIPTOR;
+}
+
+const IDN: &[u8] = b"zeptos,usbtmc,0,1.0\n";
+
+/// Stands in for a `#[zeptos::task]` by polling the spawned future alongside the device.
+struct Task {
+    fut: RefCell<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+}
+
+impl Task {
+    fn new() -> Self {
+        Task { fut: RefCell::new(None) }
+    }
+
+    fn spawn(&self, fut: impl Future<Output = ()> + 'static) {
+        self.fut.replace(Some(Box::pin(fut)));
+    }
+
+    fn cancel(&self) {
+        self.fut.replace(None);
+    }
+
+    async fn run(&self) {
+        poll_fn(|cx| {
+            let mut fut = self.fut.borrow_mut();
+            if fut.as_mut().is_some_and(|f| f.as_mut().poll(cx).is_ready()) {
+                *fut = None;
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+struct TestDevice<'a> {
+    tmc: &'static UsbtmcControl,
+    ep_int: RefCell<Option<Endpoint<In, EP_INT, MPS_INT>>>,
+    data_task: &'a Task,
+    last_message: &'static RefCell<Vec<u8>>,
+    triggers: &'static Cell<u32>,
+}
+
+impl<'a> TestDevice<'a> {
+    fn new(data_task: &'a Task) -> Self {
+        let tmc = Box::leak(Box::new(UsbtmcControl::new(
+            INTF_TMC,
+            EP_IN,
+            EP_OUT,
+            Capabilities {
+                listen_only: false,
+                talk_only: false,
+                indicator_pulse: true,
+                term_char: true,
+                usb488: Some(Usb488Capabilities {
+                    trigger: true,
+                    remote_local: true,
+                    service_request: true,
+                    scpi: true,
+                    interrupt_in: true,
+                }),
+            },
+        )));
+        TestDevice {
+            tmc,
+            ep_int: RefCell::new(None),
+            data_task,
+            last_message: Box::leak(Box::new(RefCell::new(Vec::new()))),
+            triggers: Box::leak(Box::new(Cell::new(0))),
+        }
+    }
+}
+
+async fn message_loop(
+    mut tmc: UsbtmcData<'static, EP_IN, EP_OUT, MPS>,
+    last_message: &'static RefCell<Vec<u8>>,
+    triggers: &'static Cell<u32>,
+) {
+    let mut buf = [0; 128];
+    let mut response = Vec::new();
+    loop {
+        match tmc.receive(&mut buf).await {
+            Ok(Request::Message(msg)) => {
+                last_message.replace(msg.to_vec());
+                response = match msg {
+                    b"*IDN?\n" => IDN.to_vec(),
+                    b"DATA?\n" => (0..200u8).collect(),
+                    _ => Vec::new(),
+                };
+            }
+            Ok(Request::Read { .. }) => {
+                if let Ok(n) = tmc.respond(&response).await {
+                    response.drain(..n);
+                }
+            }
+            Ok(Request::Trigger) => triggers.set(triggers.get() + 1),
+            Err(_) => {
+                last_message.replace(b"error".to_vec());
+            }
+        }
+    }
+}
+
+impl Handler for TestDevice<'_> {
+    fn handle_reset(&self) {
+        self.tmc.reset();
+        self.data_task.cancel();
+        self.ep_int.replace(None);
+    }
+
+    fn get_descriptor<'a>(&self, kind: u8, index: u8, _lang: u16, _builder: &'a mut DescriptorBuilder) -> Option<&'a [u8]> {
+        use usb::descriptor_type::{CONFIGURATION, DEVICE};
+        match (kind, index) {
+            (DEVICE, 0) => Some(DEVICE_DESCRIPTOR),
+            (CONFIGURATION, 0) => Some(CONFIG_DESCRIPTOR),
+            _ => None,
+        }
+    }
+
+    async fn set_configuration(&self, cfg: u8, endpoints: &mut Endpoints) -> Result<(), ()> {
+        self.tmc.reset();
+        self.data_task.cancel();
+        self.ep_int.replace(None);
+        match cfg {
+            0 => Ok(()),
+            1 => {
+                let endpoints = endpoints.described::<TmcConfig>();
+                let data = UsbtmcData::new(self.tmc, endpoints.endpoint(), endpoints.endpoint());
+                self.data_task.spawn(message_loop(data, self.last_message, self.triggers));
+                self.ep_int.replace(Some(endpoints.endpoint()));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    async fn handle_control<'a>(&self, req: Setup<'a>) -> Responded {
+        if !self.tmc.accepts(&req) {
+            return req.reject();
+        }
+        let responded = self.tmc.handle_control(req).await;
+        let ep_int = self.ep_int.take();
+        if let Some(mut ep) = ep_int {
+            self.tmc.notify_status(&mut ep).await;
+            self.ep_int.replace(Some(ep));
+        }
+        responded
+    }
+}
+
+fn dev_dep_msg_out(tag: u8, data: &[u8], eom: bool) -> Vec<u8> {
+    let mut t = vec![1, tag, !tag, 0];
+    t.extend((data.len() as u32).to_le_bytes());
+    t.extend([eom as u8, 0, 0, 0]);
+    t.extend(data);
+    t.resize(t.len().next_multiple_of(4), 0);
+    t
+}
+
+fn request_dev_dep_msg_in(tag: u8, max_len: u32, term_char: Option<u8>) -> Vec<u8> {
+    let mut t = vec![2, tag, !tag, 0];
+    t.extend(max_len.to_le_bytes());
+    t.extend([if term_char.is_some() { 0x02 } else { 0 }, term_char.unwrap_or(0), 0, 0]);
+    t
+}
+
+/// Send a Bulk-OUT transfer as USBTMC hosts do, without a zero-length packet.
+fn bulk_out<F: Future>(host: &mut Host, mut dev: Pin<&mut F>, transfer: &[u8]) {
+    for pkt in transfer.chunks(MPS as usize) {
+        host.out_packet(dev.as_mut(), EP_OUT, pkt);
+    }
+}
+
+/// Read one Bulk-IN transfer, returning the header and the message data.
+fn bulk_in<F: Future>(host: &mut Host, dev: Pin<&mut F>) -> (Vec<u8>, Vec<u8>) {
+    let transfer = common::bulk_in(host, dev, EP_IN, MPS as usize);
+    let len = u32::from_le_bytes(transfer[4..8].try_into().unwrap()) as usize;
+    assert_eq!(transfer.len(), (12 + len).next_multiple_of(4));
+    (transfer[..12].to_vec(), transfer[12..12 + len].to_vec())
+}
+
+fn configure<F: Future>(host: &mut Host, mut dev: Pin<&mut F>) {
+    host.reset(dev.as_mut());
+    host.control_out(dev.as_mut(), setup(0x00, 0x09, 1, 0, 0), &[]).unwrap();
+}
+
+/// Poll two futures together until both complete.
+async fn join<A: Future, B: Future>(a: A, b: B) {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    let (mut a_done, mut b_done) = (false, false);
+    poll_fn(|cx| {
+        a_done = a_done || a.as_mut().poll(cx).is_ready();
+        b_done = b_done || b.as_mut().poll(cx).is_ready();
+        if a_done && b_done { Poll::Ready(()) } else { Poll::Pending }
+    })
+    .await
+}
+
+#[test]
+fn test_control_requests() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let task = Task::new();
+    let mut handler = TestDevice::new(&task);
+    let tmc = handler.tmc;
+    let mut dev = pin!(join(usb.run_device(&mut handler), task.run()));
+    configure(&mut host, dev.as_mut());
+
+    let caps = host.control_in(dev.as_mut(), setup(0xa1, 7, 0, INTF_TMC as u16, 0x18)).unwrap();
+    assert_eq!(
+        caps,
+        [0x01, 0, 0x00, 0x01, 0b100, 0b1, 0, 0, 0, 0, 0, 0, 0x00, 0x01, 0b111, 0b1111, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    // Requests to another interface are not handled
+    assert!(host.control_in(dev.as_mut(), setup(0xa1, 7, 0, 1, 0x18)).is_err());
+    assert!(host.control_in(dev.as_mut(), setup(0xa1, 7, 0, 0x0100 | INTF_TMC as u16, 0x18)).is_err());
+    assert!(host.control_in(dev.as_mut(), setup(0xa2, 1, 1, 0x0100 | EP_OUT as u16, 2)).is_err());
+
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 64, 0, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert!(tmc.take_indicator_pulse());
+    assert!(!tmc.take_indicator_pulse());
+
+    // The status byte is sent on the interrupt endpoint
+    tmc.set_status_byte(0x42);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 128, 5, INTF_TMC as u16, 3)), Ok(vec![0x01, 5, 0]));
+    assert_eq!(host.take_in(EP_INT), [vec![0x85, 0x42]]);
+
+    // Remote/local
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 160, 1, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 162, 0, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert!(tmc.local_lockout());
+    assert!(!tmc.is_remote());
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(1, b"*RST\n", true));
+    assert!(tmc.is_remote());
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 161, 0, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert!(!tmc.is_remote());
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 160, 0, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert!(!tmc.local_lockout());
+}
+
+#[test]
+fn test_messages() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let task = Task::new();
+    let mut handler = TestDevice::new(&task);
+    let last_message = handler.last_message;
+    let triggers = handler.triggers;
+    let mut dev = pin!(join(usb.run_device(&mut handler), task.run()));
+    configure(&mut host, dev.as_mut());
+
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(1, b"*IDN?\n", true));
+    assert_eq!(*last_message.borrow(), b"*IDN?\n");
+
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(2, 1024, None));
+    let (header, data) = bulk_in(&mut host, dev.as_mut());
+    assert_eq!(header, [2, 2, !2, 0, IDN.len() as u8, 0, 0, 0, 0x01, 0, 0, 0]);
+    assert_eq!(data, IDN);
+
+    // A message split over transfers, the first spanning several packets
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(3, &[b'x'; 100], false));
+    assert_eq!(*last_message.borrow(), b"*IDN?\n");
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(4, b"\n", true));
+    assert_eq!(last_message.borrow().len(), 101);
+
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(5, &[b'y'; 200], true));
+    assert_eq!(*last_message.borrow(), b"error");
+
+    // A response longer than the host's read request is split
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(6, b"DATA?\n", true));
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(7, 150, None));
+    let (header, data) = bulk_in(&mut host, dev.as_mut());
+    assert_eq!(header[8], 0x00);
+    assert_eq!(data, (0..150).collect::<Vec<u8>>());
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(8, 150, None));
+    let (header, data) = bulk_in(&mut host, dev.as_mut());
+    assert_eq!(header[8], 0x01);
+    assert_eq!(data, (150..200).collect::<Vec<u8>>());
+
+    // Ended at the termination character
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(9, b"DATA?\n", true));
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(10, 150, Some(9)));
+    let (header, data) = bulk_in(&mut host, dev.as_mut());
+    assert_eq!(header[8], 0x03);
+    assert_eq!(data, (0..10).collect::<Vec<u8>>());
+
+    bulk_out(&mut host, dev.as_mut(), &[128, 11, !11, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(triggers.get(), 1);
+
+    // An invalid header halts the endpoint
+    bulk_out(&mut host, dev.as_mut(), &[1, 12, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert!(host.is_halted(EP_OUT));
+}
+
+#[test]
+fn test_abort() {
+    let mut host = Host::new();
+    let mut usb = unsafe { Usb::new(Runtime::steal()) };
+    let task = Task::new();
+    let mut handler = TestDevice::new(&task);
+    let last_message = handler.last_message;
+    let mut dev = pin!(join(usb.run_device(&mut handler), task.run()));
+    configure(&mut host, dev.as_mut());
+
+    // Nothing to abort
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 1, 1, EP_OUT as u16, 2)), Ok(vec![0x80, 1]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 3, 1, EP_IN as u16, 2)), Ok(vec![0x80, 1]));
+
+    // Abort a Bulk-OUT transfer after its first 64 byte chunk
+    let transfer = dev_dep_msg_out(1, &[b'z'; 100], true);
+    bulk_out(&mut host, dev.as_mut(), &transfer[..64]);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 1, 2, EP_OUT as u16, 2)), Ok(vec![0x81, 1]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 1, 1, EP_OUT as u16, 2)), Ok(vec![0x01, 1]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 2, 0, EP_OUT as u16, 8)), Ok(vec![0x01, 0, 0, 0, 52, 0, 0, 0]));
+    host.control_out(dev.as_mut(), setup(0x02, 0x01, 0, EP_OUT as u16, 0), &[]).unwrap();
+
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(2, b"DATA?\n", true));
+    assert_eq!(*last_message.borrow(), b"DATA?\n");
+
+    // Abort a Bulk-IN transfer after the host read its first 64 byte chunk
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(3, 1024, None));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 3, 3, EP_IN as u16, 2)), Ok(vec![0x01, 3]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 4, 0, EP_IN as u16, 8)), Ok(vec![0x02, 1, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(host.take_in(EP_IN).len(), 64 / MPS as usize);
+    host.run(dev.as_mut());
+    assert_eq!(host.take_in(EP_IN), [vec![]]);
+    host.run(dev.as_mut());
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa2, 4, 0, EP_IN as u16, 8)), Ok(vec![0x01, 0, 0, 0, 52, 0, 0, 0]));
+
+    // Clear
+    bulk_out(&mut host, dev.as_mut(), &dev_dep_msg_out(4, b"*IDN?\n", true));
+    bulk_out(&mut host, dev.as_mut(), &request_dev_dep_msg_in(5, 1024, None));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 5, 0, INTF_TMC as u16, 1)), Ok(vec![0x01]));
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 6, 0, INTF_TMC as u16, 2)), Ok(vec![0x02, 1]));
+    let (_, data) = bulk_in(&mut host, dev.as_mut());
+    assert_eq!(data, IDN);
+    assert_eq!(host.control_in(dev.as_mut(), setup(0xa1, 6, 0, INTF_TMC as u16, 2)), Ok(vec![0x01, 0]));
+}
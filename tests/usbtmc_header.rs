@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+
+mod header {
+    include!("../src/usb/class/usbtmc/header.rs");
+
+    #[test]
+    pub fn test_parse_dev_dep_msg_out() {
+        let buf = [1, 5, !5, 0, 6, 0, 0, 0, 0x01, 0, 0, 0, b'*', b'I', b'D', b'N', b'?', b'\n', 0, 0];
+        assert_eq!(
+            BulkOutHeader::parse(&buf),
+            Ok(BulkOutHeader::DevDepMsgOut { tag: 5, transfer_size: 6, eom: true })
+        );
+        assert_eq!(BulkOutHeader::parse(&buf).unwrap().tag(), 5);
+        assert_eq!(transfer_len(6), buf.len());
+    }
+
+    #[test]
+    pub fn test_parse_request_dev_dep_msg_in() {
+        let buf = [2, 9, !9, 0, 0x00, 0x04, 0, 0, 0x00, b'\n', 0, 0];
+        assert_eq!(
+            BulkOutHeader::parse(&buf),
+            Ok(BulkOutHeader::RequestDevDepMsgIn { tag: 9, transfer_size: 1024, term_char: None })
+        );
+
+        let buf = [2, 9, !9, 0, 0x00, 0x04, 0, 0, 0x02, b'\n', 0, 0];
+        assert_eq!(
+            BulkOutHeader::parse(&buf),
+            Ok(BulkOutHeader::RequestDevDepMsgIn { tag: 9, transfer_size: 1024, term_char: Some(b'\n') })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_invalid() {
+        assert_eq!(BulkOutHeader::parse(&[1, 5, !5, 0, 0, 0, 0, 0, 0, 0, 0]), Err(HeaderError::Short));
+        assert_eq!(BulkOutHeader::parse(&[1, 5, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(HeaderError::InvalidTag));
+        assert_eq!(BulkOutHeader::parse(&[1, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(HeaderError::InvalidTag));
+        assert_eq!(
+            BulkOutHeader::parse(&[VENDOR_SPECIFIC_OUT, 1, !1, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(HeaderError::UnsupportedMessage(VENDOR_SPECIFIC_OUT))
+        );
+        assert_eq!(
+            BulkOutHeader::parse(&[TRIGGER, 3, !3, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Ok(BulkOutHeader::Trigger { tag: 3 })
+        );
+    }
+
+    #[test]
+    pub fn test_write_dev_dep_msg_in() {
+        let mut buf = [0xaa; HEADER_LEN];
+        write_dev_dep_msg_in(&mut buf, 7, 300, true, false);
+        assert_eq!(buf, [2, 7, !7, 0, 0x2c, 0x01, 0, 0, 0x01, 0, 0, 0]);
+
+        write_dev_dep_msg_in(&mut buf, 8, 4, true, true);
+        assert_eq!(buf, [2, 8, !8, 0, 4, 0, 0, 0, 0x03, 0, 0, 0]);
+
+        write_dev_dep_msg_in(&mut buf, 8, 4, false, false);
+        assert_eq!(buf[8], 0);
+    }
+
+    #[test]
+    pub fn test_transfer_len() {
+        assert_eq!(transfer_len(0), 12);
+        assert_eq!(transfer_len(1), 16);
+        assert_eq!(transfer_len(4), 16);
+        assert_eq!(transfer_len(52), 64);
+        assert_eq!(transfer_len(53), 68);
+    }
+}
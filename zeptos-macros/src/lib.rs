@@ -42,6 +42,9 @@ impl Parse for Args {
 ///     // Function body
 /// }
 /// ```
+///
+/// With the `multicore` feature, a task for the executor on core 1 is declared with
+/// `#[zeptos::task(core = 1)]`, and spawned with that core's `Runtime<1>`.
 #[proc_macro_attribute]
 pub fn task(args: TokenStream, item: TokenStream) -> TokenStream {
     let args = syn::parse_macro_input!(args as Args);
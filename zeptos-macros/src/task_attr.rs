@@ -9,10 +9,14 @@ use crate::ctxt::Ctxt;
 
 #[derive(Debug, FromMeta)]
 struct Args {
+    /// Core whose executor runs the task.
+    #[darling(default)]
+    core: u8,
 }
 
 pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStream> {
-    let _args = Args::from_list(args).map_err(|e| e.write_errors())?;
+    let args_meta = Args::from_list(args).map_err(|e| e.write_errors())?;
+    let core = proc_macro2::Literal::u8_unsuffixed(args_meta.core);
 
     let ctxt = Ctxt::new();
 
@@ -112,7 +116,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
         #[allow(non_camel_case_types)]
         #[derive(Clone, Copy)]
         struct #task_handle_ty {
-            rt: ::zeptos::Runtime,
+            rt: ::zeptos::Runtime<#core>,
         }
 
         impl #task_handle_ty {
@@ -135,7 +139,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
                 }
             }
 
-            pub fn task_ref(self) -> ::zeptos::TaskRef {
+            pub fn task_ref(self) -> ::zeptos::TaskRef<#core> {
                 ::zeptos::TaskRef::new(self.rt, <Self as ::zeptos::internal::Task>::node())
             }
 
@@ -155,7 +159,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
 
             #[inline(always)]
             fn node() -> &'static ::zeptos::internal::RunQueueNode {
-                static NODE: ::zeptos::internal::RunQueueNode = ::zeptos::internal::RunQueueNode::new(<#task_handle_ty as ::zeptos::internal::Task>::poll);
+                static NODE: ::zeptos::internal::RunQueueNode = ::zeptos::internal::RunQueueNode::new(<#task_handle_ty as ::zeptos::internal::Task>::poll, #core);
                 &NODE
             }
 
@@ -164,7 +168,7 @@ pub fn run(args: &[NestedMeta], f: syn::ItemFn) -> Result<TokenStream, TokenStre
             }
         }
 
-        #visibility fn #task_ident(rt: ::zeptos::Runtime) -> #task_handle_ty {
+        #visibility fn #task_ident(rt: ::zeptos::Runtime<#core>) -> #task_handle_ty {
             #task_handle_ty { rt }
         }
     };